@@ -4,6 +4,8 @@ pub mod nextclade_dataset_list;
 pub mod nextclade_loop;
 pub mod nextclade_ordered_writer;
 pub mod nextclade_read_annotation;
+pub mod nextclade_results_cache;
 pub mod nextclade_seq_sort;
+pub mod nextclade_tree_check;
 pub mod print_help_markdown;
 pub mod verbosity;
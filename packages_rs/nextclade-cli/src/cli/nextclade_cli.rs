@@ -3,6 +3,7 @@ use crate::cli::nextclade_dataset_list::nextclade_dataset_list;
 use crate::cli::nextclade_loop::nextclade_run;
 use crate::cli::nextclade_read_annotation::nextclade_read_annotation;
 use crate::cli::nextclade_seq_sort::nextclade_seq_sort;
+use crate::cli::nextclade_tree_check::nextclade_tree_check;
 use crate::cli::print_help_markdown::print_help_markdown;
 use crate::cli::verbosity::{Verbosity, WarnLevel};
 use crate::io::http_client::ProxyConfig;
@@ -100,6 +101,11 @@ pub enum NextcladeCommands {
   /// For short help type: `nextclade -h`, for extended help type: `nextclade --help`. Each subcommand has its own help, for example: `nextclade sort --help`.
   ReadAnnotation(Box<NextcladeReadAnnotationArgs>),
 
+  /// Work with reference trees
+  ///
+  /// For short help type: `nextclade -h`, for extended help type: `nextclade --help`. Each subcommand has its own help, for example: `nextclade tree --help`.
+  Tree(Box<NextcladeTreeArgs>),
+
   /// Print command-line reference documentation in Markdown format
   HelpMarkdown,
 }
@@ -124,6 +130,21 @@ pub enum NextcladeDatasetCommands {
   Get(NextcladeDatasetGetArgs),
 }
 
+#[derive(Parser, Debug)]
+pub struct NextcladeTreeArgs {
+  #[clap(subcommand)]
+  pub command: NextcladeTreeCommands,
+}
+
+#[derive(Subcommand, Debug)]
+#[clap(verbatim_doc_comment)]
+pub enum NextcladeTreeCommands {
+  /// Check consistency of a reference tree with the reference sequence and genome annotation
+  ///
+  /// For short help type: `nextclade -h`, for extended help type: `nextclade --help`. Each subcommand has its own help, for example: `nextclade tree check --help`.
+  Check(NextcladeTreeCheckArgs),
+}
+
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Parser, Debug)]
 #[clap(verbatim_doc_comment)]
@@ -282,6 +303,11 @@ pub enum NextcladeOutputSelection {
   Tsv,
   Tree,
   TreeNwk,
+  TreeNexus,
+  TreeNhx,
+  TreeFocal,
+  TreeFocalNwk,
+  Jplace,
   Translations,
 }
 
@@ -343,6 +369,10 @@ pub struct NextcladeRunInputArgs {
   ///
   /// See https://nextstrain.org/docs/bioinformatics/data-formats.
   ///
+  /// Alternatively, if `--input-tree-alignment` or `--input-tree-mutations` is provided, path to a file containing reference tree in Newick format.
+  ///
+  /// Alternatively, path to a protobuf file (`.pb`) containing UShER mutation-annotated tree (MAT). The first clade annotation of the MAT is used as clade and the second as `lineage` node attribute.
+  ///
   /// Overrides path to `tree.json` in the dataset (`--input-dataset`).
   ///
   /// Supports the following compression formats: "gz", "bz2", "xz", "zst". Use "-" to read uncompressed data from standard input (stdin).
@@ -350,6 +380,40 @@ pub struct NextcladeRunInputArgs {
   #[clap(value_hint = ValueHint::FilePath)]
   pub input_tree: Option<PathBuf>,
 
+  /// Path to a TSV file containing metadata of the nodes of the Newick reference tree (`--input-tree`).
+  ///
+  /// The file should contain a column with node names (`seqName`, `strain`, `name` or `node`) and, optionally, a column with clades (`clade_membership` or `clade`). All other columns are treated as clade-like node attributes. Attributes of nodes which are not listed (e.g. internal nodes) are reconstructed using parsimony.
+  ///
+  /// Requires `--input-tree-alignment` or `--input-tree-mutations`.
+  ///
+  /// Supports the following compression formats: "gz", "bz2", "xz", "zst". Use "-" to read uncompressed data from standard input (stdin).
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::FilePath)]
+  pub input_tree_metadata: Option<PathBuf>,
+
+  /// Path to a FASTA file containing sequences of the nodes of the Newick reference tree (`--input-tree`), aligned to the reference sequence.
+  ///
+  /// Sequences are required at least for the leaves of the tree. Mutations on the branches of the tree are reconstructed from these sequences using parsimony.
+  ///
+  /// This flag is mutually exclusive with `--input-tree-mutations`.
+  ///
+  /// Supports the following compression formats: "gz", "bz2", "xz", "zst". Use "-" to read uncompressed data from standard input (stdin).
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::FilePath)]
+  #[clap(conflicts_with = "input_tree_mutations")]
+  pub input_tree_alignment: Option<PathBuf>,
+
+  /// Path to a TSV file containing nucleotide mutations on the branches of the Newick reference tree (`--input-tree`).
+  ///
+  /// The file should contain a column with node names (`seqName`, `strain`, `name` or `node`) and a column `mutations`, with comma-separated nucleotide substitutions on the branch leading to the node (e.g. `C241T,A23403G`). Unnamed internal nodes are named `NODE_0000001`, `NODE_0000002` etc. in pre-order.
+  ///
+  /// This flag is mutually exclusive with `--input-tree-alignment`.
+  ///
+  /// Supports the following compression formats: "gz", "bz2", "xz", "zst". Use "-" to read uncompressed data from standard input (stdin).
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::FilePath)]
+  pub input_tree_mutations: Option<PathBuf>,
+
   /// Path to a JSON file containing configuration and data specific to a pathogen.
   ///
   /// Overrides path to `pathogen.json` in the dataset (`--input-dataset`).
@@ -398,6 +462,15 @@ pub struct NextcladeRunInputArgs {
   #[clap(value_hint = ValueHint::FilePath)]
   pub input_pcr_primers: Option<PathBuf>,
 
+  /// Path to a TSV file containing collection dates of the query sequences. This information is used by the molecular clock QC rule, which compares divergence of each sequence with the divergence expected for its clade at its date.
+  ///
+  /// The file should contain a column with sequence names (`seqName`, `strain` or `name`) and a column `date`, with dates in `YYYY-MM-DD` format (incomplete dates, such as `2021-05` or `2021-XX-XX`, and decimal years are also accepted). Sequences without a date are not checked. Requires a reference tree with dates (`num_date` node attribute).
+  ///
+  /// Supports the following compression formats: "gz", "bz2", "xz", "zstd". Use "-" to read uncompressed data from standard input (stdin).
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::FilePath)]
+  pub input_dates: Option<PathBuf>,
+
   /// Use custom dataset server
   #[clap(long)]
   #[clap(value_hint = ValueHint::Url)]
@@ -635,6 +708,158 @@ pub struct NextcladeRunOutputArgs {
   #[clap(value_hint = ValueHint::AnyPath)]
   pub output_tree_nwk: Option<PathBuf>,
 
+  /// Path to output phylogenetic tree with input sequences placed onto it, in Nexus format, with node attributes in
+  /// BEAST/FigTree-style comments (`[&clade="...",qc_status="...",...]`)
+  ///
+  /// Each node is annotated with its clade, QC status (for new nodes), node type ("New" for query sequences and
+  /// "Reference" for nodes of the reference tree) and clade-like node attributes of the dataset.
+  ///
+  /// For file format description see: https://en.wikipedia.org/wiki/Nexus_file
+  ///
+  /// Takes precedence over paths configured with `--output-all`, `--output-basename` and `--output-selection`.
+  ///
+  /// If the provided file path ends with one of the supported extensions: "gz", "bz2", "xz", "zst", then the file will be written compressed. Use "-" to write the uncompressed to standard output (stdout).
+  ///
+  /// If the required directory tree does not exist, it will be created.
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::AnyPath)]
+  pub output_tree_nexus: Option<PathBuf>,
+
+  /// Path to output phylogenetic tree with input sequences placed onto it, in New Hampshire eXtended (NHX) format
+  ///
+  /// Nodes are annotated with the same attributes as in `--output-tree-nexus`.
+  ///
+  /// For file format description see: https://en.wikipedia.org/wiki/Newick_format#New_Hampshire_X_format
+  ///
+  /// Takes precedence over paths configured with `--output-all`, `--output-basename` and `--output-selection`.
+  ///
+  /// If the provided file path ends with one of the supported extensions: "gz", "bz2", "xz", "zst", then the file will be written compressed. Use "-" to write the uncompressed to standard output (stdout).
+  ///
+  /// If the required directory tree does not exist, it will be created.
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::AnyPath)]
+  pub output_tree_nhx: Option<PathBuf>,
+
+  /// Path to output focal context tree in Auspice JSON v2 format.
+  ///
+  /// This is the output phylogenetic tree (see `--output-tree`), pruned to contain only the query sequences, the
+  /// nearest leaf nodes of the reference tree around each of them (see `--focal-context-tips`) and the internal nodes
+  /// connecting these. Node attributes and branch mutations are the same as in the full tree. This tree is much smaller
+  /// than the full tree when the reference tree is large.
+  ///
+  /// Takes precedence over paths configured with `--output-all`, `--output-basename` and `--output-selection`.
+  ///
+  /// If the provided file path ends with one of the supported extensions: "gz", "bz2", "xz", "zst", then the file will be written compressed. Use "-" to write the uncompressed to standard output (stdout).
+  ///
+  /// If the required directory tree does not exist, it will be created.
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::AnyPath)]
+  pub output_tree_focal: Option<PathBuf>,
+
+  /// Path to output focal context tree in Newick format. See `--output-tree-focal`.
+  ///
+  /// Takes precedence over paths configured with `--output-all`, `--output-basename` and `--output-selection`.
+  ///
+  /// If the provided file path ends with one of the supported extensions: "gz", "bz2", "xz", "zst", then the file will be written compressed. Use "-" to write the uncompressed to standard output (stdout).
+  ///
+  /// If the required directory tree does not exist, it will be created.
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::AnyPath)]
+  pub output_tree_focal_nwk: Option<PathBuf>,
+
+  /// Path to output phylogenetic placements of input sequences on the reference tree, in jplace format.
+  ///
+  /// Contains the reference tree with numbered edges and, for each sequence, the edge it is placed on, the distal
+  /// length (distance from the placement point to the distal end of the edge) and the pendant length (length of the
+  /// branch leading to the sequence). Placements are as decided by the tree builder when attaching the sequences to the
+  /// tree. Nextclade placement is not based on likelihood, so each sequence has a single placement with likelihood
+  /// weight ratio of 1 and likelihood of 0.
+  ///
+  /// This file can be used for downstream analysis of placements, for example with gappa or guppy.
+  ///
+  /// For file format description see: https://doi.org/10.1371/journal.pone.0031009
+  ///
+  /// Takes precedence over paths configured with `--output-all`, `--output-basename` and `--output-selection`.
+  ///
+  /// If the provided file path ends with one of the supported extensions: "gz", "bz2", "xz", "zst", then the file will be written compressed. Use "-" to write the uncompressed to standard output (stdout).
+  ///
+  /// If the required directory tree does not exist, it will be created.
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::AnyPath)]
+  pub output_jplace: Option<PathBuf>,
+
+  /// Path to output report of the tree topology refinement, in JSON format. See `--max-refinement-iterations`.
+  ///
+  /// The report contains parsimony score of the tree before and after refinement, as well as the list of moves of query
+  /// sequences performed during refinement. This output is not produced by `--output-all`.
+  ///
+  /// If the provided file path ends with one of the supported extensions: "gz", "bz2", "xz", "zst", then the file will be written compressed. Use "-" to write the uncompressed to standard output (stdout).
+  ///
+  /// If the required directory tree does not exist, it will be created.
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::AnyPath)]
+  pub output_tree_refinement: Option<PathBuf>,
+
+  /// Path to output FASTA file with reconstructed nucleotide sequences of the nodes of the tree (after query
+  /// sequences are attached), including internal nodes of the reference tree and the auxiliary nodes added during tree
+  /// building.
+  ///
+  /// Sequences are reconstructed by applying branch mutations on the path from the root to each node onto the
+  /// reference sequence. Deletions are represented with gaps. Use `--ancestral-clades` to only output the nodes of
+  /// the given clades. This output is not produced by `--output-all`, because it can be very large for large trees.
+  ///
+  /// If the provided file path ends with one of the supported extensions: "gz", "bz2", "xz", "zst", then the file will be written compressed. Use "-" to write the uncompressed to standard output (stdout).
+  ///
+  /// If the required directory tree does not exist, it will be created.
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::AnyPath)]
+  pub output_ancestral: Option<PathBuf>,
+
+  /// Template string for path to output FASTA files with reconstructed peptides of the nodes of the tree. A separate
+  /// file will be generated for every CDS. See `--output-ancestral`.
+  ///
+  /// The string should contain template variable `{cds}`, where the CDS name will be substituted.
+  /// Make sure you properly quote and/or escape the curly braces, so that your shell, programming language or pipeline manager does not attempt to substitute the variables.
+  ///
+  /// If the provided file path ends with one of the supported extensions: "gz", "bz2", "xz", "zst", then the file will be written compressed.
+  ///
+  /// If the required directory tree does not exist, it will be created.
+  ///
+  /// Example for bash shell:
+  ///
+  ///   --output-ancestral-translations='output_dir/nextclade.ancestral_translation.{cds}.fasta'
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::AnyPath)]
+  pub output_ancestral_translations: Option<String>,
+
+  /// Path to output matrix of pairwise SNP distances between query sequences, in TSV format.
+  ///
+  /// Only the positions which are sequenced, unambiguous and not masked (see `placementMaskRanges` in the reference
+  /// tree) in both sequences of a pair are compared. The matrix is preceded by a `cluster` column with single-linkage
+  /// clusters of the query sequences, linking the sequences with distance not exceeding `--cluster-distance-threshold`.
+  ///
+  /// The number of entries grows quadratically with the number of query sequences, so it is only feasible to
+  /// calculate the matrix for at most a few thousand sequences.
+  ///
+  /// If the provided file path ends with one of the supported extensions: "gz", "bz2", "xz", "zst", then the file will be written compressed. Use "-" to write the uncompressed to standard output (stdout).
+  ///
+  /// If the required directory tree does not exist, it will be created.
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::AnyPath)]
+  pub output_distance_matrix: Option<PathBuf>,
+
+  /// Path to output summary of the run, in JSON format. The summary contains counts of sequences per clade and per QC
+  /// status, the most common private substitutions, the list of failed sequences along with the errors, and the
+  /// processing time and throughput.
+  ///
+  /// The same summary is also written as a self-contained HTML report, to the same path, but with the extension
+  /// replaced by ".html" (e.g. `--output-summary=out/summary.json` produces `out/summary.json` and `out/summary.html`).
+  ///
+  /// If the required directory tree does not exist, it will be created.
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::AnyPath)]
+  pub output_summary: Option<PathBuf>,
+
   /// REMOVED. The argument `--output-insertions` have been removed in favor of `--output-csv` and `--output-tsv`.
   #[clap(long, short = 'I')]
   #[clap(value_hint = ValueHint::AnyPath)]
@@ -653,6 +878,25 @@ pub struct NextcladeRunOtherParams {
   /// Number of processing jobs. If not specified, all available CPU threads will be used.
   #[clap(global = false, long, short = 'j', default_value_t = num_cpus::get())]
   pub jobs: usize,
+
+  /// Analyze each unique sequence only once.
+  ///
+  /// Sequences are identified by the hash of their content. Results for sequences identical to the previously seen
+  /// ones are copied rather than recomputed. Only the results of the 1024 most recently seen unique sequences are kept
+  /// in memory, so duplicates seen long after the original sequence might be analyzed again (unless `--cache-dir` is
+  /// used).
+  #[clap(long)]
+  pub deduplicate: bool,
+
+  /// Path to a directory where analysis results are cached between runs.
+  ///
+  /// Results are keyed by the hash of the sequence content, the dataset (its version and contents) and the parameters
+  /// of the run, such that repeated runs only analyze the sequences which were not analyzed before with the same
+  /// dataset and parameters. The directory is created if it does not exist. It is safe to delete the directory
+  /// to clear the cache.
+  #[clap(long)]
+  #[clap(value_hint = ValueHint::DirPath)]
+  pub cache_dir: Option<PathBuf>,
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -775,6 +1019,46 @@ pub struct NextcladeReadAnnotationArgs {
   pub json: bool,
 }
 
+#[derive(Parser, Debug)]
+#[clap(verbatim_doc_comment)]
+pub struct NextcladeTreeCheckArgs {
+  /// Path to the reference tree in Auspice JSON v2 format.
+  ///
+  /// The following inconsistencies are reported, along with node names and suggested fixes: missing and duplicate
+  /// node names, missing clade attributes, missing, malformed and duplicate branch mutations, mutations outside of the
+  /// reference sequence or CDS, mutations whose reference state does not match the state of the parent node and
+  /// mutations in CDSes which are not in the genome annotation.
+  ///
+  /// The command exits with an error if any inconsistencies are found.
+  #[clap(value_hint = ValueHint::FilePath)]
+  #[clap(display_order = 0)]
+  pub input_tree: PathBuf,
+
+  /// Path to a FASTA file containing reference sequence the tree is built against.
+  #[clap(long, short = 'r')]
+  #[clap(value_hint = ValueHint::FilePath)]
+  pub input_ref: PathBuf,
+
+  /// Path to a file containing genome annotation in GFF3 format.
+  ///
+  /// If provided, aminoacid mutations of the tree are checked against the translation of the reference sequence.
+  /// Otherwise aminoacid mutations are not checked.
+  #[clap(long, short = 'm')]
+  #[clap(value_hint = ValueHint::FilePath)]
+  pub input_annotation: Option<PathBuf>,
+
+  /// Path to output JSON or YAML file with the list of inconsistencies.
+  ///
+  /// The format is chosen based on file extension: ".json" or ".yaml".
+  #[clap(long, short = 'o')]
+  #[clap(value_hint = ValueHint::AnyPath)]
+  pub output: Option<PathBuf>,
+
+  /// Print console output in JSON format, rather than human-readable list.
+  #[clap(long)]
+  pub json: bool,
+}
+
 fn generate_completions(shell: &str) -> Result<(), Report> {
   let mut command = NextcladeArgs::command();
 
@@ -809,6 +1093,14 @@ pub fn nextclade_get_output_filenames(run_args: &mut NextcladeRunArgs) -> Result
         output_tsv,
         output_tree,
         output_tree_nwk,
+        output_tree_nexus,
+        output_tree_nhx,
+        output_tree_focal,
+        output_tree_focal_nwk,
+        output_jplace,
+        output_ancestral,
+        output_ancestral_translations,
+        output_summary,
         ..
       },
     ..
@@ -870,6 +1162,26 @@ pub fn nextclade_get_output_filenames(run_args: &mut NextcladeRunArgs) -> Result
     if output_selection.contains(&NextcladeOutputSelection::TreeNwk) {
       output_tree_nwk.get_or_insert(add_extension(&default_output_file_path, "nwk"));
     }
+
+    if output_selection.contains(&NextcladeOutputSelection::TreeNexus) {
+      output_tree_nexus.get_or_insert(add_extension(&default_output_file_path, "nexus"));
+    }
+
+    if output_selection.contains(&NextcladeOutputSelection::TreeNhx) {
+      output_tree_nhx.get_or_insert(add_extension(&default_output_file_path, "nhx"));
+    }
+
+    if output_selection.contains(&NextcladeOutputSelection::TreeFocal) {
+      output_tree_focal.get_or_insert(add_extension(&default_output_file_path, "focal.auspice.json"));
+    }
+
+    if output_selection.contains(&NextcladeOutputSelection::TreeFocalNwk) {
+      output_tree_focal_nwk.get_or_insert(add_extension(&default_output_file_path, "focal.nwk"));
+    }
+
+    if output_selection.contains(&NextcladeOutputSelection::Jplace) {
+      output_jplace.get_or_insert(add_extension(&default_output_file_path, "jplace"));
+    }
   }
 
   if let Some(output_translations) = output_translations {
@@ -890,6 +1202,24 @@ Example for bash shell:
     }
   }
 
+  if let Some(output_ancestral_translations) = output_ancestral_translations {
+    if !output_ancestral_translations.contains("{cds}") {
+      return make_error!(
+        r#"
+Expected `--output-ancestral-translations` argument to contain a template string containing template variable {{cds}} (with curly braces), but received:
+
+  {output_ancestral_translations}
+
+Make sure the variable is not substituted by your shell, programming language or workflow manager. Apply proper escaping as needed.
+Example for bash shell:
+
+  --output-ancestral-translations='output_dir/nextclade.ancestral_translation.{{cds}}.fasta'
+
+      "#
+      );
+    }
+  }
+
   let all_outputs_are_missing = [
     output_all,
     output_fasta,
@@ -898,10 +1228,19 @@ Example for bash shell:
     output_csv,
     output_tsv,
     output_tree,
+    output_tree_nwk,
+    output_tree_nexus,
+    output_tree_nhx,
+    output_tree_focal,
+    output_tree_focal_nwk,
+    output_jplace,
+    output_ancestral,
+    output_summary,
   ]
   .iter()
   .all(|o| o.is_none())
-    && output_translations.is_none();
+    && output_translations.is_none()
+    && output_ancestral_translations.is_none();
 
   if all_outputs_are_missing {
     return make_error!(
@@ -915,7 +1254,16 @@ At least one of the following flags is required:
   --output-csv
   --output-tsv
   --output-tree
-  --output-translations"#
+  --output-tree-nwk
+  --output-tree-nexus
+  --output-tree-nhx
+  --output-tree-focal
+  --output-tree-focal-nwk
+  --output-jplace
+  --output-ancestral
+  --output-ancestral-translations
+  --output-translations
+  --output-summary"#
     );
   }
 
@@ -1129,5 +1477,8 @@ pub fn nextclade_parse_cli_args() -> Result<(), Report> {
     },
     NextcladeCommands::Sort(seq_sort_args) => nextclade_seq_sort(&seq_sort_args),
     NextcladeCommands::ReadAnnotation(read_annotation_args) => nextclade_read_annotation(&read_annotation_args),
+    NextcladeCommands::Tree(tree_command) => match tree_command.command {
+      NextcladeTreeCommands::Check(tree_check_args) => nextclade_tree_check(&tree_check_args),
+    },
   }
 }
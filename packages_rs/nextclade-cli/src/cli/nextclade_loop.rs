@@ -2,20 +2,35 @@ use crate::cli::nextclade_cli::{
   NextcladeRunArgs, NextcladeRunInputArgs, NextcladeRunOtherParams, NextcladeRunOutputArgs,
 };
 use crate::cli::nextclade_ordered_writer::NextcladeOrderedWriter;
+use crate::cli::nextclade_results_cache::{
+  seq_hash, NextcladeDeduplicator, NextcladeResultsCache, ReusableAnalysisOutput,
+};
 use crate::dataset::dataset_download::nextclade_get_inputs;
 use eyre::{ContextCompat, Report, WrapErr};
+use itertools::Itertools;
 use log::info;
+use nextclade::alphabet::nuc::from_nuc_seq;
+use nextclade::analyze::pairwise_distances::{calculate_pairwise_distances, pairwise_distances_write_tsv};
 use nextclade::analyze::pcr_primers::PcrPrimer;
 use nextclade::gene::gene_map_display::gene_map_to_table_string;
 use nextclade::graph::graph::convert_graph_to_auspice_tree;
-use nextclade::io::fasta::{FastaReader, FastaRecord};
+use nextclade::io::fasta::{FastaPeptideWriter, FastaReader, FastaRecord, FastaWriter};
+use nextclade::io::jplace::{jplace_write_to_file, JplaceReferenceTree};
 use nextclade::io::json::{json_write, JsonPretty};
 use nextclade::io::nextclade_csv::CsvColumnConfig;
+use nextclade::io::nwk_annotated_writer::{nexus_write_to_file, nhx_write_to_file};
 use nextclade::io::nwk_writer::nwk_write_to_file;
+use nextclade::io::sample_dates::read_sample_dates_tsv;
 use nextclade::run::nextclade_wasm::{AnalysisInitialData, AnalysisOutput, Nextclade};
+use nextclade::run::params::NextcladeInputParams;
+use nextclade::run::run_summary::{run_summary_write, RunSummaryBuilder};
+use nextclade::tree::tree_ancestral::graph_reconstruct_ancestral_sequences;
 use nextclade::tree::tree_builder::graph_attach_new_nodes_in_place;
+use nextclade::tree::tree_focal::graph_extract_focal_subtree;
 use nextclade::types::outputs::NextcladeOutputs;
+use nextclade::utils::error::report_to_string;
 use nextclade::utils::option::OptionMapRefFallible;
+use std::time::Instant;
 
 pub struct NextcladeRecord {
   pub index: usize,
@@ -26,6 +41,8 @@ pub struct NextcladeRecord {
 pub fn nextclade_run(run_args: NextcladeRunArgs) -> Result<(), Report> {
   info!("Command-line arguments:\n{run_args:#?}");
 
+  let start_time = Instant::now();
+
   let NextcladeRunArgs {
     inputs: NextcladeRunInputArgs {
       input_fastas,
@@ -38,10 +55,24 @@ pub fn nextclade_run(run_args: NextcladeRunArgs) -> Result<(), Report> {
         output_graph,
         output_tree,
         output_tree_nwk,
+        output_tree_nexus,
+        output_tree_nhx,
+        output_tree_focal,
+        output_tree_focal_nwk,
+        output_jplace,
+        output_tree_refinement,
+        output_ancestral,
+        output_ancestral_translations,
+        output_distance_matrix,
+        output_summary,
         ..
       },
     params,
-    other_params: NextcladeRunOtherParams { jobs },
+    other_params: NextcladeRunOtherParams {
+      jobs,
+      deduplicate,
+      cache_dir,
+    },
   } = run_args.clone();
 
   let inputs = nextclade_get_inputs(&run_args, &cdses)?;
@@ -54,12 +85,50 @@ pub fn nextclade_run(run_args: NextcladeRunArgs) -> Result<(), Report> {
     .wrap_err("When parsing PCR primers input CSV")
     .unwrap_or_default();
 
-  let nextclade = Nextclade::new(inputs, primers, &params)?;
+  let sample_dates = run_args
+    .inputs
+    .input_dates
+    .as_ref()
+    .map_ref_fallible(read_sample_dates_tsv)?
+    .unwrap_or_default();
 
-  let should_write_tree = output_tree.is_some() || output_tree_nwk.is_some() || output_graph.is_some();
+  let cache = cache_dir
+    .map(|cache_dir| {
+      NextcladeResultsCache::new(
+        cache_dir,
+        &inputs,
+        &NextcladeInputParams::from_optional(&params, &inputs.virus_properties)?,
+        &cdses,
+        &run_args.inputs.input_pcr_primers,
+      )
+    })
+    .transpose()
+    .wrap_err("When initializing results cache")?;
+
+  let nextclade = Nextclade::new(inputs, primers, sample_dates, &params)?;
+
+  let deduplicator = deduplicate.then(NextcladeDeduplicator::default);
+
+  let should_write_tree = output_tree.is_some()
+    || output_tree_nwk.is_some()
+    || output_tree_nexus.is_some()
+    || output_tree_nhx.is_some()
+    || output_tree_focal.is_some()
+    || output_tree_focal_nwk.is_some()
+    || output_jplace.is_some()
+    || output_tree_refinement.is_some()
+    || output_ancestral.is_some()
+    || output_ancestral_translations.is_some()
+    || output_graph.is_some();
+  let should_keep_outputs = should_write_tree || output_distance_matrix.is_some();
   let mut outputs = Vec::<NextcladeOutputs>::new();
 
-  let csv_column_config = CsvColumnConfig::new(&output_columns_selection)?;
+  let mut summary = output_summary
+    .is_some()
+    .then(|| RunSummaryBuilder::new(nextclade.virus_properties.qc.is_some()));
+
+  let csv_column_config = CsvColumnConfig::new(&output_columns_selection)?
+    .with_placement_uncertainty(nextclade.params.general.include_placement_uncertainty);
 
   info!("Parameters (final):\n{:#?}", &nextclade.params);
   info!("Genome annotation:\n{}", gene_map_to_table_string(&nextclade.gene_map)?);
@@ -71,7 +140,10 @@ pub fn nextclade_run(run_args: NextcladeRunArgs) -> Result<(), Report> {
 
     let nextclade = &nextclade;
     let outputs = &mut outputs;
+    let summary = &mut summary;
     let run_args = &run_args;
+    let cache = &cache;
+    let deduplicator = &deduplicator;
 
     s.spawn(|| {
       let mut reader = FastaReader::from_paths(&input_fastas).unwrap();
@@ -99,7 +171,7 @@ pub fn nextclade_run(run_args: NextcladeRunArgs) -> Result<(), Report> {
         for fasta_record in &fasta_receiver {
           info!("Processing sequence '{}'", fasta_record.seq_name);
 
-          let outputs_or_err = nextclade.run(&fasta_record).wrap_err_with(|| {
+          let outputs_or_err = run_with_cache(nextclade, &fasta_record, cache, deduplicator).wrap_err_with(|| {
             format!(
               "When processing sequence #{} '{}'",
               fasta_record.index, fasta_record.seq_name
@@ -133,6 +205,10 @@ pub fn nextclade_run(run_args: NextcladeRunArgs) -> Result<(), Report> {
         clade_node_attr_key_descs,
         phenotype_attr_descs,
         aa_motif_keys,
+        nuc_motif_keys,
+        drug_resistance_keys,
+        cds_coverage_keys,
+        relative_mutations_keys,
         ..
       } = nextclade.get_initial_data();
 
@@ -141,6 +217,10 @@ pub fn nextclade_run(run_args: NextcladeRunArgs) -> Result<(), Report> {
         clade_node_attr_key_descs,
         phenotype_attr_descs,
         aa_motif_keys,
+        nuc_motif_keys,
+        drug_resistance_keys,
+        cds_coverage_keys,
+        relative_mutations_keys,
         &csv_column_config,
         &run_args.outputs,
         &nextclade.params,
@@ -156,7 +236,14 @@ pub fn nextclade_run(run_args: NextcladeRunArgs) -> Result<(), Report> {
       }
 
       for record in result_receiver {
-        if should_write_tree {
+        if let Some(summary) = summary.as_mut() {
+          match &record.outputs_or_err {
+            Ok(AnalysisOutput { analysis_result, .. }) => summary.add_result(analysis_result),
+            Err(report) => summary.add_error(record.index, &record.seq_name, &report_to_string(report)),
+          }
+        }
+
+        if should_keep_outputs {
           // Save analysis results if they will be needed later
           if let Ok(AnalysisOutput { analysis_result, .. }) = &record.outputs_or_err {
             outputs.push(analysis_result.clone());
@@ -171,12 +258,57 @@ pub fn nextclade_run(run_args: NextcladeRunArgs) -> Result<(), Report> {
     });
   });
 
+  if let Some(output_distance_matrix) = output_distance_matrix {
+    outputs.sort_by_key(|output| output.index);
+    let masked_ranges = nextclade
+      .graph
+      .as_ref()
+      .map(|graph| graph.data.meta.placement_mask_ranges())
+      .unwrap_or_default();
+    let pairwise_distances = calculate_pairwise_distances(
+      &outputs,
+      masked_ranges,
+      nextclade.params.general.cluster_distance_threshold,
+    );
+    pairwise_distances_write_tsv(output_distance_matrix, &pairwise_distances)?;
+  }
+
+  if let (Some(output_summary), Some(summary)) = (output_summary, summary) {
+    run_summary_write(output_summary, &summary.build(start_time.elapsed()))?;
+  }
+
   if should_write_tree {
     let Nextclade {
-      ref_seq, params, graph, ..
+      ref_seq,
+      ref_translation,
+      gene_map,
+      params,
+      graph,
+      ..
     } = nextclade;
     if let Some(mut graph) = graph {
-      graph_attach_new_nodes_in_place(&mut graph, outputs, ref_seq.len(), &params.tree_builder)?;
+      // Edges of the reference tree need to be numbered before the query nodes are attached
+      let jplace_reference = output_jplace
+        .is_some()
+        .then(|| JplaceReferenceTree::from_graph(&graph))
+        .transpose()?;
+
+      let refinement_report =
+        graph_attach_new_nodes_in_place(&mut graph, outputs, ref_seq.len(), &params.tree_builder)?;
+
+      if params.tree_builder.max_refinement_iterations > 0 {
+        info!(
+          "Tree refinement: {} moves in {} iterations, parsimony score {} -> {}",
+          refinement_report.moves.len(),
+          refinement_report.iterations,
+          refinement_report.score_before,
+          refinement_report.score_after
+        );
+      }
+
+      if let Some(output_tree_refinement) = output_tree_refinement {
+        json_write(output_tree_refinement, &refinement_report, JsonPretty(true))?;
+      }
 
       if let Some(output_tree) = output_tree {
         let tree = convert_graph_to_auspice_tree(&graph)?;
@@ -187,6 +319,59 @@ pub fn nextclade_run(run_args: NextcladeRunArgs) -> Result<(), Report> {
         nwk_write_to_file(output_tree_nwk, &graph)?;
       }
 
+      if let Some(output_tree_nexus) = output_tree_nexus {
+        nexus_write_to_file(output_tree_nexus, &graph)?;
+      }
+
+      if let Some(output_tree_nhx) = output_tree_nhx {
+        nhx_write_to_file(output_tree_nhx, &graph)?;
+      }
+
+      if output_tree_focal.is_some() || output_tree_focal_nwk.is_some() {
+        let focal_graph = graph_extract_focal_subtree(&graph, params.general.focal_context_tips)?;
+
+        if let Some(output_tree_focal) = output_tree_focal {
+          let tree = convert_graph_to_auspice_tree(&focal_graph)?;
+          json_write(output_tree_focal, &tree, JsonPretty(true))?;
+        }
+
+        if let Some(output_tree_focal_nwk) = output_tree_focal_nwk {
+          nwk_write_to_file(output_tree_focal_nwk, &focal_graph)?;
+        }
+      }
+
+      if let (Some(output_jplace), Some(jplace_reference)) = (output_jplace, jplace_reference) {
+        let invocation = std::env::args().join(" ");
+        jplace_write_to_file(output_jplace, &jplace_reference, &graph, &invocation)?;
+      }
+
+      if output_ancestral.is_some() || output_ancestral_translations.is_some() {
+        let mut fasta_writer = output_ancestral.map_ref_fallible(FastaWriter::from_path)?;
+        let mut fasta_peptide_writer =
+          output_ancestral_translations.map_ref_fallible(|output_ancestral_translations| {
+            FastaPeptideWriter::new(&gene_map, output_ancestral_translations)
+          })?;
+
+        graph_reconstruct_ancestral_sequences(
+          &graph,
+          &ref_seq,
+          &ref_translation,
+          &params.general.ancestral_clades,
+          |node, seq, peptides| {
+            if let Some(fasta_writer) = &mut fasta_writer {
+              fasta_writer.write(&node.name, &from_nuc_seq(seq), false)?;
+            }
+            if let Some(fasta_peptide_writer) = &mut fasta_peptide_writer {
+              for (cds_name, peptide) in peptides {
+                fasta_peptide_writer.write_peptide(&node.name, cds_name, peptide)?;
+              }
+            }
+            Ok(())
+          },
+        )
+        .wrap_err("When reconstructing ancestral sequences")?;
+      }
+
       if let Some(output_graph) = run_args.outputs.output_graph {
         json_write(output_graph, &graph, JsonPretty(true))?;
       }
@@ -195,3 +380,41 @@ pub fn nextclade_run(run_args: NextcladeRunArgs) -> Result<(), Report> {
 
   Ok(())
 }
+
+/// Runs analysis of a sequence, reusing results of identical sequences seen earlier in this run (if deduplication is
+/// enabled) or in previous runs (if the results cache is enabled)
+fn run_with_cache(
+  nextclade: &Nextclade,
+  fasta_record: &FastaRecord,
+  cache: &Option<NextcladeResultsCache>,
+  deduplicator: &Option<NextcladeDeduplicator>,
+) -> Result<AnalysisOutput, Report> {
+  if cache.is_none() && deduplicator.is_none() {
+    return nextclade.run(fasta_record);
+  }
+
+  // Results of the molecular clock QC rule depend on the date of the sample, so identical sequences with different
+  // dates are not interchangeable
+  let date = nextclade.sample_dates.get(&fasta_record.seq_name).copied();
+  let seq_hash = seq_hash(&fasta_record.seq, date);
+
+  let run = || {
+    if let Some(cache) = cache {
+      if let Some(output) = cache.get(&seq_hash) {
+        return Ok(output);
+      }
+    }
+    let output = ReusableAnalysisOutput::new(nextclade.run(fasta_record)?);
+    if let Some(cache) = cache {
+      cache.put(&seq_hash, &output)?;
+    }
+    Ok(output)
+  };
+
+  let output = match deduplicator {
+    Some(deduplicator) => deduplicator.get_or_run(&seq_hash, run),
+    None => run(),
+  }?;
+
+  Ok(output.into_output(fasta_record))
+}
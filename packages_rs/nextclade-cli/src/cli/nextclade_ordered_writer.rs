@@ -39,6 +39,10 @@ impl NextcladeOrderedWriter {
     clade_node_attr_key_descs: &[CladeNodeAttrKeyDesc],
     phenotype_attr_key_desc: &[PhenotypeAttrDesc],
     aa_motifs_keys: &[String],
+    nuc_motifs_keys: &[String],
+    drug_resistance_keys: &[String],
+    cds_coverage_keys: &[String],
+    relative_mutations_keys: &[String],
     csv_column_config: &CsvColumnConfig,
     output_params: &NextcladeRunOutputArgs,
     params: &NextcladeInputParams,
@@ -72,6 +76,10 @@ impl NextcladeOrderedWriter {
         &clade_node_attr_keys,
         &phenotype_attr_keys,
         aa_motifs_keys,
+        nuc_motifs_keys,
+        drug_resistance_keys,
+        cds_coverage_keys,
+        relative_mutations_keys,
         csv_column_config,
       )
     })?;
@@ -83,6 +91,10 @@ impl NextcladeOrderedWriter {
         &clade_node_attr_keys,
         &phenotype_attr_keys,
         aa_motifs_keys,
+        nuc_motifs_keys,
+        drug_resistance_keys,
+        cds_coverage_keys,
+        relative_mutations_keys,
         csv_column_config,
       )
     })?;
@@ -0,0 +1,523 @@
+use eyre::{eyre, Report, WrapErr};
+use itertools::Itertools;
+use log::warn;
+use nextclade::io::fasta::FastaRecord;
+use nextclade::io::json::{json_stringify, JsonPretty};
+use nextclade::run::nextclade_wasm::{AnalysisOutput, NextcladeParams};
+use nextclade::run::params::NextcladeInputParams;
+use nextclade::types::outputs::{seq_warning_prefix, PeptideWarning};
+use nextclade::utils::error::report_to_string;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Calculates hash of a sequence and of its collection date (if any), which identifies sequences with identical results
+pub fn seq_hash(seq: &str, date: Option<f64>) -> String {
+  match date {
+    None => sha256_hex(seq.as_bytes()),
+    Some(date) => sha256_hex(format!("{seq}\t{date}")),
+  }
+}
+
+fn sha256_hex(data: impl AsRef<[u8]>) -> String {
+  format!("{:x}", Sha256::digest(data))
+}
+
+/// Calculates hash of JSON representation of an object. Keys of JSON objects are sorted, so that the hash does not
+/// depend on iteration order of hash maps.
+fn json_hash<T: Serialize>(obj: &T) -> Result<String, Report> {
+  fn sort_keys(value: Value) -> Value {
+    match value {
+      Value::Object(map) => Value::Object(
+        map
+          .into_iter()
+          .sorted_by(|(a, _), (b, _)| a.cmp(b))
+          .map(|(key, value)| (key, sort_keys(value)))
+          .collect(),
+      ),
+      Value::Array(values) => Value::Array(values.into_iter().map(sort_keys).collect()),
+      value => value,
+    }
+  }
+  let value = sort_keys(serde_json::to_value(obj)?);
+  Ok(sha256_hex(json_stringify(&value, JsonPretty(false))?))
+}
+
+fn short_hash(hash: &str) -> String {
+  hash.chars().take(16).collect()
+}
+
+/// On-disk cache of analysis results.
+///
+/// Results are keyed by sequence hash and are stored in a subdirectory specific to the dataset (its version and
+/// contents) and to the parameters of the run, such that results are only reused when they would be the same.
+pub struct NextcladeResultsCache {
+  dir: PathBuf,
+  tmp_counter: AtomicUsize,
+}
+
+impl NextcladeResultsCache {
+  pub fn new(
+    cache_dir: impl AsRef<Path>,
+    inputs: &NextcladeParams,
+    params: &NextcladeInputParams,
+    cdses: &Option<Vec<String>>,
+    primers_path: &Option<PathBuf>,
+  ) -> Result<Self, Report> {
+    let dataset_version = inputs
+      .virus_properties
+      .version
+      .as_ref()
+      .map_or_else(|| "unversioned".to_owned(), |version| version.tag.clone());
+    let dataset_hash = json_hash(inputs)?;
+
+    let primers = primers_path
+      .as_ref()
+      .map(|path| std::fs::read(path).wrap_err_with(|| format!("When reading PCR primers {path:#?}")))
+      .transpose()?;
+    let params_hash = json_hash(&(env!("CARGO_PKG_VERSION"), params, cdses, primers))?;
+
+    let dir = cache_dir
+      .as_ref()
+      .join(format!("{dataset_version}-{}", short_hash(&dataset_hash)))
+      .join(short_hash(&params_hash));
+    std::fs::create_dir_all(&dir).wrap_err_with(|| format!("When creating cache directory {dir:#?}"))?;
+
+    Ok(Self {
+      dir,
+      tmp_counter: AtomicUsize::new(0),
+    })
+  }
+
+  fn entry_path(&self, seq_hash: &str) -> PathBuf {
+    self.dir.join(format!("{seq_hash}.cbor"))
+  }
+
+  /// Retrieves cached results for the sequence with a given hash, if any. Unreadable entries are treated as absent.
+  pub fn get(&self, seq_hash: &str) -> Option<ReusableAnalysisOutput> {
+    let filepath = self.entry_path(seq_hash);
+    let file = File::open(&filepath).ok()?;
+    match ciborium::de::from_reader(BufReader::new(file)) {
+      Ok(output) => Some(output),
+      Err(err) => {
+        warn!("When reading cached results from {filepath:#?}: {err}. The entry will be recomputed.");
+        None
+      }
+    }
+  }
+
+  /// Stores results for the sequence with a given hash. The file is written under a temporary name and then renamed,
+  /// so that concurrent readers never observe a partially written entry.
+  ///
+  /// Entries are stored in CBOR format, which, unlike JSON, preserves floating point numbers exactly.
+  pub fn put(&self, seq_hash: &str, output: &ReusableAnalysisOutput) -> Result<(), Report> {
+    let filepath = self.entry_path(seq_hash);
+    let tmp_id = self.tmp_counter.fetch_add(1, Ordering::Relaxed);
+    let tmp_filepath = self
+      .dir
+      .join(format!("{seq_hash}.cbor.{}.{tmp_id}.tmp", std::process::id()));
+    {
+      let file =
+        File::create(&tmp_filepath).wrap_err_with(|| format!("When creating cached results file {tmp_filepath:#?}"))?;
+      let mut writer = BufWriter::new(file);
+      ciborium::ser::into_writer(output, &mut writer)
+        .map_err(|err| eyre!("{err}"))
+        .and_then(|()| Ok(writer.flush()?))
+        .wrap_err_with(|| format!("When writing cached results to {tmp_filepath:#?}"))?;
+    }
+    std::fs::rename(&tmp_filepath, &filepath)
+      .wrap_err_with(|| format!("When moving cached results from {tmp_filepath:#?} to {filepath:#?}"))
+  }
+}
+
+/// Maximum number of results of unique sequences kept in memory by `NextcladeDeduplicator`
+pub const DEDUPLICATOR_CAPACITY: usize = 1024;
+
+/// Remembers results of sequences analyzed recently during the current run, such that duplicate sequences are only
+/// analyzed once. When several threads encounter the same sequence simultaneously, only one of them runs the
+/// analysis, while the others wait for it.
+///
+/// At most `capacity` results are kept, the least recently used ones are dropped first, such that memory consumption
+/// does not grow with the number of unique sequences. Duplicates of the dropped sequences are analyzed again (or read
+/// from the results cache, if enabled).
+pub struct NextcladeDeduplicator {
+  capacity: usize,
+  state: Mutex<DeduplicatorState>,
+}
+
+type DeduplicatedResult = OnceLock<Result<ReusableAnalysisOutput, String>>;
+
+#[derive(Default)]
+struct DeduplicatorState {
+  /// Results by sequence hash, along with the time of their last use
+  results: HashMap<String, (Arc<DeduplicatedResult>, u64)>,
+
+  /// Sequence hashes by the time of last use of their results, least recently used first
+  last_used: BTreeMap<u64, String>,
+
+  time: u64,
+}
+
+impl Default for NextcladeDeduplicator {
+  fn default() -> Self {
+    Self::new(DEDUPLICATOR_CAPACITY)
+  }
+}
+
+impl NextcladeDeduplicator {
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      capacity,
+      state: Mutex::new(DeduplicatorState::default()),
+    }
+  }
+
+  pub fn get_or_run(
+    &self,
+    seq_hash: &str,
+    run: impl FnOnce() -> Result<ReusableAnalysisOutput, Report>,
+  ) -> Result<ReusableAnalysisOutput, Report> {
+    let cell = self
+      .state
+      .lock()
+      .map_err(|err| eyre!("{err}"))?
+      .get_or_insert(seq_hash, self.capacity);
+    cell
+      .get_or_init(|| run().map_err(|report| report_to_string(&report)))
+      .clone()
+      .map_err(|err| eyre!(err))
+  }
+}
+
+impl DeduplicatorState {
+  /// Retrieves the result slot for a given sequence hash, creating it if necessary, and drops the least recently used
+  /// slots over capacity. Threads waiting on a dropped slot still receive its result.
+  fn get_or_insert(&mut self, seq_hash: &str, capacity: usize) -> Arc<DeduplicatedResult> {
+    self.time += 1;
+    let time = self.time;
+
+    let cell = if let Some((cell, last_used)) = self.results.get_mut(seq_hash) {
+      self.last_used.remove(last_used);
+      *last_used = time;
+      Arc::clone(cell)
+    } else {
+      let cell = Arc::<DeduplicatedResult>::default();
+      self.results.insert(seq_hash.to_owned(), (Arc::clone(&cell), time));
+      cell
+    };
+    self.last_used.insert(time, seq_hash.to_owned());
+
+    while self.results.len() > capacity {
+      let Some((_, oldest)) = self.last_used.pop_first() else {
+        break;
+      };
+      self.results.remove(&oldest);
+    }
+
+    cell
+  }
+}
+
+/// Results of analysis which can be reused for other sequences with identical content (duplicates or cached entries).
+///
+/// Index and name of the sequence are not stored. Warnings concerning the sequence as a whole are stored without the
+/// sequence identity, which is added back when the results are assigned to a particular sequence record.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReusableAnalysisOutput {
+  output: AnalysisOutput,
+  warnings: Vec<ReusableWarning>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReusableWarning {
+  cds_name: String,
+  warning: String,
+  is_seq_warning: bool,
+}
+
+impl ReusableAnalysisOutput {
+  pub fn new(mut output: AnalysisOutput) -> Self {
+    let result = &mut output.analysis_result;
+    let prefix = seq_warning_prefix(result.index, &result.seq_name);
+    let warnings = std::mem::take(&mut result.warnings)
+      .into_iter()
+      .map(
+        |PeptideWarning { cds_name, warning }| match warning.strip_prefix(&prefix) {
+          Some(warning) => ReusableWarning {
+            cds_name,
+            warning: warning.to_owned(),
+            is_seq_warning: true,
+          },
+          None => ReusableWarning {
+            cds_name,
+            warning,
+            is_seq_warning: false,
+          },
+        },
+      )
+      .collect();
+    result.index = 0;
+    result.seq_name.clear();
+    Self { output, warnings }
+  }
+
+  /// Assigns the results to the given sequence record
+  pub fn into_output(self, record: &FastaRecord) -> AnalysisOutput {
+    let Self { mut output, warnings } = self;
+    let result = &mut output.analysis_result;
+    let prefix = seq_warning_prefix(record.index, &record.seq_name);
+    result.warnings = warnings
+      .into_iter()
+      .map(
+        |ReusableWarning {
+           cds_name,
+           warning,
+           is_seq_warning,
+         }| PeptideWarning {
+          cds_name,
+          warning: if is_seq_warning {
+            format!("{prefix}{warning}")
+          } else {
+            warning
+          },
+        },
+      )
+      .collect();
+    result.index = record.index;
+    result.seq_name = record.seq_name.clone();
+    output
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use eyre::ContextCompat;
+  use nextclade::analyze::virus_properties::VirusProperties;
+  use nextclade::gene::gene_map::GeneMap;
+  use nextclade::io::json::json_parse;
+  use nextclade::io::sample_dates::SampleDates;
+  use nextclade::run::nextclade_wasm::Nextclade;
+  use nextclade::run::params::NextcladeInputParamsOptional;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+  use std::sync::atomic::AtomicUsize;
+
+  const REF_SEQ: &str = "ATGGCTAGCTTACGATCGATCGGCTAGCTAGGCTTACGCATCGACTAGCTAGCATCGGATCGATTACGCTAGCTAGGCATCGACT\
+    GATCGCTAGCATCGACTGACTAGCTAGCATGCATCGATCGACTAGCTAGCTAGCATCGATCGATCGACTAGCTAGCATCGACTAGCAT";
+
+  fn record(index: usize, seq_name: &str) -> FastaRecord {
+    FastaRecord {
+      seq_name: seq_name.to_owned(),
+      seq: REF_SEQ.replacen("GCTAGC", "GCTTGC", 1),
+      index,
+    }
+  }
+
+  fn nextclade_inputs() -> Result<NextcladeParams, Report> {
+    let virus_properties: VirusProperties =
+      json_parse(r#"{ "schemaVersion": "3.0.0", "files": { "reference": "", "pathogenJson": "" } }"#)?;
+    Ok(NextcladeParams {
+      ref_record: FastaRecord {
+        seq_name: "reference".to_owned(),
+        seq: REF_SEQ.to_owned(),
+        index: 0,
+      },
+      gene_map: GeneMap::new(),
+      tree: None,
+      virus_properties,
+    })
+  }
+
+  fn create_nextclade() -> Result<Nextclade, Report> {
+    Nextclade::new(
+      nextclade_inputs()?,
+      vec![],
+      SampleDates::default(),
+      &NextcladeInputParamsOptional::default(),
+    )
+  }
+
+  fn create_output(record: &FastaRecord) -> Result<AnalysisOutput, Report> {
+    let mut output = create_nextclade()?.run(record)?;
+    let result = &mut output.analysis_result;
+    // Not representable exactly in the shortest decimal form used by JSON serializers without 'float_roundtrip'
+    result.divergence = 0.1 + 0.2;
+    result.warnings = vec![
+      PeptideWarning {
+        cds_name: "nuc".to_owned(),
+        warning: format!(
+          "{}Sequence is reverse-complemented",
+          seq_warning_prefix(record.index, &record.seq_name)
+        ),
+      },
+      PeptideWarning {
+        cds_name: "S".to_owned(),
+        warning: "Unable to translate".to_owned(),
+      },
+    ];
+    Ok(output)
+  }
+
+  fn unique_temp_dir() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!(
+      "nextclade-results-cache-test-{}-{}",
+      std::process::id(),
+      COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+  }
+
+  #[rstest]
+  fn assigns_identity_of_another_sequence() -> Result<(), Report> {
+    let output = ReusableAnalysisOutput::new(create_output(&record(3, "seq3"))?).into_output(&record(7, "seq7"));
+
+    assert_eq!(output.analysis_result.index, 7);
+    assert_eq!(output.analysis_result.seq_name, "seq7");
+    assert_eq!(
+      output
+        .analysis_result
+        .warnings
+        .iter()
+        .map(|warning| warning.warning.as_str())
+        .collect_vec(),
+      vec![
+        "When processing sequence #7 'seq7': Sequence is reverse-complemented",
+        "Unable to translate",
+      ]
+    );
+    Ok(())
+  }
+
+  #[rstest]
+  fn does_not_alter_warnings_mentioning_sequence_name_elsewhere() -> Result<(), Report> {
+    let mut output = create_output(&record(1, "S"))?;
+    output.analysis_result.warnings[1].warning = "Gene S: when processing sequence #1 'S'".to_owned();
+
+    let output = ReusableAnalysisOutput::new(output).into_output(&record(2, "other"));
+
+    assert_eq!(
+      output.analysis_result.warnings[1].warning,
+      "Gene S: when processing sequence #1 'S'"
+    );
+    Ok(())
+  }
+
+  #[rstest]
+  fn stores_and_retrieves_cached_results_exactly() -> Result<(), Report> {
+    let nextclade = create_nextclade()?;
+    let dir = unique_temp_dir();
+    let cache = NextcladeResultsCache::new(&dir, &nextclade_inputs()?, &nextclade.params, &None, &None)?;
+
+    let seq_hash = seq_hash(&record(0, "").seq, None);
+    assert!(cache.get(&seq_hash).is_none());
+
+    let expected = create_output(&record(3, "seq3"))?;
+    cache.put(&seq_hash, &ReusableAnalysisOutput::new(expected.clone()))?;
+    let actual = cache
+      .get(&seq_hash)
+      .wrap_err("Cached results are not found")?
+      .into_output(&record(3, "seq3"));
+
+    std::fs::remove_dir_all(&dir)?;
+
+    assert_eq!(
+      json_stringify(&actual, JsonPretty(false))?,
+      json_stringify(&expected, JsonPretty(false))?
+    );
+    assert!(actual.analysis_result.divergence.to_bits() == expected.analysis_result.divergence.to_bits());
+    Ok(())
+  }
+
+  #[rstest]
+  fn ignores_unreadable_cache_entries() -> Result<(), Report> {
+    let nextclade = create_nextclade()?;
+    let dir = unique_temp_dir();
+    let cache = NextcladeResultsCache::new(&dir, &nextclade_inputs()?, &nextclade.params, &None, &None)?;
+
+    std::fs::write(cache.entry_path("abc"), "not a cache entry")?;
+    let actual = cache.get("abc");
+
+    std::fs::remove_dir_all(&dir)?;
+
+    assert!(actual.is_none());
+    Ok(())
+  }
+
+  #[rstest]
+  fn runs_analysis_once_per_unique_sequence() -> Result<(), Report> {
+    let deduplicator = NextcladeDeduplicator::default();
+    let output = ReusableAnalysisOutput::new(create_output(&record(0, "seq0"))?);
+    let num_runs = AtomicUsize::new(0);
+
+    let run = || {
+      num_runs.fetch_add(1, Ordering::Relaxed);
+      Ok(output.clone())
+    };
+
+    let results = std::thread::scope(|scope| {
+      let handles = (0..8)
+        .map(|i| {
+          let hash = if i % 2 == 0 { "even" } else { "odd" };
+          scope.spawn(|| deduplicator.get_or_run(hash, run))
+        })
+        .collect_vec();
+      handles.into_iter().map(|handle| handle.join().unwrap()).collect_vec()
+    });
+
+    assert_eq!(num_runs.load(Ordering::Relaxed), 2);
+    assert!(results.iter().all(Result::is_ok));
+    Ok(())
+  }
+
+  #[rstest]
+  fn keeps_bounded_number_of_results() -> Result<(), Report> {
+    let deduplicator = NextcladeDeduplicator::new(2);
+    let output = ReusableAnalysisOutput::new(create_output(&record(0, "seq0"))?);
+    let num_runs = AtomicUsize::new(0);
+
+    let run = || {
+      num_runs.fetch_add(1, Ordering::Relaxed);
+      Ok(output.clone())
+    };
+
+    for i in 0..100 {
+      deduplicator.get_or_run(&i.to_string(), run)?;
+      // Recently seen sequence is retained, because it is used again
+      deduplicator.get_or_run("recent", run)?;
+    }
+    let num_results = deduplicator.state.lock().unwrap().results.len();
+    assert_eq!((num_results, num_runs.load(Ordering::Relaxed)), (2, 101));
+
+    // Results of the sequences seen long ago are dropped and are computed again
+    deduplicator.get_or_run("0", run)?;
+    assert_eq!(num_runs.load(Ordering::Relaxed), 102);
+    Ok(())
+  }
+
+  #[rstest]
+  fn reports_failed_analysis_for_every_duplicate() {
+    let deduplicator = NextcladeDeduplicator::default();
+    let num_runs = AtomicUsize::new(0);
+
+    let run = || {
+      num_runs.fetch_add(1, Ordering::Relaxed);
+      Err(eyre!("Unable to align"))
+    };
+
+    let errors = (0..3)
+      .map(|_| report_to_string(&deduplicator.get_or_run("hash", run).unwrap_err()))
+      .collect_vec();
+
+    assert_eq!(num_runs.load(Ordering::Relaxed), 1);
+    assert_eq!(errors, vec!["Unable to align"; 3]);
+  }
+}
@@ -73,7 +73,7 @@ pub fn run(args: &NextcladeSortArgs, minimizer_index: &MinimizerIndexJson, verbo
   let NextcladeSortArgs {
     input_fastas,
     search_params,
-    other_params: NextcladeRunOtherParams { jobs },
+    other_params: NextcladeRunOtherParams { jobs, .. },
     ..
   } = args;
 
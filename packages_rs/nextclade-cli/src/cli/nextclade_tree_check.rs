@@ -0,0 +1,53 @@
+use crate::cli::nextclade_cli::NextcladeTreeCheckArgs;
+use eyre::{Report, WrapErr};
+use nextclade::align::params::AlignPairwiseParams;
+use nextclade::alphabet::nuc::to_nuc_seq;
+use nextclade::gene::gene_map::GeneMap;
+use nextclade::io::fasta::read_one_fasta;
+use nextclade::io::fs::read_file_to_string;
+use nextclade::io::json::{json_or_yaml_write, json_parse, json_stringify, JsonPretty};
+use nextclade::make_error;
+use nextclade::translate::translate_genes_ref::translate_genes_ref;
+use nextclade::tree::tree_check::tree_check;
+use nextclade::utils::option::OptionMapRefFallible;
+use serde_json::Value;
+
+pub fn nextclade_tree_check(args: &NextcladeTreeCheckArgs) -> Result<(), Report> {
+  let input_tree = &args.input_tree;
+  let tree: Value = json_parse(read_file_to_string(input_tree)?)
+    .wrap_err_with(|| format!("When parsing reference tree JSON file {input_tree:#?}"))?;
+
+  let ref_record = read_one_fasta(&args.input_ref).wrap_err("When reading reference sequence")?;
+  let ref_seq = to_nuc_seq(&ref_record.seq).wrap_err("When reading reference sequence")?;
+
+  let ref_translation = args
+    .input_annotation
+    .map_ref_fallible(GeneMap::from_path)
+    .wrap_err("When reading genome annotation")?
+    .map_ref_fallible(|gene_map| translate_genes_ref(&ref_seq, gene_map, &AlignPairwiseParams::default()))
+    .wrap_err("When translating reference sequence")?;
+
+  let issues = tree_check(&tree, &ref_seq, ref_translation.as_ref())
+    .wrap_err_with(|| format!("When checking reference tree {input_tree:#?}"))?;
+
+  if args.json {
+    println!("{}\n", json_stringify(&issues, JsonPretty(true))?);
+  } else {
+    for issue in &issues {
+      println!("{issue}");
+    }
+  }
+
+  if let Some(output) = &args.output {
+    json_or_yaml_write(output, &issues)?;
+  }
+
+  if !issues.is_empty() {
+    return make_error!(
+      "Found {} inconsistencies in the reference tree {input_tree:#?}",
+      issues.len()
+    );
+  }
+
+  Ok(())
+}
@@ -4,14 +4,22 @@ use crate::io::http_client::{HttpClient, ProxyConfig};
 use eyre::{eyre, ContextCompat, Report, WrapErr};
 use itertools::Itertools;
 use log::LevelFilter;
+use nextclade::alphabet::nuc::to_nuc_seq;
 use nextclade::analyze::virus_properties::{LabelledMutationsConfig, VirusProperties};
 use nextclade::gene::gene_map::{filter_gene_map, GeneMap};
+use nextclade::io::compression::{guess_compression_from_filepath, CompressionType};
 use nextclade::io::dataset::{Dataset, DatasetFiles, DatasetMeta, DatasetsIndexJson};
-use nextclade::io::fasta::{read_one_fasta, read_one_fasta_str};
+use nextclade::io::fasta::{read_one_fasta, read_one_fasta_str, FastaRecord};
 use nextclade::io::file::create_file_or_stdout;
 use nextclade::io::fs::{ensure_dir, has_extension, read_file_to_string};
+use nextclade::io::nwk_reader::nwk_read_file;
+use nextclade::io::usher_mat::usher_mat_read_file;
 use nextclade::run::nextclade_wasm::NextcladeParams;
 use nextclade::tree::tree::AuspiceTree;
+use nextclade::tree::tree_from_nwk::{
+  auspice_tree_from_nwk, read_node_mutations_tsv, read_tree_alignment_fasta, read_tree_metadata_tsv,
+};
+use nextclade::tree::tree_from_usher::auspice_tree_from_usher_mat;
 use nextclade::utils::option::OptionMapRefFallible;
 use nextclade::{make_error, make_internal_error, o};
 use rayon::iter::ParallelIterator;
@@ -115,9 +123,13 @@ pub fn dataset_zip_load(
     .map(|gene_map| filter_gene_map(gene_map, cdses))
     .unwrap_or_default();
 
-  let tree = read_from_path_or_zip(&run_args.inputs.input_tree, &mut zip, "tree.json")?
-    .map_ref_fallible(AuspiceTree::from_str)
-    .wrap_err("When reading reference tree JSON from dataset")?;
+  let tree = if let Some(tree) = read_tree_from_nwk_or_mat(&run_args.inputs, &ref_record, &gene_map)? {
+    Some(tree)
+  } else {
+    read_from_path_or_zip(&run_args.inputs.input_tree, &mut zip, "tree.json")?
+      .map_ref_fallible(AuspiceTree::from_str)
+      .wrap_err("When reading reference tree JSON from dataset")?
+  };
 
   Ok(NextcladeParams {
     ref_record,
@@ -179,17 +191,21 @@ pub fn dataset_dir_load(
     .map(|gen_map| filter_gene_map(gen_map, cdses))
     .unwrap_or_default();
 
-  let tree = input_tree
-    .clone()
-    .or_else(|| {
-      virus_properties
-        .files
-        .tree_json
-        .as_ref()
-        .map(|tree_json| dataset_dir.join(tree_json))
-    })
-    .map_ref_fallible(AuspiceTree::from_path)
-    .wrap_err("When reading reference tree JSON")?;
+  let tree = if let Some(tree) = read_tree_from_nwk_or_mat(&run_args.inputs, &ref_record, &gene_map)? {
+    Some(tree)
+  } else {
+    input_tree
+      .clone()
+      .or_else(|| {
+        virus_properties
+          .files
+          .tree_json
+          .as_ref()
+          .map(|tree_json| dataset_dir.join(tree_json))
+      })
+      .map_ref_fallible(AuspiceTree::from_path)
+      .wrap_err("When reading reference tree JSON")?
+  };
 
   Ok(NextcladeParams {
     ref_record,
@@ -242,6 +258,8 @@ pub fn dataset_individual_files_load(
             tree_builder_params: None,
             phenotype_data: None,
             aa_motifs: vec![],
+            nuc_motifs: vec![],
+            resistance: vec![],
             versions: vec![],
             version: None,
             compatibility: None,
@@ -260,12 +278,16 @@ pub fn dataset_individual_files_load(
         .map(|gen_map| filter_gene_map(gen_map, cdses))
         .unwrap_or_default();
 
-      let tree = run_args
-        .inputs
-        .input_tree
-        .as_ref()
-        .map_ref_fallible(AuspiceTree::from_path)
-        .wrap_err("When reading reference tree JSON")?;
+      let tree = if let Some(tree) = read_tree_from_nwk_or_mat(&run_args.inputs, &ref_record, &gene_map)? {
+        Some(tree)
+      } else {
+        run_args
+          .inputs
+          .input_tree
+          .as_ref()
+          .map_ref_fallible(AuspiceTree::from_path)
+          .wrap_err("When reading reference tree JSON")?
+      };
 
       Ok(NextcladeParams {
         ref_record,
@@ -278,6 +300,70 @@ pub fn dataset_individual_files_load(
   }
 }
 
+/// Builds reference tree from UShER mutation-annotated tree (MAT), if the tree file is a protobuf file (`.pb`), or
+/// from a tree in Newick format, if sequences or mutations of the tree nodes are provided. Returns `None` otherwise, in
+/// which case the tree is expected in Auspice JSON format.
+pub fn read_tree_from_nwk_or_mat(
+  inputs: &NextcladeRunInputArgs,
+  ref_record: &FastaRecord,
+  gene_map: &GeneMap,
+) -> Result<Option<AuspiceTree>, Report> {
+  let NextcladeRunInputArgs {
+    input_tree,
+    input_tree_metadata,
+    input_tree_alignment,
+    input_tree_mutations,
+    ..
+  } = inputs;
+
+  if input_tree_metadata.is_some() && input_tree_alignment.is_none() && input_tree_mutations.is_none() {
+    return make_error!(
+      "When `--input-tree-metadata` is provided, either `--input-tree-alignment` or `--input-tree-mutations` is required, along with `--input-tree` pointing to a tree in Newick format"
+    );
+  }
+
+  if let Some(input_tree) = input_tree.as_ref().filter(|input_tree| is_usher_mat_path(input_tree)) {
+    let mat = usher_mat_read_file(input_tree)?;
+    let ref_seq = to_nuc_seq(&ref_record.seq).wrap_err("When reading reference sequence")?;
+    return auspice_tree_from_usher_mat(&mat, &ref_seq, gene_map)
+      .wrap_err_with(|| format!("When building reference tree from UShER MAT file {input_tree:#?}"))
+      .map(Some);
+  }
+
+  let mutations = match (input_tree_alignment, input_tree_mutations) {
+    (Some(input_tree_alignment), _) => read_tree_alignment_fasta(input_tree_alignment)?,
+    (None, Some(input_tree_mutations)) => read_node_mutations_tsv(input_tree_mutations)?,
+    (None, None) => return Ok(None),
+  };
+
+  let Some(input_tree) = input_tree else {
+    return make_error!(
+      "When `--input-tree-alignment` or `--input-tree-mutations` is provided, `--input-tree` is required and should point to a tree in Newick format"
+    );
+  };
+
+  let nwk = nwk_read_file(input_tree)?;
+
+  let metadata = input_tree_metadata
+    .as_ref()
+    .map_ref_fallible(read_tree_metadata_tsv)?
+    .unwrap_or_default();
+
+  let ref_seq = to_nuc_seq(&ref_record.seq).wrap_err("When reading reference sequence")?;
+
+  auspice_tree_from_nwk(&nwk, &metadata, &mutations, &ref_seq, gene_map)
+    .wrap_err_with(|| format!("When building reference tree from Newick file {input_tree:#?}"))
+    .map(Some)
+}
+
+/// Checks whether the file is a protobuf file (`.pb`), possibly compressed
+fn is_usher_mat_path(filepath: &Path) -> bool {
+  match guess_compression_from_filepath(filepath) {
+    (CompressionType::None, _) => has_extension(filepath, "pb"),
+    _ => has_extension(filepath.with_extension(""), "pb"),
+  }
+}
+
 pub struct DatasetFilePaths<'a> {
   input_ref: &'a Path,
   input_tree: &'a Option<PathBuf>,
@@ -344,14 +430,18 @@ pub fn dataset_str_download_and_load(
   .map(|gene_map| filter_gene_map(gene_map, cdses))
   .unwrap_or_default();
 
-  let tree = read_from_path_or_url(
-    &mut http,
-    &dataset,
-    &run_args.inputs.input_tree,
-    &dataset.files.tree_json,
-  )?
-  .map_ref_fallible(AuspiceTree::from_str)
-  .wrap_err("When reading reference tree from dataset")?;
+  let tree = if let Some(tree) = read_tree_from_nwk_or_mat(&run_args.inputs, &ref_record, &gene_map)? {
+    Some(tree)
+  } else {
+    read_from_path_or_url(
+      &mut http,
+      &dataset,
+      &run_args.inputs.input_tree,
+      &dataset.files.tree_json,
+    )?
+    .map_ref_fallible(AuspiceTree::from_str)
+    .wrap_err("When reading reference tree from dataset")?
+  };
 
   Ok(NextcladeParams {
     ref_record,
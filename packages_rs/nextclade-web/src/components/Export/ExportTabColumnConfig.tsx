@@ -287,6 +287,7 @@ export function CsvColumnConfigCategory({ category, columns }: CsvColumnConfigCa
           'priv-muts': t('Mutations relative to nearest node (private mutations)'),
           'qc': t('Quality control'),
           'primers': t('PCR primers'),
+          'placement-uncertainty': t('Placement uncertainty'),
           'errs-warns': t('Errors & warnings'),
         },
         category,
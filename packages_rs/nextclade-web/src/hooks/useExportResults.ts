@@ -11,9 +11,13 @@ import {
   aaMotifsDescsAtom,
   analysisResultsAtom,
   cdsesAtom,
+  cdsNamesAtom,
   cladeNodeAttrDescsAtom,
   csvColumnConfigAtom,
+  drugResistanceDescsAtom,
+  nucMotifsDescsAtom,
   phenotypeAttrDescsAtom,
+  refNodesAtom,
   treeAtom,
   treeNwkAtom,
 } from 'src/state/results.state'
@@ -122,6 +126,10 @@ async function prepareResultsCsv(snapshot: Snapshot, worker: ExportWorker, delim
   const cladeNodeAttrDescs = await snapshot.getPromise(cladeNodeAttrDescsAtom)
   const phenotypeAttrDescs = await snapshot.getPromise(phenotypeAttrDescsAtom)
   const aaMotifsDescs = await snapshot.getPromise(aaMotifsDescsAtom)
+  const nucMotifsDescs = await snapshot.getPromise(nucMotifsDescsAtom)
+  const drugResistanceDescs = await snapshot.getPromise(drugResistanceDescsAtom)
+  const cdsNames = await snapshot.getPromise(cdsNamesAtom)
+  const refNodes = await snapshot.getPromise(refNodesAtom)
   const csvColumnConfig = await snapshot.getPromise(csvColumnConfigAtom)
   if (!csvColumnConfig) {
     throw new ErrorInternal('CSV column config is not initialized, but it should be')
@@ -133,6 +141,10 @@ async function prepareResultsCsv(snapshot: Snapshot, worker: ExportWorker, delim
     cladeNodeAttrDescs,
     phenotypeAttrDescs,
     aaMotifsDescs,
+    nucMotifsDescs,
+    drugResistanceDescs,
+    cdsNames,
+    refNodes,
     delimiter,
     csvColumnConfig,
   )
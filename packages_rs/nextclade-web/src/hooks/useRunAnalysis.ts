@@ -27,9 +27,12 @@ import {
   cdsesAtom,
   cladeNodeAttrDescsAtom,
   csvColumnConfigAtom,
+  drugResistanceDescsAtom,
   genesAtom,
   genomeSizeAtom,
+  nucMotifsDescsAtom,
   phenotypeAttrDescsAtom,
+  refNodesAtom,
   treeAtom,
   treeNwkAtom,
 } from 'src/state/results.state'
@@ -79,6 +82,9 @@ export function useRunAnalysis() {
             cladeNodeAttrKeyDescs,
             phenotypeAttrDescs,
             aaMotifsDescs,
+            nucMotifsDescs,
+            drugResistanceDescs,
+            refNodes,
             csvColumnConfigDefault,
           }) {
             const genes = Object.values(geneMap.genes)
@@ -101,6 +107,9 @@ export function useRunAnalysis() {
             set(cladeNodeAttrDescsAtom, cladeNodeAttrKeyDescs as unknown as CladeNodeAttrDesc[])
             set(phenotypeAttrDescsAtom, phenotypeAttrDescs)
             set(aaMotifsDescsAtom, aaMotifsDescs)
+            set(nucMotifsDescsAtom, nucMotifsDescs)
+            set(drugResistanceDescsAtom, drugResistanceDescs)
+            set(refNodesAtom, refNodes)
             set(csvColumnConfigAtom, csvColumnConfigDefault)
           },
           onParsedFasta(/* record */) {
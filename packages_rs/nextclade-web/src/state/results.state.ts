@@ -2,7 +2,16 @@
 import type { AuspiceJsonV2, CladeNodeAttrDesc } from 'auspice'
 import { isNil } from 'lodash'
 import { atom, atomFamily, DefaultValue, selector, selectorFamily } from 'recoil'
-import type { AaMotifsDesc, Cds, CsvColumnConfig, Gene, NextcladeResult, PhenotypeAttrDesc } from 'src/types'
+import type {
+  AaMotifsDesc,
+  Cds,
+  CsvColumnConfig,
+  DrugResistanceDesc,
+  Gene,
+  NextcladeResult,
+  NucMotifsDesc,
+  PhenotypeAttrDesc,
+} from 'src/types'
 import { AlgorithmGlobalStatus, AlgorithmSequenceStatus, getResultStatus } from 'src/types'
 import { plausible } from 'src/components/Common/Plausible'
 import { runFilters } from 'src/filtering/runFilters'
@@ -318,6 +327,21 @@ export const aaMotifsKeysAtom = selector<string[]>({
   get: ({ get }) => get(aaMotifsDescsAtom).map((desc) => desc.name),
 })
 
+export const nucMotifsDescsAtom = atom<NucMotifsDesc[]>({
+  key: 'nucMotifsDescsAtom',
+  default: [],
+})
+
+export const drugResistanceDescsAtom = atom<DrugResistanceDesc[]>({
+  key: 'drugResistanceDescsAtom',
+  default: [],
+})
+
+export const refNodesAtom = atom<string[]>({
+  key: 'refNodesAtom',
+  default: [],
+})
+
 export const csvColumnConfigAtom = atom<CsvColumnConfig | undefined>({
   key: 'csvColumnConfigAtom',
   default: undefined,
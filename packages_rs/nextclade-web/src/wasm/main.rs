@@ -1,11 +1,16 @@
 use crate::wasm::jserr::jserr;
 use eyre::{Report, WrapErr};
 use itertools::Itertools;
-use nextclade::analyze::virus_properties::{AaMotifsDesc, PhenotypeAttrDesc};
+use nextclade::analyze::cds_coverage::get_cds_coverage_keys;
+use nextclade::analyze::drug_resistance::get_drug_resistance_keys;
+use nextclade::analyze::find_nuc_motifs::get_nuc_motifs_keys;
+use nextclade::analyze::find_relative_mutations::get_relative_mutations_keys;
+use nextclade::analyze::virus_properties::{AaMotifsDesc, DrugResistanceDesc, NucMotifsDesc, PhenotypeAttrDesc};
 use nextclade::io::fasta::{read_one_fasta_str, FastaReader, FastaRecord};
 use nextclade::io::json::{json_parse, json_stringify, JsonPretty};
 use nextclade::io::nextclade_csv::{results_to_csv_string, CsvColumnConfig};
 use nextclade::io::results_json::{results_to_json_string, results_to_ndjson_string};
+use nextclade::io::sample_dates::SampleDates;
 use nextclade::run::nextclade_wasm::{Nextclade, NextcladeParams, NextcladeParamsRaw, NextcladeResult};
 use nextclade::run::params::NextcladeInputParamsOptional;
 use nextclade::tree::tree::CladeNodeAttrKeyDesc;
@@ -35,8 +40,10 @@ impl NextcladeWasm {
     // FIXME: pass params from the frontend
     let params = NextcladeInputParamsOptional::default();
 
-    let nextclade: Nextclade =
-      jserr(Nextclade::new(inputs, vec![], &params).wrap_err_with(|| "When initializing Nextclade runner"))?;
+    let nextclade: Nextclade = jserr(
+      Nextclade::new(inputs, vec![], SampleDates::new(), &params)
+        .wrap_err_with(|| "When initializing Nextclade runner"),
+    )?;
 
     Ok(Self { nextclade })
   }
@@ -161,6 +168,10 @@ impl NextcladeWasm {
     clade_node_attrs_json_str: &str,
     phenotype_attrs_json_str: &str,
     aa_motifs_keys_json_str: &str,
+    nuc_motifs_descs_json_str: &str,
+    drug_resistance_descs_json_str: &str,
+    cds_names_json_str: &str,
+    ref_nodes_json_str: &str,
     delimiter: char,
     csv_colum_config_json_str: &str,
   ) -> Result<String, JsError> {
@@ -187,9 +198,33 @@ impl NextcladeWasm {
         .wrap_err("When serializing results into CSV: When parsing AA motifs keys JSON internally"),
     )?;
 
+    let nuc_motifs_descs: Vec<NucMotifsDesc> = jserr(
+      json_parse(nuc_motifs_descs_json_str)
+        .wrap_err("When serializing results into CSV: When parsing nucleotide motifs descriptions JSON internally"),
+    )?;
+
+    let drug_resistance_descs: Vec<DrugResistanceDesc> = jserr(
+      json_parse(drug_resistance_descs_json_str)
+        .wrap_err("When serializing results into CSV: When parsing drug resistance descriptions JSON internally"),
+    )?;
+
+    let cds_names: Vec<String> = jserr(
+      json_parse(cds_names_json_str)
+        .wrap_err("When serializing results into CSV: When parsing CDS names JSON internally"),
+    )?;
+
+    let ref_nodes: Vec<String> = jserr(
+      json_parse(ref_nodes_json_str)
+        .wrap_err("When serializing results into CSV: When parsing reference node names JSON internally"),
+    )?;
+
     let clade_node_attr_keys = clade_node_attrs.into_iter().map(|attr| attr.name).collect_vec();
     let phenotype_attr_keys = phenotype_attrs.into_iter().map(|attr| attr.name).collect_vec();
     let aa_motifs_keys = aa_motifs_descs.into_iter().map(|desc| desc.name).collect_vec();
+    let nuc_motifs_keys = get_nuc_motifs_keys(&nuc_motifs_descs);
+    let drug_resistance_keys = get_drug_resistance_keys(&drug_resistance_descs);
+    let cds_coverage_keys = get_cds_coverage_keys(cds_names);
+    let relative_mutations_keys = get_relative_mutations_keys(&ref_nodes);
 
     let csv_colum_config: CsvColumnConfig = jserr(
       json_parse(csv_colum_config_json_str)
@@ -202,6 +237,10 @@ impl NextcladeWasm {
       &clade_node_attr_keys,
       &phenotype_attr_keys,
       &aa_motifs_keys,
+      &nuc_motifs_keys,
+      &drug_resistance_keys,
+      &cds_coverage_keys,
+      &relative_mutations_keys,
       delimiter as u8,
       &csv_colum_config,
     ))
@@ -1,5 +1,12 @@
 import { CladeNodeAttrDesc } from 'auspice'
-import type { AaMotifsDesc, AnalysisError, AnalysisResult, PhenotypeAttrDesc } from 'src/types'
+import type {
+  AaMotifsDesc,
+  AnalysisError,
+  AnalysisResult,
+  DrugResistanceDesc,
+  NucMotifsDesc,
+  PhenotypeAttrDesc,
+} from 'src/types'
 import type { NextcladeWasmWorker } from 'src/workers/nextcladeWasm.worker'
 import { spawn } from 'src/workers/spawn'
 import { CsvColumnConfig } from 'src/types'
@@ -46,6 +53,10 @@ export class ExportWorker {
     cladeNodeAttrs: CladeNodeAttrDesc[],
     phenotypeAttrs: PhenotypeAttrDesc[],
     aaMotifsDescs: AaMotifsDesc[],
+    nucMotifsDescs: NucMotifsDesc[],
+    drugResistanceDescs: DrugResistanceDesc[],
+    cdsNames: string[],
+    refNodes: string[],
     delimiter: string,
     csvColumnConfig: CsvColumnConfig,
   ) {
@@ -55,6 +66,10 @@ export class ExportWorker {
       cladeNodeAttrs,
       phenotypeAttrs,
       aaMotifsDescs,
+      nucMotifsDescs,
+      drugResistanceDescs,
+      cdsNames,
+      refNodes,
       delimiter,
       csvColumnConfig,
     )
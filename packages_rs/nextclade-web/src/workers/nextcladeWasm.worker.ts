@@ -11,9 +11,11 @@ import type {
   AnalysisError,
   AnalysisResult,
   CsvColumnConfig,
+  DrugResistanceDesc,
   FastaRecord,
   NextcladeParamsRaw,
   NextcladeResult,
+  NucMotifsDesc,
   PhenotypeAttrDesc,
 } from 'src/types'
 import { sanitizeError } from 'src/helpers/sanitizeError'
@@ -145,6 +147,10 @@ export async function serializeResultsCsv(
   cladeNodeAttrsJson: CladeNodeAttrDesc[],
   phenotypeAttrsJson: PhenotypeAttrDesc[],
   aaMotifsDescs: AaMotifsDesc[],
+  nucMotifsDescs: NucMotifsDesc[],
+  drugResistanceDescs: DrugResistanceDesc[],
+  cdsNames: string[],
+  refNodes: string[],
   delimiter: string,
   csvColumnConfig: CsvColumnConfig,
 ) {
@@ -154,6 +160,10 @@ export async function serializeResultsCsv(
     JSON.stringify(cladeNodeAttrsJson),
     JSON.stringify(phenotypeAttrsJson),
     JSON.stringify(aaMotifsDescs),
+    JSON.stringify(nucMotifsDescs),
+    JSON.stringify(drugResistanceDescs),
+    JSON.stringify(cdsNames),
+    JSON.stringify(refNodes),
     delimiter,
     JSON.stringify(csvColumnConfig),
   )
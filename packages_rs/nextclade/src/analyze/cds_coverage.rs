@@ -0,0 +1,101 @@
+use crate::analyze::letter_ranges::CdsAaRange;
+use crate::coord::range::{intersect, AaRefRange};
+use itertools::Itertools;
+use std::collections::BTreeMap;
+
+/// Calculate coverage of each CDS: fraction of codons which are sequenced and are not unknown (`X`).
+/// CDS length is the sum of sequenced and unsequenced ranges.
+pub fn calculate_cds_coverage(
+  aa_alignment_ranges: &BTreeMap<String, Vec<AaRefRange>>,
+  aa_unsequenced_ranges: &BTreeMap<String, Vec<AaRefRange>>,
+  unknown_aa_ranges: &[CdsAaRange],
+) -> BTreeMap<String, f64> {
+  aa_alignment_ranges
+    .iter()
+    .map(|(cds_name, alignment_ranges)| {
+      let total_sequenced: usize = alignment_ranges.iter().map(AaRefRange::len).sum();
+
+      let total_unsequenced: usize = aa_unsequenced_ranges
+        .get(cds_name)
+        .map_or(0, |ranges| ranges.iter().map(AaRefRange::len).sum());
+
+      // Only count unknown codons inside of the sequenced ranges
+      let total_unknown: usize = unknown_aa_ranges
+        .iter()
+        .filter(|unknown| &unknown.cds_name == cds_name)
+        .flat_map(|unknown| &unknown.ranges)
+        .cartesian_product(alignment_ranges)
+        .map(|(unknown, alignment_range)| intersect(&unknown.range, alignment_range).len())
+        .sum();
+
+      let cds_len = total_sequenced + total_unsequenced;
+      let coverage = if cds_len == 0 {
+        0.0
+      } else {
+        total_sequenced.saturating_sub(total_unknown) as f64 / cds_len as f64
+      };
+
+      (cds_name.clone(), coverage)
+    })
+    .collect()
+}
+
+/// Names of dynamic CSV columns: coverage of each CDS
+pub fn get_cds_coverage_keys<S: AsRef<str>>(cds_names: impl IntoIterator<Item = S>) -> Vec<String> {
+  cds_names
+    .into_iter()
+    .map(|cds_name| cds_coverage_key(cds_name.as_ref()))
+    .collect_vec()
+}
+
+pub fn cds_coverage_key(cds_name: &str) -> String {
+  format!("cdsCoverage.{cds_name}")
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+  use super::*;
+  use crate::alphabet::aa::Aa;
+  use crate::analyze::letter_ranges::AaRange;
+  use maplit::btreemap;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  #[rstest]
+  fn calculates_cds_coverage() {
+    let aa_alignment_ranges = btreemap! {
+      "S".to_owned() => vec![AaRefRange::from_usize(10, 90)],
+      "N".to_owned() => vec![],
+    };
+    let aa_unsequenced_ranges = btreemap! {
+      "S".to_owned() => vec![AaRefRange::from_usize(0, 10), AaRefRange::from_usize(90, 100)],
+      "N".to_owned() => vec![AaRefRange::from_usize(0, 50)],
+    };
+    let unknown_aa_ranges = vec![CdsAaRange {
+      cds_name: "S".to_owned(),
+      letter: Aa::X,
+      ranges: vec![
+        AaRange {
+          range: AaRefRange::from_usize(5, 15),
+          letter: Aa::X,
+        },
+        AaRange {
+          range: AaRefRange::from_usize(50, 55),
+          letter: Aa::X,
+        },
+      ],
+      length: 15,
+    }];
+
+    let actual = calculate_cds_coverage(&aa_alignment_ranges, &aa_unsequenced_ranges, &unknown_aa_ranges);
+
+    assert_eq!(
+      actual,
+      btreemap! {
+        "N".to_owned() => 0.0,
+        "S".to_owned() => 0.7,
+      }
+    );
+  }
+}
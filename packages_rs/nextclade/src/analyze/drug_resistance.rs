@@ -0,0 +1,265 @@
+use crate::analyze::aa_del::AaDel;
+use crate::analyze::aa_sub::AaSub;
+use crate::analyze::virus_properties::{DrugResistanceDesc, DrugResistanceLevelDesc, DrugResistanceMutationsDesc};
+use crate::make_error;
+use eyre::{Report, WrapErr};
+use itertools::Itertools;
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Clone, Debug, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DrugResistance {
+  pub name: String,
+  pub score: f64,
+  pub level: Option<String>,
+  pub mutations: Vec<DrugResistanceMutations>,
+}
+
+/// Group of mutations found in the query sequence which contributes to the resistance score
+#[derive(Clone, Debug, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DrugResistanceMutations {
+  pub mutations: Vec<String>,
+  pub score: f64,
+}
+
+/// Drug resistance description with the amino acid mutations parsed and validated. Prepared once per run, from the
+/// descriptions in pathogen.json, such that mutation strings are not re-parsed for every sequence.
+#[derive(Clone, Debug)]
+pub struct DrugResistanceRules {
+  pub name: String,
+  pub levels: Vec<DrugResistanceLevelDesc>,
+  pub mutations: Vec<DrugResistanceMutationsRule>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DrugResistanceMutationsRule {
+  /// Mutations, as written in pathogen.json. These are reported in the outputs.
+  pub mutations: Vec<String>,
+  pub parsed: Vec<AaSub>,
+  pub score: f64,
+}
+
+impl DrugResistanceRules {
+  pub fn from_desc(desc: &DrugResistanceDesc) -> Result<Self, Report> {
+    let mut malformed = vec![];
+
+    let mutations = desc
+      .mutations
+      .iter()
+      .enumerate()
+      .filter_map(|(i, DrugResistanceMutationsDesc { mutations, score })| {
+        if mutations.is_empty() {
+          malformed.push(format!("group of mutations #{} is empty", i + 1));
+          return None;
+        }
+
+        let parsed = mutations
+          .iter()
+          .filter_map(|mutation| match AaSub::from_str(mutation) {
+            Ok(sub) => Some(sub),
+            Err(err) => {
+              malformed.push(format!("'{mutation}': {err}"));
+              None
+            }
+          })
+          .collect_vec();
+
+        Some(DrugResistanceMutationsRule {
+          mutations: mutations.clone(),
+          parsed,
+          score: *score,
+        })
+      })
+      .collect_vec();
+
+    if !malformed.is_empty() {
+      return make_error!(
+        "Drug resistance description for '{}' contains malformed entries:\n  {}",
+        desc.name,
+        malformed.join("\n  ")
+      );
+    }
+
+    Ok(Self {
+      name: desc.name.clone(),
+      levels: desc.levels.clone(),
+      mutations,
+    })
+  }
+}
+
+/// Parses and validates drug resistance descriptions from pathogen.json
+pub fn parse_drug_resistance_descs(
+  resistance_descs: &[DrugResistanceDesc],
+) -> Result<Vec<DrugResistanceRules>, Report> {
+  resistance_descs
+    .iter()
+    .map(DrugResistanceRules::from_desc)
+    .collect::<Result<Vec<_>, Report>>()
+    .wrap_err("When parsing drug resistance descriptions in pathogen.json")
+}
+
+/// Evaluate resistance to each of the drugs, given amino acid substitutions and deletions of the query sequence
+pub fn find_drug_resistance(
+  resistance_rules: &[DrugResistanceRules],
+  aa_substitutions: &[AaSub],
+  aa_deletions: &[AaDel],
+) -> Vec<DrugResistance> {
+  let aa_changes = aa_substitutions
+    .iter()
+    .cloned()
+    .chain(aa_deletions.iter().map(AaSub::from))
+    .collect_vec();
+
+  resistance_rules
+    .iter()
+    .map(|rules| find_drug_resistance_one(rules, &aa_changes))
+    .collect()
+}
+
+fn find_drug_resistance_one(rules: &DrugResistanceRules, aa_changes: &[AaSub]) -> DrugResistance {
+  let mutations = rules
+    .mutations
+    .iter()
+    .filter(|rule| {
+      rule
+        .parsed
+        .iter()
+        .all(|mutation| is_mutation_present(mutation, aa_changes))
+    })
+    .map(|rule| DrugResistanceMutations {
+      mutations: rule.mutations.clone(),
+      score: rule.score,
+    })
+    .collect_vec();
+
+  let score = mutations.iter().map(|muts| muts.score).sum();
+
+  let level = rules
+    .levels
+    .iter()
+    .filter(|level| level.min_score <= score)
+    .max_by_key(|level| OrderedFloat(level.min_score))
+    .map(|DrugResistanceLevelDesc { name, .. }| name.clone());
+
+  DrugResistance {
+    name: rules.name.clone(),
+    score,
+    level,
+    mutations,
+  }
+}
+
+/// Checks whether a mutation (e.g. `NA:H275Y`) is present. Reference amino acid is not compared, because
+/// it is already implied by the position.
+fn is_mutation_present(mutation: &AaSub, aa_changes: &[AaSub]) -> bool {
+  aa_changes.iter().any(|change| {
+    change.cds_name == mutation.cds_name && change.pos == mutation.pos && change.qry_aa == mutation.qry_aa
+  })
+}
+
+/// Names of dynamic CSV columns: resistance call and supporting mutations for each drug
+pub fn get_drug_resistance_keys(resistance_descs: &[DrugResistanceDesc]) -> Vec<String> {
+  resistance_descs
+    .iter()
+    .flat_map(|desc| {
+      [
+        drug_resistance_key(&desc.name),
+        drug_resistance_mutations_key(&desc.name),
+      ]
+    })
+    .collect_vec()
+}
+
+pub fn drug_resistance_key(name: &str) -> String {
+  format!("resistance.{name}")
+}
+
+pub fn drug_resistance_mutations_key(name: &str) -> String {
+  format!("resistance.{name}.mutations")
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+  use super::*;
+  use crate::io::json::json_parse;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  fn resistance_rules() -> Vec<DrugResistanceRules> {
+    parse_drug_resistance_descs(&[resistance_desc()]).unwrap()
+  }
+
+  fn resistance_desc() -> DrugResistanceDesc {
+    json_parse(
+      r#"{
+        "name": "oseltamivir",
+        "nameFriendly": "Oseltamivir",
+        "description": "",
+        "levels": [
+          { "name": "susceptible", "minScore": 0 },
+          { "name": "reduced", "minScore": 10 },
+          { "name": "highlyReduced", "minScore": 100 }
+        ],
+        "mutations": [
+          { "mutations": ["NA:H275Y"], "score": 100 },
+          { "mutations": ["NA:E119V", "NA:I222V"], "score": 30 },
+          { "mutations": ["NA:K150-"], "score": 15 }
+        ]
+      }"#,
+    )
+    .unwrap()
+  }
+
+  fn subs(muts: &[&str]) -> Vec<AaSub> {
+    muts.iter().map(|m| AaSub::from_str(m).unwrap()).collect_vec()
+  }
+
+  #[rstest]
+  fn finds_no_resistance() -> Result<(), Report> {
+    let actual = find_drug_resistance(&resistance_rules(), &subs(&["NA:E119V", "HA:H275Y"]), &[]);
+    assert_eq!(actual[0].score, 0.0);
+    assert_eq!(actual[0].level.as_deref(), Some("susceptible"));
+    assert!(actual[0].mutations.is_empty());
+    Ok(())
+  }
+
+  #[rstest]
+  fn finds_resistance_from_combinations_and_deletions() -> Result<(), Report> {
+    let dels = subs(&["NA:K150-"])
+      .iter()
+      .map(|sub| AaDel {
+        cds_name: sub.cds_name.clone(),
+        pos: sub.pos,
+        ref_aa: sub.ref_aa,
+      })
+      .collect_vec();
+    let actual = find_drug_resistance(&resistance_rules(), &subs(&["NA:E119V", "NA:I222V"]), &dels);
+    assert_eq!(actual[0].score, 45.0);
+    assert_eq!(actual[0].level.as_deref(), Some("reduced"));
+    assert_eq!(
+      actual[0]
+        .mutations
+        .iter()
+        .map(|muts| muts.mutations.join("+"))
+        .collect_vec(),
+      vec!["NA:E119V+NA:I222V", "NA:K150-"]
+    );
+    Ok(())
+  }
+
+  #[rstest]
+  fn reports_malformed_mutations() {
+    let mut desc = resistance_desc();
+    desc.mutations[1].mutations.push("NA:222".to_owned());
+    desc.mutations[2].mutations.clear();
+    let err = parse_drug_resistance_descs(&[desc]).unwrap_err();
+    let message = format!("{err:?}");
+    assert!(message.contains("'NA:222'"), "{message}");
+    assert!(message.contains("group of mutations #3 is empty"), "{message}");
+  }
+}
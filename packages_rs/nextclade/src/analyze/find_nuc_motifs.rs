@@ -0,0 +1,219 @@
+use crate::alphabet::nuc::{from_nuc_seq, Nuc};
+use crate::analyze::virus_properties::NucMotifsDesc;
+use crate::coord::position::{NucRefGlobalPosition, PositionLike};
+use crate::coord::range::{intersect_or_none, NucRefGlobalRange};
+use eyre::{Report, WrapErr};
+use itertools::Itertools;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+pub type NucMotifsMap = BTreeMap<String, Vec<NucMotif>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct NucMotif {
+  pub name: String,
+  pub position: NucRefGlobalPosition,
+  pub seq: String,
+}
+
+/// Motif regexes of one kind of nucleotide motifs, compiled once per run from the description in pathogen.json
+#[derive(Clone, Debug)]
+pub struct NucMotifsMatcher {
+  pub name: String,
+  pub ranges: Vec<NucRefGlobalRange>,
+  pub regexes: Vec<Regex>,
+}
+
+impl NucMotifsMatcher {
+  pub fn from_desc(desc: &NucMotifsDesc) -> Result<Self, Report> {
+    let regexes = desc
+      .motifs
+      .iter()
+      .map(|motif| {
+        let pattern = nuc_motif_to_regex(motif);
+        Regex::new(&pattern).wrap_err_with(|| format!("When compiling motif RegEx '{motif}' (expanded to '{pattern}')"))
+      })
+      .collect::<Result<Vec<Regex>, Report>>()
+      .wrap_err_with(|| format!("When preparing nucleotide motifs '{}'", desc.name))?;
+
+    Ok(Self {
+      name: desc.name.clone(),
+      ranges: desc.ranges.clone(),
+      regexes,
+    })
+  }
+}
+
+/// Compiles regexes of all nucleotide motifs descriptions
+pub fn compile_nuc_motifs_descs(nuc_motifs_descs: &[NucMotifsDesc]) -> Result<Vec<NucMotifsMatcher>, Report> {
+  nuc_motifs_descs.iter().map(NucMotifsMatcher::from_desc).collect()
+}
+
+/// Find motifs in nucleotide sequence (in reference coordinates), given a list of regexes with IUPAC ambiguity codes
+/// (with optional restriction by ranges). This is useful for example to find transcription regulatory sequences.
+pub fn find_nuc_motifs(
+  nuc_motifs_matchers: &[NucMotifsMatcher],
+  seq: &[Nuc],
+  alignment_range: &NucRefGlobalRange,
+) -> NucMotifsMap {
+  nuc_motifs_matchers
+    .iter()
+    .map(|matcher| (matcher.name.clone(), find_nuc_motifs_one(matcher, seq, alignment_range)))
+    .collect()
+}
+
+fn find_nuc_motifs_one(matcher: &NucMotifsMatcher, seq: &[Nuc], alignment_range: &NucRefGlobalRange) -> Vec<NucMotif> {
+  let NucMotifsMatcher { name, ranges, regexes } = matcher;
+
+  // If no ranges specified, search the whole sequence
+  let ranges = if ranges.is_empty() {
+    Cow::Owned(vec![NucRefGlobalRange::from_usize(0, seq.len())])
+  } else {
+    Cow::Borrowed(ranges)
+  };
+
+  ranges
+    .iter()
+    // Trim motif ranges outside alignment range and outside of the sequence
+    .filter_map(|range| intersect_or_none(range, alignment_range))
+    .filter_map(|range| intersect_or_none(&range, &NucRefGlobalRange::from_usize(0, seq.len())))
+    .flat_map(|range| {
+      let seq = from_nuc_seq(&seq[range.to_std()]);
+      regexes
+        .iter()
+        .flat_map(|re| {
+          re.find_iter(&seq)
+            .map(|m| NucMotif {
+              name: name.clone(),
+              position: range.begin + m.start() as isize,
+              seq: m.as_str().to_owned(),
+            })
+            .collect_vec()
+        })
+        .collect_vec()
+    })
+    .sorted()
+    .dedup()
+    .collect_vec()
+}
+
+/// Names of dynamic CSV columns: found motifs, gained motifs and lost motifs for each of the motif kinds
+pub fn get_nuc_motifs_keys(nuc_motifs_descs: &[NucMotifsDesc]) -> Vec<String> {
+  nuc_motifs_descs
+    .iter()
+    .flat_map(|desc| {
+      [
+        desc.name.clone(),
+        nuc_motifs_gained_key(&desc.name),
+        nuc_motifs_lost_key(&desc.name),
+      ]
+    })
+    .collect_vec()
+}
+
+pub fn nuc_motifs_gained_key(name: &str) -> String {
+  format!("{name}.gained")
+}
+
+pub fn nuc_motifs_lost_key(name: &str) -> String {
+  format!("{name}.lost")
+}
+
+/// Converts IUPAC nucleotide ambiguity codes in a motif to regex character classes,
+/// e.g. `ACGAAY` becomes `ACGAA[CT]`. The rest of the regex syntax is preserved.
+pub fn nuc_motif_to_regex(motif: &str) -> String {
+  let mut regex = String::with_capacity(motif.len() * 4);
+  let mut is_in_class = false;
+  let mut is_escaped = false;
+
+  for c in motif.chars() {
+    if is_escaped {
+      regex.push(c);
+      is_escaped = false;
+      continue;
+    }
+
+    match (c, iupac_to_nucs(c)) {
+      ('\\', _) => {
+        is_escaped = true;
+        regex.push(c);
+      }
+      ('[', _) => {
+        is_in_class = true;
+        regex.push(c);
+      }
+      (']', _) => {
+        is_in_class = false;
+        regex.push(c);
+      }
+      (_, Some(nucs)) if is_in_class => regex.push_str(nucs),
+      (_, Some(nucs)) => {
+        regex.push('[');
+        regex.push_str(nucs);
+        regex.push(']');
+      }
+      (_, None) => regex.push(c),
+    }
+  }
+
+  regex
+}
+
+const fn iupac_to_nucs(c: char) -> Option<&'static str> {
+  match c {
+    'R' => Some("AG"),
+    'Y' => Some("CT"),
+    'S' => Some("CG"),
+    'W' => Some("AT"),
+    'K' => Some("GT"),
+    'M' => Some("AC"),
+    'B' => Some("CGT"),
+    'D' => Some("AGT"),
+    'H' => Some("ACT"),
+    'V' => Some("ACG"),
+    'N' => Some("ACGT"),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::alphabet::nuc::to_nuc_seq;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  #[rstest]
+  #[case("ACGAAC", "ACGAAC")]
+  #[case("ACGAAY", "ACGAA[CT]")]
+  #[case("CG(N{2})?CG", "CG([ACGT]{2})?CG")]
+  #[case("[RT]GA", "[AGT]GA")]
+  #[case(r"A\SC", r"A\SC")]
+  fn converts_iupac_motif_to_regex(#[case] motif: &str, #[case] expected: &str) {
+    assert_eq!(nuc_motif_to_regex(motif), expected);
+  }
+
+  #[rstest]
+  fn finds_nuc_motifs_within_ranges() -> Result<(), Report> {
+    let desc = NucMotifsDesc {
+      name: "trs".to_owned(),
+      name_short: "TRS".to_owned(),
+      name_friendly: "TRS".to_owned(),
+      description: String::new(),
+      motifs: vec!["ACGAAY".to_owned()],
+      ranges: vec![NucRefGlobalRange::from_usize(5, 30)],
+    };
+    let seq = to_nuc_seq("ACGAACTTACGAATGGACGAACGGGACGAAC")?;
+    let aln_range = NucRefGlobalRange::from_usize(0, seq.len());
+    let actual = find_nuc_motifs(&compile_nuc_motifs_descs(&[desc])?, &seq, &aln_range);
+    let actual = actual["trs"]
+      .iter()
+      .map(|m| (m.position.as_usize(), m.seq.as_str()))
+      .collect_vec();
+    assert_eq!(actual, vec![(8, "ACGAAT"), (16, "ACGAAC")]);
+    Ok(())
+  }
+}
@@ -0,0 +1,190 @@
+use crate::alphabet::nuc::{from_nuc_seq, Nuc};
+use crate::analyze::find_nuc_motifs::{NucMotif, NucMotifsMap};
+use crate::coord::position::{NucRefGlobalPosition, PositionLike};
+use crate::coord::range::NucRefGlobalRange;
+use itertools::{Either, Itertools};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub type NucMotifsChangesMap = BTreeMap<String, NucMotifChanges>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NucMotifChanges {
+  pub preserved: Vec<NucMotifMutation>,
+  pub gained: Vec<NucMotifMutation>,
+  pub lost: Vec<NucMotifMutation>,
+  pub ambiguous: Vec<NucMotifMutation>,
+  pub total: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema, Ord, PartialOrd, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NucMotifMutation {
+  pub name: String,
+  pub position: NucRefGlobalPosition,
+  pub ref_seq: String,
+  pub qry_seq: String,
+}
+
+/// Find changes between nucleotide motifs in reference and in query sequence
+pub fn find_nuc_motifs_changes(
+  nuc_motifs_ref: &NucMotifsMap,
+  nuc_motifs_qry: &NucMotifsMap,
+  ref_seq: &[Nuc],
+  qry_seq: &[Nuc],
+  alignment_range: &NucRefGlobalRange,
+) -> NucMotifsChangesMap {
+  nuc_motifs_qry
+    .iter()
+    .map(|(name, motifs_qry)| {
+      let motifs_ref = nuc_motifs_ref.get(name).map_or(&[] as &[NucMotif], Vec::as_slice);
+      let changes = find_nuc_motifs_changes_one(motifs_ref, motifs_qry, ref_seq, qry_seq, alignment_range);
+      (name.clone(), changes)
+    })
+    .collect()
+}
+
+fn find_nuc_motifs_changes_one(
+  motifs_ref: &[NucMotif],
+  motifs_qry: &[NucMotif],
+  ref_seq: &[Nuc],
+  qry_seq: &[Nuc],
+  alignment_range: &NucRefGlobalRange,
+) -> NucMotifChanges {
+  // Motifs are compared by position only, disregarding the matched sequence
+  let positions_ref: BTreeSet<NucRefGlobalPosition> = motifs_ref.iter().map(|motif| motif.position).collect();
+  let positions_qry: BTreeSet<NucRefGlobalPosition> = motifs_qry.iter().map(|motif| motif.position).collect();
+
+  // Gained motifs: not present in ref, present in qry
+  let gained = motifs_qry
+    .iter()
+    .filter(|motif| !positions_ref.contains(&motif.position))
+    .map(|motif| NucMotifMutation {
+      name: motif.name.clone(),
+      position: motif.position,
+      ref_seq: extract_seq(ref_seq, motif.position, motif.seq.len()),
+      qry_seq: motif.seq.clone(),
+    })
+    .sorted()
+    .collect_vec();
+
+  // Lost motifs: present in ref, not present in query.
+  // Ambiguous motifs: present in ref, contain nucleotide N in query.
+  // Motifs not fully covered by the alignment are ignored.
+  let (lost, ambiguous): (Vec<NucMotifMutation>, Vec<NucMotifMutation>) = motifs_ref
+    .iter()
+    .filter(|motif| !positions_qry.contains(&motif.position))
+    .filter(|motif| {
+      let end = motif.position + motif.seq.len() as isize;
+      alignment_range.contains(motif.position) && end <= alignment_range.end
+    })
+    .map(|motif| NucMotifMutation {
+      name: motif.name.clone(),
+      position: motif.position,
+      ref_seq: motif.seq.clone(),
+      qry_seq: extract_seq(qry_seq, motif.position, motif.seq.len()),
+    })
+    .sorted()
+    .partition_map(|motif_change| {
+      if motif_change.qry_seq.contains('N') {
+        Either::Right(motif_change)
+      } else {
+        Either::Left(motif_change)
+      }
+    });
+
+  // Preserved motifs: present in ref and qry
+  let preserved = motifs_qry
+    .iter()
+    .filter(|motif| positions_ref.contains(&motif.position))
+    .map(|motif| NucMotifMutation {
+      name: motif.name.clone(),
+      position: motif.position,
+      ref_seq: extract_seq(ref_seq, motif.position, motif.seq.len()),
+      qry_seq: motif.seq.clone(),
+    })
+    .sorted()
+    .collect_vec();
+
+  let total = gained.len() + preserved.len();
+
+  NucMotifChanges {
+    preserved,
+    gained,
+    lost,
+    ambiguous,
+    total,
+  }
+}
+
+fn extract_seq(seq: &[Nuc], position: NucRefGlobalPosition, len: usize) -> String {
+  let begin = position.as_usize().min(seq.len());
+  let end = (begin + len).min(seq.len());
+  from_nuc_seq(&seq[begin..end])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::alphabet::nuc::to_nuc_seq;
+  use eyre::Report;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  fn motifs(motifs: &[(usize, &str)]) -> NucMotifsMap {
+    let motifs = motifs
+      .iter()
+      .map(|&(position, seq)| NucMotif {
+        name: "trs".to_owned(),
+        position: position.into(),
+        seq: seq.to_owned(),
+      })
+      .collect_vec();
+    BTreeMap::from([("trs".to_owned(), motifs)])
+  }
+
+  fn summarize(muts: &[NucMotifMutation]) -> Vec<(usize, &str, &str)> {
+    muts
+      .iter()
+      .map(|m| (m.position.as_usize(), m.ref_seq.as_str(), m.qry_seq.as_str()))
+      .collect_vec()
+  }
+
+  #[rstest]
+  fn finds_gained_lost_preserved_and_ambiguous_motifs() -> Result<(), Report> {
+    //                       0     6     12    18    24
+    let ref_seq = to_nuc_seq("ACGAACTTTTTTACGAACACGAACTTTTTT")?;
+    let qry_seq = to_nuc_seq("ACGAACTTTTTTTCGAACACGNACACGAAT")?;
+    let ref_motifs = motifs(&[(0, "ACGAAC"), (12, "ACGAAC"), (18, "ACGAAC")]);
+    let qry_motifs = motifs(&[(0, "ACGAAC"), (24, "ACGAAT")]);
+    let aln_range = NucRefGlobalRange::from_usize(0, qry_seq.len());
+
+    let actual = find_nuc_motifs_changes(&ref_motifs, &qry_motifs, &ref_seq, &qry_seq, &aln_range);
+    let changes = &actual["trs"];
+
+    assert_eq!(summarize(&changes.preserved), vec![(0, "ACGAAC", "ACGAAC")]);
+    assert_eq!(summarize(&changes.gained), vec![(24, "TTTTTT", "ACGAAT")]);
+    assert_eq!(summarize(&changes.lost), vec![(12, "ACGAAC", "TCGAAC")]);
+    assert_eq!(summarize(&changes.ambiguous), vec![(18, "ACGAAC", "ACGNAC")]);
+    assert_eq!(changes.total, 2);
+    Ok(())
+  }
+
+  #[rstest]
+  fn ignores_lost_motifs_not_fully_covered_by_alignment() -> Result<(), Report> {
+    let ref_seq = to_nuc_seq("ACGAACTTTTTTACGAAC")?;
+    let qry_seq = to_nuc_seq("NNNAACTTTTTTACGNNN")?;
+    let ref_motifs = motifs(&[(0, "ACGAAC"), (12, "ACGAAC")]);
+    let qry_motifs = motifs(&[]);
+    let aln_range = NucRefGlobalRange::from_usize(3, 15);
+
+    let actual = find_nuc_motifs_changes(&ref_motifs, &qry_motifs, &ref_seq, &qry_seq, &aln_range);
+    let changes = &actual["trs"];
+
+    assert!(changes.lost.is_empty());
+    assert!(changes.ambiguous.is_empty());
+    assert_eq!(changes.total, 0);
+    Ok(())
+  }
+}
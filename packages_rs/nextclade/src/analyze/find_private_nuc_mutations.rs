@@ -4,7 +4,7 @@ use crate::analyze::aa_sub::AaSub;
 use crate::analyze::is_sequenced::{is_nuc_non_acgtn, is_nuc_sequenced};
 use crate::analyze::letter_ranges::NucRange;
 use crate::analyze::nuc_del::{NucDel, NucDelRange};
-use crate::analyze::nuc_sub::{NucSub, NucSubLabeled};
+use crate::analyze::nuc_sub::{NucSub, NucSubLabeled, NucSubSeenInTree};
 use crate::analyze::virus_properties::{NucLabelMap, VirusProperties};
 use crate::coord::position::{NucRefGlobalPosition, PositionLike};
 use crate::coord::range::NucRefGlobalRange;
@@ -51,11 +51,23 @@ pub struct PrivateNucMutations {
   /// A subset of `private_substitutions` which has no label
   pub unlabeled_substitutions: Vec<NucSub>,
 
+  /// A subset of `private_substitutions` which also occur on branches of the reference tree (homoplasies)
+  #[serde(default)]
+  pub seen_in_tree_substitutions: Vec<NucSubSeenInTree>,
+
+  /// A subset of `private_substitutions` which do not occur anywhere on the reference tree
+  #[serde(default)]
+  pub novel_substitutions: Vec<NucSub>,
+
   pub total_private_substitutions: usize,
   pub total_private_deletions: usize,
   pub total_reversion_substitutions: usize,
   pub total_labeled_substitutions: usize,
   pub total_unlabeled_substitutions: usize,
+  #[serde(default)]
+  pub total_seen_in_tree_substitutions: usize,
+  #[serde(default)]
+  pub total_novel_substitutions: usize,
 }
 
 /// Finds private mutations.
@@ -87,6 +99,7 @@ pub fn find_private_nuc_mutations(
   ref_seq: &[Nuc],
   non_acgtns: &[NucRange],
   virus_properties: &VirusProperties,
+  nuc_sub_branch_counts: &BTreeMap<NucSub, usize>,
 ) -> PrivateNucMutations {
   let node_mut_map = &node.tmp.mutations;
 
@@ -125,6 +138,9 @@ pub fn find_private_nuc_mutations(
   private_substitutions.sort();
   private_substitutions.dedup();
 
+  let (seen_in_tree_substitutions, novel_substitutions) =
+    find_homoplasies(&private_substitutions, nuc_sub_branch_counts);
+
   let mut private_deletions = non_reversion_deletions;
   private_deletions.sort();
   private_deletions.dedup();
@@ -134,6 +150,8 @@ pub fn find_private_nuc_mutations(
   let total_reversion_substitutions = reversion_substitutions.len();
   let total_labeled_substitutions = labeled_substitutions.len();
   let total_unlabeled_substitutions = unlabeled_substitutions.len();
+  let total_seen_in_tree_substitutions = seen_in_tree_substitutions.len();
+  let total_novel_substitutions = novel_substitutions.len();
 
   // TODO: Do something with the undeletions, they are not returned from this function
 
@@ -143,11 +161,15 @@ pub fn find_private_nuc_mutations(
     reversion_substitutions,
     labeled_substitutions,
     unlabeled_substitutions,
+    seen_in_tree_substitutions,
+    novel_substitutions,
     total_private_substitutions,
     total_private_deletions,
     total_reversion_substitutions,
     total_labeled_substitutions,
     total_unlabeled_substitutions,
+    total_seen_in_tree_substitutions,
+    total_novel_substitutions,
   }
 }
 
@@ -317,3 +339,56 @@ fn label_private_mutations(
 
   (labeled_substitutions, unlabeled_substitutions)
 }
+
+/// Subdivides private substitutions into the ones which are also found on branches of the reference tree
+/// (homoplasies), annotated with the number of such branches, and the novel ones, never seen in the tree.
+fn find_homoplasies(
+  private_substitutions: &[NucSub],
+  nuc_sub_branch_counts: &BTreeMap<NucSub, usize>,
+) -> (Vec<NucSubSeenInTree>, Vec<NucSub>) {
+  let mut seen_in_tree_substitutions = Vec::<NucSubSeenInTree>::new();
+  let mut novel_substitutions = Vec::<NucSub>::new();
+
+  for substitution in private_substitutions {
+    match nuc_sub_branch_counts.get(substitution) {
+      Some(&tree_occurrences) if tree_occurrences > 0 => seen_in_tree_substitutions.push(NucSubSeenInTree {
+        substitution: substitution.clone(),
+        tree_occurrences,
+      }),
+      _ => novel_substitutions.push(substitution.clone()),
+    }
+  }
+
+  (seen_in_tree_substitutions, novel_substitutions)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use maplit::btreemap;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+  use std::str::FromStr;
+
+  #[rstest]
+  fn splits_private_substitutions_into_seen_in_tree_and_novel() {
+    let sub = |s: &str| NucSub::from_str(s).unwrap();
+
+    let private_substitutions = vec![sub("C241T"), sub("A1000G"), sub("G3000T")];
+    let nuc_sub_branch_counts = btreemap! {
+      sub("C241T") => 3,
+      sub("A1000C") => 1,
+    };
+
+    let (seen_in_tree, novel) = find_homoplasies(&private_substitutions, &nuc_sub_branch_counts);
+
+    assert_eq!(
+      seen_in_tree,
+      vec![NucSubSeenInTree {
+        substitution: sub("C241T"),
+        tree_occurrences: 3
+      }]
+    );
+    assert_eq!(novel, vec![sub("A1000G"), sub("G3000T")]);
+  }
+}
@@ -0,0 +1,231 @@
+use crate::alphabet::nuc::Nuc;
+use crate::analyze::aa_del::AaDel;
+use crate::analyze::aa_sub::AaSub;
+use crate::analyze::find_private_aa_mutations::{find_private_aa_mutations, PrivateAaMutations};
+use crate::analyze::find_private_nuc_mutations::{find_private_nuc_mutations, PrivateNucMutations};
+use crate::analyze::letter_ranges::{CdsAaRange, NucRange};
+use crate::analyze::nuc_del::NucDelRange;
+use crate::analyze::nuc_sub::NucSub;
+use crate::analyze::virus_properties::VirusProperties;
+use crate::coord::range::{AaRefRange, NucRefGlobalRange};
+use crate::gene::gene_map::GeneMap;
+use crate::graph::node::GraphNodeKey;
+use crate::make_error;
+use crate::translate::translate_genes::Translation;
+use crate::tree::tree::AuspiceGraph;
+use eyre::Report;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Mutations of the query sequence relative to one of the reference nodes of the tree (e.g. a clade founder or a
+/// vaccine strain), rather than relative to the nearest node.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RelativeMutations {
+  pub ref_node_name: String,
+  pub nuc_muts: PrivateNucMutations,
+  pub aa_muts: BTreeMap<String, PrivateAaMutations>,
+}
+
+/// Finds keys of tree nodes given their names. All nodes must exist in the tree.
+pub fn find_ref_node_keys(graph: &AuspiceGraph, ref_node_names: &[String]) -> Result<Vec<GraphNodeKey>, Report> {
+  ref_node_names
+    .iter()
+    .map(|name| {
+      let node = graph.iter_nodes().find(|node| &node.payload().name == name);
+      match node {
+        Some(node) => Ok(node.key()),
+        None => make_error!("Reference node '{name}' is not found in the reference tree"),
+      }
+    })
+    .collect()
+}
+
+/// Finds mutations of the query sequence relative to each of the given reference nodes.
+///
+/// The mutations are found the same way as private mutations are found relative to the nearest node, so that
+/// missing and unsequenced regions of the query sequence are not reported as differences.
+pub fn find_relative_mutations(
+  graph: &AuspiceGraph,
+  ref_node_keys: &[GraphNodeKey],
+  substitutions: &[NucSub],
+  deletions: &[NucDelRange],
+  missing: &[NucRange],
+  alignment_range: &NucRefGlobalRange,
+  ref_seq: &[Nuc],
+  non_acgtns: &[NucRange],
+  aa_substitutions: &[AaSub],
+  aa_deletions: &[AaDel],
+  unknown_aa_ranges: &[CdsAaRange],
+  aa_unsequenced_ranges: &BTreeMap<String, Vec<AaRefRange>>,
+  ref_translation: &Translation,
+  gene_map: &GeneMap,
+  virus_properties: &VirusProperties,
+) -> Result<Vec<RelativeMutations>, Report> {
+  ref_node_keys
+    .iter()
+    .map(|&ref_node_key| {
+      let ref_node = graph.get_node(ref_node_key)?.payload();
+
+      let nuc_muts = find_private_nuc_mutations(
+        ref_node,
+        substitutions,
+        deletions,
+        missing,
+        alignment_range,
+        ref_seq,
+        non_acgtns,
+        virus_properties,
+        &graph.data.tmp.nuc_sub_branch_counts,
+      );
+
+      let aa_muts = find_private_aa_mutations(
+        ref_node,
+        aa_substitutions,
+        aa_deletions,
+        unknown_aa_ranges,
+        aa_unsequenced_ranges,
+        ref_translation,
+        gene_map,
+      );
+
+      Ok(RelativeMutations {
+        ref_node_name: ref_node.name.clone(),
+        nuc_muts,
+        aa_muts,
+      })
+    })
+    .collect()
+}
+
+/// Names of dynamic CSV columns: nucleotide and aminoacid mutations relative to each of the reference nodes
+pub fn get_relative_mutations_keys(ref_node_names: &[String]) -> Vec<String> {
+  ref_node_names
+    .iter()
+    .flat_map(|name| {
+      [
+        relative_nuc_substitutions_key(name),
+        relative_nuc_deletions_key(name),
+        relative_aa_substitutions_key(name),
+        relative_aa_deletions_key(name),
+      ]
+    })
+    .collect_vec()
+}
+
+pub fn relative_nuc_substitutions_key(ref_node_name: &str) -> String {
+  format!("relativeMutations.{ref_node_name}.substitutions")
+}
+
+pub fn relative_nuc_deletions_key(ref_node_name: &str) -> String {
+  format!("relativeMutations.{ref_node_name}.deletions")
+}
+
+pub fn relative_aa_substitutions_key(ref_node_name: &str) -> String {
+  format!("relativeMutations.{ref_node_name}.aaSubstitutions")
+}
+
+pub fn relative_aa_deletions_key(ref_node_name: &str) -> String {
+  format!("relativeMutations.{ref_node_name}.aaDeletions")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::alphabet::nuc::to_nuc_seq;
+  use crate::graph::graph::convert_auspice_tree_to_graph;
+  use crate::io::json::json_parse;
+  use crate::tree::tree::AuspiceTree;
+  use crate::tree::tree_preprocess::graph_preprocess_in_place;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+  use serde_json::json;
+  use std::str::FromStr;
+
+  const REF_SEQ: &str = "ACGTGCATGC";
+
+  fn graph() -> Result<AuspiceGraph, Report> {
+    let tree: AuspiceTree = serde_json::from_value(json!({
+      "meta": {},
+      "tree": {
+        "name": "root",
+        "branch_attrs": { "mutations": {} },
+        "node_attrs": { "clade_membership": { "value": "A" } },
+        "children": [{
+          "name": "vaccine",
+          "branch_attrs": { "mutations": { "nuc": ["A1T", "G5C", "C10A"] } },
+          "node_attrs": { "clade_membership": { "value": "B" } },
+        }],
+      },
+    }))?;
+    let mut graph = convert_auspice_tree_to_graph(tree)?;
+    graph_preprocess_in_place(&mut graph, &to_nuc_seq(REF_SEQ)?, &Translation::default())?;
+    Ok(graph)
+  }
+
+  fn virus_properties() -> VirusProperties {
+    json_parse(r#"{ "schemaVersion": "3.0.0", "files": { "reference": "", "pathogenJson": "" } }"#).unwrap()
+  }
+
+  #[rstest]
+  #[case::fully_sequenced(&[], (0, 10), &["T1A", "C2G", "C5G", "A10C"])]
+  #[case::missing_data_is_not_a_reversion(&[(4, 5)], (0, 10), &["T1A", "C2G", "A10C"])]
+  #[case::unsequenced_region_is_not_a_reversion(&[], (0, 8), &["T1A", "C2G", "C5G"])]
+  fn finds_mutations_relative_to_ref_node(
+    #[case] missing: &[(usize, usize)],
+    #[case] alignment_range: (usize, usize),
+    #[case] expected: &[&str],
+  ) -> Result<(), Report> {
+    let graph = graph()?;
+    let ref_seq = to_nuc_seq(REF_SEQ)?;
+    let ref_node_keys = find_ref_node_keys(&graph, &["vaccine".to_owned()])?;
+
+    let missing = missing
+      .iter()
+      .map(|&(begin, end)| NucRange {
+        range: NucRefGlobalRange::from_usize(begin, end),
+        letter: Nuc::N,
+      })
+      .collect_vec();
+    let alignment_range = NucRefGlobalRange::from_usize(alignment_range.0, alignment_range.1);
+
+    let actual = find_relative_mutations(
+      &graph,
+      &ref_node_keys,
+      &[NucSub::from_str("C2G")?],
+      &[],
+      &missing,
+      &alignment_range,
+      &ref_seq,
+      &[],
+      &[],
+      &[],
+      &[],
+      &BTreeMap::new(),
+      &Translation::default(),
+      &GeneMap::new(),
+      &virus_properties(),
+    )?;
+
+    assert_eq!(actual.len(), 1);
+    assert_eq!(actual[0].ref_node_name, "vaccine");
+    assert_eq!(
+      actual[0]
+        .nuc_muts
+        .private_substitutions
+        .iter()
+        .map(ToString::to_string)
+        .collect_vec(),
+      expected.iter().map(|&sub| sub.to_owned()).collect_vec()
+    );
+    Ok(())
+  }
+
+  #[rstest]
+  fn fails_on_unknown_ref_node() -> Result<(), Report> {
+    let graph = graph()?;
+    let _: Report = find_ref_node_keys(&graph, &["nonexistent".to_owned()]).unwrap_err();
+    Ok(())
+  }
+}
@@ -2,18 +2,24 @@ pub mod aa_changes;
 pub mod aa_del;
 pub mod aa_sub;
 pub mod abstract_mutation;
+pub mod cds_coverage;
 pub mod count_gaps;
 pub mod divergence;
+pub mod drug_resistance;
 pub mod find_aa_motifs;
 pub mod find_aa_motifs_changes;
+pub mod find_nuc_motifs;
+pub mod find_nuc_motifs_changes;
 pub mod find_private_aa_mutations;
 pub mod find_private_nuc_mutations;
+pub mod find_relative_mutations;
 pub mod is_sequenced;
 pub mod letter_composition;
 pub mod letter_ranges;
 pub mod nuc_changes;
 pub mod nuc_del;
 pub mod nuc_sub;
+pub mod pairwise_distances;
 pub mod pcr_primer_changes;
 pub mod pcr_primers;
 pub mod phenotype;
@@ -133,3 +133,11 @@ pub struct NucSubLabeled {
   pub substitution: NucSub,
   pub labels: Vec<String>,
 }
+
+/// Substitution which also occurs on branches of the reference tree, along with the number of such branches
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NucSubSeenInTree {
+  pub substitution: NucSub,
+  pub tree_occurrences: usize,
+}
@@ -0,0 +1,244 @@
+use crate::alphabet::nuc::Nuc;
+use crate::analyze::is_sequenced::{is_nuc_non_acgtn, is_nuc_sequenced};
+use crate::analyze::letter_ranges::NucRange;
+use crate::analyze::nuc_sub::NucSub;
+use crate::coord::position::NucRefGlobalPosition;
+use crate::coord::range::NucRefGlobalRange;
+use crate::io::csv::{CsvVecFileWriter, VecWriter};
+use crate::types::outputs::NextcladeOutputs;
+use crate::utils::vec2d::Vec2d;
+use eyre::{Report, WrapErr};
+use itertools::{chain, Itertools};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Pairwise SNP distances between query sequences and single-linkage clusters of the query sequences
+pub struct PairwiseDistances {
+  pub seq_names: Vec<String>,
+  pub distances: Vec2d<usize>,
+  pub clusters: Vec<usize>,
+}
+
+/// Nucleotide states of a query sequence, which are needed for comparison with other query sequences
+struct SnpProfile<'a> {
+  substitutions: BTreeMap<NucRefGlobalPosition, Nuc>,
+  missing: &'a [NucRange],
+  non_acgtns: &'a [NucRange],
+  alignment_range: &'a NucRefGlobalRange,
+  masked_ranges: &'a [NucRefGlobalRange],
+}
+
+impl<'a> SnpProfile<'a> {
+  fn new(
+    substitutions: &[NucSub],
+    missing: &'a [NucRange],
+    non_acgtns: &'a [NucRange],
+    alignment_range: &'a NucRefGlobalRange,
+    masked_ranges: &'a [NucRefGlobalRange],
+  ) -> Self {
+    let substitutions = substitutions
+      .iter()
+      .filter(|sub| !masked_ranges.iter().any(|range| range.contains(sub.pos)))
+      .map(|sub| (sub.pos, sub.qry_nuc))
+      .collect();
+
+    Self {
+      substitutions,
+      missing,
+      non_acgtns,
+      alignment_range,
+      masked_ranges,
+    }
+  }
+
+  /// Decides whether the nucleotide at a given position is known, i.e. the position is sequenced, is not masked and
+  /// does not contain an ambiguous nucleotide
+  fn is_known(&self, pos: NucRefGlobalPosition) -> bool {
+    is_nuc_sequenced(pos, self.missing, self.alignment_range)
+      && !is_nuc_non_acgtn(pos, self.non_acgtns)
+      && !self.masked_ranges.iter().any(|range| range.contains(pos))
+  }
+}
+
+/// Calculates number of nucleotide differences between two query sequences.
+///
+/// Only the positions known in both sequences are compared: missing, unsequenced, ambiguous and masked positions in
+/// either of the sequences are not counted as differences.
+fn calculate_snp_distance(left: &SnpProfile, right: &SnpProfile) -> usize {
+  let left_diffs = left
+    .substitutions
+    .iter()
+    .filter(|(pos, nuc)| match right.substitutions.get(pos) {
+      // Both sequences are mutated at this position
+      Some(right_nuc) => right_nuc != *nuc,
+      // Only left sequence is mutated, so the right one has reference nucleotide, if known
+      None => right.is_known(**pos),
+    })
+    .count();
+
+  // Positions mutated in both sequences are already counted above
+  let right_diffs = right
+    .substitutions
+    .keys()
+    .filter(|pos| !left.substitutions.contains_key(pos) && left.is_known(**pos))
+    .count();
+
+  left_diffs + right_diffs
+}
+
+/// Calculates matrix of pairwise SNP distances between query sequences and groups the sequences into single-linkage
+/// clusters: two sequences belong to the same cluster if they are connected by a chain of sequences, with each
+/// neighbouring pair not exceeding the distance threshold.
+///
+/// Clusters are numbered from 1, in order of their first appearance in the input.
+pub fn calculate_pairwise_distances(
+  outputs: &[NextcladeOutputs],
+  masked_ranges: &[NucRefGlobalRange],
+  cluster_distance_threshold: usize,
+) -> PairwiseDistances {
+  let profiles = outputs
+    .iter()
+    .map(|output| {
+      SnpProfile::new(
+        &output.substitutions,
+        &output.missing,
+        &output.non_acgtns,
+        &output.alignment_range,
+        masked_ranges,
+      )
+    })
+    .collect_vec();
+
+  let (distances, clusters) = calculate_distances_and_clusters(&profiles, cluster_distance_threshold);
+
+  PairwiseDistances {
+    seq_names: outputs.iter().map(|output| output.seq_name.clone()).collect_vec(),
+    distances,
+    clusters,
+  }
+}
+
+fn calculate_distances_and_clusters(
+  profiles: &[SnpProfile],
+  cluster_distance_threshold: usize,
+) -> (Vec2d<usize>, Vec<usize>) {
+  let n = profiles.len();
+  let mut distances = Vec2d::<usize>::new(n, n);
+  let mut cluster_roots = (0..n).collect_vec();
+  for i in 0..n {
+    for j in (i + 1)..n {
+      let distance = calculate_snp_distance(&profiles[i], &profiles[j]);
+      distances[(i, j)] = distance;
+      distances[(j, i)] = distance;
+      if distance <= cluster_distance_threshold {
+        union(&mut cluster_roots, i, j);
+      }
+    }
+  }
+
+  let mut cluster_ids = BTreeMap::<usize, usize>::new();
+  let clusters = (0..n)
+    .map(|i| {
+      let root = find_root(&mut cluster_roots, i);
+      let next_id = cluster_ids.len() + 1;
+      *cluster_ids.entry(root).or_insert(next_id)
+    })
+    .collect_vec();
+
+  (distances, clusters)
+}
+
+fn find_root(roots: &mut [usize], mut i: usize) -> usize {
+  while roots[i] != i {
+    roots[i] = roots[roots[i]];
+    i = roots[i];
+  }
+  i
+}
+
+fn union(roots: &mut [usize], i: usize, j: usize) {
+  let root_i = find_root(roots, i);
+  let root_j = find_root(roots, j);
+  if root_i != root_j {
+    roots[root_j.max(root_i)] = root_i.min(root_j);
+  }
+}
+
+/// Writes pairwise distance matrix as TSV. Each row contains sequence name, its cluster and distances to each of the
+/// sequences.
+pub fn pairwise_distances_write_tsv(
+  filepath: impl AsRef<Path>,
+  pairwise_distances: &PairwiseDistances,
+) -> Result<(), Report> {
+  let filepath = filepath.as_ref();
+  let PairwiseDistances {
+    seq_names,
+    distances,
+    clusters,
+  } = pairwise_distances;
+
+  let headers = chain!(["seqName".to_owned(), "cluster".to_owned()], seq_names.iter().cloned()).collect_vec();
+  let mut writer = CsvVecFileWriter::new(filepath, b'\t', &headers)?;
+  for (i, seq_name) in seq_names.iter().enumerate() {
+    let row = chain!(
+      [seq_name.clone(), clusters[i].to_string()],
+      (0..seq_names.len()).map(|j| distances[(i, j)].to_string())
+    );
+    writer
+      .write(row)
+      .wrap_err_with(|| format!("When writing pairwise distances to '{filepath:#?}'"))?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use eyre::Report;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  fn subs(subs: &[(usize, Nuc)]) -> Vec<NucSub> {
+    subs
+      .iter()
+      .map(|&(pos, qry_nuc)| NucSub {
+        pos: pos.into(),
+        ref_nuc: Nuc::A,
+        qry_nuc,
+      })
+      .collect()
+  }
+
+  #[rstest]
+  fn calculates_distances_and_clusters() -> Result<(), Report> {
+    let alignment_range = NucRefGlobalRange::from_usize(0, 100);
+    let missing = vec![NucRange {
+      range: NucRefGlobalRange::from_usize(5, 15),
+      letter: Nuc::N,
+    }];
+    let masked = vec![NucRefGlobalRange::from_usize(50, 51)];
+
+    let a = subs(&[(10, Nuc::T)]);
+    let b = subs(&[(10, Nuc::T), (20, Nuc::G)]);
+    let c = subs(&[(10, Nuc::C), (20, Nuc::G), (30, Nuc::G)]);
+    // Missing position 10 and masked position 50 are not counted
+    let d = subs(&[(50, Nuc::C), (60, Nuc::T), (70, Nuc::T), (80, Nuc::T)]);
+
+    let profiles = [
+      SnpProfile::new(&a, &[], &[], &alignment_range, &masked),
+      SnpProfile::new(&b, &[], &[], &alignment_range, &masked),
+      SnpProfile::new(&c, &[], &[], &alignment_range, &masked),
+      SnpProfile::new(&d, &missing, &[], &alignment_range, &masked),
+    ];
+
+    let (distances, clusters) = calculate_distances_and_clusters(&profiles, 1);
+
+    assert_eq!(distances[(0, 1)], 1_usize);
+    assert_eq!(distances[(1, 2)], 2_usize);
+    assert_eq!(distances[(0, 3)], 3_usize);
+    assert_eq!(distances[(3, 0)], 3_usize);
+    assert_eq!(distances[(2, 3)], 5_usize);
+    assert_eq!(clusters, vec![1, 1, 2, 3]);
+    Ok(())
+  }
+}
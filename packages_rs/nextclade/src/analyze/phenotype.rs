@@ -1,27 +1,96 @@
+use crate::align::insertions_strip::AaIns;
+use crate::alphabet::aa::Aa;
+use crate::analyze::aa_del::AaDel;
 use crate::analyze::aa_sub::AaSub;
-use crate::analyze::virus_properties::{PhenotypeAttrDesc, PhenotypeData, VirusProperties};
+use crate::analyze::virus_properties::{
+  PhenotypeAttrDesc, PhenotypeData, PhenotypeInteraction, PhenotypeInteractionMutation, PhenotypeLinkFunction,
+  VirusProperties,
+};
+use crate::coord::position::AaRefPosition;
 use itertools::Itertools;
 use num_traits::real::Real;
 
-pub fn calculate_phenotype(phenotype_data: &PhenotypeData, aa_substitutions: &[AaSub]) -> f64 {
+pub fn calculate_phenotype(
+  phenotype_data: &PhenotypeData,
+  aa_substitutions: &[AaSub],
+  aa_deletions: &[AaDel],
+  aa_insertions: &[AaIns],
+) -> f64 {
+  let is_in_range = |cds: &str, pos: AaRefPosition| cds == phenotype_data.cds && phenotype_data.aa_range.contains(pos);
+
   let aa_substitutions = aa_substitutions
     .iter()
-    .filter_map(|sub| (sub.cds_name == phenotype_data.cds && phenotype_data.aa_range.contains(sub.pos)).then_some(sub))
+    .filter(|sub| is_in_range(&sub.cds_name, sub.pos))
     .collect_vec();
 
-  let phenotype: f64 = phenotype_data
-    .data
+  let aa_deletions = aa_deletions
     .iter()
-    .map(|phenotype_data| {
-      let phenotype_for_antibody: f64 = aa_substitutions
-        .iter()
-        .map(|AaSub { pos, qry_aa: qry, .. }| phenotype_data.get_coeff(*pos, *qry))
-        .sum();
-      phenotype_data.weight * (-phenotype_for_antibody).exp()
-    })
-    .sum();
+    .filter(|del| is_in_range(&del.cds_name, del.pos))
+    .collect_vec();
+
+  let aa_insertions = aa_insertions
+    .iter()
+    .map(|ins| (&ins.cds, AaRefPosition::from(ins.pos)))
+    .filter(|(cds, pos)| is_in_range(cds, *pos))
+    .collect_vec();
 
-  -phenotype.ln()
+  let weighted_scores = phenotype_data.data.iter().map(|entry| {
+    let score_subs: f64 = aa_substitutions
+      .iter()
+      .map(|AaSub { pos, qry_aa: qry, .. }| entry.get_coeff(*pos, *qry))
+      .sum();
+
+    let score_dels: f64 = aa_deletions.iter().map(|del| entry.get_deletion_coeff(del.pos)).sum();
+
+    let score_ins: f64 = aa_insertions
+      .iter()
+      .map(|(_, pos)| entry.get_insertion_coeff(*pos))
+      .sum();
+
+    let score_interactions: f64 = entry
+      .interactions
+      .iter()
+      .filter(|interaction| is_interaction_present(interaction, &aa_substitutions, &aa_deletions))
+      .map(|interaction| interaction.coeff)
+      .sum();
+
+    let score = entry.intercept + score_subs + score_dels + score_ins + score_interactions;
+    (entry.weight, score)
+  });
+
+  apply_link_function(phenotype_data.link, weighted_scores)
+}
+
+/// Interaction term is present when every one of its mutations is found in the query
+fn is_interaction_present(
+  interaction: &PhenotypeInteraction,
+  aa_substitutions: &[&AaSub],
+  aa_deletions: &[&AaDel],
+) -> bool {
+  !interaction.mutations.is_empty()
+    && interaction
+      .mutations
+      .iter()
+      .all(|PhenotypeInteractionMutation { pos, qry_aa }| match qry_aa {
+        Some(Aa::Gap) => aa_deletions.iter().any(|del| del.pos == *pos),
+        Some(qry_aa) => aa_substitutions
+          .iter()
+          .any(|sub| sub.pos == *pos && sub.qry_aa == *qry_aa),
+        None => aa_substitutions.iter().any(|sub| sub.pos == *pos),
+      })
+}
+
+fn apply_link_function(link: PhenotypeLinkFunction, weighted_scores: impl Iterator<Item = (f64, f64)>) -> f64 {
+  match link {
+    PhenotypeLinkFunction::Escape => {
+      let phenotype: f64 = weighted_scores.map(|(weight, score)| weight * (-score).exp()).sum();
+      -phenotype.ln()
+    }
+    PhenotypeLinkFunction::Linear => weighted_scores.map(|(weight, score)| weight * score).sum(),
+    PhenotypeLinkFunction::Logistic => weighted_scores
+      .map(|(weight, score)| weight / (1.0 + (-score).exp()))
+      .sum(),
+  }
 }
 
 pub fn get_phenotype_attr_descs(virus_properties: &VirusProperties) -> Vec<PhenotypeAttrDesc> {
@@ -46,3 +115,84 @@ pub fn get_phenotype_attr_keys(virus_properties: &VirusProperties) -> Vec<String
     .map(|ph| ph.name.clone())
     .collect_vec()
 }
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+  use super::*;
+  use crate::io::json::json_parse;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+  use std::str::FromStr;
+
+  fn phenotype_data(link: &str) -> PhenotypeData {
+    json_parse(format!(
+      r#"{{
+        "name": "test",
+        "nameFriendly": "Test",
+        "description": "",
+        "cds": "S",
+        "aaRange": {{ "begin": 0, "end": 100 }},
+        "link": "{link}",
+        "data": [
+          {{
+            "name": "ab1",
+            "weight": 1.0,
+            "locations": {{ "9": 0.5, "19": {{ "K": 1.0, "default": 0.25 }} }},
+            "deletions": {{ "29": 2.0 }},
+            "insertions": {{ "39": 3.0 }},
+            "interactions": [
+              {{ "mutations": [{{ "pos": 9 }}, {{ "pos": 19, "qryAa": "K" }}], "coeff": 4.0 }},
+              {{ "mutations": [{{ "pos": 9 }}, {{ "pos": 29, "qryAa": "-" }}], "coeff": 8.0 }}
+            ]
+          }}
+        ]
+      }}"#
+    ))
+    .unwrap()
+  }
+
+  fn subs(muts: &[&str]) -> Vec<AaSub> {
+    muts.iter().map(|m| AaSub::from_str(m).unwrap()).collect_vec()
+  }
+
+  #[rstest]
+  fn calculates_phenotype_with_substitutions_only() {
+    let actual = calculate_phenotype(&phenotype_data("linear"), &subs(&["S:A10T", "S:N20Y"]), &[], &[]);
+    assert_eq!(actual, 0.75);
+  }
+
+  #[rstest]
+  fn calculates_phenotype_with_interactions() {
+    let actual = calculate_phenotype(&phenotype_data("linear"), &subs(&["S:A10T", "S:N20K"]), &[], &[]);
+    assert_eq!(actual, 5.5);
+  }
+
+  #[rstest]
+  fn calculates_phenotype_with_indels() {
+    let dels = vec![AaDel {
+      cds_name: "S".to_owned(),
+      pos: AaRefPosition::from(29),
+      ref_aa: Aa::A,
+    }];
+    let ins = vec![AaIns {
+      cds: "S".to_owned(),
+      pos: 39,
+      ins: vec![Aa::G],
+    }];
+    let actual = calculate_phenotype(&phenotype_data("linear"), &subs(&["S:A10T"]), &dels, &ins);
+    assert_eq!(actual, 13.5);
+  }
+
+  #[rstest]
+  fn calculates_phenotype_with_escape_link() {
+    let actual = calculate_phenotype(&phenotype_data("escape"), &subs(&["S:A10T"]), &[], &[]);
+    assert!((actual - 0.5).abs() < 1e-12);
+  }
+
+  #[rstest]
+  fn calculates_phenotype_with_logistic_link() {
+    let actual = calculate_phenotype(&phenotype_data("logistic"), &[], &[], &[]);
+    assert_eq!(actual, 0.5);
+  }
+}
@@ -3,7 +3,7 @@ use crate::alphabet::aa::Aa;
 use crate::alphabet::nuc::Nuc;
 use crate::analyze::pcr_primers::PcrPrimer;
 use crate::coord::position::AaRefPosition;
-use crate::coord::range::AaRefRange;
+use crate::coord::range::{AaRefRange, NucRefGlobalRange};
 use crate::gene::genotype::Genotype;
 use crate::io::dataset::{DatasetCompatibility, DatasetFiles, DatasetMeta, DatasetVersion};
 use crate::io::fs::read_file_to_string;
@@ -62,6 +62,12 @@ pub struct VirusProperties {
   #[serde(default, skip_serializing_if = "Vec::is_empty")]
   pub aa_motifs: Vec<AaMotifsDesc>,
 
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub nuc_motifs: Vec<NucMotifsDesc>,
+
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub resistance: Vec<DrugResistanceDesc>,
+
   #[serde(default, skip_serializing_if = "Vec::is_empty")]
   pub versions: Vec<DatasetVersion>,
 
@@ -124,12 +130,68 @@ pub struct PhenotypeDataEntry {
   pub name: String,
   pub weight: f64,
   pub locations: BTreeMap<AaRefPosition, PhenotypeCoeff>,
+
+  /// Constant term added to the score of this entry
+  #[serde(default)]
+  pub intercept: f64,
+
+  /// Coefficients for deletions, by position
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub deletions: BTreeMap<AaRefPosition, f64>,
+
+  /// Coefficients for insertions, by position of the reference amino acid after which the insertion occurs
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub insertions: BTreeMap<AaRefPosition, f64>,
+
+  /// Epistatic terms: contribute their coefficient only when all of the listed mutations are present together
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub interactions: Vec<PhenotypeInteraction>,
 }
 
 impl PhenotypeDataEntry {
   pub fn get_coeff(&self, pos: AaRefPosition, aa: Aa) -> f64 {
     self.locations.get(&pos).map_or(0.0, |location| location.get_coeff(aa))
   }
+
+  pub fn get_deletion_coeff(&self, pos: AaRefPosition) -> f64 {
+    self.deletions.get(&pos).copied().unwrap_or(0.0)
+  }
+
+  pub fn get_insertion_coeff(&self, pos: AaRefPosition) -> f64 {
+    self.insertions.get(&pos).copied().unwrap_or(0.0)
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct PhenotypeInteraction {
+  pub mutations: Vec<PhenotypeInteractionMutation>,
+  pub coeff: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct PhenotypeInteractionMutation {
+  pub pos: AaRefPosition,
+
+  /// Query amino acid. If not set, any substitution at this position matches. Gap (`-`) matches a deletion.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub qry_aa: Option<Aa>,
+}
+
+/// Function which combines the scores of individual entries (e.g. antibodies) into the final phenotype value
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum PhenotypeLinkFunction {
+  /// Escape model: `-ln(sum(weight * exp(-score)))`
+  #[default]
+  Escape,
+
+  /// Weighted sum: `sum(weight * score)`
+  Linear,
+
+  /// Weighted sum of logistic functions: `sum(weight / (1 + exp(-score)))`
+  Logistic,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema, Validate)]
@@ -142,6 +204,8 @@ pub struct PhenotypeData {
   pub aa_range: AaRefRange,
   #[serde(default)]
   pub ignore: PhenotypeDataIgnore,
+  #[serde(default)]
+  pub link: PhenotypeLinkFunction,
   pub data: Vec<PhenotypeDataEntry>,
 }
 
@@ -175,6 +239,51 @@ pub struct CountAaMotifsCdsDesc {
   pub ranges: Vec<AaRefRange>,
 }
 
+/// Nucleotide motifs. Motifs are regular expressions, where IUPAC nucleotide ambiguity codes are allowed.
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NucMotifsDesc {
+  pub name: String,
+  pub name_short: String,
+  pub name_friendly: String,
+  pub description: String,
+  pub motifs: Vec<String>,
+
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub ranges: Vec<NucRefGlobalRange>,
+}
+
+/// Describes resistance to one drug, in terms of known amino acid mutations and their scores
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DrugResistanceDesc {
+  pub name: String,
+  pub name_friendly: String,
+  pub description: String,
+
+  /// Resistance levels. The level with the highest `minScore` not exceeding the total score is reported.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub levels: Vec<DrugResistanceLevelDesc>,
+
+  pub mutations: Vec<DrugResistanceMutationsDesc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DrugResistanceLevelDesc {
+  pub name: String,
+  pub min_score: f64,
+}
+
+/// Score contributed when all of the listed amino acid mutations (e.g. `NA:H275Y`, or `NA:K150-` for a deletion)
+/// are present in the query sequence
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DrugResistanceMutationsDesc {
+  pub mutations: Vec<String>,
+  pub score: f64,
+}
+
 impl VirusProperties {
   pub fn from_path(filepath: impl AsRef<Path>) -> Result<Self, Report> {
     let filepath = filepath.as_ref();
@@ -81,6 +81,11 @@ impl<E: GraphEdge> Edge<E> {
     self.key
   }
 
+  #[inline]
+  pub fn set_key(&mut self, key: GraphEdgeKey) {
+    self.key = key;
+  }
+
   #[inline]
   pub const fn source(&self) -> GraphNodeKey {
     self.source
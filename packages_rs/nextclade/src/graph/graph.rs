@@ -12,7 +12,7 @@ use itertools::Itertools;
 use num_traits::Float;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 #[allow(clippy::partial_pub_fields)]
@@ -338,38 +338,49 @@ where
     Ok(())
   }
 
-  pub fn remove_edge(&mut self, edge_key: GraphEdgeKey) -> Result<&Edge<E>, Report> {
-    // Remove edge from source.outbound_edges
-    {
-      let source_key = self
+  /// Removes an edge, disconnecting its source and target nodes, and returns it. The last edge takes the place of the
+  /// removed one, so that no storage is left behind, which means that the key of the last edge changes.
+  pub fn remove_edge(&mut self, edge_key: GraphEdgeKey) -> Result<Edge<E>, Report> {
+    let (source_key, target_key) = {
+      let edge = self
         .get_edge(edge_key)
-        .wrap_err_with(|| format!("When removing edge {edge_key}"))?
-        .source();
-
-      let source = self
-        .get_node_mut(source_key)
         .wrap_err_with(|| format!("When removing edge {edge_key}"))?;
+      (edge.source(), edge.target())
+    };
 
-      source.outbound_mut().retain(|&x| x != edge_key);
-    }
-
-    // Remove edge from target.inbound_edges
-    {
-      let target_key = self
-        .get_edge(edge_key)
-        .wrap_err_with(|| format!("When removing edge {edge_key}"))?
-        .target();
-
-      let target = self
-        .get_node_mut(target_key)
-        .wrap_err_with(|| format!("When removing edge {edge_key}"))?;
+    self
+      .get_node_mut(source_key)
+      .wrap_err_with(|| format!("When removing edge {edge_key}"))?
+      .outbound_mut()
+      .retain(|&x| x != edge_key);
 
-      target.inbound_mut().retain(|&x| x != edge_key);
+    self
+      .get_node_mut(target_key)
+      .wrap_err_with(|| format!("When removing edge {edge_key}"))?
+      .inbound_mut()
+      .retain(|&x| x != edge_key);
+
+    let last_key = GraphEdgeKey::new(self.edges.len() - 1);
+    let removed = self.edges.swap_remove(edge_key.as_usize());
+
+    // Update references to the edge which is moved into the place of the removed one
+    if edge_key != last_key {
+      let moved = &mut self.edges[edge_key.as_usize()];
+      moved.set_key(edge_key);
+      let (source_key, target_key) = (moved.source(), moved.target());
+      for key in self.get_node_mut(source_key)?.outbound_mut() {
+        if *key == last_key {
+          *key = edge_key;
+        }
+      }
+      for key in self.get_node_mut(target_key)?.inbound_mut() {
+        if *key == last_key {
+          *key = edge_key;
+        }
+      }
     }
 
-    self
-      .get_edge(edge_key)
-      .wrap_err_with(|| format!("When removing edge {edge_key}"))
+    Ok(removed)
   }
 
   /// Given a new node ID and insertion target ID, insert a new node between target and the parent of the target
@@ -444,6 +455,38 @@ where
     Ok(self)
   }
 
+  /// Removes nodes which are not connected to any other node (e.g. the ones disconnected using `.remove_edge()`).
+  /// Remaining nodes and edges are renumbered, preserving their order, so the keys of the nodes added before the first
+  /// removed node remain valid.
+  pub fn remove_detached_nodes(&mut self) -> Result<(), Report> {
+    if self.nodes.len() <= 1 {
+      return Ok(());
+    }
+
+    let nodes = std::mem::take(&mut self.nodes);
+    let edges = std::mem::take(&mut self.edges);
+
+    let mut new_node_keys = HashMap::<GraphNodeKey, GraphNodeKey>::new();
+    for node in nodes {
+      if node.is_root() && node.is_leaf() {
+        continue;
+      }
+      let new_node_key = GraphNodeKey::new(self.nodes.len());
+      new_node_keys.insert(node.key(), new_node_key);
+      self.nodes.push(Node::new(new_node_key, node.payload().clone()));
+    }
+
+    for edge in &edges {
+      self.add_edge(
+        new_node_keys[&edge.source()],
+        new_node_keys[&edge.target()],
+        edge.payload().clone(),
+      )?;
+    }
+
+    self.build_ref()
+  }
+
   pub fn get_ladderize_map(&self) -> Result<HashMap<GraphNodeKey, Vec<GraphEdgeKey>>, Report> {
     let root = self.get_exactly_one_root()?;
     let mut terminal_count_map = HashMap::<GraphNodeKey, usize>::new();
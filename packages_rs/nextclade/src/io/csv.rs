@@ -104,8 +104,17 @@ impl VecWriter for CsvVecFileWriter {
 
 /// Parses CSV data from string.
 pub fn parse_csv<T: for<'de> Deserialize<'de>, S: AsRef<str>>(data: S) -> Result<Vec<T>, Report> {
+  parse_csv_with_delimiter(data, b',')
+}
+
+/// Parses CSV data with a given delimiter (e.g. TSV) from string.
+pub fn parse_csv_with_delimiter<T: for<'de> Deserialize<'de>, S: AsRef<str>>(
+  data: S,
+  delimiter: u8,
+) -> Result<Vec<T>, Report> {
   let reader = CsvReaderBuilder::new()
     .has_headers(true)
+    .delimiter(delimiter)
     .from_reader(data.as_ref().as_bytes());
   reader
     .into_deserialize::<T>()
@@ -1,4 +1,4 @@
-use crate::alphabet::aa::from_aa_seq;
+use crate::alphabet::aa::{from_aa_seq, Aa};
 use crate::constants::REVERSE_COMPLEMENT_SUFFIX;
 use crate::gene::gene_map::GeneMap;
 use crate::io::compression::Decompressor;
@@ -48,6 +48,7 @@ pub struct FastaReader<'a> {
   reader: Box<dyn BufRead + 'a>,
   line: String,
   index: usize,
+  keep_gaps: bool,
 }
 
 impl<'a> FastaReader<'a> {
@@ -56,9 +57,17 @@ impl<'a> FastaReader<'a> {
       reader,
       line: String::new(),
       index: 0,
+      keep_gaps: false,
     }
   }
 
+  /// Keeps gap characters (`-`) in sequences, which are otherwise removed. Useful for reading aligned sequences.
+  #[must_use]
+  pub const fn keep_gaps(mut self) -> Self {
+    self.keep_gaps = true;
+    self
+  }
+
   pub fn from_str(contents: &'a impl AsRef<str>) -> Result<Self, Report> {
     let reader = contents.as_ref().as_bytes();
     Ok(Self::new(Box::new(reader)))
@@ -120,7 +129,7 @@ impl<'a> FastaReader<'a> {
         .line
         .trim_end()
         .chars()
-        .filter(|c| is_char_allowed(*c))
+        .filter(|c| is_char_allowed(*c) || (self.keep_gaps && *c == '-'))
         .map(|c| c.to_ascii_uppercase());
 
       record.seq.extend(fragment);
@@ -233,9 +242,13 @@ impl FastaPeptideWriter {
   }
 
   pub fn write(&mut self, seq_name: &str, translation: &CdsTranslation) -> Result<(), Report> {
-    match self.writers.get_mut(&translation.name) {
-      None => make_internal_error!("Fasta file writer not found for gene '{}'", &translation.name),
-      Some(writer) => writer.write(seq_name, &from_aa_seq(&translation.seq), false),
+    self.write_peptide(seq_name, &translation.name, &translation.seq)
+  }
+
+  pub fn write_peptide(&mut self, seq_name: &str, cds_name: &str, peptide: &[Aa]) -> Result<(), Report> {
+    match self.writers.get_mut(cds_name) {
+      None => make_internal_error!("Fasta file writer not found for gene '{cds_name}'"),
+      Some(writer) => writer.write(seq_name, &from_aa_seq(peptide), false),
     }
   }
 }
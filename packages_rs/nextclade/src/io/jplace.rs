@@ -0,0 +1,284 @@
+use crate::graph::node::GraphNodeKey;
+use crate::graph::traits::HasDivergence;
+use crate::io::json::{json_write, JsonPretty};
+use crate::io::nwk_annotated_writer::nwk_quote_name;
+use crate::make_internal_report;
+use crate::tree::tree::AuspiceGraph;
+use eyre::{Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+pub const JPLACE_VERSION: u32 = 3;
+
+pub const JPLACE_FIELDS: &[&str] = &[
+  "edge_num",
+  "likelihood",
+  "like_weight_ratio",
+  "distal_length",
+  "pendant_length",
+];
+
+/// Phylogenetic placements in jplace format (version 3), as consumed by gappa, guppy and other tools.
+///
+/// See: Matsen et al. (2012) "A Format for Phylogenetic Placements", https://doi.org/10.1371/journal.pone.0031009
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Jplace {
+  pub tree: String,
+  pub placements: Vec<JplacePlacement>,
+  pub fields: Vec<String>,
+  pub version: u32,
+  pub metadata: JplaceMetadata,
+}
+
+/// Placement of a query. Values of `p` are in the order of `Jplace::fields`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JplacePlacement {
+  pub p: Vec<(usize, f64, f64, f64, f64)>,
+  pub n: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JplaceMetadata {
+  pub invocation: String,
+}
+
+/// Reference tree with numbered edges. Needs to be taken before the query nodes are attached to the graph, because
+/// placements are described relative to the edges of the reference tree.
+#[derive(Clone, Debug)]
+pub struct JplaceReferenceTree {
+  /// Reference tree in Newick format, with edge numbers in curly braces after branch lengths
+  pub nwk: String,
+
+  /// Edge numbers, by the key of the node at the distal end of the edge
+  pub edge_nums: BTreeMap<GraphNodeKey, usize>,
+}
+
+impl JplaceReferenceTree {
+  /// Numbers edges of the reference tree in post-order. The root node has no edge leading to it, so it receives no
+  /// edge number.
+  pub fn from_graph(graph: &AuspiceGraph) -> Result<Self, Report> {
+    let root_key = graph.get_exactly_one_root()?.key();
+    let mut edge_nums = BTreeMap::new();
+    let nwk = convert_graph_to_jplace_nwk_recursive(graph, root_key, 0.0, &mut edge_nums)
+      .wrap_err("When converting reference tree to jplace Newick string")?;
+    Ok(Self {
+      nwk: format!("{nwk};"),
+      edge_nums,
+    })
+  }
+}
+
+fn convert_graph_to_jplace_nwk_recursive(
+  graph: &AuspiceGraph,
+  node_key: GraphNodeKey,
+  parent_div: f64,
+  edge_nums: &mut BTreeMap<GraphNodeKey, usize>,
+) -> Result<String, Report> {
+  let node = graph.get_node(node_key)?;
+  let div = node.payload().divergence();
+
+  let label = if node.is_leaf() {
+    nwk_quote_name(&node.payload().name)
+  } else {
+    let children = graph
+      .iter_child_keys_of(node)
+      .map(|child_key| convert_graph_to_jplace_nwk_recursive(graph, child_key, div, edge_nums))
+      .collect::<Result<Vec<String>, Report>>()?
+      .join(",");
+    format!("({children})")
+  };
+
+  if node.is_root() {
+    return Ok(label);
+  }
+
+  let edge_num = edge_nums.len();
+  edge_nums.insert(node_key, edge_num);
+  let branch_length = div - parent_div;
+  Ok(format!("{label}:{branch_length}{{{edge_num}}}"))
+}
+
+/// Finds placements of the query nodes on the reference tree, given the graph after query nodes are attached.
+///
+/// Nextclade places queries by parsimony, so each query has exactly one placement, with likelihood weight ratio of 1.
+/// The likelihood is not computed and is reported as 0.
+///
+/// A query node hangs off the reference tree at its nearest ancestor which is either a reference node or an auxiliary
+/// node inserted into one of the reference branches. If it is an auxiliary node, the query is placed onto that branch,
+/// at the distance from the distal end of the branch equal to the distance from the auxiliary node to the reference
+/// node below. If it is a reference node, the query is placed at the distal end of the branch leading to the node.
+/// The root has no branch leading to it, so queries attached to the root are placed at the proximal end of the
+/// branch leading to its first child.
+pub fn jplace_find_placements(
+  reference: &JplaceReferenceTree,
+  graph: &AuspiceGraph,
+) -> Result<Vec<JplacePlacement>, Report> {
+  let is_reference =
+    |key: &GraphNodeKey| reference.edge_nums.contains_key(key) || graph.parent_key_of_by_key(*key).is_none();
+
+  // Nodes which lie on the branches of the reference tree: reference nodes and their ancestors
+  let mut backbone = BTreeSet::new();
+  for node in graph.iter_nodes().filter(|node| is_reference(&node.key())) {
+    let mut key = Some(node.key());
+    while let Some(k) = key {
+      if !backbone.insert(k) {
+        break;
+      }
+      key = graph.parent_key_of_by_key(k);
+    }
+  }
+
+  graph
+    .iter_leaves()
+    .filter(|node| !is_reference(&node.key()))
+    .map(|query| {
+      let name = &query.payload().name;
+
+      let mut attachment_key = query.key();
+      while !backbone.contains(&attachment_key) {
+        attachment_key = graph
+          .parent_key_of_by_key(attachment_key)
+          .ok_or_else(|| make_internal_report!("Query node '{name}' is not connected to the reference tree"))?;
+      }
+      let attachment_div = graph.get_node(attachment_key)?.payload().divergence();
+
+      // Reference node at the distal end of the branch of placement
+      let mut distal_key = attachment_key;
+      while !is_reference(&distal_key) || graph.get_node(distal_key)?.is_root() {
+        distal_key = graph
+          .iter_child_keys_of_by_key(distal_key)
+          .find(|child_key| backbone.contains(child_key))
+          .ok_or_else(|| make_internal_report!("Reference branch of the query node '{name}' is not found"))?;
+      }
+
+      let edge_num = reference.edge_nums[&distal_key];
+      let distal_length = graph.get_node(distal_key)?.payload().divergence() - attachment_div;
+      let pendant_length = query.payload().divergence() - attachment_div;
+
+      // Identical query sequences collapsed into one node share the placement
+      let identical_seq_names = &query.payload().tmp.identical_seq_names;
+      let names = if identical_seq_names.is_empty() {
+        vec![name.clone()]
+      } else {
+        identical_seq_names.clone()
+      };
+
+      Ok(JplacePlacement {
+        p: vec![(edge_num, 0.0, 1.0, distal_length, pendant_length)],
+        n: names,
+      })
+    })
+    .collect()
+}
+
+pub fn jplace_write_to_file(
+  filepath: impl AsRef<Path>,
+  reference: &JplaceReferenceTree,
+  graph: &AuspiceGraph,
+  invocation: &str,
+) -> Result<(), Report> {
+  let filepath = filepath.as_ref();
+  let jplace = Jplace {
+    tree: reference.nwk.clone(),
+    placements: jplace_find_placements(reference, graph)?,
+    fields: JPLACE_FIELDS.iter().map(|&field| field.to_owned()).collect(),
+    version: JPLACE_VERSION,
+    metadata: JplaceMetadata {
+      invocation: invocation.to_owned(),
+    },
+  };
+  json_write(filepath, &jplace, JsonPretty(true)).wrap_err_with(|| format!("When writing jplace file: {filepath:#?}"))
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::float_cmp)]
+
+  use super::*;
+  use crate::graph::graph::convert_auspice_tree_to_graph;
+  use crate::tree::tree::{AuspiceGraphEdgePayload, AuspiceGraphNodePayload, AuspiceTree};
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+  use serde_json::json;
+
+  fn node(name: &str, div: f64, children: &[serde_json::Value]) -> serde_json::Value {
+    json!({
+      "name": name,
+      "branch_attrs": { "mutations": {} },
+      "node_attrs": { "div": div, "clade_membership": { "value": "X" } },
+      "children": children,
+    })
+  }
+
+  fn find_key(graph: &AuspiceGraph, name: &str) -> GraphNodeKey {
+    graph
+      .iter_nodes()
+      .find(|node| node.payload().name == name)
+      .unwrap()
+      .key()
+  }
+
+  fn new_node(graph: &AuspiceGraph, name: &str, div: f64) -> AuspiceGraphNodePayload {
+    let mut payload = graph.get_exactly_one_root().unwrap().payload().clone();
+    payload.name = name.to_owned();
+    payload.node_attrs.div = Some(div);
+    payload
+  }
+
+  #[rstest]
+  fn finds_placements() -> Result<(), Report> {
+    let tree: AuspiceTree = serde_json::from_value(json!({
+      "meta": {},
+      "tree": node("root", 0.0, &[
+        node("A", 0.5, &[]),
+        node("N", 0.25, &[node("B", 0.5, &[]), node("C", 0.75, &[])]),
+      ]),
+    }))?;
+    let mut graph = convert_auspice_tree_to_graph(tree)?;
+
+    let reference = JplaceReferenceTree::from_graph(&graph)?;
+    assert_eq!(reference.nwk, "(A:0.5{0},(B:0.25{1},C:0.5{2}):0.25{3});");
+
+    // Query on a new internal node, splitting the branch leading to C
+    let internal = graph.add_node(new_node(&graph, "C_internal", 0.375));
+    graph.insert_node_before(
+      internal,
+      find_key(&graph, "C"),
+      AuspiceGraphEdgePayload,
+      AuspiceGraphEdgePayload,
+    )?;
+    let q1 = graph.add_node(new_node(&graph, "Q1", 0.5));
+    graph.add_edge(internal, q1, AuspiceGraphEdgePayload)?;
+
+    // Query attached to the root
+    let q2 = graph.add_node(new_node(&graph, "Q2", 0.125));
+    graph.add_edge(find_key(&graph, "root"), q2, AuspiceGraphEdgePayload)?;
+
+    // Query attached to an internal reference node
+    let q3 = graph.add_node(new_node(&graph, "Q3", 0.5));
+    graph.add_edge(find_key(&graph, "N"), q3, AuspiceGraphEdgePayload)?;
+
+    let mut placements = jplace_find_placements(&reference, &graph)?;
+    placements.sort_by(|a, b| a.n.cmp(&b.n));
+
+    assert_eq!(
+      placements,
+      vec![
+        JplacePlacement {
+          p: vec![(2, 0.0, 1.0, 0.375, 0.125)],
+          n: vec!["Q1".to_owned()],
+        },
+        JplacePlacement {
+          p: vec![(0, 0.0, 1.0, 0.5, 0.125)],
+          n: vec!["Q2".to_owned()],
+        },
+        JplacePlacement {
+          p: vec![(3, 0.0, 1.0, 0.0, 0.25)],
+          n: vec!["Q3".to_owned()],
+        },
+      ]
+    );
+    Ok(())
+  }
+}
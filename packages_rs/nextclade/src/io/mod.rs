@@ -7,11 +7,16 @@ pub mod fasta;
 pub mod file;
 pub mod fs;
 pub mod gff3;
+pub mod jplace;
 pub mod json;
 pub mod ndjson;
 pub mod nextclade_csv;
+pub mod nwk_annotated_writer;
+pub mod nwk_reader;
 pub mod nwk_writer;
 pub mod parse_pos;
 pub mod results_json;
+pub mod sample_dates;
 pub mod schema_version;
+pub mod usher_mat;
 pub mod yaml;
@@ -3,16 +3,29 @@ use crate::alphabet::aa::from_aa_seq;
 use crate::alphabet::nuc::{from_nuc, from_nuc_seq, Nuc};
 use crate::analyze::aa_del::AaDel;
 use crate::analyze::aa_sub::AaSub;
+use crate::analyze::cds_coverage::cds_coverage_key;
+use crate::analyze::drug_resistance::{
+  drug_resistance_key, drug_resistance_mutations_key, DrugResistance, DrugResistanceMutations,
+};
 use crate::analyze::find_aa_motifs::AaMotif;
+use crate::analyze::find_nuc_motifs::{nuc_motifs_gained_key, nuc_motifs_lost_key, NucMotif};
+use crate::analyze::find_nuc_motifs_changes::NucMotifMutation;
+use crate::analyze::find_relative_mutations::{
+  relative_aa_deletions_key, relative_aa_substitutions_key, relative_nuc_deletions_key, relative_nuc_substitutions_key,
+  RelativeMutations,
+};
 use crate::analyze::letter_ranges::{CdsAaRange, NucRange};
 use crate::analyze::nuc_del::NucDelRange;
-use crate::analyze::nuc_sub::{NucSub, NucSubLabeled};
+use crate::analyze::nuc_sub::{NucSub, NucSubLabeled, NucSubSeenInTree};
 use crate::analyze::pcr_primer_changes::PcrPrimerChange;
 use crate::coord::range::NucRefGlobalRange;
 use crate::io::csv::{CsvVecFileWriter, CsvVecWriter, VecWriter};
 use crate::qc::qc_config::StopCodonLocation;
+use crate::qc::qc_rule_custom::QcResultCustomRule;
 use crate::qc::qc_rule_snp_clusters::ClusteredSnp;
 use crate::translate::frame_shifts_translate::FrameShift;
+use crate::tree::tree_nearest_tips::NearestTip;
+use crate::tree::tree_placement_uncertainty::clade_node_attr_confidence_key;
 use crate::types::outputs::{
   combine_outputs_and_errors_sorted, NextcladeErrorOutputs, NextcladeOutputOrError, NextcladeOutputs, PeptideWarning,
   PhenotypeValue,
@@ -48,6 +61,7 @@ pub enum CsvColumnCategory {
   ErrsWarns,
   Qc,
   Primers,
+  PlacementUncertainty,
   Dynamic,
 }
 
@@ -111,6 +125,23 @@ impl CsvColumnConfig {
       })
     }
   }
+
+  /// Removes the columns of placement uncertainty, unless it is requested. The confidence of the assigned clade and of
+  /// the clade-like attributes is only calculated when placement uncertainty is requested.
+  #[must_use]
+  pub fn with_placement_uncertainty(mut self, include_placement_uncertainty: bool) -> Self {
+    if !include_placement_uncertainty {
+      self.categories.shift_remove(&CsvColumnCategory::PlacementUncertainty);
+    }
+    self
+  }
+
+  fn includes_placement_uncertainty(&self) -> bool {
+    self
+      .categories
+      .get(&CsvColumnCategory::PlacementUncertainty)
+      .map_or(false, |columns| columns.values().any(|enabled| *enabled))
+  }
 }
 
 impl Default for CsvColumnConfig {
@@ -145,6 +176,7 @@ lazy_static! {
       o!("alignmentEnd") => true,
       o!("coverage") => true,
       o!("isReverseComplement") => true,
+      o!("nearestTips") => true,
     },
     CsvColumnCategory::RefMuts => indexmap! {
       o!("substitutions") => true,
@@ -159,9 +191,13 @@ lazy_static! {
       o!("privateNucMutations.reversionSubstitutions") => true,
       o!("privateNucMutations.labeledSubstitutions") => true,
       o!("privateNucMutations.unlabeledSubstitutions") => true,
+      o!("privateNucMutations.seenInTreeSubstitutions") => true,
+      o!("privateNucMutations.novelSubstitutions") => true,
       o!("privateNucMutations.totalReversionSubstitutions") => true,
       o!("privateNucMutations.totalLabeledSubstitutions") => true,
       o!("privateNucMutations.totalUnlabeledSubstitutions") => true,
+      o!("privateNucMutations.totalSeenInTreeSubstitutions") => true,
+      o!("privateNucMutations.totalNovelSubstitutions") => true,
       o!("privateNucMutations.totalPrivateSubstitutions") => true,
     },
     CsvColumnCategory::Qc => indexmap! {
@@ -197,11 +233,25 @@ lazy_static! {
       o!("qc.stopCodons.totalStopCodons") => true,
       o!("qc.stopCodons.score") => true,
       o!("qc.stopCodons.status") => true,
+      o!("qc.indels.totalDeletionRanges") => true,
+      o!("qc.indels.totalDeletedNucs") => true,
+      o!("qc.indels.totalInsertions") => true,
+      o!("qc.indels.totalInsertedNucs") => true,
+      o!("qc.indels.score") => true,
+      o!("qc.indels.status") => true,
+      o!("qc.clock.expectedDivergence") => true,
+      o!("qc.clock.deviation") => true,
+      o!("qc.clock.score") => true,
+      o!("qc.clock.status") => true,
+      o!("qc.customRules") => true,
     },
     CsvColumnCategory::Primers => indexmap! {
       o!("totalPcrPrimerChanges") => true,
       o!("pcrPrimerChanges") => true,
     },
+    CsvColumnCategory::PlacementUncertainty => indexmap! {
+      o!("cladeConfidence") => true,
+    },
     CsvColumnCategory::ErrsWarns => indexmap! {
       o!("failedCdses") => true,
       o!("warnings") => true,
@@ -225,6 +275,10 @@ fn prepare_headers(
   custom_node_attr_keys: &[String],
   phenotype_attr_keys: &[String],
   aa_motifs_keys: &[String],
+  nuc_motifs_keys: &[String],
+  drug_resistance_keys: &[String],
+  cds_coverage_keys: &[String],
+  relative_mutations_keys: &[String],
   column_config: &CsvColumnConfig,
 ) -> Vec<String> {
   // Get names of enabled columns
@@ -253,25 +307,40 @@ fn prepare_headers(
       .position(|header| header == "clade")
       .unwrap_or_else(|| headers.len().saturating_sub(1));
 
-    custom_node_attr_keys.iter().rev().for_each(|key| {
-      headers.insert(insert_custom_cols_at_index + 1, key.clone());
-    });
-    insert_custom_cols_at_index += custom_node_attr_keys.len();
-
-    phenotype_attr_keys.iter().rev().for_each(|key| {
-      headers.insert(insert_custom_cols_at_index + 1, key.clone());
-    });
-    insert_custom_cols_at_index += phenotype_attr_keys.len();
-
-    aa_motifs_keys.iter().rev().for_each(|key| {
-      headers.insert(insert_custom_cols_at_index + 1, key.clone());
-      insert_custom_cols_at_index += aa_motifs_keys.len();
-    });
+    let include_confidence = column_config.includes_placement_uncertainty();
+    let custom_node_attr_keys = custom_node_attr_keys
+      .iter()
+      .flat_map(|key| {
+        let confidence_key = include_confidence.then(|| clade_node_attr_confidence_key(key));
+        chain![[key.clone()], confidence_key]
+      })
+      .collect_vec();
+    insert_custom_cols_at_index =
+      insert_headers_after(&mut headers, insert_custom_cols_at_index, &custom_node_attr_keys);
+
+    for keys in [
+      phenotype_attr_keys,
+      aa_motifs_keys,
+      nuc_motifs_keys,
+      drug_resistance_keys,
+      cds_coverage_keys,
+      relative_mutations_keys,
+    ] {
+      insert_custom_cols_at_index = insert_headers_after(&mut headers, insert_custom_cols_at_index, keys);
+    }
   }
 
   headers
 }
 
+/// Inserts a group of dynamic columns, in order, after the column with the given index. Returns index of the last
+/// inserted column, such that the next group can be inserted after it.
+fn insert_headers_after(headers: &mut Vec<String>, index: usize, keys: &[String]) -> usize {
+  let at = index + 1;
+  headers.splice(at..at, keys.iter().cloned());
+  index + keys.len()
+}
+
 /// Writes content of nextclade.csv and nextclade.tsv files (but not necessarily files themselves - writer is generic)
 pub struct NextcladeResultsCsvWriter<W: VecWriter> {
   writer: W,
@@ -326,12 +395,19 @@ impl<W: VecWriter> NextcladeResultsCsvWriter<W> {
       missing_cdses,
       // divergence,
       coverage,
+      cds_coverage,
+      relative_mutations,
+      placement_uncertainty,
+      nearest_tips,
       phenotype_values,
       qc,
       custom_node_attributes,
       is_reverse_complement,
       warnings,
       aa_motifs,
+      nuc_motifs,
+      nuc_motifs_changes,
+      drug_resistance,
       ..
     } = nextclade_outputs;
 
@@ -339,6 +415,11 @@ impl<W: VecWriter> NextcladeResultsCsvWriter<W> {
       .iter()
       .try_for_each(|(key, val)| self.add_entry(key, &val))?;
 
+    placement_uncertainty
+      .clade_node_attr_confidence
+      .iter()
+      .try_for_each(|(key, confidence)| self.add_entry(clade_node_attr_confidence_key(key), confidence))?;
+
     if let Some(phenotype_values) = phenotype_values {
       phenotype_values
         .iter()
@@ -349,10 +430,71 @@ impl<W: VecWriter> NextcladeResultsCsvWriter<W> {
       .iter()
       .try_for_each(|(name, motifs)| self.add_entry(name, &format_aa_motifs(motifs)))?;
 
+    nuc_motifs
+      .iter()
+      .try_for_each(|(name, motifs)| self.add_entry(name, &format_nuc_motifs(motifs)))?;
+
+    nuc_motifs_changes.iter().try_for_each(|(name, changes)| {
+      self.add_entry(
+        nuc_motifs_gained_key(name),
+        &format_nuc_motif_mutations(&changes.gained),
+      )?;
+      self.add_entry(nuc_motifs_lost_key(name), &format_nuc_motif_mutations(&changes.lost))
+    })?;
+
+    drug_resistance.iter().try_for_each(|resistance| {
+      self.add_entry(
+        drug_resistance_key(&resistance.name),
+        &format_drug_resistance(resistance),
+      )?;
+      self.add_entry(
+        drug_resistance_mutations_key(&resistance.name),
+        &format_drug_resistance_mutations(&resistance.mutations, ARRAY_ITEM_DELIMITER),
+      )
+    })?;
+
+    cds_coverage
+      .iter()
+      .try_for_each(|(cds_name, coverage)| self.add_entry(cds_coverage_key(cds_name), coverage))?;
+
+    relative_mutations.iter().try_for_each(|relative_mutations| {
+      let RelativeMutations {
+        ref_node_name,
+        nuc_muts,
+        aa_muts,
+      } = relative_mutations;
+      self.add_entry(
+        relative_nuc_substitutions_key(ref_node_name),
+        &format_nuc_substitutions(&nuc_muts.private_substitutions, ARRAY_ITEM_DELIMITER),
+      )?;
+      self.add_entry(
+        relative_nuc_deletions_key(ref_node_name),
+        &nuc_muts.private_deletions.iter().join(ARRAY_ITEM_DELIMITER),
+      )?;
+      self.add_entry(
+        relative_aa_substitutions_key(ref_node_name),
+        &aa_muts
+          .values()
+          .flat_map(|muts| &muts.private_substitutions)
+          .join(ARRAY_ITEM_DELIMITER),
+      )?;
+      self.add_entry(
+        relative_aa_deletions_key(ref_node_name),
+        &aa_muts
+          .values()
+          .flat_map(|muts| &muts.private_deletions)
+          .join(ARRAY_ITEM_DELIMITER),
+      )
+    })?;
+
     self.add_entry("index", index)?;
     self.add_entry("seqName", seq_name)?;
 
     self.add_entry("clade", clade)?;
+    self.add_entry_maybe(
+      "cladeConfidence",
+      (!placement_uncertainty.candidates.is_empty()).then_some(placement_uncertainty.clade_confidence),
+    )?;
     self.add_entry("qc.overallScore", &format_qc_score(qc.overall_score))?;
     self.add_entry("qc.overallStatus", &qc.overall_status.to_string())?;
     self.add_entry("totalSubstitutions", &total_substitutions.to_string())?;
@@ -387,6 +529,14 @@ impl<W: VecWriter> NextcladeResultsCsvWriter<W> {
       "privateNucMutations.unlabeledSubstitutions",
       &format_nuc_substitutions_minimal(&private_nuc_mutations.unlabeled_substitutions, ARRAY_ITEM_DELIMITER),
     )?;
+    self.add_entry(
+      "privateNucMutations.seenInTreeSubstitutions",
+      &format_nuc_substitutions_seen_in_tree(&private_nuc_mutations.seen_in_tree_substitutions, ARRAY_ITEM_DELIMITER),
+    )?;
+    self.add_entry(
+      "privateNucMutations.novelSubstitutions",
+      &format_nuc_substitutions_minimal(&private_nuc_mutations.novel_substitutions, ARRAY_ITEM_DELIMITER),
+    )?;
     self.add_entry(
       "privateNucMutations.totalReversionSubstitutions",
       &private_nuc_mutations.total_reversion_substitutions.to_string(),
@@ -399,6 +549,14 @@ impl<W: VecWriter> NextcladeResultsCsvWriter<W> {
       "privateNucMutations.totalUnlabeledSubstitutions",
       &private_nuc_mutations.total_unlabeled_substitutions.to_string(),
     )?;
+    self.add_entry(
+      "privateNucMutations.totalSeenInTreeSubstitutions",
+      &private_nuc_mutations.total_seen_in_tree_substitutions.to_string(),
+    )?;
+    self.add_entry(
+      "privateNucMutations.totalNovelSubstitutions",
+      &private_nuc_mutations.total_novel_substitutions.to_string(),
+    )?;
     self.add_entry(
       "privateNucMutations.totalPrivateSubstitutions",
       &private_nuc_mutations.total_private_substitutions.to_string(),
@@ -545,7 +703,43 @@ impl<W: VecWriter> NextcladeResultsCsvWriter<W> {
       "qc.stopCodons.status",
       qc.stop_codons.as_ref().map(|sc| sc.status.to_string()),
     )?;
+    self.add_entry_maybe(
+      "qc.indels.totalDeletionRanges",
+      qc.indels.as_ref().map(|id| id.total_deletion_ranges.to_string()),
+    )?;
+    self.add_entry_maybe(
+      "qc.indels.totalDeletedNucs",
+      qc.indels.as_ref().map(|id| id.total_deleted_nucs.to_string()),
+    )?;
+    self.add_entry_maybe(
+      "qc.indels.totalInsertions",
+      qc.indels.as_ref().map(|id| id.total_insertions.to_string()),
+    )?;
+    self.add_entry_maybe(
+      "qc.indels.totalInsertedNucs",
+      qc.indels.as_ref().map(|id| id.total_inserted_nucs.to_string()),
+    )?;
+    self.add_entry_maybe(
+      "qc.indels.score",
+      qc.indels.as_ref().map(|id| format_qc_score(id.score)),
+    )?;
+    self.add_entry_maybe("qc.indels.status", qc.indels.as_ref().map(|id| id.status.to_string()))?;
+    self.add_entry_maybe(
+      "qc.clock.expectedDivergence",
+      qc.clock.as_ref().map(|cl| format_qc_score(cl.expected_divergence)),
+    )?;
+    self.add_entry_maybe(
+      "qc.clock.deviation",
+      qc.clock.as_ref().map(|cl| format_qc_score(cl.deviation)),
+    )?;
+    self.add_entry_maybe("qc.clock.score", qc.clock.as_ref().map(|cl| format_qc_score(cl.score)))?;
+    self.add_entry_maybe("qc.clock.status", qc.clock.as_ref().map(|cl| cl.status.to_string()))?;
+    self.add_entry(
+      "qc.customRules",
+      &format_qc_custom_rules(&qc.custom_rules, ARRAY_ITEM_DELIMITER),
+    )?;
     self.add_entry("isReverseComplement", &is_reverse_complement.to_string())?;
+    self.add_entry("nearestTips", &format_nearest_tips(nearest_tips, ARRAY_ITEM_DELIMITER))?;
     self.add_entry("failedCdses", &format_failed_cdses(missing_cdses, ARRAY_ITEM_DELIMITER))?;
     self.add_entry(
       "warnings",
@@ -615,9 +809,22 @@ impl NextcladeResultsCsvFileWriter {
     clade_attr_keys: &[String],
     phenotype_attr_keys: &[String],
     aa_motifs_keys: &[String],
+    nuc_motifs_keys: &[String],
+    drug_resistance_keys: &[String],
+    cds_coverage_keys: &[String],
+    relative_mutations_keys: &[String],
     column_config: &CsvColumnConfig,
   ) -> Result<Self, Report> {
-    let headers: Vec<String> = prepare_headers(clade_attr_keys, phenotype_attr_keys, aa_motifs_keys, column_config);
+    let headers: Vec<String> = prepare_headers(
+      clade_attr_keys,
+      phenotype_attr_keys,
+      aa_motifs_keys,
+      nuc_motifs_keys,
+      drug_resistance_keys,
+      cds_coverage_keys,
+      relative_mutations_keys,
+      column_config,
+    );
     let csv_writer = CsvVecFileWriter::new(filepath, delimiter, &headers)?;
     let writer = NextcladeResultsCsvWriter::new(csv_writer, &headers)?;
     Ok(Self { writer })
@@ -655,6 +862,14 @@ pub fn format_nuc_substitutions_labeled(substitutions: &[NucSubLabeled], delimit
     .join(delimiter)
 }
 
+#[inline]
+pub fn format_nuc_substitutions_seen_in_tree(substitutions: &[NucSubSeenInTree], delimiter: &str) -> String {
+  substitutions
+    .iter()
+    .map(|sub| format!("{}|{}", sub.substitution, sub.tree_occurrences))
+    .join(delimiter)
+}
+
 #[inline]
 pub fn format_nuc_deletions(deletions: &[NucDelRange], delimiter: &str) -> String {
   deletions.iter().map(|del| del.range().to_string()).join(delimiter)
@@ -771,11 +986,38 @@ pub fn format_stop_codons(stop_codons: &[StopCodonLocation], delimiter: &str) ->
     .join(delimiter)
 }
 
+/// Formats nearest tips as `<name>:<distance>`, followed by the requested node attributes, if any, as
+/// `(<attr>=<value>|...)`
+#[inline]
+pub fn format_nearest_tips(nearest_tips: &[NearestTip], delimiter: &str) -> String {
+  nearest_tips
+    .iter()
+    .map(|tip| {
+      if tip.attrs.is_empty() {
+        format!("{}:{}", tip.name, tip.distance)
+      } else {
+        let attrs = tip.attrs.iter().map(|(key, value)| format!("{key}={value}")).join("|");
+        format!("{}:{}({attrs})", tip.name, tip.distance)
+      }
+    })
+    .join(delimiter)
+}
+
 #[inline]
 pub fn format_failed_cdses(failed_cdses: &[String], delimiter: &str) -> String {
   failed_cdses.join(delimiter)
 }
 
+/// Formats triggered custom QC rules as `<name>:<score>`
+#[inline]
+pub fn format_qc_custom_rules(custom_rules: &[QcResultCustomRule], delimiter: &str) -> String {
+  custom_rules
+    .iter()
+    .filter(|rule| rule.triggered)
+    .map(|rule| format!("{}:{}", rule.name, format_qc_score(rule.score)))
+    .join(delimiter)
+}
+
 #[inline]
 pub fn format_qc_score(score: f64) -> String {
   if !is_int(score) {
@@ -811,19 +1053,69 @@ fn format_aa_motifs(motifs: &[AaMotif]) -> String {
     .join(";")
 }
 
+#[inline]
+fn format_nuc_motifs(motifs: &[NucMotif]) -> String {
+  motifs
+    .iter()
+    .map(|NucMotif { position, seq, .. }| format!("{}:{seq}", position + 1))
+    .join(";")
+}
+
+#[inline]
+fn format_nuc_motif_mutations(motifs: &[NucMotifMutation]) -> String {
+  motifs
+    .iter()
+    .map(
+      |NucMotifMutation {
+         position,
+         ref_seq,
+         qry_seq,
+         ..
+       }| format!("{}:{ref_seq}>{qry_seq}", position + 1),
+    )
+    .join(";")
+}
+
+/// Resistance level, if levels are configured for the drug, otherwise resistance score
+#[inline]
+pub fn format_drug_resistance(resistance: &DrugResistance) -> String {
+  resistance
+    .level
+    .clone()
+    .unwrap_or_else(|| format_qc_score(resistance.score))
+}
+
+#[inline]
+pub fn format_drug_resistance_mutations(mutations: &[DrugResistanceMutations], delimiter: &str) -> String {
+  mutations.iter().map(|muts| muts.mutations.join("+")).join(delimiter)
+}
+
 pub fn results_to_csv_string(
   outputs: &[NextcladeOutputs],
   errors: &[NextcladeErrorOutputs],
   clade_attr_keys: &[String],
   phenotype_attr_keys: &[String],
   aa_motifs_keys: &[String],
+  nuc_motifs_keys: &[String],
+  drug_resistance_keys: &[String],
+  cds_coverage_keys: &[String],
+  relative_mutations_keys: &[String],
   delimiter: u8,
   column_config: &CsvColumnConfig,
 ) -> Result<String, Report> {
   let mut buf = Vec::<u8>::new();
 
   {
-    let headers: Vec<String> = prepare_headers(clade_attr_keys, phenotype_attr_keys, aa_motifs_keys, column_config);
+    let headers: Vec<String> = prepare_headers(
+      clade_attr_keys,
+      phenotype_attr_keys,
+      aa_motifs_keys,
+      nuc_motifs_keys,
+      drug_resistance_keys,
+      cds_coverage_keys,
+      relative_mutations_keys,
+      column_config,
+    );
     let csv_writer = CsvVecWriter::new(&mut buf, delimiter, &headers)?;
     let mut writer = NextcladeResultsCsvWriter::new(csv_writer, &headers)?;
 
@@ -840,3 +1132,95 @@ pub fn results_to_csv_string(
 
   Ok(String::from_utf8(buf)?)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  fn keys(keys: &[&str]) -> Vec<String> {
+    keys.iter().map(|&key| key.to_owned()).collect_vec()
+  }
+
+  #[rstest]
+  fn inserts_dynamic_columns_after_clade_in_order() {
+    let column_config = CsvColumnConfig {
+      categories: CsvColumnConfigMap::new(),
+      individual: keys(&["clade", "qc.overallScore"]),
+      include_dynamic: true,
+    };
+
+    let headers = prepare_headers(
+      &[],
+      &keys(&["ace2_binding"]),
+      &keys(&["glycosylation", "cleavage"]),
+      &keys(&["tata"]),
+      &keys(&["resistance.drug"]),
+      &[],
+      &[],
+      &column_config,
+    );
+
+    assert_eq!(
+      headers,
+      keys(&[
+        "index",
+        "seqName",
+        "clade",
+        "ace2_binding",
+        "glycosylation",
+        "cleavage",
+        "tata",
+        "resistance.drug",
+        "qc.overallScore",
+      ])
+    );
+  }
+
+  #[rstest]
+  #[case::requested(true, &["index", "seqName", "cladeConfidence", "clade", "region", "region.confidence"])]
+  #[case::not_requested(false, &["index", "seqName", "clade", "region"])]
+  fn includes_confidence_columns_only_when_placement_uncertainty_is_requested(
+    #[case] include_placement_uncertainty: bool,
+    #[case] expected: &[&str],
+  ) {
+    let column_config = CsvColumnConfig {
+      categories: CSV_COLUMN_CONFIG_MAP_DEFAULT
+        .iter()
+        .filter(|(category, _)| matches!(category, CsvColumnCategory::PlacementUncertainty))
+        .map(|(category, columns)| (category.clone(), columns.clone()))
+        .collect(),
+      individual: keys(&["clade"]),
+      include_dynamic: true,
+    }
+    .with_placement_uncertainty(include_placement_uncertainty);
+
+    let headers = prepare_headers(&keys(&["region"]), &[], &[], &[], &[], &[], &[], &column_config);
+
+    assert_eq!(headers, keys(expected));
+  }
+
+  #[rstest]
+  fn formats_nearest_tips_with_attrs() {
+    let tips = vec![
+      NearestTip {
+        name: "tip1".to_owned(),
+        distance: 3,
+        attrs: IndexMap::from([
+          ("country".to_owned(), "Sweden".to_owned()),
+          ("num_date".to_owned(), "2021.5".to_owned()),
+        ]),
+      },
+      NearestTip {
+        name: "tip2".to_owned(),
+        distance: 4,
+        attrs: IndexMap::new(),
+      },
+    ];
+    assert_eq!(
+      format_nearest_tips(&tips, ","),
+      "tip1:3(country=Sweden|num_date=2021.5),tip2:4"
+    );
+  }
+}
@@ -0,0 +1,199 @@
+use crate::graph::node::GraphNodeKey;
+use crate::graph::traits::HasDivergence;
+use crate::io::file::create_file_or_stdout;
+use crate::tree::tree::{AuspiceGraph, AuspiceGraphNodePayload};
+use eyre::{Report, WrapErr};
+use itertools::Itertools;
+use std::io::Write;
+use std::path::Path;
+
+/// Flavor of comments which carry node attributes in a Newick string
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NwkAnnotationStyle {
+  /// BEAST/FigTree-style comments `name[&key="value",...]:length`, as used in Nexus files
+  Nexus,
+  /// New Hampshire eXtended comments `name:length[&&NHX:key=value:...]`
+  Nhx,
+}
+
+/// Writes the graph in Nexus format, with node attributes in comments
+pub fn nexus_write_to_file(filepath: impl AsRef<Path>, graph: &AuspiceGraph) -> Result<(), Report> {
+  let filepath = filepath.as_ref();
+  let mut file = create_file_or_stdout(filepath)?;
+  let nwk = convert_graph_to_annotated_nwk_string(graph, NwkAnnotationStyle::Nexus)?;
+  writeln!(file, "#NEXUS\nbegin trees;\n\ttree tree1 = [&R] {nwk}\nend;")
+    .wrap_err_with(|| format!("When writing graph to Nexus file: {filepath:#?}"))
+}
+
+/// Writes the graph in New Hampshire eXtended (NHX) format
+pub fn nhx_write_to_file(filepath: impl AsRef<Path>, graph: &AuspiceGraph) -> Result<(), Report> {
+  let filepath = filepath.as_ref();
+  let mut file = create_file_or_stdout(filepath)?;
+  let nwk = convert_graph_to_annotated_nwk_string(graph, NwkAnnotationStyle::Nhx)?;
+  writeln!(file, "{nwk}").wrap_err_with(|| format!("When writing graph to NHX file: {filepath:#?}"))
+}
+
+/// Converts the graph to a Newick string, annotating each node with clade, QC status, node type (new or reference)
+/// and clade-like node attributes.
+pub fn convert_graph_to_annotated_nwk_string(
+  graph: &AuspiceGraph,
+  style: NwkAnnotationStyle,
+) -> Result<String, Report> {
+  let root_node_key = graph.get_exactly_one_root()?.key();
+  let nwk = convert_graph_to_annotated_nwk_recursive(graph, root_node_key, 0.0, style)
+    .wrap_err("When converting graph to annotated Newick string")?;
+  Ok(format!("{nwk};"))
+}
+
+fn convert_graph_to_annotated_nwk_recursive(
+  graph: &AuspiceGraph,
+  node_key: GraphNodeKey,
+  parent_div: f64,
+  style: NwkAnnotationStyle,
+) -> Result<String, Report> {
+  let node = graph.get_node(node_key)?.payload();
+  let branch_length = node.divergence() - parent_div;
+
+  let children = if graph.is_leaf_key(node_key) {
+    String::new()
+  } else {
+    let children = graph
+      .iter_child_keys_of_by_key(node_key)
+      .map(|child_key| convert_graph_to_annotated_nwk_recursive(graph, child_key, node.divergence(), style))
+      .collect::<Result<Vec<String>, Report>>()?
+      .join(",");
+    format!("({children})")
+  };
+
+  let name = nwk_quote_name(&node.name);
+  let attrs = get_node_annotations(graph, node);
+
+  Ok(match style {
+    NwkAnnotationStyle::Nexus => {
+      let comment = attrs
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", sanitize_key(key), value.replace('"', "'")))
+        .join(",");
+      format!("{children}{name}[&{comment}]:{branch_length}")
+    }
+    NwkAnnotationStyle::Nhx => {
+      let comment = attrs
+        .iter()
+        .map(|(key, value)| format!("{}={}", sanitize_key(key), sanitize_nhx_value(value)))
+        .join(":");
+      format!("{children}{name}:{branch_length}[&&NHX:{comment}]")
+    }
+  })
+}
+
+/// Lists node attributes to be written into the node comment
+fn get_node_annotations(graph: &AuspiceGraph, node: &AuspiceGraphNodePayload) -> Vec<(String, String)> {
+  let attrs = &node.node_attrs;
+  let node_type = attrs
+    .node_type
+    .as_ref()
+    .map_or_else(|| "Reference".to_owned(), |node_type| node_type.value.clone());
+
+  let mut annotations = vec![("clade".to_owned(), node.clade()), ("node_type".to_owned(), node_type)];
+  if let Some(qc_status) = &attrs.qc_status {
+    annotations.push(("qc_status".to_owned(), qc_status.value.clone()));
+  }
+  annotations.extend(node.get_clade_node_attrs(graph.data.meta.clade_node_attr_descs()));
+  annotations
+}
+
+/// Quotes node name if it contains characters which have special meaning in Newick format
+pub fn nwk_quote_name(name: &str) -> String {
+  if name.chars().any(|c| "()[]{}:;,'\" \t".contains(c)) {
+    format!("'{}'", name.replace('\'', "''"))
+  } else {
+    name.to_owned()
+  }
+}
+
+fn sanitize_key(key: &str) -> String {
+  key
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+    .collect()
+}
+
+/// NHX values cannot contain delimiters of the NHX comment, so these are replaced
+fn sanitize_nhx_value(value: &str) -> String {
+  value
+    .chars()
+    .map(|c| if "()[]:;,=' \t".contains(c) { '_' } else { c })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::graph::graph::convert_auspice_tree_to_graph;
+  use crate::tree::tree::AuspiceTree;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+  use serde_json::json;
+
+  #[rstest]
+  #[case("B.1.1.7", "B.1.1.7")]
+  #[case("USA/CA-1/2020", "USA/CA-1/2020")]
+  #[case("a b", "'a b'")]
+  #[case("it's", "'it''s'")]
+  fn quotes_names(#[case] name: &str, #[case] expected: &str) {
+    assert_eq!(nwk_quote_name(name), expected);
+  }
+
+  fn graph() -> Result<AuspiceGraph, Report> {
+    let tree: AuspiceTree = serde_json::from_value(json!({
+      "meta": {
+        "extensions": {
+          "nextclade": {
+            "clade_node_attrs": [{ "name": "Nextclade lineage", "displayName": "Lineage" }]
+          }
+        }
+      },
+      "tree": {
+        "name": "root",
+        "branch_attrs": { "mutations": {} },
+        "node_attrs": { "div": 0.0, "clade_membership": { "value": "20A" } },
+        "children": [
+          {
+            "name": "ref seq",
+            "branch_attrs": { "mutations": {} },
+            "node_attrs": {
+              "div": 1.0,
+              "clade_membership": { "value": "20B" },
+              "Nextclade lineage": { "value": "B.1 (\"x\": y)" }
+            }
+          },
+          {
+            "name": "query",
+            "branch_attrs": { "mutations": {} },
+            "node_attrs": {
+              "div": 3.5,
+              "clade_membership": { "value": "20A" },
+              "Node type": { "value": "New" },
+              "QC Status": { "value": "good" }
+            }
+          }
+        ]
+      }
+    }))?;
+    convert_auspice_tree_to_graph(tree)
+  }
+
+  #[rstest]
+  #[case::nexus(
+    NwkAnnotationStyle::Nexus,
+    r#"('ref seq'[&clade="20B",node_type="Reference",Nextclade_lineage="B.1 ('x': y)"]:1,query[&clade="20A",node_type="New",qc_status="good"]:3.5)root[&clade="20A",node_type="Reference"]:0;"#
+  )]
+  #[case::nhx(
+    NwkAnnotationStyle::Nhx,
+    r#"('ref seq':1[&&NHX:clade=20B:node_type=Reference:Nextclade_lineage=B.1__"x"__y_],query:3.5[&&NHX:clade=20A:node_type=New:qc_status=good])root:0[&&NHX:clade=20A:node_type=Reference];"#
+  )]
+  fn writes_node_annotations(#[case] style: NwkAnnotationStyle, #[case] expected: &str) -> Result<(), Report> {
+    assert_eq!(convert_graph_to_annotated_nwk_string(&graph()?, style)?, expected);
+    Ok(())
+  }
+}
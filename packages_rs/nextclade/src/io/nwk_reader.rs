@@ -0,0 +1,193 @@
+use crate::io::fs::read_file_to_string;
+use crate::make_error;
+use eyre::{Report, WrapErr};
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+/// Node of a tree parsed from a Newick string
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NwkNode {
+  pub name: Option<String>,
+  pub branch_length: Option<f64>,
+  pub children: Vec<NwkNode>,
+}
+
+impl NwkNode {
+  pub fn is_leaf(&self) -> bool {
+    self.children.is_empty()
+  }
+}
+
+pub fn nwk_read_file(filepath: impl AsRef<Path>) -> Result<NwkNode, Report> {
+  let filepath = filepath.as_ref();
+  let data = read_file_to_string(filepath).wrap_err_with(|| format!("When reading Newick file {filepath:#?}"))?;
+  nwk_parse(&data).wrap_err_with(|| format!("When parsing Newick file {filepath:#?}"))
+}
+
+/// Parses a tree in Newick format. Comments (`[...]`) are ignored. Labels of internal nodes which are numbers are
+/// treated as branch support values rather than names, and are ignored.
+pub fn nwk_parse(data: &str) -> Result<NwkNode, Report> {
+  let mut parser = NwkParser {
+    chars: data.chars().peekable(),
+  };
+  let root = parser.parse_node()?;
+  parser.skip_whitespace_and_comments()?;
+  // Terminating semicolon is required by the format, but some tools omit it, so it is optional here
+  match parser.chars.next() {
+    Some(';') | None => {}
+    Some(c) => return make_error!("Expected ';' at the end of the tree, but found '{c}'"),
+  }
+  parser.skip_whitespace_and_comments()?;
+  if let Some(c) = parser.chars.next() {
+    return make_error!("Unexpected character '{c}' after the end of the tree. Only one tree per file is supported");
+  }
+  Ok(root)
+}
+
+struct NwkParser<'a> {
+  chars: Peekable<Chars<'a>>,
+}
+
+impl NwkParser<'_> {
+  fn parse_node(&mut self) -> Result<NwkNode, Report> {
+    self.skip_whitespace_and_comments()?;
+
+    let mut children = vec![];
+    if self.chars.peek() == Some(&'(') {
+      self.chars.next();
+      loop {
+        children.push(self.parse_node()?);
+        self.skip_whitespace_and_comments()?;
+        match self.chars.next() {
+          Some(',') => continue,
+          Some(')') => break,
+          Some(c) => return make_error!("Expected ',' or ')' after a node, but found '{c}'"),
+          None => return make_error!("Unexpected end of input: unbalanced parentheses"),
+        }
+      }
+    }
+
+    self.skip_whitespace_and_comments()?;
+    let mut name = self.parse_label()?;
+    if !children.is_empty() && name.as_ref().map_or(false, |name| name.parse::<f64>().is_ok()) {
+      name = None;
+    }
+
+    self.skip_whitespace_and_comments()?;
+    let branch_length = if self.chars.peek() == Some(&':') {
+      self.chars.next();
+      self.skip_whitespace_and_comments()?;
+      let length = self.take_while(|c| !is_delimiter(c));
+      let length = length
+        .parse::<f64>()
+        .wrap_err_with(|| format!("When parsing branch length '{length}'"))?;
+      Some(length)
+    } else {
+      None
+    };
+
+    Ok(NwkNode {
+      name,
+      branch_length,
+      children,
+    })
+  }
+
+  fn parse_label(&mut self) -> Result<Option<String>, Report> {
+    if self.chars.peek() == Some(&'\'') {
+      self.chars.next();
+      let mut label = String::new();
+      loop {
+        match self.chars.next() {
+          // Quote inside of a quoted label is escaped by doubling it
+          Some('\'') if self.chars.peek() == Some(&'\'') => {
+            self.chars.next();
+            label.push('\'');
+          }
+          Some('\'') => break,
+          Some(c) => label.push(c),
+          None => return make_error!("Unexpected end of input: unterminated quoted label"),
+        }
+      }
+      return Ok(Some(label));
+    }
+
+    let label = self.take_while(|c| !is_delimiter(c));
+    Ok((!label.is_empty()).then_some(label))
+  }
+
+  fn take_while(&mut self, pred: impl Fn(char) -> bool) -> String {
+    let mut s = String::new();
+    while let Some(&c) = self.chars.peek() {
+      if !pred(c) {
+        break;
+      }
+      s.push(c);
+      self.chars.next();
+    }
+    s
+  }
+
+  fn skip_whitespace_and_comments(&mut self) -> Result<(), Report> {
+    while let Some(&c) = self.chars.peek() {
+      if c.is_whitespace() {
+        self.chars.next();
+      } else if c == '[' {
+        self.chars.next();
+        if !self.chars.by_ref().any(|c| c == ']') {
+          return make_error!("Unexpected end of input: unterminated comment");
+        }
+      } else {
+        break;
+      }
+    }
+    Ok(())
+  }
+}
+
+fn is_delimiter(c: char) -> bool {
+  matches!(c, '(' | ')' | ',' | ':' | ';' | '[' | ']') || c.is_whitespace()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  fn leaf(name: &str, branch_length: f64) -> NwkNode {
+    NwkNode {
+      name: Some(name.to_owned()),
+      branch_length: Some(branch_length),
+      children: vec![],
+    }
+  }
+
+  #[rstest]
+  fn parses_newick() -> Result<(), Report> {
+    let actual = nwk_parse("((A:0.1,'B c':0.2)95:0.3[&comment],C:0.4)root;\n")?;
+    let expected = NwkNode {
+      name: Some("root".to_owned()),
+      branch_length: None,
+      children: vec![
+        NwkNode {
+          name: None,
+          branch_length: Some(0.3),
+          children: vec![leaf("A", 0.1), leaf("B c", 0.2)],
+        },
+        leaf("C", 0.4),
+      ],
+    };
+    assert_eq!(actual, expected);
+    Ok(())
+  }
+
+  #[rstest]
+  #[case("(A,B;")]
+  #[case("(A,B));")]
+  #[case("(A:x,B);")]
+  fn rejects_malformed_newick(#[case] data: &str) {
+    let _: Report = nwk_parse(data).unwrap_err();
+  }
+}
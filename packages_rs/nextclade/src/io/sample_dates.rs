@@ -0,0 +1,153 @@
+use crate::io::csv::parse_csv_with_delimiter;
+use crate::io::fs::read_file_to_string;
+use crate::make_error;
+use chrono::{Datelike, NaiveDate};
+use eyre::{Report, WrapErr};
+use log::warn;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Names of columns which can contain sequence names, in order of preference
+const SEQ_NAME_COLUMNS: &[&str] = &["seqName", "strain", "name"];
+
+const DATE_COLUMN: &str = "date";
+
+/// Collection dates of samples, as decimal years, by sequence name
+pub type SampleDates = BTreeMap<String, f64>;
+
+/// Reads collection dates of samples from a metadata TSV file. The file should contain a column with sequence names
+/// (`seqName`, `strain` or `name`) and a column `date`. Missing and malformed dates are skipped.
+pub fn read_sample_dates_tsv(filepath: impl AsRef<Path>) -> Result<SampleDates, Report> {
+  let filepath = filepath.as_ref();
+  let data = read_file_to_string(filepath).wrap_err_with(|| format!("When reading dates file {filepath:#?}"))?;
+  parse_sample_dates_tsv(&data).wrap_err_with(|| format!("When parsing dates file {filepath:#?}"))
+}
+
+pub fn parse_sample_dates_tsv(data: &str) -> Result<SampleDates, Report> {
+  let rows: Vec<BTreeMap<String, String>> = parse_csv_with_delimiter(data, b'\t')?;
+
+  let Some(first_row) = rows.first() else {
+    return Ok(SampleDates::new());
+  };
+
+  let Some(name_column) = SEQ_NAME_COLUMNS.iter().find(|column| first_row.contains_key(**column)) else {
+    return make_error!(
+      "Column with sequence names not found. Expected one of: {}",
+      SEQ_NAME_COLUMNS.join(", ")
+    );
+  };
+
+  if !first_row.contains_key(DATE_COLUMN) {
+    return make_error!("Column '{DATE_COLUMN}' not found");
+  }
+
+  let mut dates = SampleDates::new();
+  for row in &rows {
+    let (Some(seq_name), Some(date)) = (row.get(*name_column), row.get(DATE_COLUMN)) else {
+      continue;
+    };
+    match parse_date_to_decimal_year(date) {
+      Ok(Some(date)) => {
+        dates.insert(seq_name.clone(), date);
+      }
+      Ok(None) => {}
+      Err(report) => warn!("Date of sequence '{seq_name}' is ignored: {report}"),
+    }
+  }
+  Ok(dates)
+}
+
+/// Converts date to a decimal year (e.g. `2021-07-02` becomes approximately `2021.5`). Accepts full dates
+/// (`YYYY-MM-DD`), incomplete dates (`YYYY-MM`, `YYYY`, possibly with unknown components given as `XX`), which are
+/// placed in the middle of the corresponding month or year, and decimal years. Returns `None` for unknown dates.
+pub fn parse_date_to_decimal_year(date: &str) -> Result<Option<f64>, Report> {
+  let date = date.trim();
+  if date.is_empty() || date == "?" {
+    return Ok(None);
+  }
+
+  if date.contains('.') {
+    let date = date
+      .parse::<f64>()
+      .wrap_err_with(|| format!("When parsing decimal date '{date}'"))?;
+    return Ok(Some(date));
+  }
+
+  let parts = date
+    .split('-')
+    .map(|part| {
+      let is_unknown = part.chars().all(|c| c.eq_ignore_ascii_case(&'X'));
+      (!is_unknown).then_some(part)
+    })
+    .collect::<Vec<_>>();
+
+  let parse_part = |part: &str| {
+    part
+      .parse::<u32>()
+      .wrap_err_with(|| format!("When parsing date '{date}'"))
+  };
+
+  let (year, month, day) = match parts.as_slice() {
+    [Some(year), rest @ ..] if rest.len() <= 2 => {
+      let year = parse_part(year)? as i32;
+      let month = rest.first().copied().flatten().map(parse_part).transpose()?;
+      let day = rest.get(1).copied().flatten().map(parse_part).transpose()?;
+      (year, month, day)
+    }
+    [None, ..] => return Ok(None),
+    _ => return make_error!("Unrecognized date format: '{date}'"),
+  };
+
+  let days_in_year = if NaiveDate::from_ymd_opt(year, 2, 29).is_some() {
+    366.0
+  } else {
+    365.0
+  };
+
+  let ordinal = match (month, day) {
+    (Some(month), Some(day)) => {
+      let Some(date) = NaiveDate::from_ymd_opt(year, month, day) else {
+        return make_error!("Invalid date: '{date}'");
+      };
+      f64::from(date.ordinal0()) + 0.5
+    }
+    (Some(month), None) => {
+      let first = NaiveDate::from_ymd_opt(year, month, 1);
+      let next = if month == 12 {
+        NaiveDate::from_ymd_opt(year + 1, 1, 1)
+      } else {
+        NaiveDate::from_ymd_opt(year, month + 1, 1)
+      };
+      let (Some(first), Some(next)) = (first, next) else {
+        return make_error!("Invalid date: '{date}'");
+      };
+      f64::from(first.ordinal0()) + (next - first).num_days() as f64 / 2.0
+    }
+    (None, _) => days_in_year / 2.0,
+  };
+
+  Ok(Some(f64::from(year) + ordinal / days_in_year))
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::float_cmp)]
+
+  use super::*;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  #[rstest]
+  #[case("2021-01-01", Some(2021.0 + 0.5 / 365.0))]
+  #[case("2020-12-31", Some(2020.0 + 365.5 / 366.0))]
+  #[case("2021-02", Some(2021.0 + 45.0 / 365.0))]
+  #[case("2021-XX-XX", Some(2021.5))]
+  #[case("2021", Some(2021.5))]
+  #[case("2021.25", Some(2021.25))]
+  #[case("XXXX-XX-XX", None)]
+  #[case("", None)]
+  fn parses_dates(#[case] date: &str, #[case] expected: Option<f64>) -> Result<(), Report> {
+    assert_eq!(parse_date_to_decimal_year(date)?, expected);
+    Ok(())
+  }
+}
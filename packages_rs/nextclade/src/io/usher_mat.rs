@@ -0,0 +1,266 @@
+use crate::io::file::open_file_or_stdin;
+use crate::make_error;
+use eyre::{Report, WrapErr};
+use std::io::Read;
+use std::path::Path;
+
+/// Mutation-annotated tree (MAT), as stored in protobuf files (`.pb`) by UShER and matUtils.
+///
+/// Mirrors the `data` message of UShER's `parsimony.proto`. Lists of node mutations and of node metadata are in
+/// pre-order of the nodes of the Newick tree.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UsherMat {
+  pub newick: String,
+  pub node_mutations: Vec<Vec<UsherMut>>,
+  pub condensed_nodes: Vec<UsherCondensedNode>,
+  pub metadata: Vec<UsherNodeMetadata>,
+}
+
+/// Mutation on a branch of MAT. Position is 1-based, nucleotides are encoded as 0, 1, 2, 3 for A, C, G, T.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UsherMut {
+  pub position: i32,
+  pub ref_nuc: i32,
+  pub par_nuc: i32,
+  pub mut_nuc: Vec<i32>,
+  pub chromosome: String,
+}
+
+/// Leaf of the Newick tree of MAT which stands for several identical samples
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UsherCondensedNode {
+  pub node_name: String,
+  pub condensed_leaves: Vec<String>,
+}
+
+/// Clade annotations of a node of MAT. Annotations are only present on the nodes where clades originate.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UsherNodeMetadata {
+  pub clade_annotations: Vec<String>,
+}
+
+pub fn usher_mat_read_file(filepath: impl AsRef<Path>) -> Result<UsherMat, Report> {
+  let filepath = filepath.as_ref();
+  let mut data = vec![];
+  open_file_or_stdin(&Some(filepath))?
+    .read_to_end(&mut data)
+    .wrap_err_with(|| format!("When reading UShER MAT file {filepath:#?}"))?;
+  usher_mat_parse(&data).wrap_err_with(|| format!("When parsing UShER MAT file {filepath:#?}"))
+}
+
+pub fn usher_mat_parse(data: &[u8]) -> Result<UsherMat, Report> {
+  let mut mat = UsherMat::default();
+  let mut reader = ProtoReader::new(data);
+  while let Some((field, wire_type)) = reader.read_key()? {
+    match (field, wire_type) {
+      (1, WIRE_LEN) => mat.newick = reader.read_string()?,
+      (2, WIRE_LEN) => mat.node_mutations.push(parse_mutation_list(reader.read_bytes()?)?),
+      (3, WIRE_LEN) => mat.condensed_nodes.push(parse_condensed_node(reader.read_bytes()?)?),
+      (4, WIRE_LEN) => mat.metadata.push(parse_node_metadata(reader.read_bytes()?)?),
+      _ => reader.skip(wire_type)?,
+    }
+  }
+  Ok(mat)
+}
+
+fn parse_mutation_list(data: &[u8]) -> Result<Vec<UsherMut>, Report> {
+  let mut muts = vec![];
+  let mut reader = ProtoReader::new(data);
+  while let Some((field, wire_type)) = reader.read_key()? {
+    match (field, wire_type) {
+      (1, WIRE_LEN) => muts.push(parse_mutation(reader.read_bytes()?)?),
+      _ => reader.skip(wire_type)?,
+    }
+  }
+  Ok(muts)
+}
+
+fn parse_mutation(data: &[u8]) -> Result<UsherMut, Report> {
+  let mut m = UsherMut::default();
+  let mut reader = ProtoReader::new(data);
+  while let Some((field, wire_type)) = reader.read_key()? {
+    match (field, wire_type) {
+      (1, WIRE_VARINT) => m.position = reader.read_int32()?,
+      (2, WIRE_VARINT) => m.ref_nuc = reader.read_int32()?,
+      (3, WIRE_VARINT) => m.par_nuc = reader.read_int32()?,
+      (4, WIRE_VARINT) => m.mut_nuc.push(reader.read_int32()?),
+      // Repeated scalars are packed by default in proto3
+      (4, WIRE_LEN) => {
+        let mut packed = ProtoReader::new(reader.read_bytes()?);
+        while !packed.is_empty() {
+          m.mut_nuc.push(packed.read_int32()?);
+        }
+      }
+      (5, WIRE_LEN) => m.chromosome = reader.read_string()?,
+      _ => reader.skip(wire_type)?,
+    }
+  }
+  Ok(m)
+}
+
+fn parse_condensed_node(data: &[u8]) -> Result<UsherCondensedNode, Report> {
+  let mut node = UsherCondensedNode::default();
+  let mut reader = ProtoReader::new(data);
+  while let Some((field, wire_type)) = reader.read_key()? {
+    match (field, wire_type) {
+      (1, WIRE_LEN) => node.node_name = reader.read_string()?,
+      (2, WIRE_LEN) => node.condensed_leaves.push(reader.read_string()?),
+      _ => reader.skip(wire_type)?,
+    }
+  }
+  Ok(node)
+}
+
+fn parse_node_metadata(data: &[u8]) -> Result<UsherNodeMetadata, Report> {
+  let mut meta = UsherNodeMetadata::default();
+  let mut reader = ProtoReader::new(data);
+  while let Some((field, wire_type)) = reader.read_key()? {
+    match (field, wire_type) {
+      (1, WIRE_LEN) => meta.clade_annotations.push(reader.read_string()?),
+      _ => reader.skip(wire_type)?,
+    }
+  }
+  Ok(meta)
+}
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_I64: u8 = 1;
+const WIRE_LEN: u8 = 2;
+const WIRE_I32: u8 = 5;
+
+/// Minimal reader of protobuf wire format, sufficient for the messages of MAT
+struct ProtoReader<'a> {
+  data: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> ProtoReader<'a> {
+  const fn new(data: &'a [u8]) -> Self {
+    Self { data, pos: 0 }
+  }
+
+  const fn is_empty(&self) -> bool {
+    self.pos >= self.data.len()
+  }
+
+  /// Reads field number and wire type. Returns `None` at the end of the message.
+  fn read_key(&mut self) -> Result<Option<(u64, u8)>, Report> {
+    if self.is_empty() {
+      return Ok(None);
+    }
+    let key = self.read_varint()?;
+    Ok(Some((key >> 3, (key & 0x7) as u8)))
+  }
+
+  fn read_varint(&mut self) -> Result<u64, Report> {
+    let mut value = 0_u64;
+    for shift in (0..64).step_by(7) {
+      let Some(&byte) = self.data.get(self.pos) else {
+        return make_error!("Unexpected end of data when reading a varint");
+      };
+      self.pos += 1;
+      value |= u64::from(byte & 0x7F) << shift;
+      if byte & 0x80 == 0 {
+        return Ok(value);
+      }
+    }
+    make_error!("Malformed varint")
+  }
+
+  fn read_int32(&mut self) -> Result<i32, Report> {
+    // Negative int32 values are encoded as 10-byte varints, truncation recovers them
+    Ok(self.read_varint()? as i32)
+  }
+
+  fn read_bytes(&mut self) -> Result<&'a [u8], Report> {
+    let len = self.read_varint()? as usize;
+    self.take(len)
+  }
+
+  fn read_string(&mut self) -> Result<String, Report> {
+    let bytes = self.read_bytes()?;
+    String::from_utf8(bytes.to_vec()).wrap_err("When reading a string")
+  }
+
+  fn take(&mut self, len: usize) -> Result<&'a [u8], Report> {
+    // Length comes from the data, so it can be arbitrarily large if the data is corrupted
+    let Some(end) = self.pos.checked_add(len).filter(|&end| end <= self.data.len()) else {
+      return make_error!(
+        "Unexpected end of data: expected {len} bytes, but only {} left",
+        self.data.len() - self.pos
+      );
+    };
+    let bytes = &self.data[self.pos..end];
+    self.pos = end;
+    Ok(bytes)
+  }
+
+  fn skip(&mut self, wire_type: u8) -> Result<(), Report> {
+    match wire_type {
+      WIRE_VARINT => self.read_varint().map(|_| ()),
+      WIRE_I64 => self.take(8).map(|_| ()),
+      WIRE_LEN => self.read_bytes().map(|_| ()),
+      WIRE_I32 => self.take(4).map(|_| ()),
+      _ => make_error!("Unsupported protobuf wire type: {wire_type}"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::utils::error::report_to_string;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  fn len_field(field: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![(field << 3) | WIRE_LEN, payload.len() as u8];
+    bytes.extend_from_slice(payload);
+    bytes
+  }
+
+  #[rstest]
+  fn parses_mat() -> Result<(), Report> {
+    // Mutation G241T (par_nuc 2, mut_nuc 3, packed), on the second node
+    let mutation = [&[0x08, 0xF1, 0x01, 0x10, 0x01, 0x18, 0x02][..], &len_field(4, &[0x03])].concat();
+    let data = [
+      len_field(1, b"(A,B);"),
+      len_field(2, &[]),
+      len_field(2, &len_field(1, &mutation)),
+      len_field(2, &[]),
+      len_field(4, &len_field(1, b"20A")),
+    ]
+    .concat();
+
+    let mat = usher_mat_parse(&data)?;
+
+    assert_eq!(mat.newick, "(A,B);");
+    assert_eq!(
+      mat.node_mutations,
+      vec![
+        vec![],
+        vec![UsherMut {
+          position: 241,
+          ref_nuc: 1,
+          par_nuc: 2,
+          mut_nuc: vec![3],
+          chromosome: String::new(),
+        }],
+        vec![]
+      ]
+    );
+    assert_eq!(mat.metadata[0].clade_annotations, vec!["20A".to_owned()]);
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::truncated(vec![(1 << 3) | WIRE_LEN, 10, b'(', b'A'], "expected 10 bytes, but only 2 left")]
+  #[case::overflowing(
+    [&[(1 << 3) | WIRE_LEN][..], &[0xFF; 9], &[0x01]].concat(),
+    "expected 18446744073709551615 bytes, but only 0 left"
+  )]
+  fn rejects_malformed_length(#[case] data: Vec<u8>, #[case] expected: &str) {
+    let error = report_to_string(&usher_mat_parse(&data).unwrap_err());
+    assert!(error.contains(expected), "{error}");
+  }
+}
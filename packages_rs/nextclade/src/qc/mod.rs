@@ -1,5 +1,9 @@
 pub mod qc_config;
+pub mod qc_expression;
+pub mod qc_rule_clock;
+pub mod qc_rule_custom;
 pub mod qc_rule_frame_shifts;
+pub mod qc_rule_indels;
 pub mod qc_rule_missing_data;
 pub mod qc_rule_mixed_sites;
 pub mod qc_rule_private_mutations;
@@ -1,8 +1,12 @@
-use crate::coord::range::AaRefRange;
+use crate::coord::range::{AaRefRange, NucRefGlobalRange};
 use crate::io::fs::read_file_to_string;
 use crate::io::json::json_parse;
+use crate::make_error;
+use crate::qc::qc_rule_custom::QcCustomRules;
+use crate::qc::qc_run::QcStatus;
 use eyre::{Report, WrapErr};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::str::FromStr;
 use validator::Validate;
@@ -48,6 +52,11 @@ pub struct QcRulesConfigPrivateMutations {
   #[serde(default = "one")]
   pub weight_unlabeled_deletions: f64,
 
+  /// If set, unlabeled substitutions which also occur on branches of the reference tree (homoplasies) are weighted
+  /// with this value instead of `weight_unlabeled_substitutions`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub weight_seen_in_tree_substitutions: Option<f64>,
+
   pub typical: f64,
   pub cutoff: f64,
 }
@@ -121,6 +130,138 @@ impl Default for QcRulesConfigStopCodons {
   }
 }
 
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize, schemars::JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct InsertionLocation {
+  pub pos: i32,
+  pub length: usize,
+}
+
+/// Thresholds of the indels rule. Reaching any of the thresholds yields the score of 100. Threshold of 0 disables
+/// the corresponding check.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, schemars::JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct QcRulesConfigIndels {
+  pub enabled: bool,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub ignored_deletions: Vec<NucRefGlobalRange>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub ignored_insertions: Vec<InsertionLocation>,
+  pub deletions_count_threshold: usize,
+  pub deletions_length_threshold: usize,
+  pub insertions_count_threshold: usize,
+  pub insertions_length_threshold: usize,
+}
+
+/// Molecular clock rule. Unlike other rules, it is enabled by default, because it only runs when collection dates of
+/// sequences are provided.
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct QcRulesConfigClock {
+  pub enabled: bool,
+
+  /// Deviation from the expected divergence, in standard deviations of the clock model residuals, at which the score
+  /// reaches 100
+  pub deviation_threshold: f64,
+
+  /// Lower bound of the deviation at which the score reaches 100, in units of divergence. Prevents trees with nearly
+  /// perfect clock signal from flagging every sequence.
+  pub min_allowed_deviation: f64,
+}
+
+impl Default for QcRulesConfigClock {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      deviation_threshold: 4.0,
+      min_allowed_deviation: 0.0,
+    }
+  }
+}
+
+/// User-defined QC rule. The rule is triggered when its `condition` expression evaluates to true, in which case the
+/// rule receives the score calculated using `score` expression multiplied by `weight`. Expressions are evaluated over
+/// fields of analysis results (see `QcExpression`).
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct QcRulesConfigCustom {
+  pub name: String,
+  pub condition: String,
+
+  #[serde(default = "default_custom_rule_score")]
+  pub score: String,
+
+  #[serde(default = "one")]
+  pub weight: f64,
+}
+
+fn default_custom_rule_score() -> String {
+  "100".to_owned()
+}
+
+/// Score cutoffs at which QC status changes from "good" to "mediocre" and from "mediocre" to "bad". Used for the
+/// statuses of individual rules as well as for the overall status.
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct QcStatusThresholds {
+  pub mediocre: f64,
+  pub bad: f64,
+}
+
+impl Default for QcStatusThresholds {
+  fn default() -> Self {
+    Self {
+      mediocre: 30.0,
+      bad: 100.0,
+    }
+  }
+}
+
+/// Function which combines scores of individual QC rules into the overall QC score
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum QcScoreAggregation {
+  /// Sum of squares of rule scores, divided by 100
+  #[default]
+  SumOfSquares,
+  /// Sum of rule scores
+  Sum,
+  /// Sum of rule scores, each multiplied by the weight of the rule from `ruleWeights`
+  WeightedSum,
+  /// Maximum of rule scores
+  Max,
+}
+
+/// Forces the QC status of a rule, and consequently the overall QC status, to be at least the given status whenever the
+/// rule is triggered, regardless of the score. For example, any frame shift in a particular CDS can be made to result
+/// in "bad" status.
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct QcStatusOverride {
+  /// Name of a built-in rule (e.g. `frameShifts`) or of a custom rule
+  pub rule: String,
+
+  /// Only consider findings in this CDS. Applies to `frameShifts` and `stopCodons` rules only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub cds_name: Option<String>,
+
+  pub status: QcStatus,
+}
+
+pub const QC_BUILTIN_RULE_NAMES: &[&str] = &[
+  "missingData",
+  "mixedSites",
+  "privateMutations",
+  "snpClusters",
+  "frameShifts",
+  "stopCodons",
+  "indels",
+  "clock",
+];
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize, schemars::JsonSchema, Validate)]
 #[serde(rename_all = "camelCase")]
 #[serde(default)]
@@ -132,6 +273,21 @@ pub struct QcConfig {
   pub snp_clusters: QcRulesConfigSnpClusters,
   pub frame_shifts: QcRulesConfigFrameShifts,
   pub stop_codons: QcRulesConfigStopCodons,
+  pub indels: QcRulesConfigIndels,
+  pub clock: QcRulesConfigClock,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub custom_rules: Vec<QcRulesConfigCustom>,
+
+  pub status_thresholds: QcStatusThresholds,
+
+  pub score_aggregation: QcScoreAggregation,
+
+  /// Weights of rules, by rule name, used with `weightedSum` score aggregation. Rules not listed have weight 1.
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub rule_weights: BTreeMap<String, f64>,
+
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub status_overrides: Vec<QcStatusOverride>,
 }
 
 impl FromStr for QcConfig {
@@ -143,6 +299,46 @@ impl FromStr for QcConfig {
 }
 
 impl QcConfig {
+  /// Parses expressions of custom rules and checks that rule names used elsewhere in the config refer to existing
+  /// rules, so that the errors are reported before the analysis. Returns the parsed custom rules.
+  pub fn validate_rules(&self) -> Result<QcCustomRules, Report> {
+    let custom_rules = QcCustomRules::from_configs(&self.custom_rules)?;
+
+    if self.status_thresholds.mediocre > self.status_thresholds.bad {
+      return make_error!(
+        "QC status threshold for 'mediocre' ({}) is greater than the threshold for 'bad' ({})",
+        self.status_thresholds.mediocre,
+        self.status_thresholds.bad
+      );
+    }
+
+    let rule_names = self
+      .rule_weights
+      .keys()
+      .chain(self.status_overrides.iter().map(|o| &o.rule));
+    for rule_name in rule_names {
+      if !self.has_rule(rule_name) {
+        return make_error!("QC config refers to unknown rule '{rule_name}'");
+      }
+    }
+
+    for status_override in &self.status_overrides {
+      let is_cds_rule = ["frameShifts", "stopCodons"].contains(&status_override.rule.as_str());
+      if status_override.cds_name.is_some() && !is_cds_rule {
+        return make_error!(
+          "QC status override for rule '{}' specifies a CDS, but CDS can only be specified for rules 'frameShifts' and 'stopCodons'",
+          status_override.rule
+        );
+      }
+    }
+
+    Ok(custom_rules)
+  }
+
+  fn has_rule(&self, name: &str) -> bool {
+    QC_BUILTIN_RULE_NAMES.contains(&name) || self.custom_rules.iter().any(|rule| rule.name == name)
+  }
+
   pub fn from_path(filepath: impl AsRef<Path>) -> Result<Self, Report> {
     let filepath = filepath.as_ref();
     let data = read_file_to_string(filepath).wrap_err_with(|| format!("When reading QC config file {filepath:#?}"))?;
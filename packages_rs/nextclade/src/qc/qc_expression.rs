@@ -0,0 +1,429 @@
+use crate::{make_error, o};
+use eyre::{Report, WrapErr};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Arithmetic and logical expression over fields of analysis results, e.g. `totalInsertions > 30`
+/// or `cds.S.coverage < 0.95 && qc.overallScore > 10`.
+///
+/// Supports numbers, dot-separated field paths, parentheses, arithmetic (`+`, `-`, `*`, `/`), comparisons
+/// (`<`, `<=`, `>`, `>=`, `==`, `!=`), logical operators (`&&`, `||`, `!`) and functions `min`, `max` and `abs`.
+/// Logical values are represented as numbers: 1 for true and 0 for false. Any non-zero number is considered true.
+#[derive(Clone, Debug, PartialEq)]
+pub enum QcExpression {
+  Number(f64),
+  Field(Vec<String>),
+  Not(Box<QcExpression>),
+  Negate(Box<QcExpression>),
+  Binary(BinaryOp, Box<QcExpression>, Box<QcExpression>),
+  Call(String, Vec<QcExpression>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+  Or,
+  And,
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+  Add,
+  Sub,
+  Mul,
+  Div,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+  Number(f64),
+  Ident(String),
+  Op(&'static str),
+  LParen,
+  RParen,
+  Comma,
+}
+
+const OPERATORS: &[&str] = &["&&", "||", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "!"];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, Report> {
+  let mut tokens = vec![];
+  let mut chars: Peekable<Chars> = input.chars().peekable();
+  while let Some(&c) = chars.peek() {
+    if c.is_whitespace() {
+      chars.next();
+    } else if c.is_ascii_digit() || c == '.' {
+      let mut number = String::new();
+      while let Some(&c) = chars.peek() {
+        if !(c.is_ascii_digit() || c == '.') {
+          break;
+        }
+        number.push(c);
+        chars.next();
+      }
+      let number = number
+        .parse::<f64>()
+        .wrap_err_with(|| format!("Invalid number: '{number}'"))?;
+      tokens.push(Token::Number(number));
+    } else if c.is_alphabetic() || c == '_' {
+      let mut ident = String::new();
+      while let Some(&c) = chars.peek() {
+        if !(c.is_alphanumeric() || c == '_' || c == '.') {
+          break;
+        }
+        ident.push(c);
+        chars.next();
+      }
+      tokens.push(Token::Ident(ident));
+    } else if c == '(' {
+      tokens.push(Token::LParen);
+      chars.next();
+    } else if c == ')' {
+      tokens.push(Token::RParen);
+      chars.next();
+    } else if c == ',' {
+      tokens.push(Token::Comma);
+      chars.next();
+    } else {
+      let rest: String = chars.clone().take(2).collect();
+      let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) else {
+        return make_error!("Unexpected character: '{c}'");
+      };
+      for _ in 0..op.len() {
+        chars.next();
+      }
+      tokens.push(Token::Op(op));
+    }
+  }
+  Ok(tokens)
+}
+
+struct Parser {
+  tokens: Vec<Token>,
+  pos: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.pos)
+  }
+
+  fn next(&mut self) -> Option<Token> {
+    let token = self.tokens.get(self.pos).cloned();
+    self.pos += 1;
+    token
+  }
+
+  fn expect(&mut self, expected: &Token) -> Result<(), Report> {
+    match self.next() {
+      Some(token) if &token == expected => Ok(()),
+      Some(token) => make_error!("Expected {expected:?}, but found {token:?}"),
+      None => make_error!("Expected {expected:?}, but the expression ended"),
+    }
+  }
+
+  /// Parses a chain of binary operators of the same precedence level
+  fn parse_binary(
+    &mut self,
+    ops: &[(&str, BinaryOp)],
+    parse_operand: fn(&mut Self) -> Result<QcExpression, Report>,
+  ) -> Result<QcExpression, Report> {
+    let mut left = parse_operand(self)?;
+    while let Some(Token::Op(op)) = self.peek() {
+      let Some((_, op)) = ops.iter().find(|(s, _)| s == op) else {
+        break;
+      };
+      let op = *op;
+      self.pos += 1;
+      let right = parse_operand(self)?;
+      left = QcExpression::Binary(op, Box::new(left), Box::new(right));
+    }
+    Ok(left)
+  }
+
+  fn parse_or(&mut self) -> Result<QcExpression, Report> {
+    self.parse_binary(&[("||", BinaryOp::Or)], Self::parse_and)
+  }
+
+  fn parse_and(&mut self) -> Result<QcExpression, Report> {
+    self.parse_binary(&[("&&", BinaryOp::And)], Self::parse_comparison)
+  }
+
+  fn parse_comparison(&mut self) -> Result<QcExpression, Report> {
+    self.parse_binary(
+      &[
+        ("==", BinaryOp::Eq),
+        ("!=", BinaryOp::Ne),
+        ("<", BinaryOp::Lt),
+        ("<=", BinaryOp::Le),
+        (">", BinaryOp::Gt),
+        (">=", BinaryOp::Ge),
+      ],
+      Self::parse_additive,
+    )
+  }
+
+  fn parse_additive(&mut self) -> Result<QcExpression, Report> {
+    self.parse_binary(
+      &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+      Self::parse_multiplicative,
+    )
+  }
+
+  fn parse_multiplicative(&mut self) -> Result<QcExpression, Report> {
+    self.parse_binary(&[("*", BinaryOp::Mul), ("/", BinaryOp::Div)], Self::parse_unary)
+  }
+
+  fn parse_unary(&mut self) -> Result<QcExpression, Report> {
+    match self.peek() {
+      Some(Token::Op("!")) => {
+        self.pos += 1;
+        Ok(QcExpression::Not(Box::new(self.parse_unary()?)))
+      }
+      Some(Token::Op("-")) => {
+        self.pos += 1;
+        Ok(QcExpression::Negate(Box::new(self.parse_unary()?)))
+      }
+      _ => self.parse_primary(),
+    }
+  }
+
+  fn parse_primary(&mut self) -> Result<QcExpression, Report> {
+    match self.next() {
+      Some(Token::Number(number)) => Ok(QcExpression::Number(number)),
+      Some(Token::Ident(ident)) => {
+        if self.peek() == Some(&Token::LParen) {
+          self.pos += 1;
+          let mut args = vec![];
+          if self.peek() != Some(&Token::RParen) {
+            loop {
+              args.push(self.parse_or()?);
+              if self.peek() == Some(&Token::Comma) {
+                self.pos += 1;
+              } else {
+                break;
+              }
+            }
+          }
+          self.expect(&Token::RParen)?;
+          validate_function_call(&ident, args.len())?;
+          Ok(QcExpression::Call(ident, args))
+        } else {
+          let path: Vec<String> = ident.split('.').map(ToOwned::to_owned).collect();
+          if path.iter().any(String::is_empty) {
+            return make_error!("Invalid field path: '{ident}'");
+          }
+          // Path `cds.<name>.coverage` is a shorthand for `cdsCoverage.<name>`
+          let path = match path.as_slice() {
+            [cds, name, coverage] if cds == "cds" && coverage == "coverage" => vec![o!("cdsCoverage"), name.clone()],
+            _ => path,
+          };
+          Ok(QcExpression::Field(path))
+        }
+      }
+      Some(Token::LParen) => {
+        let expr = self.parse_or()?;
+        self.expect(&Token::RParen)?;
+        Ok(expr)
+      }
+      Some(token) => make_error!("Unexpected {token:?}"),
+      None => make_error!("Unexpected end of the expression"),
+    }
+  }
+}
+
+fn validate_function_call(name: &str, num_args: usize) -> Result<(), Report> {
+  match (name, num_args) {
+    ("abs", 1) | ("min" | "max", 2..) => Ok(()),
+    ("abs" | "min" | "max", _) => make_error!("Wrong number of arguments of function '{name}': {num_args}"),
+    _ => make_error!("Unknown function: '{name}'. Supported functions are: 'abs', 'min', 'max'"),
+  }
+}
+
+impl QcExpression {
+  pub fn parse(input: &str) -> Result<Self, Report> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.parse_or()?;
+    if let Some(token) = parser.peek() {
+      return make_error!("Unexpected {token:?}");
+    }
+    Ok(expr)
+  }
+
+  /// Adds names of the top-level fields the expression refers to
+  pub fn collect_fields(&self, fields: &mut BTreeSet<String>) {
+    match self {
+      QcExpression::Number(_) => {}
+      QcExpression::Field(path) => {
+        fields.insert(path[0].clone());
+      }
+      QcExpression::Not(expr) | QcExpression::Negate(expr) => expr.collect_fields(fields),
+      QcExpression::Binary(_, left, right) => {
+        left.collect_fields(fields);
+        right.collect_fields(fields);
+      }
+      QcExpression::Call(_, args) => {
+        for arg in args {
+          arg.collect_fields(fields);
+        }
+      }
+    }
+  }
+
+  /// Evaluates the expression. Field paths are looked up in the given JSON object. Returns `None` if the result depends
+  /// on a field which is missing, is null or is not a number. Logical operators short-circuit: `||` is true if either
+  /// of the operands is true and `&&` is false if either of the operands is false, even if the other one has no value.
+  pub fn eval(&self, fields: &Value) -> Option<f64> {
+    let bool_to_num = |b: bool| if b { 1.0 } else { 0.0 };
+    Some(match self {
+      QcExpression::Number(number) => *number,
+      QcExpression::Field(path) => resolve_field(fields, path)?,
+      QcExpression::Not(expr) => bool_to_num(expr.eval(fields)? == 0.0),
+      QcExpression::Negate(expr) => -expr.eval(fields)?,
+      QcExpression::Binary(BinaryOp::Or, left, right) => match left.eval(fields) {
+        Some(left) if left != 0.0 => 1.0,
+        left => match right.eval(fields) {
+          Some(right) if right != 0.0 => 1.0,
+          right => {
+            left?;
+            right?;
+            0.0
+          }
+        },
+      },
+      QcExpression::Binary(BinaryOp::And, left, right) => match left.eval(fields) {
+        Some(left) if left == 0.0 => 0.0,
+        left => match right.eval(fields) {
+          Some(right) if right == 0.0 => 0.0,
+          right => {
+            left?;
+            right?;
+            1.0
+          }
+        },
+      },
+      QcExpression::Binary(op, left, right) => {
+        let left = left.eval(fields)?;
+        let right = right.eval(fields)?;
+        match op {
+          BinaryOp::Or | BinaryOp::And => unreachable!("Logical operators are evaluated above"),
+          BinaryOp::Eq => bool_to_num((left - right).abs() < f64::EPSILON),
+          BinaryOp::Ne => bool_to_num((left - right).abs() >= f64::EPSILON),
+          BinaryOp::Lt => bool_to_num(left < right),
+          BinaryOp::Le => bool_to_num(left <= right),
+          BinaryOp::Gt => bool_to_num(left > right),
+          BinaryOp::Ge => bool_to_num(left >= right),
+          BinaryOp::Add => left + right,
+          BinaryOp::Sub => left - right,
+          BinaryOp::Mul => left * right,
+          BinaryOp::Div => left / right,
+        }
+      }
+      QcExpression::Call(name, args) => {
+        let args = args.iter().map(|arg| arg.eval(fields)).collect::<Option<Vec<_>>>()?;
+        match name.as_str() {
+          "abs" => args[0].abs(),
+          "min" => args.into_iter().fold(f64::INFINITY, f64::min),
+          "max" => args.into_iter().fold(f64::NEG_INFINITY, f64::max),
+          _ => unreachable!("Function names are validated during parsing"),
+        }
+      }
+    })
+  }
+}
+
+/// Finds numeric value of a field given its path. Logical values are converted to numbers and arrays to their
+/// lengths.
+fn resolve_field(fields: &Value, path: &[String]) -> Option<f64> {
+  let mut value = fields;
+  for segment in path {
+    value = match value {
+      Value::Object(object) => object.get(segment)?,
+      Value::Array(array) if segment == "length" => return Some(array.len() as f64),
+      _ => return None,
+    };
+  }
+
+  match value {
+    Value::Number(number) => number.as_f64(),
+    Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+    Value::Array(array) => Some(array.len() as f64),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::float_cmp)]
+
+  use super::*;
+  use eyre::Report;
+  use rstest::rstest;
+  use serde_json::json;
+
+  #[rstest]
+  #[case("totalInsertions > 30", 1.0)]
+  #[case("totalInsertions > 30 && coverage < 0.9", 0.0)]
+  #[case("cds.S.coverage < 0.95", 1.0)]
+  #[case("(totalInsertions - 30) * 2 + -1", 13.0)]
+  #[case("!isReverseComplement || substitutions.length == 2", 1.0)]
+  #[case("max(substitutions, 1, abs(-3)) / 2", 1.5)]
+  #[case("totalInsertions > 30 || clockDeviation > 3", 1.0)]
+  #[case("clockDeviation > 3 || totalInsertions > 30", 1.0)]
+  #[case("totalInsertions < 30 && clockDeviation > 3", 0.0)]
+  #[case("clockDeviation > 3 && totalInsertions < 30", 0.0)]
+  fn evaluates_expressions(#[case] input: &str, #[case] expected: f64) -> Result<(), Report> {
+    let fields = json!({
+      "totalInsertions": 37,
+      "coverage": 0.97,
+      "cdsCoverage": { "S": 0.9 },
+      "isReverseComplement": false,
+      "substitutions": [{}, {}],
+    });
+    assert_eq!(QcExpression::parse(input)?.eval(&fields), Some(expected));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case("clockDeviation > 3")]
+  #[case("qc.clock.score > 3")]
+  #[case("cds.ORF1a.coverage < 0.95")]
+  #[case("totalInsertions < 30 || clockDeviation > 3")]
+  #[case("totalInsertions > 30 && clockDeviation > 3")]
+  #[case("seqName > 3")]
+  fn has_no_value_when_field_is_unavailable(#[case] input: &str) -> Result<(), Report> {
+    let fields = json!({
+      "seqName": "seq",
+      "totalInsertions": 37,
+      "qc": { "clock": null },
+      "cdsCoverage": { "S": 0.9 },
+    });
+    assert_eq!(QcExpression::parse(input)?.eval(&fields), None);
+    Ok(())
+  }
+
+  #[rstest]
+  fn collects_fields() -> Result<(), Report> {
+    let mut fields = BTreeSet::new();
+    QcExpression::parse("max(qc.clock.score, -totalInsertions) > 1 && !cds.S.coverage")?.collect_fields(&mut fields);
+    assert_eq!(
+      fields.into_iter().collect::<Vec<_>>(),
+      vec!["cdsCoverage", "qc", "totalInsertions"]
+    );
+    Ok(())
+  }
+
+  #[rstest]
+  #[case("totalInsertions >")]
+  #[case("(coverage < 0.9")]
+  #[case("coverage < 0.9)")]
+  #[case("coverage ? 1")]
+  #[case("median(coverage)")]
+  fn rejects_invalid_expressions(#[case] input: &str) {
+    let _: Report = QcExpression::parse(input).unwrap_err();
+  }
+}
@@ -0,0 +1,63 @@
+use crate::qc::qc_config::{QcRulesConfigClock, QcStatusThresholds};
+use crate::qc::qc_run::{QcRule, QcStatus};
+use crate::tree::tree_clock::ClockModel;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QcResultClock {
+  pub score: f64,
+  pub status: QcStatus,
+  pub date: f64,
+  pub expected_divergence: f64,
+
+  /// Difference between divergence of the sequence and the expected divergence. Positive values mean that the sequence
+  /// is more divergent than expected for its date, negative values mean that it is less divergent.
+  pub deviation: f64,
+
+  /// Deviation at which the score reaches 100
+  pub allowed_deviation: f64,
+}
+
+impl QcRule for QcResultClock {
+  fn score(&self) -> f64 {
+    self.score
+  }
+}
+
+/// Compares divergence of the sequence with the divergence expected for its clade at its collection date. Only runs
+/// when the collection date of the sequence is known.
+pub fn rule_clock(
+  clade: &str,
+  divergence: f64,
+  date: Option<f64>,
+  clock_model: Option<&ClockModel>,
+  config: &QcRulesConfigClock,
+  thresholds: &QcStatusThresholds,
+) -> Option<QcResultClock> {
+  if !config.enabled {
+    return None;
+  }
+
+  let (date, clock_model) = (date?, clock_model?);
+
+  let expected_divergence = clock_model.expected_divergence(clade, date);
+  let deviation = divergence - expected_divergence;
+  let allowed_deviation = (config.deviation_threshold * clock_model.residual_std_dev).max(config.min_allowed_deviation);
+
+  let score = if allowed_deviation > 0.0 {
+    100.0 * deviation.abs() / allowed_deviation
+  } else {
+    0.0
+  };
+  let status = QcStatus::from_score(score, thresholds);
+
+  Some(QcResultClock {
+    score,
+    status,
+    date,
+    expected_divergence,
+    deviation,
+    allowed_deviation,
+  })
+}
@@ -0,0 +1,279 @@
+use crate::qc::qc_config::{QcRulesConfigCustom, QcStatusThresholds};
+use crate::qc::qc_expression::QcExpression;
+use crate::qc::qc_run::{QcRule, QcStatus};
+use crate::types::outputs::NextcladeOutputs;
+use eyre::{Report, WrapErr};
+use num::traits::clamp_min;
+use serde::ser::{Error as _, Impossible, SerializeStruct};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QcResultCustomRule {
+  pub name: String,
+  pub score: f64,
+  pub status: QcStatus,
+  pub triggered: bool,
+}
+
+impl QcRule for QcResultCustomRule {
+  fn score(&self) -> f64 {
+    self.score
+  }
+}
+
+/// Custom QC rule with parsed expressions
+#[derive(Clone, Debug)]
+pub struct QcCustomRule {
+  pub name: String,
+  pub condition: QcExpression,
+  pub score: QcExpression,
+  pub weight: f64,
+}
+
+/// Custom QC rules of a dataset, parsed once before the analysis
+#[derive(Clone, Debug, Default)]
+pub struct QcCustomRules {
+  pub rules: Vec<QcCustomRule>,
+
+  /// Top-level fields of analysis results used in expressions of the rules
+  pub fields: BTreeSet<String>,
+}
+
+impl QcCustomRules {
+  pub fn from_configs(configs: &[QcRulesConfigCustom]) -> Result<Self, Report> {
+    let rules = configs
+      .iter()
+      .map(|config| {
+        Ok::<_, Report>(QcCustomRule {
+          name: config.name.clone(),
+          condition: QcExpression::parse(&config.condition)?,
+          score: QcExpression::parse(&config.score)?,
+          weight: config.weight,
+        })
+        .wrap_err_with(|| format!("When parsing expressions of custom QC rule '{}'", config.name))
+      })
+      .collect::<Result<Vec<_>, Report>>()?;
+
+    let mut fields = BTreeSet::new();
+    for rule in &rules {
+      rule.condition.collect_fields(&mut fields);
+      rule.score.collect_fields(&mut fields);
+    }
+
+    Ok(Self { rules, fields })
+  }
+}
+
+/// Evaluates user-defined QC rules over the analysis results of a sequence.
+///
+/// A rule which refers to a field that is not available for this sequence (missing, null or not a number) is not
+/// triggered.
+pub fn rule_custom(
+  outputs: &NextcladeOutputs,
+  rules: &QcCustomRules,
+  thresholds: &QcStatusThresholds,
+) -> Result<Vec<QcResultCustomRule>, Report> {
+  if rules.rules.is_empty() {
+    return Ok(vec![]);
+  }
+
+  let fields = outputs
+    .serialize(SelectFieldsSerializer { fields: &rules.fields })
+    .map(Value::Object)
+    .wrap_err("When preparing analysis results for custom QC rules")?;
+
+  Ok(
+    rules
+      .rules
+      .iter()
+      .map(|rule| rule_custom_one(&fields, rule, thresholds))
+      .collect(),
+  )
+}
+
+fn rule_custom_one(fields: &Value, rule: &QcCustomRule, thresholds: &QcStatusThresholds) -> QcResultCustomRule {
+  let score = rule
+    .condition
+    .eval(fields)
+    .filter(|condition| *condition != 0.0)
+    .and_then(|_| rule.score.eval(fields))
+    .map(|score| clamp_min(rule.weight * score, 0.0));
+
+  let triggered = score.is_some();
+  let score = score.unwrap_or_default();
+  let status = QcStatus::from_score(score, thresholds);
+
+  QcResultCustomRule {
+    name: rule.name.clone(),
+    score,
+    status,
+    triggered,
+  }
+}
+
+/// Serializes only the given fields of a struct into a JSON object, such that the fields not used by custom QC rules
+/// are not converted.
+struct SelectFieldsSerializer<'a> {
+  fields: &'a BTreeSet<String>,
+}
+
+struct SelectFieldsStruct<'a> {
+  fields: &'a BTreeSet<String>,
+  map: Map<String, Value>,
+}
+
+impl SerializeStruct for SelectFieldsStruct<'_> {
+  type Ok = Map<String, Value>;
+  type Error = serde_json::Error;
+
+  fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error> {
+    if self.fields.contains(key) {
+      self.map.insert(key.to_owned(), serde_json::to_value(value)?);
+    }
+    Ok(())
+  }
+
+  fn end(self) -> Result<Self::Ok, Self::Error> {
+    Ok(self.map)
+  }
+}
+
+fn unsupported_type() -> serde_json::Error {
+  serde_json::Error::custom("Only structs are supported")
+}
+
+macro_rules! unsupported {
+  ($($method:ident($($arg:ty),*) -> $ret:ty;)*) => {
+    $(
+      fn $method(self, $(_: $arg),*) -> Result<$ret, Self::Error> {
+        Err(unsupported_type())
+      }
+    )*
+  };
+}
+
+impl<'a> Serializer for SelectFieldsSerializer<'a> {
+  type Ok = Map<String, Value>;
+  type Error = serde_json::Error;
+  type SerializeSeq = Impossible<Self::Ok, Self::Error>;
+  type SerializeTuple = Impossible<Self::Ok, Self::Error>;
+  type SerializeTupleStruct = Impossible<Self::Ok, Self::Error>;
+  type SerializeTupleVariant = Impossible<Self::Ok, Self::Error>;
+  type SerializeMap = Impossible<Self::Ok, Self::Error>;
+  type SerializeStruct = SelectFieldsStruct<'a>;
+  type SerializeStructVariant = Impossible<Self::Ok, Self::Error>;
+
+  fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeStruct, Self::Error> {
+    Ok(SelectFieldsStruct {
+      fields: self.fields,
+      map: Map::new(),
+    })
+  }
+
+  fn serialize_some<T: ?Sized + Serialize>(self, _: &T) -> Result<Self::Ok, Self::Error> {
+    Err(unsupported_type())
+  }
+
+  fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _: &'static str, _: &T) -> Result<Self::Ok, Self::Error> {
+    Err(unsupported_type())
+  }
+
+  fn serialize_newtype_variant<T: ?Sized + Serialize>(
+    self,
+    _: &'static str,
+    _: u32,
+    _: &'static str,
+    _: &T,
+  ) -> Result<Self::Ok, Self::Error> {
+    Err(unsupported_type())
+  }
+
+  unsupported! {
+    serialize_bool(bool) -> Self::Ok;
+    serialize_i8(i8) -> Self::Ok;
+    serialize_i16(i16) -> Self::Ok;
+    serialize_i32(i32) -> Self::Ok;
+    serialize_i64(i64) -> Self::Ok;
+    serialize_u8(u8) -> Self::Ok;
+    serialize_u16(u16) -> Self::Ok;
+    serialize_u32(u32) -> Self::Ok;
+    serialize_u64(u64) -> Self::Ok;
+    serialize_f32(f32) -> Self::Ok;
+    serialize_f64(f64) -> Self::Ok;
+    serialize_char(char) -> Self::Ok;
+    serialize_str(&str) -> Self::Ok;
+    serialize_bytes(&[u8]) -> Self::Ok;
+    serialize_none() -> Self::Ok;
+    serialize_unit() -> Self::Ok;
+    serialize_unit_struct(&'static str) -> Self::Ok;
+    serialize_unit_variant(&'static str, u32, &'static str) -> Self::Ok;
+    serialize_seq(Option<usize>) -> Self::SerializeSeq;
+    serialize_tuple(usize) -> Self::SerializeTuple;
+    serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+    serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+    serialize_map(Option<usize>) -> Self::SerializeMap;
+    serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+  use serde_json::json;
+
+  #[derive(Serialize)]
+  #[serde(rename_all = "camelCase")]
+  struct Outputs {
+    total_insertions: usize,
+    clock_deviation: Option<f64>,
+    substitutions: Vec<usize>,
+  }
+
+  #[rstest]
+  fn serializes_only_selected_fields() -> Result<(), Report> {
+    let outputs = Outputs {
+      total_insertions: 37,
+      clock_deviation: None,
+      substitutions: vec![1, 2, 3],
+    };
+    let fields = BTreeSet::from(["clockDeviation".to_owned(), "unknown".to_owned()]);
+    let actual = Value::Object(outputs.serialize(SelectFieldsSerializer { fields: &fields })?);
+    assert_eq!(actual, json!({ "clockDeviation": null }));
+    Ok(())
+  }
+
+  #[rstest]
+  #[case("totalInsertions > 30", "totalInsertions", true, 37.0)]
+  #[case("totalInsertions > 40", "totalInsertions", false, 0.0)]
+  #[case("clockDeviation > 3", "100", false, 0.0)]
+  #[case("totalInsertions > 30", "clockDeviation * 10", false, 0.0)]
+  #[case("unknownField > 0", "100", false, 0.0)]
+  #[case("totalInsertions > 30 || clockDeviation > 3", "100", true, 100.0)]
+  fn evaluates_custom_rule(
+    #[case] condition: &str,
+    #[case] score: &str,
+    #[case] triggered: bool,
+    #[case] expected_score: f64,
+  ) -> Result<(), Report> {
+    let rules = QcCustomRules::from_configs(&[QcRulesConfigCustom {
+      name: "custom".to_owned(),
+      condition: condition.to_owned(),
+      score: score.to_owned(),
+      weight: 1.0,
+    }])?;
+    let fields = json!({ "totalInsertions": 37, "clockDeviation": null });
+
+    let result = rule_custom_one(&fields, &rules.rules[0], &QcStatusThresholds::default());
+
+    assert_eq!(
+      (result.triggered, result.score.to_string()),
+      (triggered, expected_score.to_string())
+    );
+    Ok(())
+  }
+}
@@ -0,0 +1,151 @@
+use crate::align::insertions_strip::NucIns;
+use crate::analyze::find_private_nuc_mutations::PrivateNucMutations;
+use crate::analyze::letter_ranges::NucRange;
+use crate::qc::qc_config::{InsertionLocation, QcRulesConfigIndels, QcStatusThresholds};
+use crate::qc::qc_rule_private_mutations::find_deletion_ranges;
+use crate::qc::qc_run::{QcRule, QcStatus};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QcResultIndels {
+  pub score: f64,
+  pub status: QcStatus,
+  pub total_deletion_ranges: usize,
+  pub total_deleted_nucs: usize,
+  pub total_deletion_ranges_ignored: usize,
+  pub total_insertions: usize,
+  pub total_inserted_nucs: usize,
+  pub total_insertions_ignored: usize,
+}
+
+impl QcRule for QcResultIndels {
+  fn score(&self) -> f64 {
+    self.score
+  }
+}
+
+/// Scores the sequence by the number and the total length of its private deletion ranges and of its insertions.
+///
+/// Each of the four quantities is compared with its threshold (a threshold of 0 disables the comparison), and the
+/// score is the largest of the ratios, scaled such that reaching any of the thresholds yields the score of 100.
+pub fn rule_indels(
+  private_nuc_mutations: &PrivateNucMutations,
+  insertions: &[NucIns],
+  config: &QcRulesConfigIndels,
+  thresholds: &QcStatusThresholds,
+) -> Option<QcResultIndels> {
+  if !config.enabled {
+    return None;
+  }
+
+  let (deletion_ranges_ignored, deletion_ranges): (Vec<NucRange>, Vec<NucRange>) =
+    find_deletion_ranges(&private_nuc_mutations.private_deletions)
+      .into_iter()
+      .partition(|del| config.ignored_deletions.contains(&del.range));
+
+  let (insertions_ignored, insertions): (Vec<&NucIns>, Vec<&NucIns>) = insertions
+    .iter()
+    .partition(|ins| is_insertion_ignored(ins, &config.ignored_insertions));
+
+  let total_deletion_ranges = deletion_ranges.len();
+  let total_deleted_nucs = deletion_ranges.iter().map(NucRange::len).sum();
+  let total_insertions = insertions.len();
+  let total_inserted_nucs = insertions.iter().map(|ins| ins.len()).sum();
+
+  let score = [
+    (total_deletion_ranges, config.deletions_count_threshold),
+    (total_deleted_nucs, config.deletions_length_threshold),
+    (total_insertions, config.insertions_count_threshold),
+    (total_inserted_nucs, config.insertions_length_threshold),
+  ]
+  .into_iter()
+  .filter(|(_, threshold)| *threshold > 0)
+  .map(|(total, threshold)| 100.0 * (total as f64 / threshold as f64))
+  .fold(0.0, f64::max);
+
+  let status = QcStatus::from_score(score, thresholds);
+
+  Some(QcResultIndels {
+    score,
+    status,
+    total_deletion_ranges,
+    total_deleted_nucs,
+    total_deletion_ranges_ignored: deletion_ranges_ignored.len(),
+    total_insertions,
+    total_inserted_nucs,
+    total_insertions_ignored: insertions_ignored.len(),
+  })
+}
+
+#[inline]
+fn is_insertion_ignored(ins: &NucIns, ignored_insertions: &[InsertionLocation]) -> bool {
+  ignored_insertions
+    .iter()
+    .any(|ignored| ignored.pos == ins.pos && ignored.length == ins.len())
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::float_cmp)]
+
+  use super::*;
+  use crate::alphabet::nuc::Nuc;
+  use crate::analyze::nuc_del::NucDel;
+  use crate::coord::range::NucRefGlobalRange;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  fn dels(positions: impl IntoIterator<Item = usize>) -> PrivateNucMutations {
+    PrivateNucMutations {
+      private_deletions: positions
+        .into_iter()
+        .map(|pos| NucDel {
+          pos: pos.into(),
+          ref_nuc: Nuc::A,
+        })
+        .collect(),
+      ..PrivateNucMutations::default()
+    }
+  }
+
+  fn ins(pos: i32, len: usize) -> NucIns {
+    NucIns {
+      pos,
+      ins: vec![Nuc::A; len],
+    }
+  }
+
+  #[rstest]
+  fn scores_by_largest_ratio_and_skips_ignored_indels() {
+    let config = QcRulesConfigIndels {
+      enabled: true,
+      ignored_deletions: vec![NucRefGlobalRange::from_usize(100, 106)],
+      ignored_insertions: vec![InsertionLocation { pos: 200, length: 3 }],
+      deletions_count_threshold: 4,
+      deletions_length_threshold: 0,
+      insertions_count_threshold: 0,
+      insertions_length_threshold: 10,
+    };
+
+    let private_nuc_mutations = dels([10, 11, 20, 100, 101, 102, 103, 104, 105]);
+    let insertions = [ins(50, 2), ins(200, 3), ins(300, 6)];
+
+    let result = rule_indels(
+      &private_nuc_mutations,
+      &insertions,
+      &config,
+      &QcStatusThresholds::default(),
+    )
+    .unwrap();
+
+    assert_eq!(result.total_deletion_ranges, 2);
+    assert_eq!(result.total_deleted_nucs, 3);
+    assert_eq!(result.total_deletion_ranges_ignored, 1);
+    assert_eq!(result.total_insertions, 2);
+    assert_eq!(result.total_inserted_nucs, 8);
+    assert_eq!(result.total_insertions_ignored, 1);
+    assert_eq!(result.score, 80.0);
+    assert_eq!(result.status, QcStatus::Mediocre);
+  }
+}
@@ -4,7 +4,7 @@ use crate::analyze::letter_ranges::NucRange;
 use crate::analyze::nuc_del::NucDel;
 use crate::coord::position::PositionLike;
 use crate::coord::range::Range;
-use crate::qc::qc_config::QcRulesConfigPrivateMutations;
+use crate::qc::qc_config::{QcRulesConfigPrivateMutations, QcStatusThresholds};
 use crate::qc::qc_run::{QcRule, QcStatus};
 use num::traits::clamp_min;
 use serde::{Deserialize, Serialize};
@@ -17,6 +17,8 @@ pub struct QcResultPrivateMutations {
   pub num_reversion_substitutions: usize,
   pub num_labeled_substitutions: usize,
   pub num_unlabeled_substitutions: usize,
+  #[serde(default)]
+  pub num_seen_in_tree_substitutions: usize,
   pub total_deletion_ranges: usize,
   pub weighted_total: f64,
   pub excess: f64,
@@ -32,6 +34,7 @@ impl QcRule for QcResultPrivateMutations {
 pub fn rule_private_mutations(
   private_nuc_mutations: &PrivateNucMutations,
   config: &QcRulesConfigPrivateMutations,
+  thresholds: &QcStatusThresholds,
 ) -> Option<QcResultPrivateMutations> {
   if !config.enabled {
     return None;
@@ -45,15 +48,33 @@ pub fn rule_private_mutations(
   let deletion_ranges = find_deletion_ranges(&private_nuc_mutations.private_deletions);
   let total_deletion_ranges = deletion_ranges.len();
 
+  // Unlabeled substitutions which are also seen in the reference tree are optionally weighted separately
+  let num_seen_in_tree_substitutions = if config.weight_seen_in_tree_substitutions.is_some() {
+    private_nuc_mutations
+      .unlabeled_substitutions
+      .iter()
+      .filter(|sub| {
+        private_nuc_mutations
+          .seen_in_tree_substitutions
+          .iter()
+          .any(|seen| &seen.substitution == *sub)
+      })
+      .count()
+  } else {
+    0
+  };
+  let num_novel_unlabeled_substitutions = num_unlabeled_substitutions - num_seen_in_tree_substitutions;
+
   let weighted_total = 0.0
     + config.weight_reversion_substitutions * num_reversion_substitutions as f64
     + config.weight_labeled_substitutions * num_labeled_substitutions as f64
-    + config.weight_unlabeled_substitutions * num_unlabeled_substitutions as f64
+    + config.weight_unlabeled_substitutions * num_novel_unlabeled_substitutions as f64
+    + config.weight_seen_in_tree_substitutions.unwrap_or_default() * num_seen_in_tree_substitutions as f64
     + total_deletion_ranges as f64;
 
   // the score hits 100 if the excess mutations equals the cutoff value
   let score = (clamp_min(weighted_total - config.typical, 0.0) * 100.0) / config.cutoff;
-  let status = QcStatus::from_score(score);
+  let status = QcStatus::from_score(score, thresholds);
 
   Some(QcResultPrivateMutations {
     score,
@@ -61,6 +82,7 @@ pub fn rule_private_mutations(
     num_reversion_substitutions,
     num_labeled_substitutions,
     num_unlabeled_substitutions,
+    num_seen_in_tree_substitutions,
     total_deletion_ranges,
     weighted_total,
     excess: weighted_total - config.typical,
@@ -74,7 +96,7 @@ pub fn rule_private_mutations(
 /// form of ranges, private nucleotide deletions are listed
 /// individually. We compute the ranges for private deletions here.
 ///
-fn find_deletion_ranges(dels: &[NucDel]) -> Vec<NucRange> {
+pub fn find_deletion_ranges(dels: &[NucDel]) -> Vec<NucRange> {
   if dels.is_empty() {
     return vec![];
   }
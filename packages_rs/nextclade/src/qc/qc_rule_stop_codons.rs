@@ -1,4 +1,4 @@
-use crate::qc::qc_config::{QcRulesConfigStopCodons, StopCodonLocation};
+use crate::qc::qc_config::{QcRulesConfigStopCodons, QcStatusThresholds, StopCodonLocation};
 use crate::qc::qc_run::{QcRule, QcStatus};
 use crate::translate::translate_genes::{CdsTranslation, Translation};
 use serde::{Deserialize, Serialize};
@@ -20,7 +20,11 @@ impl QcRule for QcResultStopCodons {
   }
 }
 
-pub fn rule_stop_codons(translation: &Translation, config: &QcRulesConfigStopCodons) -> Option<QcResultStopCodons> {
+pub fn rule_stop_codons(
+  translation: &Translation,
+  config: &QcRulesConfigStopCodons,
+  thresholds: &QcStatusThresholds,
+) -> Option<QcResultStopCodons> {
   if !config.enabled {
     return None;
   }
@@ -51,7 +55,7 @@ pub fn rule_stop_codons(translation: &Translation, config: &QcRulesConfigStopCod
   let total_stop_codons_ignored = stop_codons_ignored.len();
 
   let score = total_stop_codons as f64 * config.score_weight;
-  let status = QcStatus::from_score(score);
+  let status = QcStatus::from_score(score, thresholds);
 
   Some(QcResultStopCodons {
     score,
@@ -1,7 +1,11 @@
+use crate::align::insertions_strip::NucIns;
 use crate::alphabet::nuc::Nuc;
 use crate::analyze::find_private_nuc_mutations::PrivateNucMutations;
-use crate::qc::qc_config::QcConfig;
+use crate::qc::qc_config::{QcConfig, QcScoreAggregation, QcStatusOverride, QcStatusThresholds};
+use crate::qc::qc_rule_clock::{rule_clock, QcResultClock};
+use crate::qc::qc_rule_custom::QcResultCustomRule;
 use crate::qc::qc_rule_frame_shifts::{rule_frame_shifts, QcResultFrameShifts};
+use crate::qc::qc_rule_indels::{rule_indels, QcResultIndels};
 use crate::qc::qc_rule_missing_data::{rule_missing_data, QcResultMissingData};
 use crate::qc::qc_rule_mixed_sites::{rule_mixed_sites, QcResultMixedSites};
 use crate::qc::qc_rule_private_mutations::{rule_private_mutations, QcResultPrivateMutations};
@@ -9,11 +13,13 @@ use crate::qc::qc_rule_snp_clusters::{rule_snp_clusters, QcResultSnpClusters};
 use crate::qc::qc_rule_stop_codons::{rule_stop_codons, QcResultStopCodons};
 use crate::translate::frame_shifts_translate::FrameShift;
 use crate::translate::translate_genes::Translation;
+use crate::tree::tree_clock::ClockModel;
 use num::traits::Pow;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
-#[derive(Clone, Debug, Serialize, Deserialize, schemars::JsonSchema)]
+/// QC status. Variants are ordered from best to worst.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "lowercase")]
 #[derive(Default)]
 pub enum QcStatus {
@@ -34,11 +40,11 @@ impl ToString for QcStatus {
 }
 
 impl QcStatus {
-  pub fn from_score(score: f64) -> QcStatus {
-    if (30.0..100.0).contains(&score) {
-      QcStatus::Mediocre
-    } else if score >= 100.0 {
+  pub fn from_score(score: f64, thresholds: &QcStatusThresholds) -> QcStatus {
+    if score >= thresholds.bad {
       QcStatus::Bad
+    } else if score >= thresholds.mediocre {
+      QcStatus::Mediocre
     } else {
       QcStatus::Good
     }
@@ -54,6 +60,12 @@ pub struct QcResult {
   pub snp_clusters: Option<QcResultSnpClusters>,
   pub frame_shifts: Option<QcResultFrameShifts>,
   pub stop_codons: Option<QcResultStopCodons>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub indels: Option<QcResultIndels>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub clock: Option<QcResultClock>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub custom_rules: Vec<QcResultCustomRule>,
   pub overall_score: f64,
   pub overall_status: QcStatus,
 }
@@ -68,35 +80,271 @@ pub fn qc_run(
   total_missing: usize,
   translation: &Translation,
   frame_shifts: &[FrameShift],
+  insertions: &[NucIns],
+  clade: &str,
+  divergence: f64,
+  date: Option<f64>,
+  clock_model: Option<&ClockModel>,
   config: &QcConfig,
 ) -> QcResult {
+  let thresholds = &config.status_thresholds;
   let mut result = QcResult {
-    missing_data: rule_missing_data(total_missing, &config.missing_data),
-    mixed_sites: rule_mixed_sites(nucleotide_composition, &config.mixed_sites),
-    private_mutations: rule_private_mutations(private_nuc_mutations, &config.private_mutations),
-    snp_clusters: rule_snp_clusters(private_nuc_mutations, &config.snp_clusters),
-    frame_shifts: rule_frame_shifts(frame_shifts, &config.frame_shifts),
-    stop_codons: rule_stop_codons(translation, &config.stop_codons),
+    missing_data: rule_missing_data(total_missing, &config.missing_data, thresholds),
+    mixed_sites: rule_mixed_sites(nucleotide_composition, &config.mixed_sites, thresholds),
+    private_mutations: rule_private_mutations(private_nuc_mutations, &config.private_mutations, thresholds),
+    snp_clusters: rule_snp_clusters(private_nuc_mutations, &config.snp_clusters, thresholds),
+    frame_shifts: rule_frame_shifts(frame_shifts, &config.frame_shifts, thresholds),
+    stop_codons: rule_stop_codons(translation, &config.stop_codons, thresholds),
+    indels: rule_indels(private_nuc_mutations, insertions, &config.indels, thresholds),
+    clock: rule_clock(clade, divergence, date, clock_model, &config.clock, thresholds),
+    custom_rules: vec![],
     overall_score: 0.0,
     overall_status: QcStatus::Good,
   };
 
-  result.overall_score += add_score(&result.missing_data);
-  result.overall_score += add_score(&result.mixed_sites);
-  result.overall_score += add_score(&result.private_mutations);
-  result.overall_score += add_score(&result.snp_clusters);
-  result.overall_score += add_score(&result.frame_shifts);
-  result.overall_score += add_score(&result.stop_codons);
-
-  result.overall_status = QcStatus::from_score(result.overall_score);
+  qc_aggregate(&mut result, config);
 
   result
 }
 
-fn add_score<R: QcRule>(rule_result: &Option<R>) -> f64 {
-  if let Some(rule_result) = rule_result {
-    rule_result.score().pow(2.0) * 0.01
-  } else {
-    0.0
+/// Adds results of user-defined rules to the results of built-in rules. Custom rules are evaluated over complete
+/// analysis results, so they can only run after the built-in rules.
+pub fn qc_add_custom_rules(result: &mut QcResult, custom_rules: Vec<QcResultCustomRule>, config: &QcConfig) {
+  result.custom_rules = custom_rules;
+  qc_aggregate(result, config);
+}
+
+/// Calculates overall QC score and status from the results of individual rules and applies status overrides
+fn qc_aggregate(result: &mut QcResult, config: &QcConfig) {
+  let scores = rule_scores(result);
+  result.overall_score = aggregate_scores(&scores, config);
+  result.overall_status = QcStatus::from_score(result.overall_score, &config.status_thresholds);
+
+  for status_override in &config.status_overrides {
+    if let Some(status) = apply_status_override(result, status_override) {
+      result.overall_status = result.overall_status.max(status);
+    }
+  }
+}
+
+/// Lists scores of all rules which produced a result, along with rule names
+fn rule_scores(result: &QcResult) -> Vec<(&str, f64)> {
+  fn score<'a, R: QcRule>(name: &'a str, rule_result: &Option<R>) -> Option<(&'a str, f64)> {
+    rule_result.as_ref().map(|rule_result| (name, rule_result.score()))
+  }
+
+  [
+    score("missingData", &result.missing_data),
+    score("mixedSites", &result.mixed_sites),
+    score("privateMutations", &result.private_mutations),
+    score("snpClusters", &result.snp_clusters),
+    score("frameShifts", &result.frame_shifts),
+    score("stopCodons", &result.stop_codons),
+    score("indels", &result.indels),
+    score("clock", &result.clock),
+  ]
+  .into_iter()
+  .flatten()
+  .chain(result.custom_rules.iter().map(|rule| (rule.name.as_str(), rule.score)))
+  .collect()
+}
+
+fn aggregate_scores(scores: &[(&str, f64)], config: &QcConfig) -> f64 {
+  let scores = scores.iter();
+  match config.score_aggregation {
+    QcScoreAggregation::SumOfSquares => scores.map(|(_, score)| score.pow(2.0) * 0.01).sum(),
+    QcScoreAggregation::Sum => scores.map(|(_, score)| score).sum(),
+    QcScoreAggregation::WeightedSum => scores
+      .map(|(name, score)| config.rule_weights.get(*name).unwrap_or(&1.0) * score)
+      .sum(),
+    QcScoreAggregation::Max => scores.map(|(_, score)| *score).fold(0.0, f64::max),
+  }
+}
+
+/// Raises status of the rule to the override status, if the rule is triggered. Returns the new status of the rule, or
+/// `None` if the override does not apply.
+fn apply_status_override(result: &mut QcResult, status_override: &QcStatusOverride) -> Option<QcStatus> {
+  let QcStatusOverride { rule, cds_name, status } = status_override;
+  let in_cds = |name: &str| cds_name.as_ref().map_or(true, |cds_name| cds_name == name);
+
+  let (triggered, rule_status) = match rule.as_str() {
+    "missingData" => result.missing_data.as_mut().map(|r| (r.score > 0.0, &mut r.status)),
+    "mixedSites" => result.mixed_sites.as_mut().map(|r| (r.score > 0.0, &mut r.status)),
+    "privateMutations" => result
+      .private_mutations
+      .as_mut()
+      .map(|r| (r.score > 0.0, &mut r.status)),
+    "snpClusters" => result.snp_clusters.as_mut().map(|r| (r.score > 0.0, &mut r.status)),
+    "indels" => result.indels.as_mut().map(|r| (r.score > 0.0, &mut r.status)),
+    // Clock score is positive for any deviation, and reaches 100 when the deviation reaches the allowed deviation
+    "clock" => result.clock.as_mut().map(|r| (r.score >= 100.0, &mut r.status)),
+    "frameShifts" => result.frame_shifts.as_mut().map(|r| {
+      let triggered = r.frame_shifts.iter().any(|fs| in_cds(&fs.cds_name));
+      (triggered, &mut r.status)
+    }),
+    "stopCodons" => result.stop_codons.as_mut().map(|r| {
+      let triggered = r.stop_codons.iter().any(|sc| in_cds(&sc.cds_name));
+      (triggered, &mut r.status)
+    }),
+    name => result
+      .custom_rules
+      .iter_mut()
+      .find(|r| r.name == name)
+      .map(|r| (r.triggered, &mut r.status)),
+  }?;
+
+  triggered.then(|| {
+    *rule_status = (*rule_status).max(*status);
+    *rule_status
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::float_cmp)]
+
+  use super::*;
+  use crate::qc::qc_config::QcRulesConfigClock;
+  use crate::qc::qc_rule_custom::QcResultCustomRule;
+  use crate::qc::qc_rule_missing_data::QcResultMissingData;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  fn result_with_scores(missing_data: f64, custom: f64) -> QcResult {
+    QcResult {
+      missing_data: Some(QcResultMissingData {
+        score: missing_data,
+        ..QcResultMissingData::default()
+      }),
+      custom_rules: vec![QcResultCustomRule {
+        name: "custom".to_owned(),
+        score: custom,
+        status: QcStatus::Good,
+        triggered: custom > 0.0,
+      }],
+      ..QcResult::default()
+    }
+  }
+
+  #[rstest]
+  #[case(QcScoreAggregation::SumOfSquares, 29.0, QcStatus::Good)]
+  #[case(QcScoreAggregation::Sum, 70.0, QcStatus::Bad)]
+  #[case(QcScoreAggregation::WeightedSum, 90.0, QcStatus::Bad)]
+  #[case(QcScoreAggregation::Max, 50.0, QcStatus::Mediocre)]
+  fn aggregates_scores(
+    #[case] score_aggregation: QcScoreAggregation,
+    #[case] expected_score: f64,
+    #[case] expected_status: QcStatus,
+  ) {
+    let config = QcConfig {
+      score_aggregation,
+      status_thresholds: QcStatusThresholds {
+        mediocre: 40.0,
+        bad: 60.0,
+      },
+      rule_weights: BTreeMap::from([("missingData".to_owned(), 2.0)]),
+      ..QcConfig::default()
+    };
+    let mut result = result_with_scores(20.0, 50.0);
+    qc_aggregate(&mut result, &config);
+    assert_eq!(result.overall_score, expected_score);
+    assert_eq!(result.overall_status, expected_status);
+  }
+
+  #[rstest]
+  fn applies_status_overrides() {
+    let config = QcConfig {
+      status_overrides: vec![QcStatusOverride {
+        rule: "custom".to_owned(),
+        cds_name: None,
+        status: QcStatus::Bad,
+      }],
+      ..QcConfig::default()
+    };
+
+    let mut result = result_with_scores(0.0, 1.0);
+    qc_aggregate(&mut result, &config);
+    assert_eq!(result.overall_status, QcStatus::Bad);
+    assert_eq!(result.custom_rules[0].status, QcStatus::Bad);
+
+    let mut result = result_with_scores(0.0, 0.0);
+    qc_aggregate(&mut result, &config);
+    assert_eq!(result.overall_status, QcStatus::Good);
+  }
+
+  #[rstest]
+  #[case::small_deviation(50.0, QcStatus::Good)]
+  #[case::outlier(100.0, QcStatus::Bad)]
+  fn applies_clock_status_override(#[case] score: f64, #[case] expected_status: QcStatus) {
+    let config = QcConfig {
+      status_overrides: vec![QcStatusOverride {
+        rule: "clock".to_owned(),
+        cds_name: None,
+        status: QcStatus::Bad,
+      }],
+      ..QcConfig::default()
+    };
+    let mut result = QcResult {
+      clock: Some(QcResultClock {
+        score,
+        status: QcStatus::Good,
+        ..QcResultClock::default()
+      }),
+      ..result_with_scores(0.0, 0.0)
+    };
+    qc_aggregate(&mut result, &config);
+    assert_eq!(
+      (result.clock.map(|clock| clock.status), result.overall_status),
+      (Some(expected_status), expected_status)
+    );
+  }
+
+  #[rstest]
+  #[case(Some(2020.0), 0.0015, Some(50.0))]
+  #[case(Some(2020.0), 0.0005, Some(50.0))]
+  #[case(Some(2020.0), 0.001, Some(0.0))]
+  #[case(None, 0.0015, None)]
+  fn scores_clock_deviation(#[case] date: Option<f64>, #[case] divergence: f64, #[case] expected_score: Option<f64>) {
+    let clock_model = ClockModel {
+      rate: 0.0001,
+      intercept: -0.2,
+      clade_intercepts: BTreeMap::from([("A".to_owned(), -0.201)]),
+      residual_std_dev: 0.00025,
+    };
+    let config = QcRulesConfigClock {
+      enabled: true,
+      deviation_threshold: 4.0,
+      min_allowed_deviation: 0.0,
+    };
+
+    // Expected divergence of clade "A" at 2020.0 is 0.001, allowed deviation is 0.001
+    let actual = rule_clock(
+      "A",
+      divergence,
+      date,
+      Some(&clock_model),
+      &config,
+      &QcStatusThresholds::default(),
+    )
+    .map(|result| (result.score * 1e6).round() / 1e6);
+
+    assert_eq!(actual, expected_score);
+  }
+
+  #[rstest]
+  fn includes_clock_score_in_overall_score() {
+    let mut result = QcResult {
+      clock: Some(QcResultClock {
+        score: 50.0,
+        ..QcResultClock::default()
+      }),
+      ..result_with_scores(0.0, 0.0)
+    };
+    let config = QcConfig {
+      score_aggregation: QcScoreAggregation::Sum,
+      ..QcConfig::default()
+    };
+    qc_aggregate(&mut result, &config);
+    assert_eq!(result.overall_score, 50.0);
   }
 }
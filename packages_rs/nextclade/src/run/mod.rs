@@ -2,3 +2,4 @@ pub mod nextclade_run_one;
 pub mod nextclade_wasm;
 pub mod params;
 pub mod params_general;
+pub mod run_summary;
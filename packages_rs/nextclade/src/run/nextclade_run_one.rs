@@ -6,11 +6,16 @@ use crate::alphabet::nuc::Nuc;
 use crate::analyze::aa_changes::{find_aa_changes, AaChangesGroup, FindAaChangesOutput};
 use crate::analyze::aa_del::AaDel;
 use crate::analyze::aa_sub::AaSub;
+use crate::analyze::cds_coverage::calculate_cds_coverage;
 use crate::analyze::divergence::calculate_branch_length;
+use crate::analyze::drug_resistance::find_drug_resistance;
 use crate::analyze::find_aa_motifs::find_aa_motifs;
 use crate::analyze::find_aa_motifs_changes::find_aa_motifs_changes;
+use crate::analyze::find_nuc_motifs::find_nuc_motifs;
+use crate::analyze::find_nuc_motifs_changes::find_nuc_motifs_changes;
 use crate::analyze::find_private_aa_mutations::{find_private_aa_mutations, PrivateAaMutations};
 use crate::analyze::find_private_nuc_mutations::{find_private_nuc_mutations, PrivateNucMutations};
+use crate::analyze::find_relative_mutations::{find_relative_mutations, RelativeMutations};
 use crate::analyze::letter_composition::get_letter_composition;
 use crate::analyze::letter_ranges::{
   find_aa_letter_ranges, find_letter_ranges, find_letter_ranges_by, CdsAaRange, NucRange,
@@ -23,14 +28,17 @@ use crate::analyze::virus_properties::PhenotypeData;
 use crate::coord::coord_map_global::CoordMapGlobal;
 use crate::coord::range::AaRefRange;
 use crate::graph::node::GraphNodeKey;
-use crate::qc::qc_run::qc_run;
+use crate::qc::qc_rule_custom::rule_custom;
+use crate::qc::qc_run::{qc_add_custom_rules, qc_run};
 use crate::run::nextclade_wasm::{AnalysisOutput, Nextclade};
 use crate::translate::aa_alignment_ranges::{gather_aa_alignment_ranges, GatherAaAlignmentRangesResult};
 use crate::translate::frame_shifts_flatten::frame_shifts_flatten;
 use crate::translate::frame_shifts_translate::FrameShift;
 use crate::translate::translate_genes::{translate_genes, Translation};
 use crate::tree::tree_find_nearest_node::graph_find_nearest_nodes;
-use crate::types::outputs::{NextcladeOutputs, PeptideWarning, PhenotypeValue};
+use crate::tree::tree_nearest_tips::{find_nearest_tips, NearestTip};
+use crate::tree::tree_placement_uncertainty::{calculate_placement_uncertainty, PlacementUncertainty};
+use crate::types::outputs::{seq_warning_prefix, NextcladeOutputs, PeptideWarning, PhenotypeValue};
 use eyre::Report;
 use itertools::Itertools;
 use std::collections::{BTreeMap, HashSet};
@@ -67,6 +75,9 @@ struct NextcladeResultWithGraph {
   custom_node_attributes: BTreeMap<String, String>,
   nearest_node_id: GraphNodeKey,
   nearest_nodes: Option<Vec<String>>,
+  relative_mutations: Vec<RelativeMutations>,
+  placement_uncertainty: PlacementUncertainty,
+  nearest_tips: Vec<NearestTip>,
 }
 
 pub fn nextclade_run_one(
@@ -85,8 +96,15 @@ pub fn nextclade_run_one(
     gap_open_close_aa,
     ref_translation,
     aa_motifs_ref,
+    nuc_motifs_ref,
     graph,
     primers,
+    ref_node_keys,
+    sample_dates,
+    clock_model,
+    drug_resistance_rules,
+    nuc_motifs_matchers,
+    qc_custom_rules,
     ..
   } = &state;
 
@@ -183,7 +201,7 @@ pub fn nextclade_run_one(
       if alignment.is_reverse_complement {
         warnings.push(PeptideWarning {
             cds_name: "nuc".to_owned(),
-            warning: format!("When processing sequence #{index} '{seq_name}': Sequence is reverse-complemented: Seed matching failed for the original sequence, but succeeded for its reverse complement. Outputs will be derived from the reverse complement and 'reverse complement' suffix will be added to sequence ID.")
+            warning: format!("{}Sequence is reverse-complemented: Seed matching failed for the original sequence, but succeeded for its reverse complement. Outputs will be derived from the reverse complement and 'reverse complement' suffix will be added to sequence ID.", seq_warning_prefix(index, seq_name))
           });
       }
 
@@ -246,6 +264,8 @@ pub fn nextclade_run_one(
     NextcladeResultWithAa::default()
   };
 
+  let cds_coverage = calculate_cds_coverage(&aa_alignment_ranges, &aa_unsequenced_ranges, &unknown_aa_ranges);
+
   let NextcladeResultWithGraph {
     clade,
     private_nuc_mutations,
@@ -255,8 +275,24 @@ pub fn nextclade_run_one(
     custom_node_attributes,
     nearest_node_id,
     nearest_nodes,
+    relative_mutations,
+    placement_uncertainty,
+    nearest_tips,
   } = if let Some(graph) = graph {
-    let nearest_node_candidates = graph_find_nearest_nodes(graph, &substitutions, &missing, &alignment_range)?;
+    // Candidates are only needed within the tolerance for placement uncertainty and until enough tips are found
+    let distance_tolerance = if params.general.include_placement_uncertainty {
+      params.general.placement_distance_tolerance
+    } else {
+      0
+    };
+    let nearest_node_candidates = graph_find_nearest_nodes(
+      graph,
+      &substitutions,
+      &missing,
+      &alignment_range,
+      distance_tolerance,
+      params.general.nearest_tips,
+    )?;
     let nearest_node_key = nearest_node_candidates[0].node_key;
     let nearest_node = graph.get_node(nearest_node_key)?.payload();
 
@@ -274,6 +310,24 @@ pub fn nextclade_run_one(
     let clade_node_attr_keys = graph.data.meta.clade_node_attr_descs();
     let clade_node_attrs = nearest_node.get_clade_node_attrs(clade_node_attr_keys);
 
+    let placement_uncertainty = if params.general.include_placement_uncertainty {
+      calculate_placement_uncertainty(
+        graph,
+        &nearest_node_candidates,
+        params.general.placement_distance_tolerance,
+        clade_node_attr_keys,
+      )?
+    } else {
+      PlacementUncertainty::default()
+    };
+
+    let nearest_tips = find_nearest_tips(
+      graph,
+      &nearest_node_candidates,
+      params.general.nearest_tips,
+      &params.general.nearest_tips_attrs,
+    )?;
+
     let private_nuc_mutations = find_private_nuc_mutations(
       nearest_node,
       &substitutions,
@@ -283,6 +337,7 @@ pub fn nextclade_run_one(
       ref_seq,
       &non_acgtns,
       virus_properties,
+      &graph.data.tmp.nuc_sub_branch_counts,
     );
 
     let private_aa_mutations = find_private_aa_mutations(
@@ -294,6 +349,25 @@ pub fn nextclade_run_one(
       ref_translation,
       gene_map,
     );
+
+    let relative_mutations = find_relative_mutations(
+      graph,
+      ref_node_keys,
+      &substitutions,
+      &deletions,
+      &missing,
+      &alignment_range,
+      ref_seq,
+      &non_acgtns,
+      &aa_substitutions,
+      &aa_deletions,
+      &unknown_aa_ranges,
+      &aa_unsequenced_ranges,
+      ref_translation,
+      gene_map,
+      virus_properties,
+    )?;
+
     let parent_div = nearest_node.node_attrs.div.unwrap_or(0.0);
     let masked_ranges = graph.data.meta.placement_mask_ranges();
     let divergence = parent_div
@@ -312,7 +386,7 @@ pub fn nextclade_run_one(
           if ignore.clades.contains(&clade) {
             return None;
           }
-          let phenotype = calculate_phenotype(phenotype_data, &aa_substitutions);
+          let phenotype = calculate_phenotype(phenotype_data, &aa_substitutions, &aa_deletions, &aa_insertions);
           Some(PhenotypeValue {
             name: name.clone(),
             cds: cds.clone(),
@@ -331,6 +405,9 @@ pub fn nextclade_run_one(
       custom_node_attributes: clade_node_attrs,
       nearest_node_id: nearest_node_key,
       nearest_nodes,
+      relative_mutations,
+      placement_uncertainty,
+      nearest_tips,
     }
   } else {
     NextcladeResultWithGraph::default()
@@ -339,6 +416,17 @@ pub fn nextclade_run_one(
   let aa_motifs = find_aa_motifs(&virus_properties.aa_motifs, &translation)?;
   let aa_motifs_changes = find_aa_motifs_changes(aa_motifs_ref, &aa_motifs, ref_translation, &translation)?;
 
+  let nuc_motifs = find_nuc_motifs(nuc_motifs_matchers, &stripped.qry_seq, &alignment_range);
+  let nuc_motifs_changes = find_nuc_motifs_changes(
+    nuc_motifs_ref,
+    &nuc_motifs,
+    ref_seq,
+    &stripped.qry_seq,
+    &alignment_range,
+  );
+
+  let drug_resistance = find_drug_resistance(drug_resistance_rules, &aa_substitutions, &aa_deletions);
+
   let qc = virus_properties
     .qc
     .as_ref()
@@ -349,6 +437,11 @@ pub fn nextclade_run_one(
         total_missing,
         &translation,
         &frame_shifts,
+        &insertions,
+        &clade,
+        divergence,
+        sample_dates.get(seq_name).copied(),
+        clock_model.as_ref(),
         qc_config,
       )
     })
@@ -356,56 +449,70 @@ pub fn nextclade_run_one(
 
   let is_reverse_complement = alignment.is_reverse_complement;
 
+  let mut analysis_result = NextcladeOutputs {
+    index,
+    seq_name: seq_name.to_owned(),
+    substitutions,
+    total_substitutions,
+    deletions,
+    total_deletions,
+    insertions,
+    total_insertions,
+    missing,
+    total_missing,
+    non_acgtns,
+    total_non_acgtns,
+    nucleotide_composition,
+    frame_shifts,
+    total_frame_shifts,
+    aa_substitutions,
+    total_aminoacid_substitutions,
+    aa_deletions,
+    total_aminoacid_deletions,
+    aa_insertions,
+    total_aminoacid_insertions,
+    unknown_aa_ranges,
+    total_unknown_aa,
+    aa_changes_groups,
+    nuc_to_aa_muts,
+    alignment_range,
+    alignment_score,
+    aa_alignment_ranges,
+    aa_unsequenced_ranges,
+    pcr_primer_changes,
+    total_pcr_primer_changes,
+    warnings,
+    missing_cdses: missing_genes,
+    coverage,
+    cds_coverage,
+    aa_motifs,
+    aa_motifs_changes,
+    nuc_motifs,
+    nuc_motifs_changes,
+    drug_resistance,
+    qc,
+    clade,
+    private_nuc_mutations,
+    private_aa_mutations,
+    phenotype_values,
+    divergence,
+    custom_node_attributes,
+    nearest_node_id,
+    nearest_nodes,
+    relative_mutations,
+    placement_uncertainty,
+    nearest_tips,
+    is_reverse_complement,
+  };
+
+  if let Some(qc_config) = &virus_properties.qc {
+    let custom_rules = rule_custom(&analysis_result, qc_custom_rules, &qc_config.status_thresholds)?;
+    qc_add_custom_rules(&mut analysis_result.qc, custom_rules, qc_config);
+  }
+
   Ok(AnalysisOutput {
     query: stripped.qry_seq,
     translation,
-    analysis_result: NextcladeOutputs {
-      index,
-      seq_name: seq_name.to_owned(),
-      substitutions,
-      total_substitutions,
-      deletions,
-      total_deletions,
-      insertions,
-      total_insertions,
-      missing,
-      total_missing,
-      non_acgtns,
-      total_non_acgtns,
-      nucleotide_composition,
-      frame_shifts,
-      total_frame_shifts,
-      aa_substitutions,
-      total_aminoacid_substitutions,
-      aa_deletions,
-      total_aminoacid_deletions,
-      aa_insertions,
-      total_aminoacid_insertions,
-      unknown_aa_ranges,
-      total_unknown_aa,
-      aa_changes_groups,
-      nuc_to_aa_muts,
-      alignment_range,
-      alignment_score,
-      aa_alignment_ranges,
-      aa_unsequenced_ranges,
-      pcr_primer_changes,
-      total_pcr_primer_changes,
-      warnings,
-      missing_cdses: missing_genes,
-      coverage,
-      aa_motifs,
-      aa_motifs_changes,
-      qc,
-      clade,
-      private_nuc_mutations,
-      private_aa_mutations,
-      phenotype_values,
-      divergence,
-      custom_node_attributes,
-      nearest_node_id,
-      nearest_nodes,
-      is_reverse_complement,
-    },
+    analysis_result,
   })
 }
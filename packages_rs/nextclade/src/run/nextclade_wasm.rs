@@ -2,22 +2,37 @@ use crate::align::gap_open::{get_gap_open_close_scores_codon_aware, get_gap_open
 use crate::align::seed_match2::CodonSpacedIndex;
 use crate::alphabet::letter::{serde_deserialize_seq, serde_serialize_seq};
 use crate::alphabet::nuc::{to_nuc_seq, to_nuc_seq_replacing, Nuc};
+use crate::analyze::cds_coverage::get_cds_coverage_keys;
+use crate::analyze::drug_resistance::{get_drug_resistance_keys, parse_drug_resistance_descs, DrugResistanceRules};
 use crate::analyze::find_aa_motifs::find_aa_motifs;
 use crate::analyze::find_aa_motifs_changes::AaMotifsMap;
+use crate::analyze::find_nuc_motifs::{
+  compile_nuc_motifs_descs, find_nuc_motifs, get_nuc_motifs_keys, NucMotifsMap, NucMotifsMatcher,
+};
+use crate::analyze::find_relative_mutations::{find_ref_node_keys, get_relative_mutations_keys};
 use crate::analyze::pcr_primers::PcrPrimer;
 use crate::analyze::phenotype::get_phenotype_attr_descs;
-use crate::analyze::virus_properties::{AaMotifsDesc, PhenotypeAttrDesc, VirusProperties};
+use crate::analyze::virus_properties::{
+  AaMotifsDesc, DrugResistanceDesc, NucMotifsDesc, PhenotypeAttrDesc, VirusProperties,
+};
+use crate::coord::range::NucRefGlobalRange;
 use crate::gene::gene_map::GeneMap;
 use crate::graph::graph::{convert_auspice_tree_to_graph, convert_graph_to_auspice_tree};
+use crate::graph::node::GraphNodeKey;
 use crate::io::fasta::{read_one_fasta_str, FastaRecord};
 use crate::io::nextclade_csv::CsvColumnConfig;
 use crate::io::nwk_writer::convert_graph_to_nwk_string;
+use crate::io::sample_dates::SampleDates;
+use crate::make_error;
+use crate::qc::qc_config::QcConfig;
+use crate::qc::qc_rule_custom::QcCustomRules;
 use crate::run::nextclade_run_one::nextclade_run_one;
 use crate::run::params::{NextcladeInputParams, NextcladeInputParamsOptional};
 use crate::translate::translate_genes::Translation;
 use crate::translate::translate_genes_ref::translate_genes_ref;
 use crate::tree::tree::{AuspiceGraph, AuspiceTree, CladeNodeAttrKeyDesc};
 use crate::tree::tree_builder::graph_attach_new_nodes_in_place;
+use crate::tree::tree_clock::ClockModel;
 use crate::tree::tree_preprocess::graph_preprocess_in_place;
 use crate::types::outputs::NextcladeOutputs;
 use eyre::{Report, WrapErr};
@@ -93,6 +108,13 @@ pub struct AnalysisInitialData<'a> {
   pub phenotype_attr_descs: &'a [PhenotypeAttrDesc],
   pub aa_motifs_descs: &'a [AaMotifsDesc],
   pub aa_motif_keys: &'a [String],
+  pub nuc_motifs_descs: &'a [NucMotifsDesc],
+  pub nuc_motif_keys: &'a [String],
+  pub drug_resistance_descs: &'a [DrugResistanceDesc],
+  pub drug_resistance_keys: &'a [String],
+  pub cds_coverage_keys: &'a [String],
+  pub ref_nodes: &'a [String],
+  pub relative_mutations_keys: &'a [String],
   pub csv_column_config_default: CsvColumnConfig,
 }
 
@@ -125,6 +147,11 @@ pub struct Nextclade {
   pub virus_properties: VirusProperties,
   pub primers: Vec<PcrPrimer>,
   pub params: NextcladeInputParams,
+  pub qc_custom_rules: QcCustomRules,
+  pub nuc_motifs_ref: NucMotifsMap,
+  pub nuc_motifs_descs: Vec<NucMotifsDesc>,
+  pub nuc_motifs_matchers: Vec<NucMotifsMatcher>,
+  pub nuc_motifs_keys: Vec<String>,
 
   // If genome annotation is provided
   pub gene_map: GeneMap,
@@ -133,11 +160,22 @@ pub struct Nextclade {
   pub aa_motifs_ref: AaMotifsMap,
   pub aa_motifs_descs: Vec<AaMotifsDesc>,
   pub aa_motifs_keys: Vec<String>,
+  pub cds_coverage_keys: Vec<String>,
+
+  pub drug_resistance_descs: Vec<DrugResistanceDesc>,
+  pub drug_resistance_rules: Vec<DrugResistanceRules>,
+  pub drug_resistance_keys: Vec<String>,
 
   // If ref tree is provided
   pub graph: Option<AuspiceGraph>,
   pub clade_attr_descs: Vec<CladeNodeAttrKeyDesc>,
   pub phenotype_attr_descs: Vec<PhenotypeAttrDesc>,
+  pub ref_node_keys: Vec<GraphNodeKey>,
+  pub relative_mutations_keys: Vec<String>,
+
+  // If sample dates are provided
+  pub sample_dates: SampleDates,
+  pub clock_model: Option<ClockModel>,
 }
 
 pub struct InitialStateWithAa {
@@ -157,6 +195,7 @@ impl Nextclade {
   pub fn new(
     inputs: NextcladeParams,
     primers: Vec<PcrPrimer>,
+    sample_dates: SampleDates,
     params: &NextcladeInputParamsOptional,
   ) -> Result<Self, Report> {
     let NextcladeParams {
@@ -167,9 +206,26 @@ impl Nextclade {
     } = inputs;
 
     let params = NextcladeInputParams::from_optional(params, &virus_properties)?;
+
+    let qc_custom_rules = virus_properties
+      .qc
+      .as_ref()
+      .map(QcConfig::validate_rules)
+      .transpose()?
+      .unwrap_or_default();
+
     let ref_seq = to_nuc_seq(&ref_record.seq).wrap_err("When converting reference sequence")?;
     let seed_index = CodonSpacedIndex::from_sequence(&ref_seq);
 
+    let nuc_motifs_descs = virus_properties.nuc_motifs.clone();
+    let nuc_motifs_matchers = compile_nuc_motifs_descs(&nuc_motifs_descs)?;
+    let nuc_motifs_ref = find_nuc_motifs(
+      &nuc_motifs_matchers,
+      &ref_seq,
+      &NucRefGlobalRange::from_usize(0, ref_seq.len()),
+    );
+    let nuc_motifs_keys = get_nuc_motifs_keys(&nuc_motifs_descs);
+
     // If genome annotation is present, calculate AA-related parameters
     let InitialStateWithAa {
       gap_open_close_nuc,
@@ -221,9 +277,36 @@ impl Nextclade {
 
     let phenotype_attr_descs = get_phenotype_attr_descs(&virus_properties);
 
+    let ref_node_keys = match &graph {
+      Some(graph) => find_ref_node_keys(graph, &params.general.ref_nodes)
+        .wrap_err("When looking up reference nodes to find relative mutations against")?,
+      None if !params.general.ref_nodes.is_empty() => {
+        return make_error!(
+          "Reference nodes to find relative mutations against are requested, but reference tree is not provided"
+        );
+      }
+      None => vec![],
+    };
+    let relative_mutations_keys = get_relative_mutations_keys(&params.general.ref_nodes);
+
+    let is_clock_rule_enabled = virus_properties.qc.as_ref().map_or(false, |qc| qc.clock.enabled);
+    let clock_model = match &graph {
+      Some(graph) if is_clock_rule_enabled && !sample_dates.is_empty() => Some(
+        ClockModel::from_graph(graph)
+          .wrap_err("When estimating molecular clock of the reference tree for the 'clock' QC rule")?,
+      ),
+      _ => None,
+    };
+
     let aa_motifs_descs = virus_properties.aa_motifs.clone();
     let aa_motifs_keys = aa_motifs_descs.iter().map(|desc| desc.name.clone()).collect_vec();
 
+    let cds_coverage_keys = get_cds_coverage_keys(gene_map.iter_cdses().map(|cds| &cds.name));
+
+    let drug_resistance_descs = virus_properties.resistance.clone();
+    let drug_resistance_rules = parse_drug_resistance_descs(&drug_resistance_descs)?;
+    let drug_resistance_keys = get_drug_resistance_keys(&drug_resistance_descs);
+
     Ok(Self {
       ref_record,
       ref_seq,
@@ -232,15 +315,28 @@ impl Nextclade {
       virus_properties,
       primers,
       params,
+      qc_custom_rules,
+      nuc_motifs_ref,
+      nuc_motifs_descs,
+      nuc_motifs_matchers,
+      nuc_motifs_keys,
       gene_map,
       gap_open_close_aa,
       ref_translation,
       aa_motifs_ref,
       aa_motifs_descs,
       aa_motifs_keys,
+      cds_coverage_keys,
+      drug_resistance_descs,
+      drug_resistance_rules,
+      drug_resistance_keys,
       graph,
       clade_attr_descs,
       phenotype_attr_descs,
+      ref_node_keys,
+      relative_mutations_keys,
+      sample_dates,
+      clock_model,
     })
   }
 
@@ -254,7 +350,15 @@ impl Nextclade {
       phenotype_attr_descs: &self.phenotype_attr_descs,
       aa_motifs_descs: &self.aa_motifs_descs,
       aa_motif_keys: &self.aa_motifs_keys,
-      csv_column_config_default: CsvColumnConfig::default(),
+      nuc_motifs_descs: &self.nuc_motifs_descs,
+      nuc_motif_keys: &self.nuc_motifs_keys,
+      drug_resistance_descs: &self.drug_resistance_descs,
+      drug_resistance_keys: &self.drug_resistance_keys,
+      cds_coverage_keys: &self.cds_coverage_keys,
+      ref_nodes: &self.params.general.ref_nodes,
+      relative_mutations_keys: &self.relative_mutations_keys,
+      csv_column_config_default: CsvColumnConfig::default()
+        .with_placement_uncertainty(self.params.general.include_placement_uncertainty),
     }
   }
 
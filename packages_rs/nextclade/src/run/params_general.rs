@@ -1,3 +1,4 @@
+use crate::o;
 use clap::Parser;
 use optfield::optfield;
 use serde::{Deserialize, Serialize};
@@ -17,6 +18,56 @@ pub struct NextcladeGeneralParams {
   #[clap(num_args=0..=1, default_missing_value = "true")]
   pub include_nearest_node_info: bool,
 
+  /// Whether to estimate uncertainty of the placement of the query sequence on the reference tree and the confidence of
+  /// the clade assignment, and to include them to the outputs. See `--placement-distance-tolerance`.
+  #[clap(long)]
+  #[clap(num_args=0..=1, default_missing_value = "true")]
+  #[serde(default)]
+  pub include_placement_uncertainty: bool,
+
+  /// Maximum excess of distance to the nearest node for a node of the reference tree to be considered a plausible
+  /// placement of the query sequence.
+  ///
+  /// Plausible placements are weighted by their placement prior to estimate the placement uncertainty and the
+  /// confidence of the clade assignment. With the default value of 0 only the nodes equidistant to the query are considered.
+  /// Only used with `--include-placement-uncertainty`.
+  #[clap(long)]
+  #[serde(default)]
+  pub placement_distance_tolerance: usize,
+
+  /// Number of the nearest leaf nodes of the reference tree (i.e. known samples) to report for each query sequence.
+  ///
+  /// Distances are calculated the same way as during placement of the query sequence on the tree. With the default
+  /// value of 0 the nearest leaf nodes are not reported.
+  #[clap(long)]
+  #[serde(default)]
+  pub nearest_tips: usize,
+
+  /// Node attributes of the nearest leaf nodes to report along with their names and distances. See `--nearest-tips`.
+  #[clap(long, value_delimiter = ',')]
+  #[serde(default = "default_nearest_tips_attrs")]
+  #[schemars(default = "default_nearest_tips_attrs::<Vec<String>>")]
+  pub nearest_tips_attrs: Vec<String>,
+
+  /// Maximum SNP distance between two query sequences for them to be linked into the same cluster. See `--output-distance-matrix`.
+  #[clap(long)]
+  #[serde(default = "default_cluster_distance_threshold")]
+  #[schemars(default = "default_cluster_distance_threshold::<usize>")]
+  pub cluster_distance_threshold: usize,
+
+  /// Number of the nearest leaf nodes of the reference tree to keep around each query sequence in the focal context
+  /// tree. See `--output-tree-focal`.
+  #[clap(long)]
+  #[serde(default = "default_focal_context_tips")]
+  #[schemars(default = "default_focal_context_tips::<usize>")]
+  pub focal_context_tips: usize,
+
+  /// Clades of the nodes to include into the ancestral sequence outputs. By default, sequences of all nodes are
+  /// included. See `--output-ancestral`.
+  #[clap(long, value_delimiter = ',')]
+  #[serde(default)]
+  pub ancestral_clades: Vec<String>,
+
   /// Emit output sequences in-order.
   ///
   /// With this flag the program will wait for results from the previous sequences to be written to the output files before writing the results of the next sequences, preserving the same order as in the input file. Due to variable sequence processing times, this might introduce unnecessary waiting times, but ensures that the resulting sequences are written in the same order as they occur in the inputs (except for sequences which have errors).
@@ -39,6 +90,15 @@ pub struct NextcladeGeneralParams {
   #[clap(long)]
   #[clap(num_args=0..=1, default_missing_value = "true")]
   pub replace_unknown: bool,
+
+  /// Names of nodes of the reference tree to report mutations relative to (e.g. clade founders or vaccine strains).
+  ///
+  /// For each of these nodes, nucleotide and aminoacid differences between the query sequence and the node are
+  /// reported, in addition to the private mutations, which are relative to the nearest node. Missing and unsequenced
+  /// regions of the query sequence are not reported as differences. Requires reference tree.
+  #[clap(long, value_delimiter = ',')]
+  #[serde(default)]
+  pub ref_nodes: Vec<String>,
 }
 
 #[allow(clippy::derivable_impls)]
@@ -47,8 +107,77 @@ impl Default for NextcladeGeneralParams {
     Self {
       include_reference: false,
       include_nearest_node_info: false,
+      include_placement_uncertainty: false,
+      placement_distance_tolerance: 0,
+      nearest_tips: 0,
+      nearest_tips_attrs: default_nearest_tips_attrs(),
+      cluster_distance_threshold: default_cluster_distance_threshold(),
+      focal_context_tips: default_focal_context_tips(),
+      ancestral_clades: vec![],
       in_order: false,
       replace_unknown: false,
+      ref_nodes: vec![],
     }
   }
 }
+
+// NOTE: The serde attributes are also copied to the optional counterpart of the struct (see `optfield`), where a
+// missing field must stay unset, such that it does not override the values coming from other sources. That's why the
+// default value functions are generic: they return the default value for the params struct and `None` for the
+// optional struct. The JSON schema generator cannot infer the type, so it is given explicitly in `schemars(default)`.
+trait ParamDefault<T> {
+  fn param_default(value: T) -> Self;
+}
+
+impl<T> ParamDefault<T> for T {
+  fn param_default(value: T) -> Self {
+    value
+  }
+}
+
+impl<T> ParamDefault<T> for Option<T> {
+  fn param_default(_: T) -> Self {
+    None
+  }
+}
+
+fn default_nearest_tips_attrs<T: ParamDefault<Vec<String>>>() -> T {
+  T::param_default(vec![o!("country"), o!("num_date")])
+}
+
+fn default_cluster_distance_threshold<T: ParamDefault<usize>>() -> T {
+  T::param_default(2)
+}
+
+fn default_focal_context_tips<T: ParamDefault<usize>>() -> T {
+  T::param_default(10)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::io::json::json_parse;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  #[rstest]
+  fn missing_fields_deserialize_to_defaults() -> Result<(), eyre::Report> {
+    let actual: NextcladeGeneralParams = json_parse(
+      r#"{ "includeReference": false, "includeNearestNodeInfo": false, "inOrder": false, "replaceUnknown": false }"#,
+    )?;
+    let expected = NextcladeGeneralParams::default();
+    assert_eq!(actual.nearest_tips_attrs, expected.nearest_tips_attrs);
+    assert_eq!(actual.cluster_distance_threshold, expected.cluster_distance_threshold);
+    assert_eq!(actual.focal_context_tips, expected.focal_context_tips);
+    Ok(())
+  }
+
+  #[rstest]
+  fn missing_optional_fields_stay_unset() -> Result<(), eyre::Report> {
+    let actual: NextcladeGeneralParamsOptional = json_parse("{}")?;
+    assert_eq!(actual.nearest_tips_attrs, None);
+    assert_eq!(actual.cluster_distance_threshold, None);
+    assert_eq!(actual.focal_context_tips, None);
+    Ok(())
+  }
+}
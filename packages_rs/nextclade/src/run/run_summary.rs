@@ -0,0 +1,324 @@
+use crate::analyze::nuc_sub::NucSub;
+use crate::io::file::create_file_or_stdout;
+use crate::io::json::{json_write, JsonPretty};
+use crate::make_error;
+use crate::qc::qc_run::QcStatus;
+use crate::types::outputs::NextcladeOutputs;
+use crate::utils::info::this_package_version_str;
+use eyre::{Report, WrapErr};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Maximum number of the most common private mutations listed in the summary
+const SUMMARY_MAX_PRIVATE_MUTATIONS: usize = 20;
+
+/// Overview of results of a run
+#[derive(Clone, Debug, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RunSummary {
+  pub nextclade_version: String,
+  pub total_sequences: usize,
+  pub total_succeeded: usize,
+  pub total_failed: usize,
+  pub clades: Vec<RunSummaryCount>,
+  pub qc_statuses: Vec<RunSummaryCount>,
+  pub private_substitutions: Vec<RunSummaryCount>,
+  pub failures: Vec<RunSummaryFailure>,
+  pub elapsed_seconds: f64,
+  pub sequences_per_second: f64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RunSummaryCount {
+  pub name: String,
+  pub count: usize,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RunSummaryFailure {
+  pub index: usize,
+  pub seq_name: String,
+  pub error: String,
+}
+
+/// Accumulates results of a run, one sequence at a time, to produce `RunSummary`
+#[derive(Default)]
+pub struct RunSummaryBuilder {
+  has_qc: bool,
+  total_succeeded: usize,
+  clades: BTreeMap<String, usize>,
+  qc_statuses: BTreeMap<QcStatus, usize>,
+  private_substitutions: BTreeMap<String, usize>,
+  failures: Vec<RunSummaryFailure>,
+}
+
+impl RunSummaryBuilder {
+  /// QC statuses are only summarized if QC is configured (`has_qc`), because otherwise every sequence has status "good"
+  pub fn new(has_qc: bool) -> Self {
+    Self {
+      has_qc,
+      ..Self::default()
+    }
+  }
+
+  pub fn add_result(&mut self, output: &NextcladeOutputs) {
+    self.add(
+      &output.clade,
+      output.qc.overall_status,
+      &output.private_nuc_mutations.private_substitutions,
+    );
+  }
+
+  fn add(&mut self, clade: &str, qc_status: QcStatus, private_substitutions: &[NucSub]) {
+    self.total_succeeded += 1;
+
+    if !clade.is_empty() {
+      *self.clades.entry(clade.to_owned()).or_default() += 1;
+    }
+
+    if self.has_qc {
+      *self.qc_statuses.entry(qc_status).or_default() += 1;
+    }
+
+    for sub in private_substitutions {
+      *self.private_substitutions.entry(sub.to_string()).or_default() += 1;
+    }
+  }
+
+  pub fn add_error(&mut self, index: usize, seq_name: &str, error: &str) {
+    self.failures.push(RunSummaryFailure {
+      index,
+      seq_name: seq_name.to_owned(),
+      error: error.to_owned(),
+    });
+  }
+
+  pub fn build(self, elapsed: Duration) -> RunSummary {
+    let total_failed = self.failures.len();
+    let total_sequences = self.total_succeeded + total_failed;
+    let elapsed_seconds = elapsed.as_secs_f64();
+    let sequences_per_second = if elapsed_seconds > 0.0 {
+      total_sequences as f64 / elapsed_seconds
+    } else {
+      0.0
+    };
+
+    RunSummary {
+      nextclade_version: this_package_version_str().to_owned(),
+      total_sequences,
+      total_succeeded: self.total_succeeded,
+      total_failed,
+      clades: sorted_counts(self.clades),
+      qc_statuses: self
+        .qc_statuses
+        .into_iter()
+        .map(|(status, count)| RunSummaryCount {
+          name: status.to_string(),
+          count,
+        })
+        .collect(),
+      private_substitutions: sorted_counts(self.private_substitutions)
+        .into_iter()
+        .take(SUMMARY_MAX_PRIVATE_MUTATIONS)
+        .collect(),
+      failures: self
+        .failures
+        .into_iter()
+        .sorted_by_key(|failure| failure.index)
+        .collect(),
+      elapsed_seconds,
+      sequences_per_second,
+    }
+  }
+}
+
+/// Sorts counts from the most to the least common, and alphabetically among equally common entries
+fn sorted_counts(counts: BTreeMap<String, usize>) -> Vec<RunSummaryCount> {
+  counts
+    .into_iter()
+    .map(|(name, count)| RunSummaryCount { name, count })
+    .sorted_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)))
+    .collect()
+}
+
+/// Writes run summary in JSON format to a given path and as HTML report to the same path with extension `.html`
+pub fn run_summary_write(filepath: impl AsRef<Path>, summary: &RunSummary) -> Result<(), Report> {
+  let filepath = filepath.as_ref();
+  let html_filepath = filepath.with_extension("html");
+  if html_filepath == filepath {
+    return make_error!("Path to output run summary {filepath:#?} should not have '.html' extension: the summary is written in JSON format to this path and in HTML format to the same path with extension '.html'");
+  }
+
+  json_write(filepath, summary, JsonPretty(true))?;
+
+  let mut file = create_file_or_stdout(&html_filepath)?;
+  file
+    .write_all(run_summary_to_html(summary).as_bytes())
+    .wrap_err_with(|| format!("When writing run summary report to {html_filepath:#?}"))
+}
+
+/// Renders run summary as a self-contained static HTML page
+pub fn run_summary_to_html(summary: &RunSummary) -> String {
+  let RunSummary {
+    nextclade_version,
+    total_sequences,
+    total_succeeded,
+    total_failed,
+    clades,
+    qc_statuses,
+    private_substitutions,
+    failures,
+    elapsed_seconds,
+    sequences_per_second,
+  } = summary;
+
+  let mut html = String::new();
+  html += r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Nextclade run summary</title>
+<style>
+body { font-family: sans-serif; margin: 2em auto; max-width: 960px; color: #333; }
+h1, h2 { font-weight: 500; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { padding: 0.25em 1em; text-align: left; border-bottom: 1px solid #ddd; vertical-align: top; }
+td.num { text-align: right; }
+.bar { background: #4a8bd6; height: 0.8em; }
+.good { background: #5bbf5b; }
+.mediocre { background: #e6c229; }
+.bad { background: #d64545; }
+</style>
+</head>
+<body>
+<h1>Nextclade run summary</h1>
+"#;
+
+  html += "<table>\n";
+  for (name, value) in [
+    ("Nextclade version", nextclade_version.clone()),
+    ("Sequences", total_sequences.to_string()),
+    ("Succeeded", total_succeeded.to_string()),
+    ("Failed", total_failed.to_string()),
+    ("Elapsed time", format!("{elapsed_seconds:.1} s")),
+    ("Throughput", format!("{sequences_per_second:.1} sequences/s")),
+  ] {
+    writeln!(html, "<tr><th>{name}</th><td>{}</td></tr>", html_escape(&value)).ok();
+  }
+  html += "</table>\n";
+
+  if !clades.is_empty() {
+    html += "<h2>Clades</h2>\n";
+    html += &counts_to_html_table("Clade", clades, *total_succeeded, |_| "bar");
+  }
+
+  if !qc_statuses.is_empty() {
+    html += "<h2>QC status</h2>\n";
+    html += &counts_to_html_table("Status", qc_statuses, *total_succeeded, |name| match name {
+      "good" => "bar good",
+      "mediocre" => "bar mediocre",
+      _ => "bar bad",
+    });
+  }
+
+  if !private_substitutions.is_empty() {
+    html += "<h2>Most common private substitutions</h2>\n";
+    html += &counts_to_html_table("Substitution", private_substitutions, *total_succeeded, |_| "bar");
+  }
+
+  if !failures.is_empty() {
+    html += "<h2>Failed sequences</h2>\n<table>\n<tr><th>Index</th><th>Sequence</th><th>Error</th></tr>\n";
+    for RunSummaryFailure { index, seq_name, error } in failures {
+      writeln!(
+        html,
+        "<tr><td class=\"num\">{index}</td><td>{}</td><td>{}</td></tr>",
+        html_escape(seq_name),
+        html_escape(error)
+      )
+      .ok();
+    }
+    html += "</table>\n";
+  }
+
+  html += "</body>\n</html>\n";
+  html
+}
+
+fn counts_to_html_table(
+  title: &str,
+  counts: &[RunSummaryCount],
+  total: usize,
+  bar_class: impl Fn(&str) -> &'static str,
+) -> String {
+  let mut html = format!("<table>\n<tr><th>{title}</th><th>Count</th><th>Percentage</th><th></th></tr>\n");
+  for RunSummaryCount { name, count } in counts {
+    let percentage = if total > 0 {
+      100.0 * *count as f64 / total as f64
+    } else {
+      0.0
+    };
+    writeln!(
+      html,
+      "<tr><td>{}</td><td class=\"num\">{count}</td><td class=\"num\">{percentage:.1}%</td><td style=\"width: 200px\"><div class=\"{}\" style=\"width: {percentage:.1}%\"></div></td></tr>",
+      html_escape(name),
+      bar_class(name)
+    )
+    .ok();
+  }
+  html += "</table>\n";
+  html
+}
+
+fn html_escape(s: &str) -> String {
+  s.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+    .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::float_cmp)]
+
+  use super::*;
+  use crate::alphabet::nuc::Nuc;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  #[rstest]
+  fn summarizes_results_and_errors() {
+    let mut builder = RunSummaryBuilder::new(true);
+    let sub = NucSub {
+      pos: 99.into(),
+      ref_nuc: Nuc::C,
+      qry_nuc: Nuc::T,
+    };
+    builder.add("B", QcStatus::Good, &[sub.clone()]);
+    builder.add("A", QcStatus::Bad, &[sub]);
+    builder.add("B", QcStatus::Good, &[]);
+    builder.add_error(5, "x", "Unable to align");
+    builder.add_error(3, "y", "Unable to align");
+
+    let summary = builder.build(Duration::from_secs(2));
+
+    let counts = |counts: &[RunSummaryCount]| counts.iter().map(|c| (c.name.clone(), c.count)).collect_vec();
+    assert_eq!(counts(&summary.clades), vec![("B".to_owned(), 2), ("A".to_owned(), 1)]);
+    assert_eq!(
+      counts(&summary.qc_statuses),
+      vec![("good".to_owned(), 2), ("bad".to_owned(), 1)]
+    );
+    assert_eq!(counts(&summary.private_substitutions), vec![("C100T".to_owned(), 2)]);
+    assert_eq!(summary.total_sequences, 5);
+    assert_eq!(summary.total_failed, 2);
+    assert_eq!(summary.failures.iter().map(|f| f.index).collect_vec(), vec![3, 5]);
+    assert_eq!(summary.sequences_per_second, 2.5);
+  }
+}
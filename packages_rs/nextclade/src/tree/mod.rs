@@ -2,7 +2,16 @@ pub mod params;
 pub mod split_muts;
 pub mod split_muts2;
 pub mod tree;
+pub mod tree_ancestral;
 pub mod tree_attach_new_nodes;
 pub mod tree_builder;
+pub mod tree_check;
+pub mod tree_clock;
 pub mod tree_find_nearest_node;
+pub mod tree_focal;
+pub mod tree_from_nwk;
+pub mod tree_from_usher;
+pub mod tree_nearest_tips;
+pub mod tree_placement_uncertainty;
 pub mod tree_preprocess;
+pub mod tree_refine;
@@ -17,6 +17,27 @@ pub struct TreeBuilderParams {
 
   #[clap(long)]
   pub masked_muts_weight: f64,
+
+  /// Maximum number of iterations of topology refinement after the query sequences are placed on the tree.
+  ///
+  /// Query sequences are placed onto the tree one at a time, and the placement of a sequence is not revisited when the
+  /// subsequent sequences are placed. During each iteration of refinement, each of the placed sequences is detached from
+  /// the tree and attached again at the best position nearby (subtree pruning and regrafting), if this reduces the
+  /// total parsimony score of the tree. Refinement stops when an iteration makes no changes. With the default value of 0
+  /// the refinement is disabled.
+  #[clap(long)]
+  pub max_refinement_iterations: usize,
+
+  /// Collapse query sequences which have identical private mutations relative to the same nearest node into a single
+  /// node of the output tree.
+  ///
+  /// Without this option, each of many identical sequences is attached to the tree as a separate node, which results
+  /// in large polytomies of zero-length branches. The collapsed node is named after the first of the sequences and
+  /// lists names and the number of all the collapsed sequences in its node attributes (`identical_sequences` and
+  /// `identical_sequences_count`).
+  #[clap(long)]
+  #[clap(num_args=0..=1, default_missing_value = "true")]
+  pub collapse_identical_queries: bool,
 }
 
 #[allow(clippy::derivable_impls)]
@@ -25,6 +46,8 @@ impl Default for TreeBuilderParams {
     Self {
       without_greedy_tree_builder: false,
       masked_muts_weight: 0.05,
+      max_refinement_iterations: 0,
+      collapse_identical_queries: false,
     }
   }
 }
@@ -1,6 +1,7 @@
 use crate::alphabet::aa::Aa;
 use crate::alphabet::nuc::Nuc;
 use crate::analyze::find_private_nuc_mutations::BranchMutations;
+use crate::analyze::nuc_sub::NucSub;
 use crate::coord::position::{AaRefPosition, NucRefGlobalPosition};
 use crate::coord::range::NucRefGlobalRange;
 use crate::graph::edge::{Edge, GraphEdge};
@@ -9,6 +10,7 @@ use crate::graph::node::{GraphNode, Node};
 use crate::graph::traits::{HasDivergence, HasName};
 use crate::io::fs::read_file_to_string;
 use crate::io::json::json_parse;
+use crate::tree::tree_find_nearest_node::NucSubIndex;
 use eyre::{Report, WrapErr};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::BTreeMap;
@@ -55,6 +57,11 @@ pub type AuspiceGraphEdge = Edge<AuspiceGraphNodePayload>;
 pub struct GraphTempData {
   pub max_divergence: f64,
   pub divergence_units: DivergenceUnits,
+  /// Number of branches of the reference tree carrying each of the nucleotide substitutions
+  pub nuc_sub_branch_counts: BTreeMap<NucSub, usize>,
+  /// Inverted index of nucleotide substitutions of the nodes, for the nearest node search. Only built when the tree is
+  /// preprocessed.
+  pub nuc_sub_index: Option<NucSubIndex>,
   pub other: serde_json::Value,
 }
 
@@ -200,6 +207,9 @@ pub struct TreeNodeTempData {
   pub private_mutations: BranchMutations,
   pub aa_substitutions: BTreeMap<String, BTreeMap<AaRefPosition, Aa>>,
   pub aa_mutations: BTreeMap<String, BTreeMap<AaRefPosition, Aa>>,
+
+  /// Names of the query sequences collapsed into this node, if any
+  pub identical_seq_names: Vec<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize, schemars::JsonSchema, Validate, Debug)]
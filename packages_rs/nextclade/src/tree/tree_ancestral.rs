@@ -0,0 +1,173 @@
+use crate::alphabet::aa::Aa;
+use crate::alphabet::nuc::Nuc;
+use crate::coord::position::PositionLike;
+use crate::graph::node::GraphNodeKey;
+use crate::make_error;
+use crate::translate::translate_genes::Translation;
+use crate::tree::tree::{AuspiceGraph, AuspiceGraphNodePayload};
+use eyre::{Report, WrapErr};
+use itertools::Itertools;
+use std::collections::BTreeMap;
+
+/// Reconstructs nucleotide sequences and peptides of the nodes of the tree, including internal nodes of the reference
+/// tree and the new nodes added during tree building. Sequence of each node is obtained by applying private mutations
+/// of all nodes on the path from the root to the node onto the reference sequence and the reference peptides.
+/// Deletions are reconstructed as gaps.
+///
+/// The callback receives each of the nodes in pre-order, along with its nucleotide sequence and peptides, by CDS name.
+/// If the list of clades is not empty, only the nodes belonging to these clades are reported.
+pub fn graph_reconstruct_ancestral_sequences(
+  graph: &AuspiceGraph,
+  ref_seq: &[Nuc],
+  ref_translation: &Translation,
+  clades: &[String],
+  mut callback: impl FnMut(&AuspiceGraphNodePayload, &[Nuc], &BTreeMap<String, Vec<Aa>>) -> Result<(), Report>,
+) -> Result<(), Report> {
+  let mut seq = ref_seq.to_vec();
+  let mut peptides = ref_translation
+    .iter_cdses()
+    .map(|(cds_name, cds_tr)| (cds_name.clone(), cds_tr.seq.clone()))
+    .collect();
+
+  let root_key = graph.get_exactly_one_root()?.key();
+  reconstruct_recursive(graph, root_key, &mut seq, &mut peptides, clades, &mut callback)
+}
+
+fn reconstruct_recursive(
+  graph: &AuspiceGraph,
+  node_key: GraphNodeKey,
+  seq: &mut Vec<Nuc>,
+  peptides: &mut BTreeMap<String, Vec<Aa>>,
+  clades: &[String],
+  callback: &mut impl FnMut(&AuspiceGraphNodePayload, &[Nuc], &BTreeMap<String, Vec<Aa>>) -> Result<(), Report>,
+) -> Result<(), Report> {
+  let node = graph.get_node(node_key)?.payload();
+  let muts = &node.tmp.private_mutations;
+
+  let replaced_nucs = apply_substitutions(seq, muts.nuc_muts.iter().map(|sub| (sub.pos.as_usize(), sub.qry_nuc)))
+    .wrap_err_with(|| format!("When applying nucleotide mutations of node '{}'", node.name))?;
+
+  let replaced_aas = muts
+    .aa_muts
+    .iter()
+    .map(|(cds_name, subs)| {
+      let Some(peptide) = peptides.get_mut(cds_name) else {
+        return make_error!("CDS '{cds_name}' is not found in the reference translation");
+      };
+      let replaced = apply_substitutions(peptide, subs.iter().map(|sub| (sub.pos.as_usize(), sub.qry_aa)))?;
+      Ok((cds_name, replaced))
+    })
+    .collect::<Result<Vec<_>, Report>>()
+    .wrap_err_with(|| format!("When applying aminoacid mutations of node '{}'", node.name))?;
+
+  if clades.is_empty() || clades.contains(&node.clade()) {
+    callback(node, seq, peptides)?;
+  }
+
+  for child_key in graph.iter_child_keys_of_by_key(node_key).collect_vec() {
+    reconstruct_recursive(graph, child_key, seq, peptides, clades, callback)?;
+  }
+
+  // Restore the sequences of the parent before returning to it
+  revert_substitutions(seq, replaced_nucs);
+  for (cds_name, replaced) in replaced_aas {
+    if let Some(peptide) = peptides.get_mut(cds_name) {
+      revert_substitutions(peptide, replaced);
+    }
+  }
+
+  Ok(())
+}
+
+/// Replaces letters at the given positions. Returns the replaced letters, such that the changes can be reverted.
+fn apply_substitutions<L: Copy>(
+  seq: &mut [L],
+  subs: impl Iterator<Item = (usize, L)>,
+) -> Result<Vec<(usize, L)>, Report> {
+  let len = seq.len();
+  subs
+    .map(|(pos, letter)| {
+      let Some(current) = seq.get_mut(pos) else {
+        return make_error!("Mutation at position {} is outside of the sequence of length {len}", pos + 1);
+      };
+      Ok((pos, std::mem::replace(current, letter)))
+    })
+    .collect()
+}
+
+fn revert_substitutions<L: Copy>(seq: &mut [L], replaced: Vec<(usize, L)>) {
+  for (pos, letter) in replaced.into_iter().rev() {
+    seq[pos] = letter;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::alphabet::nuc::{from_nuc_seq, to_nuc_seq};
+  use crate::graph::graph::convert_auspice_tree_to_graph;
+  use crate::tree::tree::AuspiceTree;
+  use crate::tree::tree_preprocess::graph_preprocess_in_place;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+  use serde_json::json;
+
+  fn node(name: &str, clade: &str, muts: &[&str], children: &[serde_json::Value]) -> serde_json::Value {
+    json!({
+      "name": name,
+      "branch_attrs": { "mutations": { "nuc": muts } },
+      "node_attrs": { "clade_membership": { "value": clade } },
+      "children": children,
+    })
+  }
+
+  fn reconstruct(graph: &AuspiceGraph, ref_seq: &[Nuc], clades: &[String]) -> Result<Vec<(String, String)>, Report> {
+    let mut seqs = vec![];
+    graph_reconstruct_ancestral_sequences(graph, ref_seq, &Translation::default(), clades, |node, seq, _| {
+      seqs.push((node.name.clone(), from_nuc_seq(seq)));
+      Ok(())
+    })?;
+    Ok(seqs)
+  }
+
+  #[rstest]
+  fn reconstructs_ancestral_sequences() -> Result<(), Report> {
+    let tree: AuspiceTree = serde_json::from_value(json!({
+      "meta": {},
+      "tree": node("root", "X", &[], &[
+        node("N", "Y", &["A1T"], &[
+          node("A", "Y", &["C2G"], &[]),
+          node("B", "Y", &["G3-"], &[]),
+        ]),
+        node("C", "X", &["T4A"], &[]),
+      ]),
+    }))?;
+    let ref_seq = to_nuc_seq("ACGT")?;
+    let mut graph = convert_auspice_tree_to_graph(tree)?;
+    graph_preprocess_in_place(&mut graph, &ref_seq, &Translation::default())?;
+
+    let to_owned = |seqs: &[(&str, &str)]| {
+      seqs
+        .iter()
+        .map(|(name, seq)| ((*name).to_owned(), (*seq).to_owned()))
+        .collect_vec()
+    };
+
+    assert_eq!(
+      reconstruct(&graph, &ref_seq, &[])?,
+      to_owned(&[
+        ("root", "ACGT"),
+        ("N", "TCGT"),
+        ("A", "TGGT"),
+        ("B", "TC-T"),
+        ("C", "ACGA"),
+      ])
+    );
+
+    assert_eq!(
+      reconstruct(&graph, &ref_seq, &["Y".to_owned()])?,
+      to_owned(&[("N", "TCGT"), ("A", "TGGT"), ("B", "TC-T")])
+    );
+    Ok(())
+  }
+}
@@ -6,51 +6,140 @@ use crate::analyze::nuc_del::NucDel;
 use crate::analyze::nuc_sub::NucSub;
 use crate::coord::range::NucRefGlobalRange;
 use crate::graph::node::{GraphNodeKey, Node};
+use crate::make_internal_report;
 use crate::tree::params::TreeBuilderParams;
 use crate::tree::split_muts::{difference_of_muts, split_muts, union_of_muts, SplitMutsResult};
 use crate::tree::tree::{AuspiceGraph, AuspiceGraphEdgePayload, AuspiceGraphNodePayload, TreeBranchAttrsLabels};
 use crate::tree::tree_attach_new_nodes::create_new_auspice_node;
 use crate::tree::tree_preprocess::add_auspice_metadata_in_place;
+use crate::tree::tree_refine::{graph_refine_new_nodes_in_place, TreeRefinementReport};
 use crate::types::outputs::NextcladeOutputs;
 use crate::utils::collections::concat_to_vec;
 use eyre::{Report, WrapErr};
+use indexmap::IndexMap;
 use itertools::Itertools;
+use serde_json::json;
 use std::collections::BTreeMap;
 
+/// Node attribute listing names of the query sequences collapsed into a node. See `--collapse-identical-queries`.
+pub const IDENTICAL_SEQUENCES_ATTR: &str = "identical_sequences";
+
+/// Node attribute with the number of the query sequences collapsed into a node
+pub const IDENTICAL_SEQUENCES_COUNT_ATTR: &str = "identical_sequences_count";
+
 pub fn graph_attach_new_nodes_in_place(
   graph: &mut AuspiceGraph,
   mut results: Vec<NextcladeOutputs>,
   ref_seq_len: usize,
   params: &TreeBuilderParams,
-) -> Result<(), Report> {
+) -> Result<TreeRefinementReport, Report> {
+  let num_ref_nodes = graph.num_nodes();
+
   // Add sequences with less private mutations first to avoid un-treelike behavior in the graph.
   // And then also sort by the index in the original fasta inputs, to avoid non-deterministic order due to differences
   // in thread scheduling.
   results.sort_by_key(|result| (result.private_nuc_mutations.total_private_substitutions, result.index));
 
+  let groups = if params.collapse_identical_queries {
+    group_identical_queries(&results)
+  } else {
+    results.iter().map(|result| vec![result]).collect_vec()
+  };
+
   // Look for a query sample result for which this node was decided to be nearest
-  for result in &results {
-    graph_attach_new_node_in_place(graph, result, ref_seq_len, params).wrap_err_with(|| {
+  for group in &groups {
+    let result = group[0];
+    let new_node_key = graph_attach_new_node_in_place(graph, result, ref_seq_len, params).wrap_err_with(|| {
       format!(
         "When attaching the new node for query sequence '{}' to the tree",
         result.seq_name
       )
     })?;
+
+    if group.len() > 1 {
+      let seq_names = group.iter().map(|result| result.seq_name.clone()).collect_vec();
+      set_identical_sequences_attrs(graph.get_node_mut(new_node_key)?.payload_mut(), seq_names);
+    }
   }
 
+  let refinement_report = graph_refine_new_nodes_in_place(graph, num_ref_nodes, ref_seq_len, params)
+    .wrap_err("When refining the resulting tree")?;
+
   graph.ladderize_tree().wrap_err("When ladderizing the resulting tree")?;
 
-  add_auspice_metadata_in_place(&mut graph.data.meta);
+  add_auspice_metadata_in_place(&mut graph.data.meta, params);
 
-  Ok(())
+  Ok(refinement_report)
+}
+
+/// Groups query sequences which have identical private mutations relative to the same nearest node. These sequences
+/// would be attached at the same point of the tree. Groups are in the order of their first sequence.
+fn group_identical_queries(results: &[NextcladeOutputs]) -> Vec<Vec<&NextcladeOutputs>> {
+  let mut groups = IndexMap::<_, Vec<&NextcladeOutputs>>::new();
+  for result in results {
+    let BranchMutations { mut nuc_muts, aa_muts } = result_private_mutations(result);
+    nuc_muts.sort();
+    groups
+      .entry((result.nearest_node_id, nuc_muts, aa_muts))
+      .or_default()
+      .push(result);
+  }
+  groups.into_values().collect()
 }
 
+/// Records names and the number of query sequences collapsed into the node, in node attributes
+fn set_identical_sequences_attrs(node: &mut AuspiceGraphNodePayload, seq_names: Vec<String>) {
+  let other = &mut node.node_attrs.other;
+  if !other.is_object() {
+    *other = json!({});
+  }
+  if let Some(other) = other.as_object_mut() {
+    other.insert(
+      IDENTICAL_SEQUENCES_ATTR.to_owned(),
+      json!({ "value": seq_names.join(", ") }),
+    );
+    other.insert(
+      IDENTICAL_SEQUENCES_COUNT_ATTR.to_owned(),
+      json!({ "value": seq_names.len() }),
+    );
+  }
+  node.tmp.identical_seq_names = seq_names;
+}
+
+/// Removes attributes of collapsed query sequences, e.g. from a new internal node which is copied from a query node
+fn remove_identical_sequences_attrs(node: &mut AuspiceGraphNodePayload) {
+  if let Some(other) = node.node_attrs.other.as_object_mut() {
+    other.remove(IDENTICAL_SEQUENCES_ATTR);
+    other.remove(IDENTICAL_SEQUENCES_COUNT_ATTR);
+  }
+  node.tmp.identical_seq_names = vec![];
+}
+
+/// Attaches the new node for the query sequence to the tree. Returns the key of the new node.
 pub fn graph_attach_new_node_in_place(
   graph: &mut AuspiceGraph,
   result: &NextcladeOutputs,
   ref_seq_len: usize,
   params: &TreeBuilderParams,
-) -> Result<(), Report> {
+) -> Result<GraphNodeKey, Report> {
+  let mutations_seq = result_private_mutations(result);
+
+  let (nearest_node_key, private_mutations) = if params.without_greedy_tree_builder {
+    // Skip tree fine-tuning
+    (result.nearest_node_id, mutations_seq)
+  } else {
+    // for the attachment on the reference tree ('result') fine tune the position
+    // on the updated graph to minimize the number of private mutations
+    finetune_nearest_node(graph, result.nearest_node_id, &mutations_seq, params)?
+  };
+
+  // add the new node at the fine tuned position while accounting for shared mutations
+  // on the branch leading to the nearest node.
+  knit_into_graph(graph, nearest_node_key, result, &private_mutations, ref_seq_len, params)
+}
+
+/// Private mutations of the query sequence relative to its nearest node, with deletions represented as substitutions
+fn result_private_mutations(result: &NextcladeOutputs) -> BranchMutations {
   let mut private_aa_mutations = BTreeMap::<String, Vec<AaSub>>::new();
   for key in result.private_aa_mutations.keys() {
     let subs = result.private_aa_mutations[key].private_substitutions.clone();
@@ -74,26 +163,10 @@ pub fn graph_attach_new_node_in_place(
       .collect_vec(),
   );
 
-  // Check if new seq is in between nearest node and a neighbor of nearest node
-  let mutations_seq = BranchMutations {
+  BranchMutations {
     nuc_muts: nuc_subs,
     aa_muts: private_aa_mutations,
-  };
-
-  let (nearest_node_key, private_mutations) = if params.without_greedy_tree_builder {
-    // Skip tree fine-tuning
-    (result.nearest_node_id, mutations_seq)
-  } else {
-    // for the attachment on the reference tree ('result') fine tune the position
-    // on the updated graph to minimize the number of private mutations
-    finetune_nearest_node(graph, result.nearest_node_id, &mutations_seq, params)?
-  };
-
-  // add the new node at the fine tuned position while accounting for shared mutations
-  // on the branch leading to the nearest node.
-  knit_into_graph(graph, nearest_node_key, result, &private_mutations, ref_seq_len, params)?;
-
-  Ok(())
+  }
 }
 
 /// Moves the new sequences, defined by its set of private mutations
@@ -241,7 +314,7 @@ pub fn attach_to_internal_node(
   new_private_mutations: &BranchMutations,
   result: &NextcladeOutputs,
   divergence_new_node: f64,
-) -> Result<(), Report> {
+) -> Result<GraphNodeKey, Report> {
   //generated auspice payload for new node
   let mut new_graph_node: AuspiceGraphNodePayload =
     create_new_auspice_node(result, new_private_mutations, divergence_new_node);
@@ -249,7 +322,8 @@ pub fn attach_to_internal_node(
 
   // Create and add the new node to the graph.
   let new_node_key = graph.add_node(new_graph_node);
-  graph.add_edge(nearest_node_id, new_node_key, AuspiceGraphEdgePayload::new())
+  graph.add_edge(nearest_node_id, new_node_key, AuspiceGraphEdgePayload::new())?;
+  Ok(new_node_key)
 }
 
 pub fn convert_private_mutations_to_node_branch_attrs(mutations: &BranchMutations) -> BTreeMap<String, Vec<String>> {
@@ -279,10 +353,11 @@ pub fn convert_private_mutations_to_node_branch_attrs_aa_labels(aa_muts: &BTreeM
     .join("; ")
 }
 
-struct KnitMuts {
-  muts_common_branch: BranchMutations,
-  muts_target_node: BranchMutations,
-  muts_new_node: BranchMutations,
+/// Mutations on the branches around the new node, when the new node is attached to the target node
+pub struct KnitMuts {
+  pub muts_common_branch: BranchMutations,
+  pub muts_target_node: BranchMutations,
+  pub muts_new_node: BranchMutations,
 }
 
 pub fn knit_into_graph(
@@ -292,25 +367,45 @@ pub fn knit_into_graph(
   private_mutations: &BranchMutations,
   ref_seq_len: usize,
   params: &TreeBuilderParams,
-) -> Result<(), Report> {
-  let masked_ranges = graph.data.meta.placement_mask_ranges().to_owned();
-  let divergence_units = graph.data.tmp.divergence_units;
+) -> Result<GraphNodeKey, Report> {
+  let mut new_node_key = None;
+  knit_node_into_graph(
+    graph,
+    target_key,
+    private_mutations,
+    ref_seq_len,
+    params,
+    |graph, parent_key, new_private_mutations, divergence_new_node| {
+      new_node_key = Some(attach_to_internal_node(
+        graph,
+        parent_key,
+        new_private_mutations,
+        result,
+        divergence_new_node,
+      )?);
+      Ok(())
+    },
+  )?;
+  new_node_key.ok_or_else(|| make_internal_report!("New node for query sequence '{}' is not attached", result.seq_name))
+}
 
-  // the target node will be the sister of the new node defined by "private mutations" and the "result"
+/// Splits mutations of the new node, defined by its private mutations relative to the target node, into the ones
+/// shared with the branch leading to the target node and the remaining ones
+pub fn knit_muts(
+  graph: &AuspiceGraph,
+  target_key: GraphNodeKey,
+  private_mutations: &BranchMutations,
+  params: &TreeBuilderParams,
+) -> Result<KnitMuts, Report> {
   let target_node = graph.get_node(target_key)?;
   let target_node_auspice = target_node.payload();
-  let target_node_div = &target_node_auspice.node_attrs.div.unwrap_or(0.0);
-  let KnitMuts {
-    muts_common_branch,
-    muts_target_node,
-    muts_new_node,
-  } = if params.without_greedy_tree_builder || target_node.is_root() {
+  if params.without_greedy_tree_builder || target_node.is_root() {
     // don't split branch if node is root as we don't attach nodes above the root
-    KnitMuts {
+    Ok(KnitMuts {
       muts_common_branch: target_node_auspice.tmp.private_mutations.clone(), // Keep target node muts unchanged.
       muts_target_node: BranchMutations::default(),                          // Don't subtract any shared mutations.
       muts_new_node: private_mutations.clone(),                              // Keep private muts unchanged.
-    }
+    })
   } else {
     // determine mutations shared between the private mutations of the new node
     // and the branch leading to the target node
@@ -329,14 +424,48 @@ pub fn knit_into_graph(
     // the mutations that lead to the target_node but not the new node
     let muts_common_branch = muts_common_branch_inverted.invert();
     let muts_target_node = muts_target_node_inverted.invert();
-    KnitMuts {
+    Ok(KnitMuts {
       muts_common_branch,
       muts_target_node,
       muts_new_node,
-    }
-  };
+    })
+  }
+}
+
+/// Returns whether the branch leading to the target node needs to be split, with a new internal node inserted, in
+/// order to attach the new node
+pub fn knit_needs_split(graph: &AuspiceGraph, target_key: GraphNodeKey, knit_muts: &KnitMuts) -> Result<bool, Report> {
+  Ok(graph.get_node(target_key)?.is_leaf() || !knit_muts.muts_target_node.nuc_muts.is_empty())
+}
+
+/// Adds the new node to the graph at the position of the target node, splitting the branch leading to the target node
+/// if needed. The new node is created and connected to its parent by the provided `attach` function, which receives
+/// the key of the parent, the private mutations and the divergence of the new node.
+pub fn knit_node_into_graph(
+  graph: &mut AuspiceGraph,
+  target_key: GraphNodeKey,
+  private_mutations: &BranchMutations,
+  ref_seq_len: usize,
+  params: &TreeBuilderParams,
+  attach: impl FnOnce(&mut AuspiceGraph, GraphNodeKey, &BranchMutations, f64) -> Result<(), Report>,
+) -> Result<(), Report> {
+  let masked_ranges = graph.data.meta.placement_mask_ranges().to_owned();
+  let divergence_units = graph.data.tmp.divergence_units;
+
+  let knit_muts = knit_muts(graph, target_key, private_mutations, params)?;
+  let needs_split = knit_needs_split(graph, target_key, &knit_muts)?;
+  let KnitMuts {
+    muts_common_branch,
+    muts_target_node,
+    muts_new_node,
+  } = knit_muts;
+
+  // the target node will be the sister of the new node defined by "private mutations"
+  let target_node = graph.get_node(target_key)?;
+  let target_node_auspice = target_node.payload();
+  let target_node_div = &target_node_auspice.node_attrs.div.unwrap_or(0.0);
   // if the node is a leaf or if there are non-shared mutations, need to split the branch above and insert aux node
-  if target_node.is_leaf() || !muts_target_node.nuc_muts.is_empty() {
+  if needs_split {
     // determine divergence of new internal node by subtracting shared reversions from target_node
     let divergence_middle_node = if target_node.is_root() {
       target_node_div
@@ -372,6 +501,7 @@ pub fn knit_into_graph(
       if let Some(labels) = &mut new_internal_node.branch_attrs.labels {
         labels.clade = None; //nuke existing clade labels
       }
+      remove_identical_sequences_attrs(&mut new_internal_node);
       set_branch_attrs_aa_labels(&mut new_internal_node);
 
       new_internal_node.name = format!("{target_key}_internal");
@@ -397,28 +527,26 @@ pub fn knit_into_graph(
     set_branch_attrs_aa_labels(target_node_auspice);
 
     // attach the new node as child to the new_internal_node with its mutations
-    attach_to_internal_node(
+    attach(
       graph,
       new_internal_node_key,
       &muts_new_node,
-      result,
       divergence_middle_node
         + calculate_branch_length(&muts_new_node.nuc_muts, &masked_ranges, divergence_units, ref_seq_len),
     )?;
   } else {
     //can simply attach node
-    attach_to_internal_node(
+    attach(
       graph,
       target_key,
       private_mutations,
-      result,
       target_node_div + calculate_branch_length(&muts_new_node.nuc_muts, &masked_ranges, divergence_units, ref_seq_len),
     )?;
   }
   Ok(())
 }
 
-fn set_branch_attrs_aa_labels(node: &mut AuspiceGraphNodePayload) {
+pub fn set_branch_attrs_aa_labels(node: &mut AuspiceGraphNodePayload) {
   let aa_labels = convert_private_mutations_to_node_branch_attrs_aa_labels(&node.tmp.private_mutations.aa_muts);
   if let Some(labels) = &mut node.branch_attrs.labels {
     labels.aa = Some(aa_labels);
@@ -430,3 +558,118 @@ fn set_branch_attrs_aa_labels(node: &mut AuspiceGraphNodePayload) {
     });
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::alphabet::nuc::{to_nuc_seq, Nuc};
+  use crate::analyze::find_private_nuc_mutations::PrivateNucMutations;
+  use crate::analyze::nuc_sub::NucSub;
+  use crate::graph::graph::convert_auspice_tree_to_graph;
+  use crate::translate::translate_genes::Translation;
+  use crate::tree::tree::AuspiceTree;
+  use crate::tree::tree_preprocess::graph_preprocess_in_place;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  fn query(graph: &AuspiceGraph, index: usize, nearest_node: &str, positions: &[usize]) -> NextcladeOutputs {
+    NextcladeOutputs {
+      index,
+      seq_name: format!("Q{index}"),
+      nearest_node_id: graph
+        .iter_nodes()
+        .find(|node| node.payload().name == nearest_node)
+        .unwrap()
+        .key(),
+      private_nuc_mutations: PrivateNucMutations {
+        private_substitutions: positions
+          .iter()
+          .map(|&pos| NucSub {
+            pos: pos.into(),
+            ref_nuc: Nuc::A,
+            qry_nuc: Nuc::T,
+          })
+          .collect(),
+        ..PrivateNucMutations::default()
+      },
+      ..NextcladeOutputs::default()
+    }
+  }
+
+  #[rstest]
+  fn collapses_identical_queries_sharing_nearest_node_with_other_queries() -> Result<(), Report> {
+    let node = |name: &str, children: &[serde_json::Value]| {
+      json!({
+        "name": name,
+        "branch_attrs": { "mutations": {} },
+        "node_attrs": { "clade_membership": { "value": "X" } },
+        "children": children,
+      })
+    };
+    let tree: AuspiceTree = serde_json::from_value(json!({
+      "meta": {},
+      "tree": node("R", &[node("A", &[]), node("B", &[])]),
+    }))?;
+    let ref_seq = to_nuc_seq("AAAAAAAAAA")?;
+    let mut graph = convert_auspice_tree_to_graph(tree)?;
+    graph_preprocess_in_place(&mut graph, &ref_seq, &Translation::default())?;
+
+    // Q1 and Q3 are identical, Q2 shares a mutation with them, Q4 is identical to the nearest node
+    let results = vec![
+      query(&graph, 1, "A", &[2, 5]),
+      query(&graph, 2, "A", &[2, 5, 7]),
+      query(&graph, 3, "A", &[2, 5]),
+      query(&graph, 4, "A", &[]),
+    ];
+    let params = TreeBuilderParams {
+      collapse_identical_queries: true,
+      ..TreeBuilderParams::default()
+    };
+    graph_attach_new_nodes_in_place(&mut graph, results, ref_seq.len(), &params)?;
+
+    let attrs = graph
+      .iter_nodes()
+      .map(|node| {
+        let payload = node.payload();
+        let attr = |key: &str| payload.node_attrs.other.get(key).map(|attr| attr["value"].to_string());
+        (
+          payload.name.clone(),
+          attr(IDENTICAL_SEQUENCES_ATTR),
+          attr(IDENTICAL_SEQUENCES_COUNT_ATTR),
+          payload.tmp.identical_seq_names.clone(),
+        )
+      })
+      .filter(|(_, names, count, seq_names)| names.is_some() || count.is_some() || !seq_names.is_empty())
+      .collect_vec();
+
+    assert_eq!(
+      attrs,
+      vec![(
+        "Q1".to_owned(),
+        Some(r#""Q1, Q3""#.to_owned()),
+        Some("2".to_owned()),
+        vec!["Q1".to_owned(), "Q3".to_owned()]
+      )]
+    );
+    assert_eq!(
+      graph
+        .iter_nodes()
+        .filter(|node| node.payload().name.starts_with('Q'))
+        .count(),
+      3
+    );
+    assert!(graph
+      .data
+      .meta
+      .colorings
+      .iter()
+      .any(|c| c.key == IDENTICAL_SEQUENCES_ATTR));
+    assert!(graph
+      .data
+      .meta
+      .colorings
+      .iter()
+      .any(|c| c.key == IDENTICAL_SEQUENCES_COUNT_ATTR));
+    Ok(())
+  }
+}
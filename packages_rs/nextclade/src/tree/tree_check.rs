@@ -0,0 +1,364 @@
+use crate::alphabet::aa::Aa;
+use crate::alphabet::letter::Letter;
+use crate::alphabet::nuc::Nuc;
+use crate::analyze::aa_sub::AaSub;
+use crate::analyze::nuc_sub::NucSub;
+use crate::coord::position::PositionLike;
+use crate::make_error;
+use crate::translate::translate_genes::Translation;
+use eyre::Report;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum TreeCheckIssueKind {
+  MissingName,
+  DuplicateName,
+  MissingClade,
+  MissingMutations,
+  InvalidMutation,
+  DuplicateMutation,
+  MutationOutOfRange,
+  MutationRefMismatch,
+  UnknownCds,
+}
+
+/// Inconsistency found in the reference tree, along with a suggestion of how to fix it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeCheckIssue {
+  pub node_name: String,
+  pub kind: TreeCheckIssueKind,
+  pub message: String,
+  pub suggestion: String,
+}
+
+impl Display for TreeCheckIssue {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "Node '{}': {} Suggestion: {}",
+      self.node_name, self.message, self.suggestion
+    )
+  }
+}
+
+/// Checks consistency of the reference tree (in Auspice JSON format) with the reference sequence and, if provided,
+/// with the translation of the reference sequence according to the genome annotation.
+///
+/// The tree is checked in its untyped JSON form, such that the nodes which would fail to parse (e.g. due to missing
+/// clade attribute) are reported along with all other inconsistencies, rather than stopping at the first one.
+/// Aminoacid mutations are only checked if the reference translation is provided.
+pub fn tree_check(
+  tree: &Value,
+  ref_seq: &[Nuc],
+  ref_translation: Option<&Translation>,
+) -> Result<Vec<TreeCheckIssue>, Report> {
+  let Some(root) = tree.get("tree") else {
+    return make_error!("The tree JSON has no `tree` field. Expected a tree in Auspice JSON v2 format.");
+  };
+
+  let mut checker = TreeChecker {
+    ref_seq,
+    ref_translation,
+    names: BTreeSet::new(),
+    issues: vec![],
+  };
+  checker.check_recursive(root, &BTreeMap::new(), &BTreeMap::new(), None);
+  Ok(checker.issues)
+}
+
+struct TreeChecker<'a> {
+  ref_seq: &'a [Nuc],
+  ref_translation: Option<&'a Translation>,
+  names: BTreeSet<String>,
+  issues: Vec<TreeCheckIssue>,
+}
+
+impl<'a> TreeChecker<'a> {
+  fn report(&mut self, node_name: &str, kind: TreeCheckIssueKind, message: String, suggestion: String) {
+    self.issues.push(TreeCheckIssue {
+      node_name: node_name.to_owned(),
+      kind,
+      message,
+      suggestion,
+    });
+  }
+
+  /// Checks a node, given the states of the parent node at the positions which differ from the reference
+  fn check_recursive(
+    &mut self,
+    node: &Value,
+    parent_nucs: &BTreeMap<usize, Nuc>,
+    parent_aas: &BTreeMap<String, BTreeMap<usize, Aa>>,
+    parent_clade: Option<&str>,
+  ) {
+    let name = self.check_name(node);
+
+    let clade = node
+      .pointer("/node_attrs/clade_membership/value")
+      .and_then(Value::as_str);
+    if clade.is_none() {
+      let suggestion = match parent_clade {
+        Some(parent_clade) => {
+          format!(
+            "Add `node_attrs.clade_membership.value`, for example the clade of the parent node: '{parent_clade}'."
+          )
+        }
+        None => "Add `node_attrs.clade_membership.value` with the name of the clade of the node.".to_owned(),
+      };
+      self.report(
+        &name,
+        TreeCheckIssueKind::MissingClade,
+        "Clade attribute is missing.".to_owned(),
+        suggestion,
+      );
+    }
+
+    let mut nucs = parent_nucs.clone();
+    let mut aas = parent_aas.clone();
+    match node.pointer("/branch_attrs/mutations").and_then(Value::as_object) {
+      None => self.report(
+        &name,
+        TreeCheckIssueKind::MissingMutations,
+        "Branch mutations are missing.".to_owned(),
+        "Add `branch_attrs.mutations`. Use an empty object `{}` if the branch has no mutations.".to_owned(),
+      ),
+      Some(mutations) => {
+        for (key, muts) in mutations {
+          let muts = muts.as_array().map_or_else(Vec::new, |muts| muts.iter().collect());
+          if key == "nuc" {
+            self.check_nuc_muts(&name, &muts, &mut nucs);
+          } else if let Some(ref_translation) = self.ref_translation {
+            self.check_aa_muts(&name, key, &muts, ref_translation, &mut aas);
+          }
+        }
+      }
+    }
+
+    if let Some(children) = node.get("children").and_then(Value::as_array) {
+      for child in children {
+        self.check_recursive(child, &nucs, &aas, clade.or(parent_clade));
+      }
+    }
+  }
+
+  fn check_name(&mut self, node: &Value) -> String {
+    let Some(name) = node.get("name").and_then(Value::as_str) else {
+      let name = format!("<unnamed node #{}>", self.names.len() + 1);
+      self.names.insert(name.clone());
+      self.report(
+        &name,
+        TreeCheckIssueKind::MissingName,
+        "Node has no name.".to_owned(),
+        "Add a unique `name` to every node of the tree.".to_owned(),
+      );
+      return name;
+    };
+
+    if !self.names.insert(name.to_owned()) {
+      self.report(
+        name,
+        TreeCheckIssueKind::DuplicateName,
+        "Node name is not unique.".to_owned(),
+        "Rename the nodes, such that every node of the tree has a unique name.".to_owned(),
+      );
+    }
+    name.to_owned()
+  }
+
+  fn check_nuc_muts(&mut self, name: &str, muts: &[&Value], nucs: &mut BTreeMap<usize, Nuc>) {
+    let mut positions = BTreeSet::new();
+    for m in muts {
+      let Some(sub) = m.as_str().and_then(|m| NucSub::from_str(m).ok()) else {
+        self.report(
+          name,
+          TreeCheckIssueKind::InvalidMutation,
+          format!("Unable to parse nucleotide mutation {m}."),
+          "Use the format <ref><pos><qry> with 1-based position, for example \"A123T\" or \"A123-\".".to_owned(),
+        );
+        continue;
+      };
+
+      let pos = sub.pos.as_usize();
+      let Some(&ref_nuc) = self.ref_seq.get(pos) else {
+        self.report(
+          name,
+          TreeCheckIssueKind::MutationOutOfRange,
+          format!(
+            "Nucleotide mutation '{sub}' is outside of the reference sequence of length {}.",
+            self.ref_seq.len()
+          ),
+          "Make sure the tree is built against the same reference sequence, or remove the mutation.".to_owned(),
+        );
+        continue;
+      };
+
+      if !positions.insert(pos) {
+        self.report(
+          name,
+          TreeCheckIssueKind::DuplicateMutation,
+          format!("Nucleotide mutation '{sub}' is at the same position as another mutation on this branch."),
+          "Merge the mutations into one, from the state of the parent node to the state of this node.".to_owned(),
+        );
+      }
+
+      let parent_nuc = nucs.get(&pos).copied().unwrap_or(ref_nuc);
+      self.check_ref_state(name, &sub.to_string(), sub.ref_nuc, parent_nuc, sub.qry_nuc, pos);
+      nucs.insert(pos, sub.qry_nuc);
+    }
+  }
+
+  fn check_aa_muts(
+    &mut self,
+    name: &str,
+    cds_name: &str,
+    muts: &[&Value],
+    ref_translation: &Translation,
+    aas: &mut BTreeMap<String, BTreeMap<usize, Aa>>,
+  ) {
+    let Ok(ref_cds_tr) = ref_translation.get_cds(cds_name) else {
+      self.report(
+        name,
+        TreeCheckIssueKind::UnknownCds,
+        format!("Branch mutations refer to CDS '{cds_name}', which is not found in the genome annotation."),
+        "Make sure the tree is built using the same genome annotation, or remove mutations of this CDS.".to_owned(),
+      );
+      return;
+    };
+
+    let cds_aas = aas.entry(cds_name.to_owned()).or_default();
+    let mut positions = BTreeSet::new();
+    for m in muts {
+      let Some(sub) = m
+        .as_str()
+        .and_then(|m| AaSub::from_str(&format!("{cds_name}:{m}")).ok())
+      else {
+        self.report(
+          name,
+          TreeCheckIssueKind::InvalidMutation,
+          format!("Unable to parse aminoacid mutation {m} in CDS '{cds_name}'."),
+          "Use the format <ref><pos><qry> with 1-based position, for example \"N501Y\" or \"H69-\".".to_owned(),
+        );
+        continue;
+      };
+
+      let pos = sub.pos.as_usize();
+      let Some(&ref_aa) = ref_cds_tr.seq.get(pos) else {
+        self.report(
+          name,
+          TreeCheckIssueKind::MutationOutOfRange,
+          format!(
+            "Aminoacid mutation '{sub}' is outside of the CDS of length {}.",
+            ref_cds_tr.seq.len()
+          ),
+          "Make sure the tree is built using the same genome annotation, or remove the mutation.".to_owned(),
+        );
+        continue;
+      };
+
+      if !positions.insert(pos) {
+        self.report(
+          name,
+          TreeCheckIssueKind::DuplicateMutation,
+          format!("Aminoacid mutation '{sub}' is at the same position as another mutation on this branch."),
+          "Merge the mutations into one, from the state of the parent node to the state of this node.".to_owned(),
+        );
+      }
+
+      let parent_aa = cds_aas.get(&pos).copied().unwrap_or(ref_aa);
+      self.check_ref_state(name, &sub.to_string(), sub.ref_aa, parent_aa, sub.qry_aa, pos);
+      cds_aas.insert(pos, sub.qry_aa);
+    }
+  }
+
+  /// Checks that the reference state of a mutation matches the state of the parent node
+  fn check_ref_state<L: Letter<L> + Display>(
+    &mut self,
+    name: &str,
+    mutation: &str,
+    ref_letter: L,
+    parent_letter: L,
+    qry_letter: L,
+    pos: usize,
+  ) {
+    if ref_letter == parent_letter {
+      return;
+    }
+
+    let suggestion = if qry_letter == parent_letter {
+      "Remove the mutation, because it does not change the state of the parent node.".to_owned()
+    } else {
+      format!("Replace the mutation with '{parent_letter}{}{qry_letter}'.", pos + 1)
+    };
+    self.report(
+      name,
+      TreeCheckIssueKind::MutationRefMismatch,
+      format!(
+        "Mutation '{mutation}' starts from '{ref_letter}', but the parent node has '{parent_letter}' at this position."
+      ),
+      suggestion,
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::alphabet::nuc::to_nuc_seq;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+  use serde_json::json;
+
+  #[rstest]
+  fn reports_inconsistencies() -> Result<(), Report> {
+    let tree = json!({
+      "meta": {},
+      "tree": {
+        "name": "root",
+        "branch_attrs": { "mutations": {} },
+        "node_attrs": { "clade_membership": { "value": "X" } },
+        "children": [
+          {
+            "name": "A",
+            "branch_attrs": { "mutations": { "nuc": ["A1T", "G3C", "T9A"] } },
+            "node_attrs": { "clade_membership": { "value": "X" } },
+            "children": [
+              {
+                "name": "B",
+                "branch_attrs": { "mutations": { "nuc": ["A1C", "C3G"] } },
+                "node_attrs": {},
+              },
+            ],
+          },
+          {
+            "name": "A",
+            "branch_attrs": { "mutations": { "nuc": ["C2X"] } },
+            "node_attrs": { "clade_membership": { "value": "Y" } },
+          },
+        ],
+      },
+    });
+
+    let issues = tree_check(&tree, &to_nuc_seq("ACGT")?, None)?
+      .into_iter()
+      .map(|issue| (issue.node_name, issue.kind))
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      issues,
+      vec![
+        ("A".to_owned(), TreeCheckIssueKind::MutationOutOfRange),
+        ("B".to_owned(), TreeCheckIssueKind::MissingClade),
+        ("B".to_owned(), TreeCheckIssueKind::MutationRefMismatch),
+        ("A".to_owned(), TreeCheckIssueKind::DuplicateName),
+        ("A".to_owned(), TreeCheckIssueKind::InvalidMutation),
+      ]
+    );
+    Ok(())
+  }
+}
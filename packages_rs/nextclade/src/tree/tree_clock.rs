@@ -0,0 +1,126 @@
+use crate::make_error;
+use crate::tree::tree::AuspiceGraph;
+use eyre::Report;
+use itertools::Itertools;
+use std::collections::BTreeMap;
+
+/// Minimum number of dated leaves of the reference tree required to estimate the molecular clock
+const MIN_DATED_LEAVES: usize = 3;
+
+/// Linear molecular clock model of the reference tree: expected divergence of a sample is proportional to its
+/// collection date. All clades share the same clock rate, but have their own offsets, such that the expected
+/// divergence is specific to the clade.
+#[derive(Clone, Debug)]
+pub struct ClockModel {
+  pub rate: f64,
+  pub intercept: f64,
+  pub clade_intercepts: BTreeMap<String, f64>,
+
+  /// Standard deviation of divergence of the dated leaves of the tree from the expected divergence
+  pub residual_std_dev: f64,
+}
+
+/// Dated sample: clade, date (as a decimal year) and divergence
+pub type ClockPoint = (String, f64, f64);
+
+impl ClockModel {
+  /// Estimates clock model from the leaves of the reference tree which have `num_date` attribute
+  pub fn from_graph(graph: &AuspiceGraph) -> Result<Self, Report> {
+    let points = graph
+      .iter_leaves()
+      .filter_map(|leaf| {
+        let node = leaf.payload();
+        let date = node.node_attrs.other.get("num_date")?.get("value")?.as_f64()?;
+        let div = node.node_attrs.div?;
+        Some((node.clade(), date, div))
+      })
+      .collect_vec();
+    Self::from_points(&points)
+  }
+
+  pub fn from_points(points: &[ClockPoint]) -> Result<Self, Report> {
+    let n = points.len();
+    if n < MIN_DATED_LEAVES {
+      return make_error!(
+        "Unable to estimate molecular clock: the reference tree has {n} leaves with both date ('num_date') and divergence ('div'), but at least {MIN_DATED_LEAVES} are required"
+      );
+    }
+
+    let mean_date = points.iter().map(|(_, date, _)| date).sum::<f64>() / n as f64;
+    let mean_div = points.iter().map(|(_, _, div)| div).sum::<f64>() / n as f64;
+    let covariance: f64 = points
+      .iter()
+      .map(|(_, date, div)| (date - mean_date) * (div - mean_div))
+      .sum();
+    let variance: f64 = points.iter().map(|(_, date, _)| (date - mean_date).powi(2)).sum();
+    if variance <= 0.0 {
+      return make_error!(
+        "Unable to estimate molecular clock: all dated leaves of the reference tree have the same date"
+      );
+    }
+
+    let rate = covariance / variance;
+    let intercept = mean_div - rate * mean_date;
+
+    let clade_intercepts = points
+      .iter()
+      .into_group_map_by(|(clade, _, _)| clade.clone())
+      .into_iter()
+      .map(|(clade, points)| {
+        let offsets = points.iter().map(|(_, date, div)| div - rate * date);
+        let clade_intercept = offsets.sum::<f64>() / points.len() as f64;
+        (clade, clade_intercept)
+      })
+      .collect();
+
+    let mut model = Self {
+      rate,
+      intercept,
+      clade_intercepts,
+      residual_std_dev: 0.0,
+    };
+
+    let sum_of_squares: f64 = points
+      .iter()
+      .map(|(clade, date, div)| (div - model.expected_divergence(clade, *date)).powi(2))
+      .sum();
+    model.residual_std_dev = (sum_of_squares / (n - 1) as f64).sqrt();
+
+    Ok(model)
+  }
+
+  /// Calculates expected divergence of a sample of a given clade at a given date. For clades not present among the
+  /// dated leaves of the tree, the offset of the tree as a whole is used.
+  pub fn expected_divergence(&self, clade: &str, date: f64) -> f64 {
+    let intercept = self.clade_intercepts.get(clade).unwrap_or(&self.intercept);
+    intercept + self.rate * date
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::float_cmp)]
+
+  use super::*;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  #[rstest]
+  fn estimates_clock_with_clade_offsets() -> Result<(), Report> {
+    let points = [
+      ("A".to_owned(), 2020.0, 10.0),
+      ("A".to_owned(), 2021.0, 20.0),
+      ("B".to_owned(), 2020.0, 15.0),
+      ("B".to_owned(), 2021.0, 25.0),
+    ];
+
+    let model = ClockModel::from_points(&points)?;
+
+    assert_eq!(model.rate, 10.0);
+    assert_eq!(model.expected_divergence("A", 2022.0), 30.0);
+    assert_eq!(model.expected_divergence("B", 2022.0), 35.0);
+    assert_eq!(model.expected_divergence("C", 2022.0), 32.5);
+    assert_eq!(model.residual_std_dev, 0.0);
+    Ok(())
+  }
+}
@@ -2,13 +2,79 @@ use crate::alphabet::nuc::Nuc;
 use crate::analyze::is_sequenced::is_nuc_sequenced;
 use crate::analyze::letter_ranges::NucRange;
 use crate::analyze::nuc_sub::NucSub;
+use crate::coord::position::NucRefGlobalPosition;
 use crate::coord::range::{NucRefGlobalRange, Range};
 use crate::graph::node::GraphNodeKey;
 use crate::tree::tree::{AuspiceGraph, AuspiceGraphNodePayload, TreeNodeAttr};
 use eyre::Report;
 use itertools::Itertools;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
 use traversal::DftPre;
 
+/// Inverted index of nucleotide substitutions of the nodes of the reference tree (relative to the reference
+/// sequence). Allows to only visit the nodes which have substitutions at the positions relevant for a given query
+/// when calculating distances from the query to the nodes.
+#[derive(Clone, Debug, Default)]
+pub struct NucSubIndex {
+  /// Nodes carrying each of the substitutions, by position and by nucleotide. Nodes are referred to by their
+  /// position in `nodes`.
+  pub nodes_by_sub: BTreeMap<NucRefGlobalPosition, BTreeMap<Nuc, Vec<usize>>>,
+
+  /// All nodes, in depth-first pre-order
+  pub nodes: Vec<NucSubIndexNode>,
+
+  /// Positions of all nodes in `nodes`, sorted by the number of substitutions (and then by prior, descending). This is
+  /// the order of placement scores of the nodes which share no substitutions with a query.
+  pub nodes_by_num_subs: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NucSubIndexNode {
+  pub key: GraphNodeKey,
+  pub num_subs: i64,
+  pub prior: f64,
+}
+
+impl NucSubIndex {
+  /// Builds the index from substitutions of the nodes. The tree needs to be preprocessed.
+  pub fn from_graph(graph: &AuspiceGraph) -> Result<Self, Report> {
+    let mut nodes_by_sub = BTreeMap::<_, BTreeMap<_, Vec<_>>>::new();
+    let mut nodes = vec![];
+    for (_, node) in DftPre::new(graph.get_exactly_one_root()?, |node| graph.iter_children_of(node)) {
+      let payload = node.payload();
+      for (&pos, &nuc) in &payload.tmp.substitutions {
+        nodes_by_sub
+          .entry(pos)
+          .or_default()
+          .entry(nuc)
+          .or_default()
+          .push(nodes.len());
+      }
+      nodes.push(NucSubIndexNode {
+        key: node.key(),
+        num_subs: payload.tmp.substitutions.len() as i64,
+        prior: get_prior(payload),
+      });
+    }
+    Ok(Self::new(nodes_by_sub, nodes))
+  }
+
+  fn new(nodes_by_sub: BTreeMap<NucRefGlobalPosition, BTreeMap<Nuc, Vec<usize>>>, nodes: Vec<NucSubIndexNode>) -> Self {
+    let nodes_by_num_subs = (0..nodes.len())
+      .sorted_by(|&a, &b| {
+        let (a, b) = (&nodes[a], &nodes[b]);
+        a.num_subs.cmp(&b.num_subs).then(b.prior.total_cmp(&a.prior))
+      })
+      .collect_vec();
+    Self {
+      nodes_by_sub,
+      nodes,
+      nodes_by_num_subs,
+    }
+  }
+}
+
 /// Distance and placement prior for a ref tree node
 pub struct TreePlacementInfo {
   pub node_key: GraphNodeKey,
@@ -16,31 +82,75 @@ pub struct TreePlacementInfo {
   pub prior: f64, // prior in non-log scale
 }
 
-/// For a given query sample, finds nearest node on the reference tree (according to the distance metric)
+/// Placement candidate along with its position in depth-first pre-order, which breaks ties between the candidates
+/// with equal distance and prior
+struct ScoredNode {
+  order: usize,
+  info: TreePlacementInfo,
+}
+
+/// Order of placement scores: by distance, then by prior (descending), then in depth-first pre-order
+fn cmp_placement_score(a: &ScoredNode, b: &ScoredNode) -> Ordering {
+  (a.info.distance.cmp(&b.info.distance))
+    .then(b.info.prior.total_cmp(&a.info.prior))
+    .then(a.order.cmp(&b.order))
+}
+
+/// For a given query sample, finds nearest nodes on the reference tree (according to the distance metric). Returns
+/// placement candidates sorted by placement score (best candidate first).
+///
+/// Not all nodes of the tree are returned, but only the best-scoring ones: all the nodes with distance not exceeding
+/// the distance of the best candidate by more than `distance_tolerance`, and then more nodes in the order of the score,
+/// until at least `num_leaves` leaf nodes are among the candidates (or until the nodes run out).
 pub fn graph_find_nearest_nodes(
   graph: &AuspiceGraph,
   qry_nuc_subs: &[NucSub],
   qry_missing: &[NucRange],
   aln_range: &NucRefGlobalRange,
+  distance_tolerance: usize,
+  num_leaves: usize,
 ) -> Result<Vec<TreePlacementInfo>, Report> {
   let masked_ranges = graph.data.meta.placement_mask_ranges();
 
-  // Iterate over tree nodes and calculate distance metric between the sample and each node
-  let nodes_by_placement_score = DftPre::new(graph.get_exactly_one_root()?, |node| graph.iter_children_of(node))
-    .map(|(_, node)| {
-      let node_payload = node.payload();
-      let distance = tree_calculate_node_distance(node_payload, qry_nuc_subs, qry_missing, aln_range, masked_ranges);
-      let prior = get_prior(node_payload);
-      TreePlacementInfo {
-        node_key: node.key(),
-        distance,
-        prior,
+  let scored_nodes: Box<dyn Iterator<Item = ScoredNode>> = if let Some(index) = &graph.data.tmp.nuc_sub_index {
+    Box::new(tree_calculate_node_distances_indexed(
+      index,
+      qry_nuc_subs,
+      qry_missing,
+      aln_range,
+      masked_ranges,
+    ))
+  } else {
+    // The index is not built (the tree is not preprocessed), so iterate over all tree nodes
+    let scored_nodes = DftPre::new(graph.get_exactly_one_root()?, |node| graph.iter_children_of(node))
+      .enumerate()
+      .map(|(order, (_, node))| ScoredNode {
+        order,
+        info: TreePlacementInfo {
+          node_key: node.key(),
+          distance: tree_calculate_node_distance(node.payload(), qry_nuc_subs, qry_missing, aln_range, masked_ranges),
+          prior: get_prior(node.payload()),
+        },
+      })
+      .sorted_by(cmp_placement_score);
+    Box::new(scored_nodes)
+  };
+
+  let mut candidates: Vec<TreePlacementInfo> = vec![];
+  let mut num_leaves_found = 0;
+  for ScoredNode { info, .. } in scored_nodes {
+    if let Some(best) = candidates.first() {
+      if info.distance > best.distance + distance_tolerance as i64 && num_leaves_found >= num_leaves {
+        break;
       }
-    })
-    .sorted_by(|a, b| a.distance.cmp(&b.distance).then(b.prior.total_cmp(&a.prior)))
-    .collect_vec();
+    }
+    if graph.get_node(info.node_key)?.is_leaf() {
+      num_leaves_found += 1;
+    }
+    candidates.push(info);
+  }
 
-  Ok(if nodes_by_placement_score.is_empty() {
+  Ok(if candidates.is_empty() {
     // Unlikely case: if there's no nodes, return parent
     vec![TreePlacementInfo {
       node_key: graph.get_exactly_one_root()?.key(),
@@ -48,7 +158,7 @@ pub fn graph_find_nearest_nodes(
       prior: 1.0,
     }]
   } else {
-    nodes_by_placement_score
+    candidates
   })
 }
 
@@ -64,6 +174,93 @@ fn get_prior(node: &AuspiceGraphNodePayload) -> f64 {
   )
 }
 
+/// Calculates distance metric between a given query sample and the nodes of the tree, using the inverted index of
+/// node substitutions. Gives the same distances as `tree_calculate_node_distance()`, and yields the nodes lazily,
+/// sorted by placement score.
+///
+/// Only the nodes which have substitutions at the positions either mutated or not sequenced in the query are scored
+/// and sorted. Distance of each of the remaining nodes is the sum of its number of substitutions and of the number of
+/// query substitutions, so these are yielded in the order which is precomputed in the index.
+fn tree_calculate_node_distances_indexed<'a>(
+  index: &'a NucSubIndex,
+  qry_nuc_subs: &[NucSub],
+  qry_missing: &[NucRange],
+  aln_range: &NucRefGlobalRange,
+  masked_ranges: &[NucRefGlobalRange],
+) -> impl Iterator<Item = ScoredNode> + 'a {
+  // Same as in `tree_calculate_node_distance()`, mask turns query mutations into missing
+  let masked_qry_nuc_subs = qry_nuc_subs
+    .iter()
+    .filter(|sub| !masked_ranges.iter().any(|range| range.contains(sub.pos)))
+    .collect_vec();
+
+  // For each node which overlaps with the query, the number of shared differences (counted twice), shared sites and
+  // undetermined sites
+  let mut overlaps = BTreeMap::<usize, i64>::new();
+
+  for qmut in &masked_qry_nuc_subs {
+    if let Some(nodes_by_nuc) = index.nodes_by_sub.get(&qmut.pos) {
+      for (nuc, nodes) in nodes_by_nuc {
+        let overlap = if *nuc == qmut.qry_nuc { 2 } else { 1 };
+        for &node in nodes {
+          *overlaps.entry(node).or_default() += overlap;
+        }
+      }
+    }
+  }
+
+  // Positions with node mutations which are not sequenced in the query: outside of the alignment range, missing or
+  // masked. Ranges can overlap, so positions are deduplicated.
+  let undetermined_positions: BTreeSet<NucRefGlobalPosition> = index
+    .nodes_by_sub
+    .range(..aln_range.begin)
+    .chain(index.nodes_by_sub.range(aln_range.end..))
+    .chain(
+      qry_missing
+        .iter()
+        .map(|missing| &missing.range)
+        .chain(masked_ranges)
+        .filter(|range| !range.is_empty())
+        .flat_map(|range| index.nodes_by_sub.range(range.begin..range.end)),
+    )
+    .map(|(pos, _)| *pos)
+    .collect();
+
+  for pos in &undetermined_positions {
+    for nodes in index.nodes_by_sub[pos].values() {
+      for &node in nodes {
+        *overlaps.entry(node).or_default() += 1;
+      }
+    }
+  }
+
+  let total_seq_muts = masked_qry_nuc_subs.len() as i64;
+  let score = move |order: usize, overlap: i64| {
+    let node = &index.nodes[order];
+    ScoredNode {
+      order,
+      info: TreePlacementInfo {
+        node_key: node.key,
+        distance: node.num_subs + total_seq_muts - overlap,
+        prior: node.prior,
+      },
+    }
+  };
+
+  let overlapping = overlaps
+    .iter()
+    .map(|(&order, &overlap)| score(order, overlap))
+    .sorted_by(cmp_placement_score);
+
+  let non_overlapping = index
+    .nodes_by_num_subs
+    .iter()
+    .filter(move |order| !overlaps.contains_key(order))
+    .map(move |&order| score(order, 0));
+
+  overlapping.merge_by(non_overlapping, |a, b| cmp_placement_score(a, b) != Ordering::Greater)
+}
+
 /// Calculates distance metric between a given query sample and a tree node
 fn tree_calculate_node_distance(
   node: &AuspiceGraphNodePayload,
@@ -126,13 +323,19 @@ mod tests {
   use std::collections::BTreeMap;
 
   use crate::alphabet::nuc::Nuc;
-  use crate::tree::tree::{TreeBranchAttrs, TreeNodeAttrs, TreeNodeTempData};
+  use crate::tree::tree::{TreeBranchAttrs, TreeNodeAttrF64, TreeNodeAttrs, TreeNodeTempData};
 
   use super::*;
+  use crate::alphabet::nuc::to_nuc_seq;
   use crate::coord::position::NucRefGlobalPosition;
+  use crate::graph::graph::convert_auspice_tree_to_graph;
+  use crate::translate::translate_genes::Translation;
+  use crate::tree::tree::AuspiceTree;
+  use crate::tree::tree_preprocess::graph_preprocess_in_place;
   use eyre::Report;
   use pretty_assertions::assert_eq;
   use rstest::{fixture, rstest};
+  use serde_json::json;
 
   /// Default node for testing
   #[fixture]
@@ -363,4 +566,154 @@ mod tests {
 
     Ok(())
   }
+
+  #[rstest]
+  fn indexed_distances_are_same_as_per_node() -> Result<(), Report> {
+    let nodes = [
+      (BTreeMap::new(), None),
+      (simple_node_nuc_subs(), Some(-2.0)),
+      (
+        vec![(3.into(), Nuc::C), (9.into(), Nuc::G), (12.into(), Nuc::A)]
+          .into_iter()
+          .collect(),
+        None,
+      ),
+      (vec![(40.into(), Nuc::G)].into_iter().collect(), Some(-1.0)),
+      (vec![(41.into(), Nuc::G)].into_iter().collect(), None),
+      (BTreeMap::new(), Some(-1.0)),
+    ]
+    .into_iter()
+    .map(|(substitutions, prior)| {
+      let mut node = default_node();
+      node.tmp.substitutions = substitutions;
+      node.node_attrs.placement_prior = prior.map(|value| TreeNodeAttrF64 {
+        value,
+        other: serde_json::Value::default(),
+      });
+      node
+    })
+    .collect_vec();
+
+    let mut nodes_by_sub = BTreeMap::<_, BTreeMap<_, Vec<_>>>::new();
+    for (order, node) in nodes.iter().enumerate() {
+      for (&pos, &nuc) in &node.tmp.substitutions {
+        nodes_by_sub.entry(pos).or_default().entry(nuc).or_default().push(order);
+      }
+    }
+    let index = NucSubIndex::new(
+      nodes_by_sub,
+      nodes
+        .iter()
+        .enumerate()
+        .map(|(order, node)| NucSubIndexNode {
+          key: GraphNodeKey::new(order),
+          num_subs: node.tmp.substitutions.len() as i64,
+          prior: get_prior(node),
+        })
+        .collect(),
+    );
+
+    let queries = [
+      (vec![], vec![], NucRefGlobalRange::from_usize(0, 100), vec![]),
+      (
+        simple_qry_nuc_subs(),
+        simple_qry_missing(),
+        NucRefGlobalRange::from_usize(0, 100),
+        vec![],
+      ),
+      (
+        simple_qry_nuc_subs(),
+        simple_qry_missing(),
+        NucRefGlobalRange::from_usize(5, 30),
+        vec![NucRefGlobalRange::from_usize(12, 13)],
+      ),
+      (
+        simple_qry_nuc_subs(),
+        simple_qry_missing(),
+        NucRefGlobalRange::from_usize(0, 30),
+        vec![
+          NucRefGlobalRange::from_usize(0, 5),
+          NucRefGlobalRange::from_usize(20, 50),
+        ],
+      ),
+    ];
+
+    let to_distances = |scored: Vec<ScoredNode>| {
+      scored
+        .into_iter()
+        .map(|ScoredNode { info, .. }| (info.node_key, info.distance))
+        .collect_vec()
+    };
+
+    for (qry_nuc_subs, qry_missing, aln_range, masked_ranges) in &queries {
+      let expected = nodes
+        .iter()
+        .enumerate()
+        .map(|(order, node)| ScoredNode {
+          order,
+          info: TreePlacementInfo {
+            node_key: GraphNodeKey::new(order),
+            distance: tree_calculate_node_distance(node, qry_nuc_subs, qry_missing, aln_range, masked_ranges),
+            prior: get_prior(node),
+          },
+        })
+        .sorted_by(cmp_placement_score)
+        .collect_vec();
+      let actual = tree_calculate_node_distances_indexed(&index, qry_nuc_subs, qry_missing, aln_range, masked_ranges)
+        .collect_vec();
+      assert_eq!(to_distances(actual), to_distances(expected));
+    }
+
+    Ok(())
+  }
+
+  #[rstest]
+  #[case::equidistant_only(0, 0, &["N"])]
+  #[case::within_tolerance(1, 0, &["N", "root", "A", "B"])]
+  #[case::until_enough_leaves(0, 3, &["N", "root", "A", "B", "C"])]
+  fn returns_only_best_candidates(
+    #[case] distance_tolerance: usize,
+    #[case] num_leaves: usize,
+    #[case] expected: &[&str],
+  ) -> Result<(), Report> {
+    let node = |name: &str, muts: &[&str], children: &[serde_json::Value]| {
+      json!({
+        "name": name,
+        "branch_attrs": { "mutations": { "nuc": muts } },
+        "node_attrs": { "clade_membership": { "value": "X" } },
+        "children": children,
+      })
+    };
+    let tree: AuspiceTree = serde_json::from_value(json!({
+      "meta": {},
+      "tree": node("root", &[], &[
+        node("N", &["A1T"], &[node("A", &["A2T"], &[]), node("B", &["A3T"], &[])]),
+        node("C", &["A4T", "A5T"], &[]),
+      ]),
+    }))?;
+    let ref_seq = to_nuc_seq("AAAAAAAAAA")?;
+    let mut graph = convert_auspice_tree_to_graph(tree)?;
+    graph_preprocess_in_place(&mut graph, &ref_seq, &Translation::default())?;
+
+    let qry_nuc_subs = vec![NucSub {
+      ref_nuc: Nuc::A,
+      pos: 0.into(),
+      qry_nuc: Nuc::T,
+    }];
+    let candidates = graph_find_nearest_nodes(
+      &graph,
+      &qry_nuc_subs,
+      &[],
+      &NucRefGlobalRange::from_usize(0, 10),
+      distance_tolerance,
+      num_leaves,
+    )?;
+
+    let actual = candidates
+      .iter()
+      .map(|candidate| graph.get_node(candidate.node_key).unwrap().payload().name.as_str())
+      .collect_vec();
+    assert_eq!(actual, expected);
+    Ok(())
+  }
 }
@@ -0,0 +1,198 @@
+use crate::graph::graph::convert_auspice_tree_to_graph;
+use crate::graph::node::{GraphNodeKey, Node};
+use crate::graph::traits::HasDivergence;
+use crate::tree::tree::{AuspiceGraph, AuspiceGraphNodePayload, AuspiceTree, AuspiceTreeNode};
+use eyre::{Report, WrapErr};
+use ordered_float::OrderedFloat;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap};
+
+/// Extracts the focal context subtree: the tree which contains only the new (query) leaf nodes, the given number of
+/// the nearest reference leaf nodes for each of them, and the ancestors connecting these nodes. Attributes and branch
+/// mutations of the nodes are preserved. The root of the subtree is the most recent common ancestor of the retained
+/// leaf nodes.
+///
+/// Distances between nodes are measured along the branches of the tree, using divergence.
+pub fn graph_extract_focal_subtree(graph: &AuspiceGraph, num_context_tips: usize) -> Result<AuspiceGraph, Report> {
+  let query_keys = graph
+    .iter_leaves()
+    .filter(|node| is_new_node(node.payload()))
+    .map(Node::key)
+    .collect::<Vec<_>>();
+
+  let mut focal_keys = BTreeSet::new();
+  for &query_key in &query_keys {
+    focal_keys.insert(query_key);
+    focal_keys.extend(find_nearest_reference_tips(graph, query_key, num_context_tips)?);
+  }
+
+  // Retain focal nodes and all of their ancestors
+  let mut retained_keys = BTreeSet::new();
+  for &key in &focal_keys {
+    let mut key = Some(key);
+    while let Some(k) = key {
+      if !retained_keys.insert(k) {
+        break;
+      }
+      key = graph.parent_key_of_by_key(k);
+    }
+  }
+
+  // Descend from the root to the most recent common ancestor of the focal nodes
+  let mut root_key = graph.get_exactly_one_root()?.key();
+  loop {
+    let retained_children = graph
+      .iter_child_keys_of_by_key(root_key)
+      .filter(|child_key| retained_keys.contains(child_key))
+      .collect::<Vec<_>>();
+    match retained_children[..] {
+      [child_key] if !focal_keys.contains(&root_key) => root_key = child_key,
+      _ => break,
+    }
+  }
+
+  let tree = AuspiceTree {
+    version: graph.data.auspice_tree_version.clone(),
+    meta: graph.data.meta.clone(),
+    tree: convert_retained_to_auspice_tree_recursive(graph, root_key, &retained_keys)?,
+    other: graph.data.other.clone(),
+  };
+
+  convert_auspice_tree_to_graph(tree).wrap_err("When converting focal context subtree to graph")
+}
+
+fn is_new_node(node: &AuspiceGraphNodePayload) -> bool {
+  node
+    .node_attrs
+    .node_type
+    .as_ref()
+    .map_or(false, |node_type| node_type.value == "New")
+}
+
+/// Finds reference leaf nodes nearest to the given node, by traversing the tree outwards from it in the order of
+/// increasing distance
+fn find_nearest_reference_tips(
+  graph: &AuspiceGraph,
+  start_key: GraphNodeKey,
+  num_tips: usize,
+) -> Result<Vec<GraphNodeKey>, Report> {
+  let mut tips = vec![];
+  let mut visited = BTreeSet::new();
+  let mut queue = BinaryHeap::from([Reverse((OrderedFloat(0.0), start_key))]);
+
+  while let Some(Reverse((distance, key))) = queue.pop() {
+    if tips.len() >= num_tips {
+      break;
+    }
+
+    if !visited.insert(key) {
+      continue;
+    }
+
+    let node = graph.get_node(key)?;
+    if node.is_leaf() && !is_new_node(node.payload()) {
+      tips.push(key);
+    }
+
+    let div = node.payload().divergence();
+    let neighbour_keys = graph
+      .parent_key_of_by_key(key)
+      .into_iter()
+      .chain(graph.iter_child_keys_of_by_key(key));
+    for neighbour_key in neighbour_keys {
+      if !visited.contains(&neighbour_key) {
+        let neighbour_div = graph.get_node(neighbour_key)?.payload().divergence();
+        queue.push(Reverse((distance + (neighbour_div - div).abs(), neighbour_key)));
+      }
+    }
+  }
+
+  Ok(tips)
+}
+
+fn convert_retained_to_auspice_tree_recursive(
+  graph: &AuspiceGraph,
+  node_key: GraphNodeKey,
+  retained_keys: &BTreeSet<GraphNodeKey>,
+) -> Result<AuspiceTreeNode, Report> {
+  let children = graph
+    .iter_child_keys_of_by_key(node_key)
+    .filter(|child_key| retained_keys.contains(child_key))
+    .map(|child_key| convert_retained_to_auspice_tree_recursive(graph, child_key, retained_keys))
+    .collect::<Result<Vec<_>, Report>>()?;
+  let node = graph.get_node(node_key)?;
+  Ok(AuspiceTreeNode::from_graph_node_payload(node.payload(), children))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tree::tree::{AuspiceGraphEdgePayload, TreeNodeAttr};
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+  use serde_json::json;
+
+  fn node(name: &str, div: f64, children: &[serde_json::Value]) -> serde_json::Value {
+    json!({
+      "name": name,
+      "branch_attrs": { "mutations": { "nuc": [format!("A{}T", (div * 100.0) as usize)] } },
+      "node_attrs": { "div": div, "clade_membership": { "value": "X" } },
+      "children": children,
+    })
+  }
+
+  fn names(node: &AuspiceTreeNode) -> String {
+    if node.children.is_empty() {
+      node.name.clone()
+    } else {
+      let children = node.children.iter().map(names).collect::<Vec<_>>().join(",");
+      format!("({children}){}", node.name)
+    }
+  }
+
+  #[rstest]
+  fn extracts_focal_subtree() -> Result<(), Report> {
+    let tree: AuspiceTree = serde_json::from_value(json!({
+      "meta": {},
+      "tree": node("root", 0.0, &[
+        node("A", 0.5, &[]),
+        node("N", 0.25, &[
+          node("M", 0.5, &[node("B", 0.75, &[]), node("C", 1.25, &[])]),
+          node("D", 1.0, &[]),
+        ]),
+      ]),
+    }))?;
+    let mut graph = convert_auspice_tree_to_graph(tree)?;
+
+    // Query attached the same way as the tree builder does: by splitting the branch leading to leaf B
+    let b_key = graph
+      .iter_nodes()
+      .find(|node| node.payload().name == "B")
+      .unwrap()
+      .key();
+    let mut internal = graph.get_node(b_key)?.payload().clone();
+    internal.name = "I".to_owned();
+    internal.node_attrs.div = Some(0.625);
+    let internal_key = graph.add_node(internal.clone());
+    graph.insert_node_before(
+      internal_key,
+      b_key,
+      AuspiceGraphEdgePayload::new(),
+      AuspiceGraphEdgePayload::new(),
+    )?;
+
+    let mut query = internal;
+    query.name = "Q".to_owned();
+    query.node_attrs.div = Some(0.75);
+    query.node_attrs.node_type = Some(TreeNodeAttr::new("New"));
+    let query_key = graph.add_node(query);
+    graph.add_edge(internal_key, query_key, AuspiceGraphEdgePayload::new())?;
+
+    let focal = graph_extract_focal_subtree(&graph, 2)?.to_auspice_tree()?;
+
+    // Nearest tips to Q are B (at distance 0.25) and C (1.0), which are closer than A and D (1.25)
+    assert_eq!(names(&focal.tree), "(C,(B,Q)I)M");
+    assert_eq!(focal.tree.branch_attrs.mutations["nuc"], vec!["A50T".to_owned()]);
+    Ok(())
+  }
+}
@@ -0,0 +1,819 @@
+use crate::align::params::AlignPairwiseParams;
+use crate::alphabet::aa::Aa;
+use crate::alphabet::letter::Letter;
+use crate::alphabet::nuc::{from_nuc, to_nuc_seq, Nuc};
+use crate::analyze::aa_sub::AaSub;
+use crate::analyze::nuc_sub::NucSub;
+use crate::coord::coord_map_cds_to_global::{cds_codon_pos_to_ref_range, global_ref_pos_to_local};
+use crate::coord::position::PositionLike;
+use crate::gene::cds::Cds;
+use crate::gene::gene::GeneStrand;
+use crate::gene::gene_map::GeneMap;
+use crate::io::csv::parse_csv_with_delimiter;
+use crate::io::fasta::{FastaReader, FastaRecord};
+use crate::io::fs::read_file_to_string;
+use crate::io::nwk_reader::NwkNode;
+use crate::make_error;
+use crate::translate::complement::reverse_complement_in_place;
+use crate::translate::translate::decode;
+use crate::translate::translate_genes_ref::translate_genes_ref;
+use crate::tree::tree::{
+  AuspiceColoring, AuspiceDisplayDefaults, AuspiceMetaExtensions, AuspiceMetaExtensionsNextclade, AuspiceTree,
+  AuspiceTreeMeta, AuspiceTreeNode, CladeNodeAttrKeyDesc, TreeBranchAttrs, TreeNodeAttr, TreeNodeAttrs,
+  AUSPICE_UNKNOWN_VALUE,
+};
+use eyre::{Report, WrapErr};
+use itertools::Itertools;
+use log::warn;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Names of columns which can contain node names, in order of preference
+const NODE_NAME_COLUMNS: &[&str] = &["seqName", "strain", "name", "node"];
+
+/// Names of columns which can contain clade, in order of preference
+const CLADE_COLUMNS: &[&str] = &["clade_membership", "clade"];
+
+/// Key of node attribute containing clade
+pub const CLADE_ATTR: &str = "clade_membership";
+
+const MUTATIONS_COLUMN: &str = "mutations";
+
+/// Clade and other clade-like attributes of the nodes of a Newick tree, by node name
+#[derive(Clone, Debug, Default)]
+pub struct NwkTreeMetadata {
+  /// Names of clade-like attributes, other than clade
+  pub attr_names: Vec<String>,
+  pub nodes: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Source of mutations on the branches of a Newick tree
+#[derive(Clone, Debug)]
+pub enum NwkTreeMutations {
+  /// Sequences of tree nodes (at least of the leaves), aligned to the reference sequence. Mutations are reconstructed
+  /// from these.
+  Alignment(BTreeMap<String, Vec<Nuc>>),
+
+  /// Nucleotide substitutions on the branches leading to the nodes
+  BranchMutations(BTreeMap<String, Vec<NucSub>>),
+}
+
+pub fn read_tree_metadata_tsv(filepath: impl AsRef<Path>) -> Result<NwkTreeMetadata, Report> {
+  let filepath = filepath.as_ref();
+  let data =
+    read_file_to_string(filepath).wrap_err_with(|| format!("When reading tree metadata file {filepath:#?}"))?;
+  parse_tree_metadata_tsv(&data).wrap_err_with(|| format!("When parsing tree metadata file {filepath:#?}"))
+}
+
+/// Parses tree metadata TSV. The file should contain a column with node names (`seqName`, `strain`, `name` or `node`)
+/// and, optionally, a column with clades (`clade_membership` or `clade`). All other columns are treated as clade-like
+/// attributes. Empty values are treated as unknown.
+pub fn parse_tree_metadata_tsv(data: &str) -> Result<NwkTreeMetadata, Report> {
+  let rows: Vec<BTreeMap<String, String>> = parse_csv_with_delimiter(data, b'\t')?;
+
+  let Some(first_row) = rows.first() else {
+    return Ok(NwkTreeMetadata::default());
+  };
+
+  let name_column = find_column(first_row, NODE_NAME_COLUMNS)?;
+  let clade_column = CLADE_COLUMNS.iter().find(|column| first_row.contains_key(**column));
+
+  let attr_names = first_row
+    .keys()
+    .filter(|column| *column != name_column && Some(column.as_str()) != clade_column.copied())
+    .cloned()
+    .collect_vec();
+
+  let nodes = rows
+    .into_iter()
+    .map(|mut row| {
+      let name = row.remove(name_column).unwrap_or_default();
+      let attrs = row
+        .into_iter()
+        .filter(|(_, value)| !value.trim().is_empty())
+        .map(|(column, value)| {
+          let column = if Some(column.as_str()) == clade_column.copied() {
+            CLADE_ATTR.to_owned()
+          } else {
+            column
+          };
+          (column, value.trim().to_owned())
+        })
+        .collect();
+      (name, attrs)
+    })
+    .collect();
+
+  Ok(NwkTreeMetadata { attr_names, nodes })
+}
+
+/// Reads sequences of tree nodes, aligned to the reference sequence
+pub fn read_tree_alignment_fasta(filepath: impl AsRef<Path>) -> Result<NwkTreeMutations, Report> {
+  let filepath = filepath.as_ref();
+  let mut reader = FastaReader::from_path(filepath)?.keep_gaps();
+  let mut seqs = BTreeMap::new();
+  loop {
+    let mut record = FastaRecord::default();
+    reader.read(&mut record)?;
+    if record.is_empty() {
+      break;
+    }
+    let seq = to_nuc_seq(&record.seq)
+      .wrap_err_with(|| format!("When reading sequence '{}' from {filepath:#?}", record.seq_name))?;
+    seqs.insert(record.seq_name, seq);
+  }
+  Ok(NwkTreeMutations::Alignment(seqs))
+}
+
+pub fn read_node_mutations_tsv(filepath: impl AsRef<Path>) -> Result<NwkTreeMutations, Report> {
+  let filepath = filepath.as_ref();
+  let data =
+    read_file_to_string(filepath).wrap_err_with(|| format!("When reading node mutations file {filepath:#?}"))?;
+  parse_node_mutations_tsv(&data).wrap_err_with(|| format!("When parsing node mutations file {filepath:#?}"))
+}
+
+/// Parses node mutations TSV. The file should contain a column with node names (`seqName`, `strain`, `name` or `node`)
+/// and a column `mutations`, with comma-separated nucleotide substitutions on the branch leading to the node (e.g.
+/// `C241T,A23403G`).
+pub fn parse_node_mutations_tsv(data: &str) -> Result<NwkTreeMutations, Report> {
+  let rows: Vec<BTreeMap<String, String>> = parse_csv_with_delimiter(data, b'\t')?;
+
+  let Some(first_row) = rows.first() else {
+    return Ok(NwkTreeMutations::BranchMutations(BTreeMap::new()));
+  };
+
+  let name_column = find_column(first_row, NODE_NAME_COLUMNS)?;
+  find_column(first_row, &[MUTATIONS_COLUMN])?;
+
+  let mutations = rows
+    .iter()
+    .map(|row| {
+      let name = row.get(name_column).cloned().unwrap_or_default();
+      let muts = row
+        .get(MUTATIONS_COLUMN)
+        .map(|muts| muts.split(',').map(str::trim).filter(|m| !m.is_empty()).collect_vec())
+        .unwrap_or_default()
+        .into_iter()
+        .map(NucSub::from_str)
+        .collect::<Result<Vec<NucSub>, Report>>()
+        .wrap_err_with(|| format!("When parsing mutations of node '{name}'"))?;
+      Ok((name, muts))
+    })
+    .collect::<Result<_, Report>>()?;
+
+  Ok(NwkTreeMutations::BranchMutations(mutations))
+}
+
+fn find_column<'a>(row: &BTreeMap<String, String>, candidates: &[&'a str]) -> Result<&'a str, Report> {
+  match candidates.iter().find(|column| row.contains_key(**column)) {
+    Some(column) => Ok(column),
+    None => make_error!("Required column not found. Expected one of: {}", candidates.join(", ")),
+  }
+}
+
+/// Node of a Newick tree, flattened into a list in pre-order, such that parents always precede their children
+#[derive(Clone, Debug)]
+struct FlatNode {
+  name: String,
+  branch_length: Option<f64>,
+  parent: Option<usize>,
+  children: Vec<usize>,
+}
+
+/// Name of a node of a Newick tree. Unnamed internal nodes are numbered sequentially in pre-order, counting only the
+/// unnamed internal nodes, the same way as Augur does it: `NODE_0000000`, `NODE_0000001`, etc. Leaves are expected to
+/// be named.
+pub fn nwk_node_name(node: &NwkNode, num_unnamed: &mut usize) -> Result<String, Report> {
+  match &node.name {
+    Some(name) => Ok(name.clone()),
+    None if node.is_leaf() => {
+      make_error!("Found a leaf node without a name. All leaves of the tree are expected to be named")
+    }
+    None => {
+      let name = format!("NODE_{:07}", *num_unnamed);
+      *num_unnamed += 1;
+      Ok(name)
+    }
+  }
+}
+
+fn flatten_nwk(root: &NwkNode) -> Result<Vec<FlatNode>, Report> {
+  fn flatten_recursive(
+    node: &NwkNode,
+    parent: Option<usize>,
+    nodes: &mut Vec<FlatNode>,
+    num_unnamed: &mut usize,
+  ) -> Result<usize, Report> {
+    let index = nodes.len();
+    nodes.push(FlatNode {
+      name: nwk_node_name(node, num_unnamed)?,
+      branch_length: node.branch_length,
+      parent,
+      children: vec![],
+    });
+    for child in &node.children {
+      let child_index = flatten_recursive(child, Some(index), nodes, num_unnamed)?;
+      nodes[index].children.push(child_index);
+    }
+    Ok(index)
+  }
+
+  let mut nodes = vec![];
+  flatten_recursive(root, None, &mut nodes, &mut 0)?;
+
+  let mut seen = BTreeSet::new();
+  for node in &nodes {
+    if !seen.insert(&node.name) {
+      return make_error!(
+        "Node names in the tree are expected to be unique, but found duplicate name '{}'",
+        node.name
+      );
+    }
+  }
+
+  Ok(nodes)
+}
+
+/// Reconstructs states of all nodes from states observed in some of them, using Fitch parsimony. Where there is a
+/// choice between equally parsimonious states, the state of the parent is kept if possible, then the preferred state,
+/// then the smallest one. States remain unknown (`None`) only if nothing is observed and there is no preferred state.
+fn fitch_reconstruct<T: Ord + Clone>(
+  nodes: &[FlatNode],
+  observed: &[Option<T>],
+  preferred: Option<&T>,
+) -> Vec<Option<T>> {
+  // Bottom-up pass: sets of candidate states, sorted. `None` stands for "any state".
+  let mut candidates: Vec<Option<Vec<T>>> = vec![None; nodes.len()];
+  for (index, node) in nodes.iter().enumerate().rev() {
+    candidates[index] = if let Some(state) = &observed[index] {
+      Some(vec![state.clone()])
+    } else {
+      let child_sets = node
+        .children
+        .iter()
+        .filter_map(|&child| candidates[child].as_ref())
+        .collect_vec();
+      match child_sets.split_first() {
+        None => None,
+        Some((first, rest)) => {
+          let intersection = first
+            .iter()
+            .filter(|state| rest.iter().all(|set| set.binary_search(state).is_ok()))
+            .cloned()
+            .collect_vec();
+          if intersection.is_empty() {
+            Some(child_sets.into_iter().flatten().cloned().sorted().dedup().collect())
+          } else {
+            Some(intersection)
+          }
+        }
+      }
+    };
+  }
+
+  // Top-down pass: pick one state for each node
+  let mut states: Vec<Option<T>> = vec![None; nodes.len()];
+  for (index, node) in nodes.iter().enumerate() {
+    let parent_state = match node.parent {
+      Some(parent) => states[parent].as_ref(),
+      None => preferred,
+    };
+    states[index] = match &candidates[index] {
+      None => parent_state.cloned(),
+      Some(set) => [parent_state, preferred]
+        .into_iter()
+        .flatten()
+        .find(|state| set.binary_search(state).is_ok())
+        .or_else(|| set.first())
+        .cloned(),
+    };
+  }
+
+  states
+}
+
+/// Only definite nucleotides and gaps take part in reconstruction. Ambiguous nucleotides are treated as unknown.
+fn is_definite_nuc(nuc: Nuc) -> bool {
+  nuc.is_acgt() || nuc.is_gap()
+}
+
+fn reconstruct_branch_mutations(
+  nodes: &[FlatNode],
+  seqs: &BTreeMap<String, Vec<Nuc>>,
+  ref_seq: &[Nuc],
+) -> Result<Vec<Vec<NucSub>>, Report> {
+  for (name, seq) in seqs {
+    if seq.len() != ref_seq.len() {
+      return make_error!(
+        "Sequences of tree nodes are expected to be aligned to the reference sequence, but length of sequence '{name}' ({}) differs from length of the reference sequence ({})",
+        seq.len(),
+        ref_seq.len()
+      );
+    }
+  }
+
+  let node_names: BTreeSet<&String> = nodes.iter().map(|node| &node.name).collect();
+  let num_unknown = seqs.keys().filter(|name| !node_names.contains(name)).count();
+  if num_unknown > 0 {
+    warn!("{num_unknown} sequence(s) in the tree alignment do not correspond to any node of the tree and are ignored");
+  }
+
+  let node_seqs = nodes.iter().map(|node| seqs.get(&node.name)).collect_vec();
+  let num_missing = nodes
+    .iter()
+    .zip(&node_seqs)
+    .filter(|(node, seq)| node.children.is_empty() && seq.is_none())
+    .count();
+  if num_missing > 0 {
+    warn!("{num_missing} leaf node(s) of the tree have no sequence in the tree alignment. Their mutations cannot be reconstructed.");
+  }
+
+  let mut branch_mutations = vec![vec![]; nodes.len()];
+  for (pos, &ref_nuc) in ref_seq.iter().enumerate() {
+    let is_variable = node_seqs
+      .iter()
+      .flatten()
+      .any(|seq| is_definite_nuc(seq[pos]) && seq[pos] != ref_nuc);
+    if !is_variable {
+      continue;
+    }
+
+    let observed = node_seqs
+      .iter()
+      .map(|seq| seq.map(|seq| seq[pos]).filter(|nuc| is_definite_nuc(*nuc)))
+      .collect_vec();
+    let states = fitch_reconstruct(nodes, &observed, Some(&ref_nuc));
+
+    for (index, node) in nodes.iter().enumerate() {
+      let parent_state = node.parent.map_or(Some(ref_nuc), |parent| states[parent]);
+      if let (Some(parent_nuc), Some(nuc)) = (parent_state, states[index]) {
+        if parent_nuc != nuc {
+          branch_mutations[index].push(NucSub {
+            pos: pos.into(),
+            ref_nuc: parent_nuc,
+            qry_nuc: nuc,
+          });
+        }
+      }
+    }
+  }
+
+  Ok(branch_mutations)
+}
+
+fn assign_branch_mutations(
+  nodes: &[FlatNode],
+  mutations: &BTreeMap<String, Vec<NucSub>>,
+) -> Result<Vec<Vec<NucSub>>, Report> {
+  let node_names: BTreeSet<&String> = nodes.iter().map(|node| &node.name).collect();
+  if let Some(name) = mutations.keys().find(|name| !node_names.contains(name)) {
+    return make_error!("Node '{name}' listed in the node mutations file is not found in the tree");
+  }
+
+  Ok(
+    nodes
+      .iter()
+      .map(|node| {
+        let mut muts = mutations.get(&node.name).cloned().unwrap_or_default();
+        muts.sort_by_key(|m| m.pos);
+        muts
+      })
+      .collect(),
+  )
+}
+
+/// Builds a reference tree from a tree in Newick format, metadata of its nodes and either an alignment of node
+/// sequences or mutations on its branches.
+///
+/// Attributes of the nodes which are missing in the metadata (e.g. of internal nodes) and, if an alignment is given,
+/// sequences of the nodes which are missing in the alignment (e.g. of internal nodes) are reconstructed using Fitch
+/// parsimony. Amino acid mutations are inferred by translating reconstructed node sequences. Divergence is taken from
+/// branch lengths if all branches have lengths, otherwise it is the number of nucleotide substitutions.
+pub fn auspice_tree_from_nwk(
+  nwk: &NwkNode,
+  metadata: &NwkTreeMetadata,
+  mutations: &NwkTreeMutations,
+  ref_seq: &[Nuc],
+  gene_map: &GeneMap,
+) -> Result<AuspiceTree, Report> {
+  let nodes = flatten_nwk(nwk)?;
+
+  let branch_mutations = match mutations {
+    NwkTreeMutations::Alignment(seqs) => reconstruct_branch_mutations(&nodes, seqs, ref_seq)
+      .wrap_err("When reconstructing mutations from the tree alignment")?,
+    NwkTreeMutations::BranchMutations(mutations) => assign_branch_mutations(&nodes, mutations)?,
+  };
+
+  let attr_names = std::iter::once(CLADE_ATTR.to_owned())
+    .chain(metadata.attr_names.iter().cloned())
+    .collect_vec();
+  let node_attrs: BTreeMap<&str, Vec<Option<String>>> = attr_names
+    .iter()
+    .map(|attr| {
+      let observed = nodes
+        .iter()
+        .map(|node| {
+          metadata
+            .nodes
+            .get(&node.name)
+            .and_then(|attrs| attrs.get(attr))
+            .cloned()
+        })
+        .collect_vec();
+      (attr.as_str(), fitch_reconstruct(&nodes, &observed, None))
+    })
+    .collect();
+
+  let use_branch_lengths = nodes
+    .iter()
+    .all(|node| node.parent.is_none() || node.branch_length.is_some());
+  let mut divs = vec![0.0; nodes.len()];
+  for (index, node) in nodes.iter().enumerate() {
+    if let Some(parent) = node.parent {
+      let branch_length = if use_branch_lengths {
+        node.branch_length.unwrap_or_default()
+      } else {
+        branch_mutations[index].iter().filter(|m| !m.qry_nuc.is_gap()).count() as f64
+      };
+      divs[index] = divs[parent] + branch_length;
+    }
+  }
+
+  let ref_translation = translate_genes_ref(ref_seq, gene_map, &AlignPairwiseParams::default())
+    .wrap_err("When translating reference sequence")?;
+
+  let builder = AuspiceTreeBuilder {
+    nodes: &nodes,
+    branch_mutations: &branch_mutations,
+    node_attrs: &node_attrs,
+    divs: &divs,
+    gene_map,
+  };
+  let mut seq = ref_seq.to_vec();
+  let mut peptides = ref_translation
+    .iter_cdses()
+    .map(|(cds_name, cds_tr)| (cds_name.clone(), cds_tr.seq.clone()))
+    .collect();
+  let tree = builder.build_node(0, &mut seq, &mut peptides)?;
+
+  Ok(AuspiceTree {
+    version: Some("v2".to_owned()),
+    meta: create_meta(&metadata.attr_names),
+    tree,
+    other: serde_json::Value::default(),
+  })
+}
+
+struct AuspiceTreeBuilder<'a> {
+  nodes: &'a [FlatNode],
+  branch_mutations: &'a [Vec<NucSub>],
+  node_attrs: &'a BTreeMap<&'a str, Vec<Option<String>>>,
+  divs: &'a [f64],
+  gene_map: &'a GeneMap,
+}
+
+impl AuspiceTreeBuilder<'_> {
+  fn build_node(
+    &self,
+    index: usize,
+    seq: &mut [Nuc],
+    peptides: &mut BTreeMap<String, Vec<Aa>>,
+  ) -> Result<AuspiceTreeNode, Report> {
+    let node = &self.nodes[index];
+    let nuc_muts = &self.branch_mutations[index];
+
+    // Apply mutations of the branch, remembering the replaced letters, such that the state of the parent can be restored
+    let mut replaced_nucs = Vec::with_capacity(nuc_muts.len());
+    for m in nuc_muts {
+      let pos = m.pos.as_usize();
+      let len = seq.len();
+      let Some(current) = seq.get_mut(pos) else {
+        return make_error!(
+          "Mutation {m} on the branch leading to node '{}' is outside of the reference sequence (length {len})",
+          node.name,
+        );
+      };
+      if *current != m.ref_nuc {
+        return make_error!(
+          "Mutation {m} on the branch leading to node '{}' is inconsistent with the parent node, which has '{}' at this position",
+          node.name,
+          from_nuc(*current)
+        );
+      }
+      replaced_nucs.push((pos, std::mem::replace(current, m.qry_nuc)));
+    }
+
+    let mut mutations = BTreeMap::new();
+    if !nuc_muts.is_empty() {
+      mutations.insert("nuc".to_owned(), nuc_muts.iter().map(NucSub::to_string).collect_vec());
+    }
+
+    // Only the codons containing mutated nucleotides need to be translated again
+    let mut replaced_aas = vec![];
+    for cds in self.gene_map.iter_cdses() {
+      let Some(peptide) = peptides.get_mut(&cds.name) else {
+        continue;
+      };
+
+      let codons: BTreeSet<usize> = nuc_muts
+        .iter()
+        .flat_map(|m| global_ref_pos_to_local(cds, m.pos))
+        .map(|pos| pos.as_usize() / 3)
+        .filter(|&codon| codon < peptide.len())
+        .collect();
+
+      let mut aa_muts = vec![];
+      for codon in codons {
+        let ref_aa = peptide[codon];
+        let qry_aa = translate_codon(seq, cds, codon);
+        if qry_aa != ref_aa {
+          let sub = AaSub {
+            cds_name: cds.name.clone(),
+            pos: codon.into(),
+            ref_aa,
+            qry_aa,
+          };
+          aa_muts.push(sub.to_string_without_gene());
+          peptide[codon] = qry_aa;
+          replaced_aas.push((&cds.name, codon, ref_aa));
+        }
+      }
+      if !aa_muts.is_empty() {
+        mutations.insert(cds.name.clone(), aa_muts);
+      }
+    }
+
+    let children = node
+      .children
+      .iter()
+      .map(|&child| self.build_node(child, seq, peptides))
+      .collect::<Result<Vec<_>, Report>>()?;
+
+    for (cds_name, codon, aa) in replaced_aas.into_iter().rev() {
+      if let Some(peptide) = peptides.get_mut(cds_name) {
+        peptide[codon] = aa;
+      }
+    }
+    for (pos, nuc) in replaced_nucs.into_iter().rev() {
+      seq[pos] = nuc;
+    }
+
+    let clade = self.node_attr(CLADE_ATTR, index).unwrap_or(AUSPICE_UNKNOWN_VALUE);
+    let other: serde_json::Value = self
+      .node_attrs
+      .keys()
+      .filter(|attr| **attr != CLADE_ATTR)
+      .filter_map(|attr| {
+        self
+          .node_attr(attr, index)
+          .map(|value| ((*attr).to_owned(), json!({ "value": value })))
+      })
+      .collect::<serde_json::Map<_, _>>()
+      .into();
+
+    Ok(AuspiceTreeNode {
+      name: node.name.clone(),
+      branch_attrs: TreeBranchAttrs {
+        mutations,
+        labels: None,
+        other: serde_json::Value::default(),
+      },
+      node_attrs: TreeNodeAttrs {
+        div: Some(self.divs[index]),
+        clade_membership: TreeNodeAttr::new(clade),
+        node_type: None,
+        region: None,
+        country: None,
+        division: None,
+        placement_prior: None,
+        alignment: None,
+        missing: None,
+        gaps: None,
+        non_acgtns: None,
+        has_pcr_primer_changes: None,
+        pcr_primer_changes: None,
+        qc_status: None,
+        missing_cdses: None,
+        other,
+      },
+      children,
+      other: serde_json::Value::default(),
+    })
+  }
+
+  fn node_attr(&self, attr: &str, index: usize) -> Option<&str> {
+    self.node_attrs.get(attr)?[index].as_deref()
+  }
+}
+
+/// Translates one codon of a CDS in a sequence which has the same coordinates as the reference sequence
+fn translate_codon(seq: &[Nuc], cds: &Cds, codon: usize) -> Aa {
+  let triplet = cds_codon_pos_to_ref_range(cds, codon.into())
+    .into_iter()
+    .flat_map(|(range, strand)| {
+      let mut nucs = seq[range.to_std()].to_vec();
+      if strand == GeneStrand::Reverse {
+        reverse_complement_in_place(&mut nucs);
+      }
+      nucs
+    })
+    .collect_vec();
+  decode(&triplet)
+}
+
+fn create_meta(attr_names: &[String]) -> AuspiceTreeMeta {
+  let clade_node_attrs = attr_names
+    .iter()
+    .map(|name| CladeNodeAttrKeyDesc {
+      name: name.clone(),
+      display_name: name.clone(),
+      description: None,
+      hide_in_web: false,
+      other: serde_json::Value::default(),
+    })
+    .collect_vec();
+
+  let colorings = std::iter::once(CLADE_ATTR)
+    .chain(attr_names.iter().map(String::as_str))
+    .map(|key| AuspiceColoring {
+      type_: "categorical".to_owned(),
+      key: key.to_owned(),
+      title: if key == CLADE_ATTR { "Clade" } else { key }.to_owned(),
+      scale: vec![],
+      other: serde_json::Value::default(),
+    })
+    .collect_vec();
+
+  AuspiceTreeMeta {
+    extensions: AuspiceMetaExtensions {
+      nextclade: AuspiceMetaExtensionsNextclade {
+        clade_node_attrs,
+        ..AuspiceMetaExtensionsNextclade::default()
+      },
+      other: serde_json::Value::default(),
+    },
+    colorings,
+    panels: vec![],
+    filters: vec![],
+    display_defaults: AuspiceDisplayDefaults::default(),
+    geo_resolutions: None,
+    other: serde_json::Value::default(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::float_cmp)]
+
+  use super::*;
+  use crate::io::nwk_reader::nwk_parse;
+  use crate::utils::error::report_to_string;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  fn node_muts(node: &AuspiceTreeNode) -> Vec<String> {
+    node.branch_attrs.mutations.get("nuc").cloned().unwrap_or_default()
+  }
+
+  #[rstest]
+  fn reconstructs_mutations_and_clades() -> Result<(), Report> {
+    let nwk = nwk_parse("((A:1,B:1):1,C:2);")?;
+    let ref_seq = to_nuc_seq("ACGT")?;
+    let seqs = BTreeMap::from([
+      ("A".to_owned(), to_nuc_seq("TCGA")?),
+      ("B".to_owned(), to_nuc_seq("TCGN")?),
+      ("C".to_owned(), to_nuc_seq("ACGT")?),
+    ]);
+    let metadata = parse_tree_metadata_tsv("strain\tclade\nA\t1A\nB\t1A\nC\t2\n")?;
+
+    let tree = auspice_tree_from_nwk(
+      &nwk,
+      &metadata,
+      &NwkTreeMutations::Alignment(seqs),
+      &ref_seq,
+      &GeneMap::default(),
+    )?;
+
+    let internal = &tree.tree.children[0];
+    assert_eq!(node_muts(&tree.tree), Vec::<String>::new());
+    assert_eq!(internal.name, "NODE_0000001");
+    // Ambiguous nucleotide of B is resolved to the state of its parent
+    assert_eq!(node_muts(internal), vec!["A1T".to_owned(), "T4A".to_owned()]);
+    assert_eq!(node_muts(&internal.children[0]), Vec::<String>::new());
+    assert_eq!(node_muts(&internal.children[1]), Vec::<String>::new());
+    assert_eq!(internal.node_attrs.clade_membership.value, "1A");
+    assert_eq!(internal.node_attrs.div, Some(1.0));
+    Ok(())
+  }
+
+  #[rstest]
+  fn takes_mutations_from_tsv() -> Result<(), Report> {
+    let nwk = nwk_parse("((A,B)AB,C);")?;
+    let ref_seq = to_nuc_seq("ACGT")?;
+    let mutations = parse_node_mutations_tsv("node\tmutations\nAB\tA1T\nA\tT4A, C2G\n")?;
+
+    let tree = auspice_tree_from_nwk(
+      &nwk,
+      &NwkTreeMetadata::default(),
+      &mutations,
+      &ref_seq,
+      &GeneMap::default(),
+    )?;
+
+    let internal = &tree.tree.children[0];
+    assert_eq!(node_muts(internal), vec!["A1T".to_owned()]);
+    assert_eq!(
+      node_muts(&internal.children[0]),
+      vec!["C2G".to_owned(), "T4A".to_owned()]
+    );
+    // Without branch lengths, divergence is the number of substitutions
+    assert_eq!(internal.children[0].node_attrs.div, Some(3.0));
+    assert_eq!(internal.node_attrs.clade_membership.value, AUSPICE_UNKNOWN_VALUE);
+    Ok(())
+  }
+
+  #[rstest]
+  #[case("((A,B),C);", &["NODE_0000000", "NODE_0000001", "A", "B", "C"])]
+  #[case("(A,(B,C),(D,E));", &["NODE_0000000", "A", "NODE_0000001", "B", "C", "NODE_0000002", "D", "E"])]
+  #[case("(A,(B,C)BC,(D,E));", &["NODE_0000000", "A", "BC", "B", "C", "NODE_0000001", "D", "E"])]
+  fn names_unnamed_internal_nodes_sequentially(#[case] nwk: &str, #[case] expected: &[&str]) -> Result<(), Report> {
+    let nodes = flatten_nwk(&nwk_parse(nwk)?)?;
+    assert_eq!(nodes.iter().map(|node| node.name.as_str()).collect_vec(), expected);
+    Ok(())
+  }
+
+  #[rstest]
+  fn rejects_unnamed_leaves() -> Result<(), Report> {
+    let _: Report = flatten_nwk(&nwk_parse("((A,B),);")?).unwrap_err();
+    Ok(())
+  }
+
+  #[rstest]
+  fn translates_mutated_codons() -> Result<(), Report> {
+    let nwk = nwk_parse("((A,B)AB,C);")?;
+    let ref_seq = to_nuc_seq("ATGAAACCCTAA")?;
+    let gene_map = GeneMap::from_str(
+      r#"##gff-version 3
+##sequence-region ref 1 12
+ref	feature	gene	1	12	.	+	.	gene_name="F"
+ref	feature	gene	1	12	.	-	.	gene_name="R"
+"#,
+    )?;
+    let mutations = parse_node_mutations_tsv("node\tmutations\nAB\tA5G\nA\tC7T\nC\tA5T\n")?;
+
+    let tree = auspice_tree_from_nwk(&nwk, &NwkTreeMetadata::default(), &mutations, &ref_seq, &gene_map)?;
+
+    let muts = |node: &AuspiceTreeNode| node.branch_attrs.mutations.clone();
+    let internal = &tree.tree.children[0];
+    assert_eq!(
+      muts(internal),
+      BTreeMap::from([
+        ("F".to_owned(), vec!["K2R".to_owned()]),
+        ("R".to_owned(), vec!["F3S".to_owned()]),
+        ("nuc".to_owned(), vec!["A5G".to_owned()]),
+      ])
+    );
+    // Synonymous in "R"
+    assert_eq!(
+      muts(&internal.children[0]),
+      BTreeMap::from([
+        ("F".to_owned(), vec!["P3S".to_owned()]),
+        ("nuc".to_owned(), vec!["C7T".to_owned()]),
+      ])
+    );
+    assert_eq!(muts(&internal.children[1]), BTreeMap::new());
+    // Sequences and peptides of the parent are restored before visiting the sibling
+    assert_eq!(
+      muts(&tree.tree.children[1]),
+      BTreeMap::from([
+        ("F".to_owned(), vec!["K2I".to_owned()]),
+        ("R".to_owned(), vec!["F3Y".to_owned()]),
+        ("nuc".to_owned(), vec!["A5T".to_owned()]),
+      ])
+    );
+    Ok(())
+  }
+
+  #[rstest]
+  fn rejects_mutations_inconsistent_with_parent() -> Result<(), Report> {
+    let nwk = nwk_parse("((A,B)AB,C);")?;
+    let mutations = parse_node_mutations_tsv("node\tmutations\nAB\tA1T\nA\tA1G\n")?;
+
+    let error = auspice_tree_from_nwk(
+      &nwk,
+      &NwkTreeMetadata::default(),
+      &mutations,
+      &to_nuc_seq("ACGT")?,
+      &GeneMap::default(),
+    )
+    .unwrap_err();
+
+    assert_eq!(
+      report_to_string(&error),
+      "Mutation A1G on the branch leading to node 'A' is inconsistent with the parent node, which has 'T' at this position"
+    );
+    Ok(())
+  }
+}
@@ -0,0 +1,260 @@
+use crate::alphabet::nuc::Nuc;
+use crate::analyze::nuc_sub::NucSub;
+use crate::gene::gene_map::GeneMap;
+use crate::io::nwk_reader::{nwk_parse, NwkNode};
+use crate::io::usher_mat::{UsherMat, UsherMut};
+use crate::make_error;
+use crate::tree::tree::AuspiceTree;
+use crate::tree::tree_from_nwk::{auspice_tree_from_nwk, nwk_node_name, NwkTreeMetadata, NwkTreeMutations, CLADE_ATTR};
+use eyre::{Report, WrapErr};
+use itertools::Itertools;
+use std::collections::BTreeMap;
+
+/// Names of node attributes corresponding to clade annotations of MAT, in order. By convention, the first annotation
+/// is the clade and the second is the lineage. Further annotations are named by their index.
+const ANNOTATION_ATTRS: &[&str] = &[CLADE_ATTR, "lineage"];
+
+fn annotation_attr(index: usize) -> String {
+  ANNOTATION_ATTRS
+    .get(index)
+    .map_or_else(|| format!("annotation_{}", index + 1), |attr| (*attr).to_owned())
+}
+
+/// Builds a reference tree from UShER mutation-annotated tree (MAT).
+///
+/// Clade annotations of MAT are only present on the nodes where clades originate, so the nodes inherit them from the
+/// nearest annotated ancestor. Condensed nodes are expanded into polytomies of identical samples.
+pub fn auspice_tree_from_usher_mat(mat: &UsherMat, ref_seq: &[Nuc], gene_map: &GeneMap) -> Result<AuspiceTree, Report> {
+  let mut nwk = nwk_parse(&mat.newick).wrap_err("When parsing Newick tree of UShER MAT")?;
+
+  let mut names = vec![];
+  name_nodes_recursive(&mut nwk, &mut names, &mut 0)?;
+
+  if names.len() != mat.node_mutations.len() {
+    return make_error!(
+      "Number of nodes in the tree of UShER MAT ({}) is inconsistent with the number of lists of node mutations ({})",
+      names.len(),
+      mat.node_mutations.len()
+    );
+  }
+
+  let mutations = names
+    .iter()
+    .zip(&mat.node_mutations)
+    .map(|(name, muts)| {
+      let muts = muts
+        .iter()
+        .map(convert_mutation)
+        .collect::<Result<Vec<_>, Report>>()
+        .wrap_err_with(|| format!("When converting mutations of node '{name}'"))?;
+      Ok((name.clone(), muts))
+    })
+    .collect::<Result<BTreeMap<_, _>, Report>>()?;
+
+  let condensed_nodes: BTreeMap<&str, &[String]> = mat
+    .condensed_nodes
+    .iter()
+    .map(|node| (node.node_name.as_str(), node.condensed_leaves.as_slice()))
+    .collect();
+  expand_condensed_nodes_recursive(&mut nwk, &condensed_nodes);
+
+  if names.len() != mat.metadata.len() {
+    return make_error!(
+      "Number of nodes in the tree of UShER MAT ({}) is inconsistent with the number of node metadata entries ({})",
+      names.len(),
+      mat.metadata.len()
+    );
+  }
+
+  let annotations: BTreeMap<&str, &[String]> = names
+    .iter()
+    .zip(&mat.metadata)
+    .map(|(name, meta)| (name.as_str(), meta.clade_annotations.as_slice()))
+    .collect();
+  let num_annotations = annotations.values().map(|a| a.len()).max().unwrap_or_default();
+
+  let mut metadata = NwkTreeMetadata {
+    attr_names: (1..num_annotations).map(annotation_attr).collect_vec(),
+    nodes: BTreeMap::new(),
+  };
+  propagate_annotations_recursive(&nwk, &annotations, &vec![None; num_annotations], &mut metadata);
+
+  auspice_tree_from_nwk(
+    &nwk,
+    &metadata,
+    &NwkTreeMutations::BranchMutations(mutations),
+    ref_seq,
+    gene_map,
+  )
+}
+
+/// Assigns names to unnamed nodes, the same way as it is done when building a tree from Newick, and lists names of
+/// all nodes in pre-order
+fn name_nodes_recursive(node: &mut NwkNode, names: &mut Vec<String>, num_unnamed: &mut usize) -> Result<(), Report> {
+  let name = nwk_node_name(node, num_unnamed)?;
+  node.name = Some(name.clone());
+  names.push(name);
+  for child in &mut node.children {
+    name_nodes_recursive(child, names, num_unnamed)?;
+  }
+  Ok(())
+}
+
+fn expand_condensed_nodes_recursive(node: &mut NwkNode, condensed_nodes: &BTreeMap<&str, &[String]>) {
+  if node.is_leaf() {
+    if let Some(leaves) = node.name.as_deref().and_then(|name| condensed_nodes.get(name)) {
+      node.children = leaves
+        .iter()
+        .map(|leaf| NwkNode {
+          name: Some(leaf.clone()),
+          branch_length: Some(0.0),
+          children: vec![],
+        })
+        .collect();
+    }
+  }
+  for child in &mut node.children {
+    expand_condensed_nodes_recursive(child, condensed_nodes);
+  }
+}
+
+fn propagate_annotations_recursive(
+  node: &NwkNode,
+  annotations: &BTreeMap<&str, &[String]>,
+  inherited: &[Option<String>],
+  metadata: &mut NwkTreeMetadata,
+) {
+  let name = node.name.clone().unwrap_or_default();
+
+  let own = annotations.get(name.as_str()).copied().unwrap_or_default();
+  let current = inherited
+    .iter()
+    .enumerate()
+    .map(|(index, value)| match own.get(index) {
+      Some(annotation) if !annotation.is_empty() => Some(annotation.clone()),
+      _ => value.clone(),
+    })
+    .collect_vec();
+
+  let attrs = current
+    .iter()
+    .enumerate()
+    .filter_map(|(index, value)| value.as_ref().map(|value| (annotation_attr(index), value.clone())))
+    .collect();
+  metadata.nodes.insert(name, attrs);
+
+  for child in &node.children {
+    propagate_annotations_recursive(child, annotations, &current, metadata);
+  }
+}
+
+fn convert_mutation(m: &UsherMut) -> Result<NucSub, Report> {
+  let Some(&mut_nuc) = m.mut_nuc.first() else {
+    return make_error!("Mutation at position {} has no mutated nucleotide", m.position);
+  };
+  if m.position < 1 {
+    return make_error!("Invalid mutation position: {}", m.position);
+  }
+  Ok(NucSub {
+    pos: (m.position - 1).into(),
+    ref_nuc: convert_nuc(m.par_nuc)?,
+    qry_nuc: convert_nuc(mut_nuc)?,
+  })
+}
+
+fn convert_nuc(nuc: i32) -> Result<Nuc, Report> {
+  match nuc {
+    0 => Ok(Nuc::A),
+    1 => Ok(Nuc::C),
+    2 => Ok(Nuc::G),
+    3 => Ok(Nuc::T),
+    _ => make_error!("Unknown nucleotide code in UShER MAT: {nuc}. Expected one of: 0, 1, 2, 3 (A, C, G, T)"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::alphabet::nuc::to_nuc_seq;
+  use crate::io::usher_mat::{UsherCondensedNode, UsherNodeMetadata};
+  use crate::utils::error::report_to_string;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+
+  fn create_mat() -> UsherMat {
+    UsherMat {
+      newick: "((A:1,B:1):1,node_1_condensed_2_leaves:1);".to_owned(),
+      node_mutations: vec![
+        vec![],
+        vec![UsherMut {
+          position: 2,
+          ref_nuc: 1,
+          par_nuc: 1,
+          mut_nuc: vec![3],
+          chromosome: String::new(),
+        }],
+        vec![],
+        vec![],
+        vec![],
+      ],
+      condensed_nodes: vec![UsherCondensedNode {
+        node_name: "node_1_condensed_2_leaves".to_owned(),
+        condensed_leaves: vec!["C".to_owned(), "D".to_owned()],
+      }],
+      metadata: vec![
+        UsherNodeMetadata {
+          clade_annotations: vec!["X".to_owned(), "X.1".to_owned()],
+        },
+        UsherNodeMetadata {
+          clade_annotations: vec!["Y".to_owned(), String::new()],
+        },
+        UsherNodeMetadata::default(),
+        UsherNodeMetadata::default(),
+        UsherNodeMetadata::default(),
+      ],
+    }
+  }
+
+  #[rstest]
+  fn converts_mat() -> Result<(), Report> {
+    let mat = create_mat();
+    let tree = auspice_tree_from_usher_mat(&mat, &to_nuc_seq("ACGT")?, &GeneMap::default())?;
+
+    let clade_and_lineage = |node: &crate::tree::tree::AuspiceTreeNode| {
+      (
+        node.node_attrs.clade_membership.value.clone(),
+        node.node_attrs.other["lineage"]["value"].as_str().unwrap().to_owned(),
+      )
+    };
+
+    let internal = &tree.tree.children[0];
+    assert_eq!(internal.branch_attrs.mutations["nuc"], vec!["C2T".to_owned()]);
+    assert_eq!(
+      clade_and_lineage(&internal.children[0]),
+      ("Y".to_owned(), "X.1".to_owned())
+    );
+
+    let condensed = &tree.tree.children[1];
+    assert_eq!(
+      condensed.children.iter().map(|node| node.name.as_str()).collect_vec(),
+      vec!["C", "D"]
+    );
+    assert_eq!(
+      clade_and_lineage(&condensed.children[1]),
+      ("X".to_owned(), "X.1".to_owned())
+    );
+    Ok(())
+  }
+
+  #[rstest]
+  fn rejects_inconsistent_metadata() -> Result<(), Report> {
+    let mut mat = create_mat();
+    mat.metadata.pop();
+    let error = auspice_tree_from_usher_mat(&mat, &to_nuc_seq("ACGT")?, &GeneMap::default()).unwrap_err();
+    assert_eq!(
+      report_to_string(&error),
+      "Number of nodes in the tree of UShER MAT (5) is inconsistent with the number of node metadata entries (4)"
+    );
+    Ok(())
+  }
+}
@@ -0,0 +1,193 @@
+use crate::tree::tree::{AuspiceGraph, TreeNodeAttrs};
+use crate::tree::tree_find_nearest_node::TreePlacementInfo;
+use eyre::Report;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+/// Leaf node of the reference tree (i.e. a known sample) which is close to the query sequence
+#[derive(Clone, Debug, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NearestTip {
+  pub name: String,
+  pub distance: i64,
+  pub attrs: IndexMap<String, String>,
+}
+
+/// Finds leaf nodes of the reference tree closest to the query sequence, given placement candidates sorted by
+/// distance (the distance metric is the same as the one used for placement).
+pub fn find_nearest_tips(
+  graph: &AuspiceGraph,
+  nearest_node_candidates: &[TreePlacementInfo],
+  num_tips: usize,
+  attr_keys: &[String],
+) -> Result<Vec<NearestTip>, Report> {
+  let mut tips = Vec::with_capacity(num_tips);
+  for candidate in nearest_node_candidates {
+    if tips.len() >= num_tips {
+      break;
+    }
+
+    let node = graph.get_node(candidate.node_key)?;
+    if !node.is_leaf() {
+      continue;
+    }
+
+    let node = node.payload();
+    tips.push(NearestTip {
+      name: node.name.clone(),
+      distance: candidate.distance,
+      attrs: get_node_attr_values(&node.node_attrs, attr_keys),
+    });
+  }
+  Ok(tips)
+}
+
+/// Extracts values of the given node attributes, if present, as strings, in the order of the given keys
+fn get_node_attr_values(node_attrs: &TreeNodeAttrs, attr_keys: &[String]) -> IndexMap<String, String> {
+  attr_keys
+    .iter()
+    .filter_map(|key| Some((key.clone(), get_node_attr_value(node_attrs, key)?)))
+    .collect()
+}
+
+/// Looks up value of one node attribute. Attributes known to Nextclade are stored in dedicated fields, the rest are
+/// looked up among the other attributes.
+fn get_node_attr_value(node_attrs: &TreeNodeAttrs, key: &str) -> Option<String> {
+  let attr = match key {
+    "clade_membership" => Some(&node_attrs.clade_membership),
+    "Node type" => node_attrs.node_type.as_ref(),
+    "region" => node_attrs.region.as_ref(),
+    "country" => node_attrs.country.as_ref(),
+    "division" => node_attrs.division.as_ref(),
+    "Alignment" => node_attrs.alignment.as_ref(),
+    "Missing" => node_attrs.missing.as_ref(),
+    "Gaps" => node_attrs.gaps.as_ref(),
+    "Non-ACGTNs" => node_attrs.non_acgtns.as_ref(),
+    "Has PCR primer changes" => node_attrs.has_pcr_primer_changes.as_ref(),
+    "PCR primer changes" => node_attrs.pcr_primer_changes.as_ref(),
+    "QC Status" => node_attrs.qc_status.as_ref(),
+    "Missing genes" => node_attrs.missing_cdses.as_ref(),
+    "placement_prior" => return node_attrs.placement_prior.as_ref().map(|attr| attr.value.to_string()),
+    _ => {
+      return match node_attrs.other.get(key)?.get("value")? {
+        serde_json::Value::String(value) => Some(value.clone()),
+        value => Some(value.to_string()),
+      }
+    }
+  };
+  attr.map(|attr| attr.value.clone())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::graph::graph::convert_auspice_tree_to_graph;
+  use crate::tree::tree::AuspiceTree;
+  use itertools::Itertools;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+  use serde_json::json;
+
+  fn graph() -> Result<AuspiceGraph, Report> {
+    let tree: AuspiceTree = serde_json::from_value(json!({
+      "meta": {},
+      "tree": {
+        "name": "root",
+        "branch_attrs": { "mutations": {} },
+        "node_attrs": { "clade_membership": { "value": "A" } },
+        "children": [
+          {
+            "name": "internal",
+            "branch_attrs": { "mutations": {} },
+            "node_attrs": { "clade_membership": { "value": "A" } },
+            "children": [
+              {
+                "name": "tip1",
+                "branch_attrs": { "mutations": {} },
+                "node_attrs": {
+                  "clade_membership": { "value": "A" },
+                  "country": { "value": "Sweden" },
+                  "num_date": { "value": 2021.5, "confidence": [2021.4, 2021.6] },
+                },
+              },
+              {
+                "name": "tip2",
+                "branch_attrs": { "mutations": {} },
+                "node_attrs": { "clade_membership": { "value": "B" }, "host": { "value": "Mink" } },
+              },
+            ],
+          },
+          {
+            "name": "tip3",
+            "branch_attrs": { "mutations": {} },
+            "node_attrs": { "clade_membership": { "value": "A" } },
+          },
+        ],
+      },
+    }))?;
+    convert_auspice_tree_to_graph(tree)
+  }
+
+  fn candidates(graph: &AuspiceGraph, candidates: &[(&str, i64)]) -> Vec<TreePlacementInfo> {
+    candidates
+      .iter()
+      .map(|&(name, distance)| TreePlacementInfo {
+        node_key: graph
+          .iter_nodes()
+          .find(|node| node.payload().name == name)
+          .unwrap()
+          .key(),
+        distance,
+        prior: 1.0,
+      })
+      .collect_vec()
+  }
+
+  #[rstest]
+  #[case::none(0, &[])]
+  #[case::skips_internal_nodes(2, &[("tip2", 2), ("tip1", 3)])]
+  #[case::fewer_tips_than_requested(5, &[("tip2", 2), ("tip1", 3), ("tip3", 4)])]
+  fn finds_nearest_tips(#[case] num_tips: usize, #[case] expected: &[(&str, i64)]) -> Result<(), Report> {
+    let graph = graph()?;
+    let candidates = candidates(
+      &graph,
+      &[("internal", 1), ("tip2", 2), ("root", 2), ("tip1", 3), ("tip3", 4)],
+    );
+
+    let actual = find_nearest_tips(&graph, &candidates, num_tips, &[])?;
+
+    assert_eq!(
+      actual.iter().map(|tip| (tip.name.as_str(), tip.distance)).collect_vec(),
+      expected
+    );
+    Ok(())
+  }
+
+  #[rstest]
+  fn reports_requested_attrs_in_order() -> Result<(), Report> {
+    let graph = graph()?;
+    let candidates = candidates(&graph, &[("tip1", 1), ("tip2", 2)]);
+    let attr_keys = ["num_date", "host", "country", "clade_membership"].map(ToOwned::to_owned);
+
+    let actual = find_nearest_tips(&graph, &candidates, 2, &attr_keys)?;
+
+    let attrs = actual
+      .iter()
+      .map(|tip| {
+        tip
+          .attrs
+          .iter()
+          .map(|(key, value)| (key.as_str(), value.as_str()))
+          .collect_vec()
+      })
+      .collect_vec();
+    assert_eq!(
+      attrs,
+      vec![
+        vec![("num_date", "2021.5"), ("country", "Sweden"), ("clade_membership", "A")],
+        vec![("host", "Mink"), ("clade_membership", "B")],
+      ]
+    );
+    Ok(())
+  }
+}
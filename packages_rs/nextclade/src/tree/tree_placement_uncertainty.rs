@@ -0,0 +1,200 @@
+use crate::tree::tree::{AuspiceGraph, CladeNodeAttrKeyDesc};
+use crate::tree::tree_find_nearest_node::TreePlacementInfo;
+use eyre::Report;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// One of the near-optimal placements of the query sequence on the reference tree
+#[derive(Clone, Debug, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PlacementCandidate {
+  pub node_name: String,
+  pub clade: String,
+  pub distance: i64,
+  pub probability: f64,
+}
+
+/// Uncertainty of placement of the query sequence on the reference tree and of the resulting clade assignment
+#[derive(Clone, Debug, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PlacementUncertainty {
+  pub candidates: Vec<PlacementCandidate>,
+  pub clade_probabilities: BTreeMap<String, f64>,
+  pub clade_confidence: f64,
+  pub clade_node_attr_confidence: BTreeMap<String, f64>,
+}
+
+/// Calculates placement uncertainty, given placement candidates sorted by distance (best candidate first).
+///
+/// All candidates with distance not exceeding the distance of the best candidate by more than the given tolerance are
+/// considered plausible placements. Each of them receives a probability proportional to its placement prior. The
+/// probability of a clade (or of a value of a clade-like attribute) is the sum of probabilities of the candidates
+/// belonging to it. Confidence is the probability of the clade (or the attribute value) which is assigned to the query.
+pub fn calculate_placement_uncertainty(
+  graph: &AuspiceGraph,
+  nearest_node_candidates: &[TreePlacementInfo],
+  distance_tolerance: usize,
+  clade_node_attr_descs: &[CladeNodeAttrKeyDesc],
+) -> Result<PlacementUncertainty, Report> {
+  let Some(best) = nearest_node_candidates.first() else {
+    return Ok(PlacementUncertainty::default());
+  };
+
+  let max_distance = best.distance + distance_tolerance as i64;
+  let plausible = nearest_node_candidates
+    .iter()
+    .take_while(|candidate| candidate.distance <= max_distance)
+    .map(|candidate| Ok((candidate, graph.get_node(candidate.node_key)?.payload())))
+    .collect::<Result<Vec<_>, Report>>()?;
+
+  let total_prior: f64 = plausible.iter().map(|(candidate, _)| candidate.prior).sum();
+  let probability_of = |candidate: &TreePlacementInfo| {
+    if total_prior > 0.0 {
+      candidate.prior / total_prior
+    } else {
+      1.0 / plausible.len() as f64
+    }
+  };
+
+  let candidates = plausible
+    .iter()
+    .map(|(candidate, node)| PlacementCandidate {
+      node_name: node.name.clone(),
+      clade: node.clade(),
+      distance: candidate.distance,
+      probability: probability_of(candidate),
+    })
+    .collect_vec();
+
+  let mut clade_probabilities = BTreeMap::<String, f64>::new();
+  for candidate in &candidates {
+    *clade_probabilities.entry(candidate.clade.clone()).or_default() += candidate.probability;
+  }
+
+  let (_, best_node) = plausible[0];
+  let clade_confidence = clade_probabilities.get(&best_node.clade()).copied().unwrap_or_default();
+
+  let best_attrs = best_node.get_clade_node_attrs(clade_node_attr_descs);
+  let clade_node_attr_confidence = best_attrs
+    .iter()
+    .map(|(key, best_value)| {
+      let confidence = plausible
+        .iter()
+        .filter(|(_, node)| node.get_clade_node_attrs(clade_node_attr_descs).get(key) == Some(best_value))
+        .map(|(candidate, _)| probability_of(candidate))
+        .sum();
+      (key.clone(), confidence)
+    })
+    .collect();
+
+  Ok(PlacementUncertainty {
+    candidates,
+    clade_probabilities,
+    clade_confidence,
+    clade_node_attr_confidence,
+  })
+}
+
+/// Name of CSV column with confidence of the assigned value of a clade-like attribute
+pub fn clade_node_attr_confidence_key(key: &str) -> String {
+  format!("{key}.confidence")
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+  use super::*;
+  use crate::graph::graph::convert_auspice_tree_to_graph;
+  use crate::tree::tree::AuspiceTree;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+  use serde_json::json;
+
+  fn node(name: &str, clade: &str, lineage: &str) -> serde_json::Value {
+    json!({
+      "name": name,
+      "branch_attrs": { "mutations": {} },
+      "node_attrs": { "clade_membership": { "value": clade }, "lineage": { "value": lineage } },
+    })
+  }
+
+  fn graph() -> Result<AuspiceGraph, Report> {
+    let mut root = node("root", "X", "eu");
+    root["children"] = json!([node("A", "X", "eu"), node("B", "Y", "eu"), node("C", "Y", "asia")]);
+    let tree: AuspiceTree = serde_json::from_value(json!({ "meta": {}, "tree": root }))?;
+    convert_auspice_tree_to_graph(tree)
+  }
+
+  fn candidates(graph: &AuspiceGraph, candidates: &[(&str, i64, f64)]) -> Vec<TreePlacementInfo> {
+    candidates
+      .iter()
+      .map(|&(name, distance, prior)| TreePlacementInfo {
+        node_key: graph
+          .iter_nodes()
+          .find(|node| node.payload().name == name)
+          .unwrap()
+          .key(),
+        distance,
+        prior,
+      })
+      .collect_vec()
+  }
+
+  #[rstest]
+  #[case::equidistant_only(0, &[0.5, 0.25], &[("X", 2.0 / 3.0), ("Y", 1.0 / 3.0)], 2.0 / 3.0, 1.0)]
+  #[case::within_tolerance(1, &[0.5, 0.25, 0.25], &[("X", 0.5), ("Y", 0.5)], 0.5, 0.75)]
+  #[case::zero_priors_are_weighted_equally(1, &[0.0, 0.0, 0.0], &[("X", 1.0 / 3.0), ("Y", 2.0 / 3.0)], 1.0 / 3.0, 2.0 / 3.0)]
+  fn weights_candidates_by_prior(
+    #[case] distance_tolerance: usize,
+    #[case] priors: &[f64],
+    #[case] expected_clade_probabilities: &[(&str, f64)],
+    #[case] expected_clade_confidence: f64,
+    #[case] expected_lineage_confidence: f64,
+  ) -> Result<(), Report> {
+    let graph = graph()?;
+    let prior = |i: usize| priors.get(i).copied().unwrap_or(1.0);
+    let candidates = candidates(
+      &graph,
+      &[
+        ("A", 1, prior(0)),
+        ("B", 1, prior(1)),
+        ("C", 2, prior(2)),
+        ("root", 5, 1.0),
+      ],
+    );
+    let attr_descs: Vec<CladeNodeAttrKeyDesc> =
+      serde_json::from_value(json!([{ "name": "lineage", "displayName": "Lineage" }]))?;
+
+    let actual = calculate_placement_uncertainty(&graph, &candidates, distance_tolerance, &attr_descs)?;
+
+    let probabilities = actual.candidates.iter().map(|c| c.probability).collect_vec();
+    assert!((probabilities.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    assert_eq!(actual.candidates.len(), priors.len());
+    assert_eq!(actual.candidates[0].node_name, "A");
+
+    let expected_clade_probabilities: BTreeMap<String, f64> = expected_clade_probabilities
+      .iter()
+      .map(|&(clade, p)| (clade.to_owned(), p))
+      .collect();
+    assert_eq!(
+      actual.clade_probabilities.keys().collect_vec(),
+      expected_clade_probabilities.keys().collect_vec()
+    );
+    for (clade, p) in &expected_clade_probabilities {
+      assert!((actual.clade_probabilities[clade] - p).abs() < 1e-9, "clade {clade}");
+    }
+    assert!((actual.clade_confidence - expected_clade_confidence).abs() < 1e-9);
+    assert!((actual.clade_node_attr_confidence["lineage"] - expected_lineage_confidence).abs() < 1e-9);
+    Ok(())
+  }
+
+  #[rstest]
+  fn returns_empty_uncertainty_without_candidates() -> Result<(), Report> {
+    let actual = calculate_placement_uncertainty(&graph()?, &[], 0, &[])?;
+    assert!(actual.candidates.is_empty());
+    assert!(actual.clade_probabilities.is_empty());
+    assert_eq!(actual.clade_confidence, 0.0);
+    Ok(())
+  }
+}
@@ -9,9 +9,12 @@ use crate::coord::position::{AaRefPosition, NucRefGlobalPosition, PositionLike};
 use crate::graph::node::GraphNodeKey;
 use crate::make_error;
 use crate::translate::translate_genes::Translation;
+use crate::tree::params::TreeBuilderParams;
 use crate::tree::tree::{
   AuspiceColoring, AuspiceGraph, AuspiceGraphNodePayload, AuspiceTreeMeta, AUSPICE_UNKNOWN_VALUE,
 };
+use crate::tree::tree_builder::{IDENTICAL_SEQUENCES_ATTR, IDENTICAL_SEQUENCES_COUNT_ATTR};
+use crate::tree::tree_find_nearest_node::NucSubIndex;
 use crate::utils::collections::concat_to_vec;
 use eyre::{Report, WrapErr};
 use itertools::Itertools;
@@ -37,9 +40,26 @@ pub fn graph_preprocess_in_place(
     ref_translation,
   )?;
 
+  graph.data.tmp.nuc_sub_branch_counts = count_nuc_sub_branches(graph);
+  graph.data.tmp.nuc_sub_index =
+    Some(NucSubIndex::from_graph(graph).wrap_err("When building index of nucleotide substitutions of the tree nodes")?);
+
   Ok(())
 }
 
+/// Counts how many branches of the tree carry each of the nucleotide substitutions. Substitutions occurring on more
+/// than one branch are homoplasies.
+fn count_nuc_sub_branches(graph: &AuspiceGraph) -> BTreeMap<NucSub, usize> {
+  graph
+    .iter_node_payloads()
+    .flat_map(|node| node.tmp.private_mutations.nuc_muts.iter())
+    .filter(|sub| !sub.qry_nuc.is_gap())
+    .cloned()
+    .counts()
+    .into_iter()
+    .collect()
+}
+
 pub fn graph_preprocess_in_place_recursive(
   graph: &mut AuspiceGraph,
   graph_node_key: GraphNodeKey,
@@ -245,8 +265,8 @@ fn pair(key: &str, val: &str) -> [String; 2] {
   [key.to_owned(), val.to_owned()]
 }
 
-pub fn add_auspice_metadata_in_place(meta: &mut AuspiceTreeMeta) {
-  let new_colorings: Vec<AuspiceColoring> = vec![
+pub fn add_auspice_metadata_in_place(meta: &mut AuspiceTreeMeta, params: &TreeBuilderParams) {
+  let mut new_colorings: Vec<AuspiceColoring> = vec![
     AuspiceColoring {
       key: "Node type".to_owned(),
       title: "Node type".to_owned(),
@@ -274,6 +294,26 @@ pub fn add_auspice_metadata_in_place(meta: &mut AuspiceTreeMeta) {
     },
   ];
 
+  // Attributes of the nodes representing multiple identical query sequences. Colorings provide their display names.
+  if params.collapse_identical_queries {
+    new_colorings.extend([
+      AuspiceColoring {
+        key: IDENTICAL_SEQUENCES_COUNT_ATTR.to_owned(),
+        title: "Identical sequences count".to_owned(),
+        type_: "continuous".to_owned(),
+        scale: vec![],
+        other: serde_json::Value::default(),
+      },
+      AuspiceColoring {
+        key: IDENTICAL_SEQUENCES_ATTR.to_owned(),
+        title: "Identical sequences".to_owned(),
+        type_: "categorical".to_owned(),
+        scale: vec![],
+        other: serde_json::Value::default(),
+      },
+    ]);
+  }
+
   meta.colorings = concat_to_vec(&new_colorings, &meta.colorings);
 
   meta.colorings.iter_mut().for_each(|coloring| {
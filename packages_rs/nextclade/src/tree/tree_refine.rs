@@ -0,0 +1,441 @@
+use crate::analyze::divergence::score_nuc_muts;
+use crate::analyze::find_private_nuc_mutations::BranchMutations;
+use crate::graph::node::{GraphNodeKey, Node};
+use crate::make_internal_report;
+use crate::tree::params::TreeBuilderParams;
+use crate::tree::split_muts::union_of_muts;
+use crate::tree::tree::{AuspiceGraph, AuspiceGraphEdgePayload, AuspiceGraphNodePayload};
+use crate::tree::tree_builder::{
+  convert_private_mutations_to_node_branch_attrs, finetune_nearest_node, knit_muts, knit_needs_split,
+  knit_node_into_graph, set_branch_attrs_aa_labels,
+};
+use eyre::{Report, WrapErr};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+/// Relocation of a new node performed during topology refinement
+#[derive(Clone, Debug, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeRefinementMove {
+  pub iteration: usize,
+  pub seq_name: String,
+  pub from_node: String,
+  pub to_node: String,
+  pub score_change: f64,
+}
+
+/// Summary of topology refinement: parsimony score of the tree before and after, and the moves performed
+#[derive(Clone, Debug, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeRefinementReport {
+  pub iterations: usize,
+  pub score_before: f64,
+  pub score_after: f64,
+  pub moves: Vec<TreeRefinementMove>,
+}
+
+/// Calculates parsimony score of the tree: sum of scores of nucleotide mutations on all branches
+pub fn graph_parsimony_score(graph: &AuspiceGraph, params: &TreeBuilderParams) -> f64 {
+  let masked_ranges = graph.data.meta.placement_mask_ranges();
+  graph
+    .iter_nodes()
+    .filter(|node| !node.is_root())
+    .map(|node| score_nuc_muts(&node.payload().tmp.private_mutations.nuc_muts, masked_ranges, params))
+    .sum()
+}
+
+/// Refines topology of the tree after the new nodes are attached, using subtree pruning and regrafting (SPR) moves
+/// restricted to the new leaf nodes.
+///
+/// The nodes with keys starting from `num_ref_nodes` are considered new. In each iteration, every new leaf node is
+/// detached from the tree (along with the auxiliary internal node which was created to attach it, if it becomes
+/// redundant) and the best position nearby is searched, the same way as during placement. The node is moved there if
+/// this reduces parsimony score of the tree, and is returned to its original position otherwise. The tree is not
+/// modified if `max_refinement_iterations` is 0.
+pub fn graph_refine_new_nodes_in_place(
+  graph: &mut AuspiceGraph,
+  num_ref_nodes: usize,
+  ref_seq_len: usize,
+  params: &TreeBuilderParams,
+) -> Result<TreeRefinementReport, Report> {
+  let score_before = graph_parsimony_score(graph, params);
+  if params.max_refinement_iterations == 0 {
+    return Ok(TreeRefinementReport {
+      iterations: 0,
+      score_before,
+      score_after: score_before,
+      moves: vec![],
+    });
+  }
+
+  let mut moves = vec![];
+  let mut iterations = 0;
+  while iterations < params.max_refinement_iterations {
+    iterations += 1;
+
+    let new_leaf_keys = graph
+      .iter_leaves()
+      .filter(|node| !node.is_root())
+      .map(Node::key)
+      .filter(|key| key.as_usize() >= num_ref_nodes)
+      .sorted()
+      .collect_vec();
+
+    let num_moves_before = moves.len();
+    for key in new_leaf_keys {
+      let name = graph.get_node(key)?.payload().name.clone();
+      let refinement_move = try_move_new_node(graph, key, iterations, num_ref_nodes, ref_seq_len, params)
+        .wrap_err_with(|| format!("When trying to move node '{name}' during tree refinement"))?;
+      moves.extend(refinement_move);
+    }
+
+    // Lists of roots and leaves are only used between the iterations, so they are not updated after each move
+    graph.build_ref()?;
+
+    if moves.len() == num_moves_before {
+      break;
+    }
+  }
+
+  // Auxiliary nodes made redundant by the moves are left detached. Keys of the reference nodes are not affected.
+  graph.remove_detached_nodes()?;
+
+  Ok(TreeRefinementReport {
+    iterations,
+    score_before,
+    score_after: graph_parsimony_score(graph, params),
+    moves,
+  })
+}
+
+/// Auxiliary internal node removed from the tree together with the detached node, because it became redundant
+struct RemovedAuxNode {
+  key: GraphNodeKey,
+  parent_key: GraphNodeKey,
+  child_key: GraphNodeKey,
+  child_payload: AuspiceGraphNodePayload,
+}
+
+fn try_move_new_node(
+  graph: &mut AuspiceGraph,
+  key: GraphNodeKey,
+  iteration: usize,
+  num_ref_nodes: usize,
+  ref_seq_len: usize,
+  params: &TreeBuilderParams,
+) -> Result<Option<TreeRefinementMove>, Report> {
+  let masked_ranges = graph.data.meta.placement_mask_ranges().to_owned();
+  let score = |muts: &BranchMutations| score_nuc_muts(&muts.nuc_muts, &masked_ranges, params);
+
+  let node = graph.get_node(key)?;
+  let seq_name = node.payload().name.clone();
+  let muts = node.payload().tmp.private_mutations.clone();
+  let parent_key = graph
+    .parent_key_of_by_key(key)
+    .ok_or_else(|| make_internal_report!("New node '{seq_name}' has no parent"))?;
+  let from_node = graph.get_node(parent_key)?.payload().name.clone();
+
+  detach_node(graph, parent_key, key)?;
+  let removed_aux = remove_aux_node_maybe(graph, parent_key, num_ref_nodes)?;
+
+  // Score of the branches around the node in its current position, excluding the ones which remain when it's detached
+  let (start_key, start_muts, score_current) = match &removed_aux {
+    None => {
+      let score_current = score(&muts);
+      (parent_key, muts, score_current)
+    }
+    Some(removed_aux) => {
+      let aux_muts = &graph.get_node(removed_aux.key)?.payload().tmp.private_mutations;
+      let merged_muts = &graph.get_node(removed_aux.child_key)?.payload().tmp.private_mutations;
+      let score_current =
+        score(&muts) + score(aux_muts) + score(&removed_aux.child_payload.tmp.private_mutations) - score(merged_muts);
+      let start_muts = merge_branch_muts(aux_muts, &muts)?;
+      (removed_aux.parent_key, start_muts, score_current)
+    }
+  };
+
+  let (target_key, private_mutations) = finetune_nearest_node(graph, start_key, &start_muts, params)?;
+
+  let knit_muts = knit_muts(graph, target_key, &private_mutations, params)?;
+  let score_new = if knit_needs_split(graph, target_key, &knit_muts)? {
+    let target_muts = &graph.get_node(target_key)?.payload().tmp.private_mutations;
+    score(&knit_muts.muts_new_node) + score(&knit_muts.muts_common_branch) + score(&knit_muts.muts_target_node)
+      - score(target_muts)
+  } else {
+    score(&private_mutations)
+  };
+
+  // Small tolerance prevents moves back and forth between equivalent positions due to rounding
+  if score_new >= score_current - 1e-9 {
+    restore_node(graph, parent_key, key, removed_aux)?;
+    return Ok(None);
+  }
+
+  let to_node = graph.get_node(target_key)?.payload().name.clone();
+  knit_node_into_graph(
+    graph,
+    target_key,
+    &private_mutations,
+    ref_seq_len,
+    params,
+    |graph, new_parent_key, new_private_mutations, new_divergence| {
+      let payload = graph.get_node_mut(key)?.payload_mut();
+      payload.tmp.private_mutations = new_private_mutations.clone();
+      payload.branch_attrs.mutations = convert_private_mutations_to_node_branch_attrs(new_private_mutations);
+      set_branch_attrs_aa_labels(payload);
+      payload.node_attrs.div = Some(new_divergence);
+      graph.add_edge(new_parent_key, key, AuspiceGraphEdgePayload::new())
+    },
+  )?;
+
+  Ok(Some(TreeRefinementMove {
+    iteration,
+    seq_name,
+    from_node,
+    to_node,
+    score_change: score_new - score_current,
+  }))
+}
+
+fn detach_node(graph: &mut AuspiceGraph, parent_key: GraphNodeKey, key: GraphNodeKey) -> Result<(), Report> {
+  let edge_key = graph
+    .get_node(key)?
+    .inbound()
+    .iter()
+    .copied()
+    .find(|&edge_key| {
+      graph
+        .get_edge(edge_key)
+        .map_or(false, |edge| edge.source() == parent_key)
+    })
+    .ok_or_else(|| make_internal_report!("Edge {parent_key}->{key} expected to exist, but not found"))?;
+  graph.remove_edge(edge_key)?;
+  Ok(())
+}
+
+/// If the given node is a new internal node with a single remaining child, removes it from the tree, merging its
+/// mutations into the branch leading to the child
+fn remove_aux_node_maybe(
+  graph: &mut AuspiceGraph,
+  aux_key: GraphNodeKey,
+  num_ref_nodes: usize,
+) -> Result<Option<RemovedAuxNode>, Report> {
+  if aux_key.as_usize() < num_ref_nodes {
+    return Ok(None);
+  }
+
+  let Some(parent_key) = graph.parent_key_of_by_key(aux_key) else {
+    return Ok(None);
+  };
+
+  let Some((child_key,)) = graph.iter_child_keys_of_by_key(aux_key).collect_tuple() else {
+    return Ok(None);
+  };
+
+  let aux_muts = graph.get_node(aux_key)?.payload().tmp.private_mutations.clone();
+  let child_payload = graph.get_node(child_key)?.payload().clone();
+  let merged_muts = merge_branch_muts(&aux_muts, &child_payload.tmp.private_mutations)?;
+
+  detach_node(graph, aux_key, child_key)?;
+  detach_node(graph, parent_key, aux_key)?;
+  graph.add_edge(parent_key, child_key, AuspiceGraphEdgePayload::new())?;
+
+  let child = graph.get_node_mut(child_key)?.payload_mut();
+  child.tmp.private_mutations = merged_muts;
+  child.branch_attrs.mutations = convert_private_mutations_to_node_branch_attrs(&child.tmp.private_mutations);
+  set_branch_attrs_aa_labels(child);
+
+  Ok(Some(RemovedAuxNode {
+    key: aux_key,
+    parent_key,
+    child_key,
+    child_payload,
+  }))
+}
+
+/// Returns the detached node (and the auxiliary node, if it was removed) to the original position
+fn restore_node(
+  graph: &mut AuspiceGraph,
+  parent_key: GraphNodeKey,
+  key: GraphNodeKey,
+  removed_aux: Option<RemovedAuxNode>,
+) -> Result<(), Report> {
+  if let Some(removed_aux) = removed_aux {
+    detach_node(graph, removed_aux.parent_key, removed_aux.child_key)?;
+    graph.add_edge(removed_aux.parent_key, removed_aux.key, AuspiceGraphEdgePayload::new())?;
+    graph.add_edge(removed_aux.key, removed_aux.child_key, AuspiceGraphEdgePayload::new())?;
+    *graph.get_node_mut(removed_aux.child_key)?.payload_mut() = removed_aux.child_payload;
+  }
+  graph.add_edge(parent_key, key, AuspiceGraphEdgePayload::new())
+}
+
+/// Combines mutations on two consecutive branches into mutations of a single branch. Reversions cancel out.
+fn merge_branch_muts(parent: &BranchMutations, child: &BranchMutations) -> Result<BranchMutations, Report> {
+  let mut merged = union_of_muts(parent, child)?;
+  merged.nuc_muts.retain(|m| m.ref_nuc != m.qry_nuc);
+  for aa_muts in merged.aa_muts.values_mut() {
+    aa_muts.retain(|m| m.ref_aa != m.qry_aa);
+  }
+  Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::alphabet::nuc::Nuc;
+  use crate::analyze::nuc_sub::NucSub;
+  use crate::graph::graph::convert_auspice_tree_to_graph;
+  use crate::tree::tree::AuspiceTree;
+  use pretty_assertions::assert_eq;
+  use rstest::rstest;
+  use serde_json::json;
+  use std::collections::BTreeMap;
+
+  fn muts(positions: &[usize]) -> BranchMutations {
+    BranchMutations {
+      nuc_muts: positions
+        .iter()
+        .map(|&pos| NucSub {
+          pos: pos.into(),
+          ref_nuc: Nuc::A,
+          qry_nuc: Nuc::C,
+        })
+        .collect(),
+      aa_muts: BTreeMap::new(),
+    }
+  }
+
+  fn find_key(graph: &AuspiceGraph, name: &str) -> GraphNodeKey {
+    graph
+      .iter_nodes()
+      .find(|node| node.payload().name == name)
+      .unwrap()
+      .key()
+  }
+
+  fn set_muts(graph: &mut AuspiceGraph, name: &str, positions: &[usize]) {
+    let key = find_key(graph, name);
+    graph.get_node_mut(key).unwrap().payload_mut().tmp.private_mutations = muts(positions);
+  }
+
+  fn add_new_node(graph: &mut AuspiceGraph, name: &str, positions: &[usize]) -> GraphNodeKey {
+    let mut payload = graph.get_exactly_one_root().unwrap().payload().clone();
+    payload.name = name.to_owned();
+    payload.tmp.private_mutations = muts(positions);
+    graph.add_node(payload)
+  }
+
+  fn parent_name(graph: &AuspiceGraph, name: &str) -> String {
+    graph
+      .parent_of_by_key(find_key(graph, name))
+      .unwrap()
+      .payload()
+      .name
+      .clone()
+  }
+
+  /// Creates a tree where greedy placement of Q1 (with mutations 1, 5, 6) onto the branch of A happened before Q2 and
+  /// Q3 (with mutations 5, 6, 7 and 5, 6, 8) were placed as a separate clade with the shared mutations 5 and 6.
+  /// Returns the graph and the number of reference nodes.
+  fn create_graph() -> Result<(AuspiceGraph, usize), Report> {
+    let node = |name: &str, children: &[serde_json::Value]| {
+      json!({
+        "name": name,
+        "branch_attrs": { "mutations": {} },
+        "node_attrs": { "clade_membership": { "value": "X" } },
+        "children": children,
+      })
+    };
+    let tree: AuspiceTree = serde_json::from_value(json!({
+      "meta": {},
+      "tree": node("R", &[node("A", &[]), node("B", &[])]),
+    }))?;
+    let mut graph = convert_auspice_tree_to_graph(tree)?;
+    let num_ref_nodes = graph.num_nodes();
+    set_muts(&mut graph, "B", &[2]);
+
+    let x1 = add_new_node(&mut graph, "X1", &[1]);
+    graph.insert_node_before(
+      x1,
+      find_key(&graph, "A"),
+      AuspiceGraphEdgePayload::new(),
+      AuspiceGraphEdgePayload::new(),
+    )?;
+    let q1 = add_new_node(&mut graph, "Q1", &[5, 6]);
+    graph.add_edge(x1, q1, AuspiceGraphEdgePayload::new())?;
+    let x2 = add_new_node(&mut graph, "X2", &[5, 6]);
+    graph.add_edge(find_key(&graph, "R"), x2, AuspiceGraphEdgePayload::new())?;
+    for (name, positions) in [("Q2", [7]), ("Q3", [8])] {
+      let q = add_new_node(&mut graph, name, &positions);
+      graph.add_edge(x2, q, AuspiceGraphEdgePayload::new())?;
+    }
+    graph.build_ref()?;
+
+    Ok((graph, num_ref_nodes))
+  }
+
+  #[rstest]
+  fn moves_new_node_to_better_position() -> Result<(), Report> {
+    let (mut graph, num_ref_nodes) = create_graph()?;
+    let params = TreeBuilderParams {
+      max_refinement_iterations: 10,
+      ..TreeBuilderParams::default()
+    };
+    let report = graph_refine_new_nodes_in_place(&mut graph, num_ref_nodes, 10, &params)?;
+
+    assert_eq!(
+      (report.iterations, report.score_before, report.score_after),
+      (2, 8.0, 7.0)
+    );
+    assert_eq!(
+      report
+        .moves
+        .iter()
+        .map(|m| (m.seq_name.as_str(), m.from_node.as_str(), m.to_node.as_str()))
+        .collect_vec(),
+      vec![("Q1", "X1", "X2")]
+    );
+    assert_eq!(parent_name(&graph, "Q1"), "X2");
+    assert_eq!(parent_name(&graph, "A"), "R");
+    assert_eq!(graph.num_nodes(), 7);
+    Ok(())
+  }
+
+  #[rstest]
+  fn does_not_modify_tree_without_iterations() -> Result<(), Report> {
+    let (mut graph, num_ref_nodes) = create_graph()?;
+    let params = TreeBuilderParams {
+      max_refinement_iterations: 0,
+      ..TreeBuilderParams::default()
+    };
+    let report = graph_refine_new_nodes_in_place(&mut graph, num_ref_nodes, 10, &params)?;
+
+    assert_eq!(
+      (
+        report.iterations,
+        report.score_before,
+        report.score_after,
+        report.moves.len()
+      ),
+      (0, 8.0, 8.0, 0)
+    );
+    assert_eq!(parent_name(&graph, "Q1"), "X1");
+    assert_eq!(graph.num_nodes(), 8);
+    Ok(())
+  }
+
+  #[rstest]
+  fn restores_node_after_rejected_move() -> Result<(), Report> {
+    let (mut graph, num_ref_nodes) = create_graph()?;
+    let num_edges = graph.iter_edges().count();
+
+    let q2 = find_key(&graph, "Q2");
+    let refinement_move = try_move_new_node(&mut graph, q2, 1, num_ref_nodes, 10, &TreeBuilderParams::default())?;
+
+    assert!(refinement_move.is_none());
+    assert_eq!(parent_name(&graph, "Q2"), "X2");
+    assert_eq!(graph.iter_edges().count(), num_edges);
+    assert_eq!(num_edges, graph.num_nodes() - 1);
+    Ok(())
+  }
+}
@@ -3,9 +3,13 @@ use crate::alphabet::nuc::Nuc;
 use crate::analyze::aa_changes::AaChangesGroup;
 use crate::analyze::aa_del::AaDel;
 use crate::analyze::aa_sub::AaSub;
+use crate::analyze::drug_resistance::DrugResistance;
 use crate::analyze::find_aa_motifs_changes::{AaMotifsChangesMap, AaMotifsMap};
+use crate::analyze::find_nuc_motifs::NucMotifsMap;
+use crate::analyze::find_nuc_motifs_changes::NucMotifsChangesMap;
 use crate::analyze::find_private_aa_mutations::PrivateAaMutations;
 use crate::analyze::find_private_nuc_mutations::PrivateNucMutations;
+use crate::analyze::find_relative_mutations::RelativeMutations;
 use crate::analyze::letter_ranges::{CdsAaRange, NucRange};
 use crate::analyze::nuc_del::NucDelRange;
 use crate::analyze::nuc_sub::NucSub;
@@ -15,6 +19,8 @@ use crate::graph::node::GraphNodeKey;
 use crate::io::json::json_parse;
 use crate::qc::qc_run::QcResult;
 use crate::translate::frame_shifts_translate::FrameShift;
+use crate::tree::tree_nearest_tips::NearestTip;
+use crate::tree::tree_placement_uncertainty::PlacementUncertainty;
 use eyre::{Report, WrapErr};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
@@ -26,6 +32,11 @@ pub struct PeptideWarning {
   pub warning: String,
 }
 
+/// Prefix of warnings concerning the sequence as a whole, which identifies the sequence
+pub fn seq_warning_prefix(index: usize, seq_name: &str) -> String {
+  format!("When processing sequence #{index} '{seq_name}': ")
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PhenotypeValue {
@@ -34,7 +45,7 @@ pub struct PhenotypeValue {
   pub value: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct NextcladeOutputs {
   pub index: usize,
@@ -77,15 +88,29 @@ pub struct NextcladeOutputs {
   pub missing_cdses: Vec<String>,
   pub divergence: f64,
   pub coverage: f64,
+  #[serde(default)]
+  pub cds_coverage: BTreeMap<String, f64>,
   pub qc: QcResult,
   pub custom_node_attributes: BTreeMap<String, String>,
   pub nearest_node_id: GraphNodeKey,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub nearest_nodes: Option<Vec<String>>,
+  #[serde(default)]
+  pub relative_mutations: Vec<RelativeMutations>,
+  #[serde(default)]
+  pub placement_uncertainty: PlacementUncertainty,
+  #[serde(default)]
+  pub nearest_tips: Vec<NearestTip>,
   pub is_reverse_complement: bool,
   pub phenotype_values: Option<Vec<PhenotypeValue>>,
   pub aa_motifs: AaMotifsMap,
   pub aa_motifs_changes: AaMotifsChangesMap,
+  #[serde(default)]
+  pub nuc_motifs: NucMotifsMap,
+  #[serde(default)]
+  pub nuc_motifs_changes: NucMotifsChangesMap,
+  #[serde(default)]
+  pub drug_resistance: Vec<DrugResistance>,
 }
 
 impl NextcladeOutputs {